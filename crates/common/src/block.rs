@@ -0,0 +1,103 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use ethereum_types::H256;
+use mp_poc::bls::{BlstCrypto, SignedAggregate};
+use serde::{Deserialize, Serialize};
+
+use crate::types::Transaction;
+
+/// Header of a block, the block hash covers all of its fields
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockHeader {
+    /// Height of the block, the first block has height 1
+    pub height: u64,
+    /// Hash of the previous block, zero for the first block
+    pub parent_hash: H256,
+    /// Time the block was sealed
+    pub timestamp: DateTime<Utc>,
+    /// Root of the trie over the block's transactions
+    pub tx_root: H256,
+    /// State root of the node when the block was sealed
+    pub state_root: String,
+}
+
+impl BlockHeader {
+    /// Compute the block hash
+    pub fn hash(&self) -> H256 {
+        let data = serde_json::to_vec(self).expect("header serialization cannot fail");
+        H256::from(mp_poc::keccak_256(&data))
+    }
+}
+
+/// Batch of confirmed transactions chained to its parent by hash
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Block {
+    /// Block header
+    pub header: BlockHeader,
+    /// Transactions in log order
+    pub transactions: Vec<Transaction>,
+    /// PoC aggregate signature over the block hash
+    #[serde(default)]
+    pub poc: Option<SignedAggregate>,
+}
+
+impl Block {
+    /// Create an unsigned block on top of `parent_hash`
+    pub fn new(
+        height: u64,
+        parent_hash: H256,
+        timestamp: DateTime<Utc>,
+        state_root: String,
+        transactions: Vec<Transaction>,
+    ) -> Result<Self> {
+        Ok(Self {
+            header: BlockHeader {
+                height,
+                parent_hash,
+                timestamp,
+                tx_root: transactions_root(&transactions)?,
+                state_root,
+            },
+            transactions,
+            poc: None,
+        })
+    }
+
+    /// Get the block hash
+    pub fn hash(&self) -> H256 {
+        self.header.hash()
+    }
+
+    /// Check that the transactions match the header and the PoC, if any, signs the block hash
+    pub fn verify(&self) -> Result<()> {
+        if transactions_root(&self.transactions)? != self.header.tx_root {
+            return Err(anyhow!(
+                "Transactions of block {} do not match its tx root",
+                self.header.height
+            ));
+        }
+
+        if let Some(poc) = &self.poc {
+            if poc.msg != self.hash().as_bytes() {
+                return Err(anyhow!(
+                    "PoC of block {} signs a different message",
+                    self.header.height
+                ));
+            }
+            if !BlstCrypto::verify_aggregate(poc)? {
+                return Err(anyhow!("Invalid PoC on block {}", self.header.height));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Root of the trie over the JSON encoding of the transactions
+pub fn transactions_root(transactions: &[Transaction]) -> Result<H256> {
+    let encoded = transactions
+        .iter()
+        .map(serde_json::to_vec)
+        .collect::<Result<Vec<_>, _>>()?;
+    mp_ethereum::calculate_root(encoded)
+        .map_err(|e| anyhow!("Failed to compute transaction root: {:?}", e))
+}
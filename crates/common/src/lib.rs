@@ -1,3 +1,4 @@
+pub mod block;
 pub mod error;
 pub mod types;
 pub mod utils;
@@ -16,10 +17,17 @@ pub struct TransactionResponse {
     pub transaction_id: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none", default)]
     pub status_code: Option<u32>,
+    /// Outbound requests the contract made through the node proxy
+    #[serde(skip_serializing_if = "is_zero", default)]
+    pub outbound_requests: u64,
     #[serde(flatten)]
     pub output: serde_json::Value,
 }
 
+fn is_zero(value: &u64) -> bool {
+    *value == 0
+}
+
 #[cfg(test)]
 mod tests {
     use serde_json::json;
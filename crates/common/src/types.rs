@@ -165,6 +165,12 @@ pub struct Transaction {
     pub timestamp: DateTime<Utc>,
     /// Transaction sender (if applicable)
     pub sender: Option<String>,
+    /// Position among the transactions of the sender, checked by the mempool
+    #[serde(default)]
+    pub nonce: Option<u64>,
+    /// Fee offered, a pending transaction is only replaced by one with the same nonce and a higher fee
+    #[serde(default)]
+    pub fee: u64,
     /// Index in the Raft log (used by consensus)
     #[serde(default)]
     pub log_index: u64,
@@ -284,6 +290,9 @@ pub struct TransactionResponse {
     pub status: TransactionStatus,
     /// Transaction result (if available)
     pub result: Option<serde_json::Value>,
+    /// HTTP status to report for a failed transaction
+    #[serde(default)]
+    pub status_code: Option<u16>,
 }
 
 impl TransactionResponse {
@@ -293,6 +302,7 @@ impl TransactionResponse {
             tx_id,
             status: TransactionStatus::Success,
             result: None,
+            status_code: None,
         }
     }
 
@@ -302,6 +312,7 @@ impl TransactionResponse {
             tx_id,
             status: TransactionStatus::Success,
             result: Some(result),
+            status_code: None,
         }
     }
 
@@ -311,6 +322,7 @@ impl TransactionResponse {
             tx_id: Uuid::new_v4(),
             status: TransactionStatus::Error,
             result: Some(serde_json::Value::String(message)),
+            status_code: None,
         }
     }
 }
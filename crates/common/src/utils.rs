@@ -23,6 +23,8 @@ pub fn create_transaction(
         method,
         timestamp: Utc::now(),
         sender,
+        nonce: None,
+        fee: 0,
         log_index: 0, // Will be set by the consensus layer
     }
 }
@@ -0,0 +1,267 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use mp_common::types::Transaction;
+use mp_poc::bls::{BlstCrypto, SignedAggregate, SignedByValidator, ValidatorPublicKey};
+use mp_state::diff::StateOperation;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+/// Batch of transactions ordered at a given height
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BftBlock {
+    /// Height of the block, the first block has height 1
+    pub height: u64,
+    /// Hash of the previous block
+    pub parent_hash: String,
+    /// Validator that first proposed the block
+    pub proposer: u64,
+    /// Time the block was proposed
+    pub timestamp: DateTime<Utc>,
+    /// State changes of executions, applied before the transactions
+    ///
+    /// Left out of the encoding when empty so blocks decided before state changes
+    /// were carried keep their hash.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub state_changes: Vec<StateChangeBatch>,
+    /// Ordered transactions
+    pub transactions: Vec<Transaction>,
+}
+
+/// State changes of one execution, applied on every validator once decided
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StateChangeBatch {
+    /// ID used to answer the submitter and to not apply the batch twice
+    pub id: Uuid,
+    pub operations: Vec<StateOperation>,
+}
+
+impl BftBlock {
+    /// Hash identifying the block in votes and certificates
+    pub fn hash(&self) -> String {
+        let data = serde_json::to_vec(self).expect("block serialization cannot fail");
+        hex::encode(mp_poc::keccak_256(&data))
+    }
+}
+
+/// Voting phase of a view
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Phase {
+    /// First round, a quorum locks the block
+    Prepare,
+    /// Second round, a quorum commits the block
+    Commit,
+}
+
+/// Bytes signed by a validator voting for a block
+pub fn vote_digest(phase: Phase, height: u64, view: u64, block_hash: &str) -> Vec<u8> {
+    format!("mp-bft/{:?}/{}/{}/{}", phase, height, view, block_hash).into_bytes()
+}
+
+/// Aggregated signatures of a quorum of validators on the same vote
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuorumCertificate {
+    pub phase: Phase,
+    pub height: u64,
+    pub view: u64,
+    pub block_hash: String,
+    /// BLS aggregate over the vote digest
+    pub signature: SignedAggregate,
+}
+
+impl QuorumCertificate {
+    /// Aggregate the given votes into a certificate
+    pub fn aggregate(
+        phase: Phase,
+        height: u64,
+        view: u64,
+        block_hash: String,
+        votes: &[SignedByValidator],
+    ) -> Result<Self> {
+        let digest = vote_digest(phase, height, view, &block_hash);
+        Ok(Self {
+            phase,
+            height,
+            view,
+            signature: BlstCrypto::aggregate(&digest, votes)?,
+            block_hash,
+        })
+    }
+
+    /// Check the certificate carries a quorum of valid signatures from the validator set
+    pub fn verify(&self, validators: &ValidatorSet) -> Result<()> {
+        let digest = vote_digest(self.phase, self.height, self.view, &self.block_hash);
+        if self.signature.msg != digest {
+            return Err(anyhow!("Certificate signs a different message"));
+        }
+
+        let signers: HashSet<&ValidatorPublicKey> =
+            self.signature.signature.validators.iter().collect();
+        if signers.len() != self.signature.signature.validators.len() {
+            return Err(anyhow!("Certificate contains duplicate signers"));
+        }
+        if let Some(unknown) = signers.iter().find(|pk| validators.id_of(pk).is_none()) {
+            return Err(anyhow!(
+                "Certificate signed by unknown validator {}",
+                unknown
+            ));
+        }
+        if signers.len() < validators.quorum() {
+            return Err(anyhow!(
+                "Certificate has {} signers, {} required",
+                signers.len(),
+                validators.quorum()
+            ));
+        }
+
+        if !BlstCrypto::verify_aggregate(&self.signature)? {
+            return Err(anyhow!("Invalid certificate signature"));
+        }
+        Ok(())
+    }
+}
+
+/// Block together with the certificate that committed it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommittedBlock {
+    pub block: BftBlock,
+    pub certificate: QuorumCertificate,
+}
+
+/// Block locked by a prepare certificate
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LockedBlock {
+    pub block: BftBlock,
+    pub certificate: QuorumCertificate,
+}
+
+/// Messages exchanged between validators
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum BftMessage {
+    /// Transaction gossiped to every validator so a faulty leader cannot censor it
+    Transaction(Transaction),
+    /// State changes gossiped to every validator like transactions
+    StateChanges(StateChangeBatch),
+    /// Block proposed by the leader of a view, justified by the highest known lock
+    Proposal {
+        view: u64,
+        block: BftBlock,
+        justify: Option<QuorumCertificate>,
+    },
+    /// Vote sent to the leader of a view
+    Vote {
+        phase: Phase,
+        height: u64,
+        view: u64,
+        block_hash: String,
+        signature: SignedByValidator,
+    },
+    /// Prepare certificate broadcast by the leader
+    PreCommit(QuorumCertificate),
+    /// Committed block broadcast by the leader
+    Decide(CommittedBlock),
+    /// Sent to the next leader when a view times out
+    NewView {
+        height: u64,
+        view: u64,
+        locked: Option<LockedBlock>,
+    },
+    /// Ask a peer for the blocks committed from the given height on
+    SyncRequest { from_height: u64 },
+}
+
+/// Signed message as sent over the wire
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Envelope {
+    /// ID of the sending validator
+    pub from: u64,
+    /// Serialized message signed by the sender
+    pub signed: SignedByValidator,
+}
+
+impl Envelope {
+    /// Sign a message as the given validator
+    pub fn seal(from: u64, crypto: &BlstCrypto, message: &BftMessage) -> Result<Self> {
+        Ok(Self {
+            from,
+            signed: crypto.sign(&serde_json::to_vec(message)?)?,
+        })
+    }
+
+    /// Check the sender's signature and decode the message
+    pub fn open(&self, validators: &ValidatorSet) -> Result<BftMessage> {
+        let expected = validators
+            .public_key(self.from)
+            .ok_or_else(|| anyhow!("Message from unknown validator {}", self.from))?;
+        if &self.signed.signature.validator != expected {
+            return Err(anyhow!(
+                "Message key does not match validator {}",
+                self.from
+            ));
+        }
+        if !BlstCrypto::verify(&self.signed)? {
+            return Err(anyhow!("Invalid signature from validator {}", self.from));
+        }
+        Ok(serde_json::from_slice(&self.signed.msg)?)
+    }
+}
+
+/// Validators taking part in consensus, ordered by ID
+#[derive(Debug, Clone)]
+pub struct ValidatorSet {
+    ids: Vec<u64>,
+    keys: HashMap<u64, ValidatorPublicKey>,
+}
+
+impl ValidatorSet {
+    /// Create a validator set from IDs and public keys
+    pub fn new(validators: impl IntoIterator<Item = (u64, ValidatorPublicKey)>) -> Self {
+        let keys: HashMap<u64, ValidatorPublicKey> = validators.into_iter().collect();
+        let mut ids: Vec<u64> = keys.keys().copied().collect();
+        ids.sort_unstable();
+        Self { ids, keys }
+    }
+
+    /// Number of validators
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// Check whether the set is empty
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// Number of faulty validators the set tolerates
+    pub fn max_faulty(&self) -> usize {
+        self.len().saturating_sub(1) / 3
+    }
+
+    /// Number of votes needed for a certificate
+    pub fn quorum(&self) -> usize {
+        self.len() - self.max_faulty()
+    }
+
+    /// Leader of the given height and view, rotating through the validators
+    pub fn leader(&self, height: u64, view: u64) -> u64 {
+        self.ids[((height + view) % self.ids.len() as u64) as usize]
+    }
+
+    /// Public key of a validator
+    pub fn public_key(&self, id: u64) -> Option<&ValidatorPublicKey> {
+        self.keys.get(&id)
+    }
+
+    /// ID of the validator owning a public key
+    pub fn id_of(&self, public_key: &ValidatorPublicKey) -> Option<u64> {
+        self.keys
+            .iter()
+            .find(|(_, key)| *key == public_key)
+            .map(|(id, _)| *id)
+    }
+
+    /// IDs of all validators
+    pub fn ids(&self) -> &[u64] {
+        &self.ids
+    }
+}
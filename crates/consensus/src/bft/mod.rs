@@ -0,0 +1,377 @@
+mod messages;
+mod network;
+mod protocol;
+mod storage;
+
+pub use self::messages::{
+    vote_digest, BftBlock, BftMessage, CommittedBlock, Envelope, LockedBlock, Phase,
+    QuorumCertificate, StateChangeBatch, ValidatorSet,
+};
+pub use self::protocol::GENESIS_HASH;
+
+use anyhow::{anyhow, Result};
+use mp_common::types::{Transaction, TransactionResponse, TransactionStatus};
+use mp_poc::bls::BlstCrypto;
+use mp_state::diff::StateOperation;
+use mp_state::StateStorage;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, RwLock};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio::time::{self, Duration};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use self::network::{BftNetwork, ValidatorAddresses};
+use self::protocol::{BftCore, CoreConfig, Event};
+use self::storage::BftStore;
+use crate::block::{BlockBuilder, BlockSigner, BlockStore};
+use crate::config::{BftConfig, ConsensusConfig, NodeInfo};
+use crate::{ConsensusEngine, StateControl};
+
+/// Time a submitted transaction may take to be decided
+const SUBMIT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Byzantine fault tolerant consensus engine
+///
+/// Validators run a two-phase HotStuff protocol with a rotating leader. Votes are
+/// signed with the validators' BLS keys and every decided block carries the
+/// aggregated commit votes of a quorum as its certificate, so up to a third of the
+/// validators (rounded down) may fail or misbehave.
+pub struct BftConsensusEngine {
+    /// Node ID
+    node_id: u64,
+    /// BFT settings
+    config: BftConfig,
+    /// Addresses of all validators
+    addresses: ValidatorAddresses,
+    /// Address the listener is bound to while the engine runs
+    listen_address: Option<SocketAddr>,
+    /// Signing key of the local validator
+    crypto: Arc<BlstCrypto>,
+    /// Validators and their public keys
+    validators: ValidatorSet,
+    /// Blocks decided so far
+    committed: Arc<RwLock<Vec<CommittedBlock>>>,
+    /// Decided blocks and voting state persisted under the log path
+    store: Arc<BftStore>,
+    /// Seals decided transactions into chain blocks
+    sealer: Arc<BlockBuilder>,
+    /// State the decided state changes are applied to
+    state_storage: Option<Arc<dyn StateStorage>>,
+    /// Sender for confirmed transactions
+    confirmed_tx_sender: mpsc::Sender<Transaction>,
+    /// Channel for receiving confirmed transactions
+    confirmed_tx_receiver: Arc<Mutex<Option<mpsc::Receiver<Transaction>>>>,
+    /// Input of the running event loop
+    events: Option<mpsc::UnboundedSender<Event>>,
+    /// Listener and event loop tasks
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl BftConsensusEngine {
+    /// Create a new BFT consensus engine
+    ///
+    /// Every decided block is sealed into a chain block whose state root is read
+    /// from `state_storage` when given, after the state changes the block carries
+    /// are applied to it. Decided blocks, the validator's votes and
+    /// the sealed chain are persisted under the BFT log path and resumed from on
+    /// the next start.
+    pub fn new(
+        config: ConsensusConfig,
+        state_storage: Option<Arc<dyn StateStorage>>,
+    ) -> Result<Self> {
+        let bft = config
+            .bft
+            .ok_or_else(|| anyhow!("BFT configuration is required"))?;
+        if bft.max_block_size == 0 {
+            return Err(anyhow!("BFT max_block_size must be greater than zero"));
+        }
+
+        let crypto = BlstCrypto::new(bft.key_seed.clone())?;
+        let validators =
+            ValidatorSet::new(bft.validators.iter().map(|v| (v.id, v.public_key.clone())));
+        match validators.public_key(config.node_id) {
+            Some(key) if key == crypto.validator_pubkey() => {}
+            Some(_) => {
+                return Err(anyhow!(
+                    "Key seed does not match the public key of validator {}",
+                    config.node_id
+                ))
+            }
+            None => {
+                return Err(anyhow!(
+                    "Node {} is not listed in the BFT validators",
+                    config.node_id
+                ))
+            }
+        }
+
+        let addresses: HashMap<u64, SocketAddr> =
+            config.nodes.iter().map(|n| (n.id, n.address)).collect();
+        if let Some(id) = validators
+            .ids()
+            .iter()
+            .find(|id| !addresses.contains_key(id))
+        {
+            return Err(anyhow!(
+                "Validator {} is not listed in the consensus nodes",
+                id
+            ));
+        }
+
+        let store = BftStore::open(&bft.log_path)?;
+        let committed = store.load_committed()?;
+        if !committed.is_empty() {
+            info!(
+                "Recovered {} decided blocks from {}",
+                committed.len(),
+                bft.log_path
+            );
+        }
+        let blocks = BlockStore::open(&bft.log_path)?;
+        let (confirmed_tx_sender, confirmed_tx_receiver) = mpsc::channel(1000);
+
+        Ok(Self {
+            node_id: config.node_id,
+            config: bft,
+            addresses: Arc::new(RwLock::new(addresses)),
+            listen_address: None,
+            crypto: Arc::new(crypto),
+            validators,
+            committed: Arc::new(RwLock::new(committed)),
+            store: Arc::new(store),
+            sealer: Arc::new(BlockBuilder::new(
+                config.block,
+                Arc::new(blocks),
+                state_storage.clone(),
+            )),
+            state_storage,
+            confirmed_tx_sender,
+            confirmed_tx_receiver: Arc::new(Mutex::new(Some(confirmed_tx_receiver))),
+            events: None,
+            tasks: Vec::new(),
+        })
+    }
+
+    /// Get the ID of the local node
+    pub fn node_id(&self) -> u64 {
+        self.node_id
+    }
+
+    /// Get the validator set
+    pub fn validators(&self) -> &ValidatorSet {
+        &self.validators
+    }
+
+    /// Get the blocks decided so far, in height order
+    pub fn committed_blocks(&self) -> Vec<CommittedBlock> {
+        self.committed.read().unwrap().clone()
+    }
+
+    /// Get the height of the last decided block, 0 before the first decision
+    pub fn height(&self) -> u64 {
+        self.committed.read().unwrap().len() as u64
+    }
+
+    /// Address the listener is bound to, known once the engine is started
+    ///
+    /// With port 0 in the configuration this is where the picked port shows.
+    pub fn listen_address(&self) -> Option<SocketAddr> {
+        self.listen_address
+    }
+
+    /// Point this validator at the address another one listens on
+    pub fn register_peer(&self, node: NodeInfo) {
+        self.addresses
+            .write()
+            .unwrap()
+            .insert(node.id, node.address);
+    }
+}
+
+#[async_trait::async_trait]
+impl ConsensusEngine for BftConsensusEngine {
+    /// Start the consensus engine
+    async fn start(&mut self) -> Result<()> {
+        info!("Starting BFT consensus engine (node ID: {})", self.node_id);
+
+        if self.events.is_some() {
+            warn!("BFT consensus engine already running");
+            return Ok(());
+        }
+
+        let (events, receiver) = mpsc::unbounded_channel();
+        let address = self.addresses.read().unwrap()[&self.node_id];
+        let (listener, address) =
+            network::start_listener(address, self.validators.clone(), events.clone()).await?;
+        self.addresses
+            .write()
+            .unwrap()
+            .insert(self.node_id, address);
+        self.listen_address = Some(address);
+
+        let network = BftNetwork::new(
+            self.node_id,
+            self.crypto.clone(),
+            &self.addresses,
+            events.clone(),
+        );
+        let core = BftCore::new(
+            CoreConfig {
+                node_id: self.node_id,
+                crypto: self.crypto.clone(),
+                validators: self.validators.clone(),
+                view_timeout: Duration::from_millis(self.config.view_timeout),
+                max_block_size: self.config.max_block_size,
+            },
+            network,
+            self.committed.clone(),
+            self.store.clone(),
+            self.sealer.clone(),
+            self.state_storage.clone(),
+            self.confirmed_tx_sender.clone(),
+        )?;
+
+        self.tasks = vec![listener, tokio::spawn(core.run(receiver))];
+        self.events = Some(events);
+
+        info!("BFT consensus engine started");
+        Ok(())
+    }
+
+    /// Stop the consensus engine
+    async fn stop(&mut self) -> Result<()> {
+        info!("Stopping BFT consensus engine");
+
+        self.events = None;
+        for task in self.tasks.drain(..) {
+            // Wait for the listener to be dropped so the address can be reused right away
+            task.abort();
+            let _ = task.await;
+        }
+        self.listen_address = None;
+
+        info!("BFT consensus engine stopped");
+        Ok(())
+    }
+
+    /// Submit a transaction to the consensus engine
+    ///
+    /// The transaction is sent to every validator and the call returns once it is decided.
+    async fn submit_transaction(&self, transaction: Transaction) -> Result<TransactionResponse> {
+        debug!(
+            "Submitting transaction to BFT consensus: {:?}",
+            transaction.id
+        );
+
+        let events = self
+            .events
+            .as_ref()
+            .ok_or_else(|| anyhow!("BFT consensus engine is not running"))?;
+        let tx_id = transaction.id;
+        let (reply, response) = oneshot::channel();
+        events
+            .send(Event::Submit { transaction, reply })
+            .map_err(|_| anyhow!("BFT consensus engine is not running"))?;
+
+        match time::timeout(SUBMIT_TIMEOUT, response).await {
+            Ok(Ok(response)) => Ok(response),
+            Ok(Err(_)) => Err(anyhow!(
+                "BFT consensus engine stopped before transaction {} was decided",
+                tx_id
+            )),
+            Err(_) => Err(anyhow!(
+                "Timed out waiting for transaction {} to be decided",
+                tx_id
+            )),
+        }
+    }
+
+    /// Get the store of sealed blocks
+    fn blocks(&self) -> Arc<BlockStore> {
+        self.sealer.store()
+    }
+
+    /// Sign sealed blocks with the node's PoC keys
+    fn set_block_signer(&self, signer: BlockSigner) {
+        self.sealer.set_signer(signer);
+    }
+
+    /// Get a channel for confirmed transactions
+    async fn get_confirmed_tx_channel(&self) -> mpsc::Receiver<Transaction> {
+        let mut guard = self.confirmed_tx_receiver.lock().unwrap();
+        if let Some(rx) = guard.take() {
+            rx
+        } else {
+            // Create a dummy channel if the original one has been taken
+            let (_, rx) = mpsc::channel(1);
+            rx
+        }
+    }
+
+    /// Get a handle for changing the state through decided blocks
+    fn state_control(&self) -> Option<Arc<dyn StateControl>> {
+        self.events.as_ref().map(|events| {
+            Arc::new(BftStateControl {
+                events: events.clone(),
+            }) as Arc<dyn StateControl>
+        })
+    }
+}
+
+/// State administration backed by a running BFT validator
+///
+/// State changes are gossiped to the validators and decided in blocks like
+/// transactions, every validator applies them when it commits the block.
+struct BftStateControl {
+    events: mpsc::UnboundedSender<Event>,
+}
+
+#[async_trait::async_trait]
+impl StateControl for BftStateControl {
+    async fn revert_state(&self, _root: String) -> Result<()> {
+        Err(anyhow!("State reverts are not supported by the BFT engine"))
+    }
+
+    async fn commit_state_changes(&self, operations: Vec<StateOperation>) -> Result<()> {
+        debug!("Submitting {} state changes", operations.len());
+        let batch = StateChangeBatch {
+            id: Uuid::new_v4(),
+            operations,
+        };
+        let batch_id = batch.id;
+        let (reply, response) = oneshot::channel();
+        self.events
+            .send(Event::SubmitStateChanges { batch, reply })
+            .map_err(|_| anyhow!("BFT consensus engine is not running"))?;
+
+        let response = match time::timeout(SUBMIT_TIMEOUT, response).await {
+            Ok(Ok(response)) => response,
+            Ok(Err(_)) => {
+                return Err(anyhow!(
+                    "BFT consensus engine stopped before state changes {} were decided",
+                    batch_id
+                ))
+            }
+            Err(_) => {
+                return Err(anyhow!(
+                    "Timed out waiting for state changes {} to be decided",
+                    batch_id
+                ))
+            }
+        };
+        if response.status == TransactionStatus::Error {
+            return Err(anyhow!(
+                "Applying state changes failed: {}",
+                response
+                    .result
+                    .as_ref()
+                    .and_then(|result| result.as_str())
+                    .unwrap_or("unknown error")
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,200 @@
+use anyhow::Result;
+use mp_poc::bls::BlstCrypto;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::{self, Duration, Instant};
+use tracing::{debug, error, info, warn};
+
+use super::messages::{BftMessage, Envelope, ValidatorSet};
+use super::protocol::Event;
+use crate::network::{read_frame, write_frame};
+
+/// Timeout for establishing a connection to a validator
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Time to wait before retrying an unreachable validator, messages sent meanwhile are dropped
+const RECONNECT_DELAY: Duration = Duration::from_millis(200);
+
+/// Addresses of the validators, looked up again whenever a link is (re)established
+pub(crate) type ValidatorAddresses = Arc<RwLock<HashMap<u64, SocketAddr>>>;
+
+/// Outgoing links to the other validators
+///
+/// Every validator gets its own queue drained by a dedicated task, so a slow or
+/// unreachable peer does not hold up the others. Messages to the local node skip
+/// the network and go straight to the event loop.
+pub(crate) struct BftNetwork {
+    node_id: u64,
+    crypto: Arc<BlstCrypto>,
+    peers: HashMap<u64, mpsc::UnboundedSender<Envelope>>,
+    local: mpsc::UnboundedSender<Event>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl BftNetwork {
+    /// Open queues to the given validators
+    pub(crate) fn new(
+        node_id: u64,
+        crypto: Arc<BlstCrypto>,
+        addresses: &ValidatorAddresses,
+        local: mpsc::UnboundedSender<Event>,
+    ) -> Self {
+        let ids: Vec<u64> = addresses.read().unwrap().keys().copied().collect();
+        let mut peers = HashMap::new();
+        let mut tasks = Vec::new();
+        for id in ids.into_iter().filter(|id| *id != node_id) {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            peers.insert(id, sender);
+            tasks.push(tokio::spawn(run_peer(id, addresses.clone(), receiver)));
+        }
+
+        Self {
+            node_id,
+            crypto,
+            peers,
+            local,
+            tasks,
+        }
+    }
+
+    /// Send a message to a single validator
+    pub(crate) fn send(&self, to: u64, message: BftMessage) {
+        if to == self.node_id {
+            let _ = self.local.send(Event::Message {
+                from: self.node_id,
+                message,
+            });
+        } else if let Some(peer) = self.peers.get(&to) {
+            match Envelope::seal(self.node_id, &self.crypto, &message) {
+                Ok(envelope) => {
+                    let _ = peer.send(envelope);
+                }
+                Err(e) => error!("Failed to sign BFT message: {}", e),
+            }
+        }
+    }
+
+    /// Send a message to every validator, including the local one
+    pub(crate) fn broadcast(&self, message: BftMessage) {
+        match Envelope::seal(self.node_id, &self.crypto, &message) {
+            Ok(envelope) => {
+                for peer in self.peers.values() {
+                    let _ = peer.send(envelope.clone());
+                }
+            }
+            Err(e) => error!("Failed to sign BFT message: {}", e),
+        }
+        let _ = self.local.send(Event::Message {
+            from: self.node_id,
+            message,
+        });
+    }
+}
+
+impl Drop for BftNetwork {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+/// Deliver queued messages to a validator, reconnecting when the link breaks
+async fn run_peer(
+    id: u64,
+    addresses: ValidatorAddresses,
+    mut queue: mpsc::UnboundedReceiver<Envelope>,
+) {
+    let mut stream: Option<TcpStream> = None;
+    let mut last_failure: Option<Instant> = None;
+
+    while let Some(envelope) = queue.recv().await {
+        if stream.is_none() {
+            if last_failure.is_some_and(|at| at.elapsed() < RECONNECT_DELAY) {
+                continue;
+            }
+            let Some(address) = addresses.read().unwrap().get(&id).copied() else {
+                continue;
+            };
+            match time::timeout(CONNECT_TIMEOUT, TcpStream::connect(address)).await {
+                Ok(Ok(connected)) => {
+                    debug!("Connected to validator {} at {}", id, address);
+                    stream = Some(connected);
+                    last_failure = None;
+                }
+                _ => {
+                    debug!("Validator {} at {} is unreachable", id, address);
+                    last_failure = Some(Instant::now());
+                    continue;
+                }
+            }
+        }
+
+        if let Some(connection) = stream.as_mut() {
+            if let Err(e) = write_frame(connection, &envelope).await {
+                debug!("Lost connection to validator {}: {}", id, e);
+                stream = None;
+                last_failure = Some(Instant::now());
+            }
+        }
+    }
+}
+
+/// Accept connections from the other validators and feed their messages to the event loop
+///
+/// Returns the address the listener is bound to, which tells the port picked
+/// when `address` has port 0.
+pub(crate) async fn start_listener(
+    address: SocketAddr,
+    validators: ValidatorSet,
+    events: mpsc::UnboundedSender<Event>,
+) -> Result<(JoinHandle<()>, SocketAddr)> {
+    let listener = TcpListener::bind(address).await?;
+    let address = listener.local_addr()?;
+    info!("BFT listener bound to {}", address);
+
+    let task = tokio::spawn(async move {
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    error!("Failed to accept BFT connection: {}", e);
+                    continue;
+                }
+            };
+            debug!("Accepted BFT connection from {}", peer);
+
+            let validators = validators.clone();
+            let events = events.clone();
+            tokio::spawn(async move {
+                if let Err(e) = handle_connection(stream, validators, events).await {
+                    debug!("BFT connection from {} closed: {}", peer, e);
+                }
+            });
+        }
+    });
+    Ok((task, address))
+}
+
+async fn handle_connection(
+    mut stream: TcpStream,
+    validators: ValidatorSet,
+    events: mpsc::UnboundedSender<Event>,
+) -> Result<()> {
+    loop {
+        let envelope = read_frame::<Envelope>(&mut stream).await?;
+        match envelope.open(&validators) {
+            Ok(message) => events
+                .send(Event::Message {
+                    from: envelope.from,
+                    message,
+                })
+                .map_err(|_| anyhow::anyhow!("BFT consensus engine stopped"))?,
+            Err(e) => warn!("Dropping BFT message: {}", e),
+        }
+    }
+}
@@ -0,0 +1,820 @@
+use anyhow::{anyhow, bail, Result};
+use mp_common::types::{Transaction, TransactionResponse};
+use mp_poc::bls::{BlstCrypto, SignedByValidator};
+use mp_state::diff::StateOperation;
+use mp_state::StateStorage;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::{Arc, RwLock};
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{self, Duration, Instant};
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+use super::messages::{
+    vote_digest, BftBlock, BftMessage, CommittedBlock, LockedBlock, Phase, QuorumCertificate,
+    StateChangeBatch, ValidatorSet,
+};
+use super::network::BftNetwork;
+use super::storage::{BftStore, SafetyState};
+use crate::block::{BlockBuilder, ChainState};
+use crate::nonce::CommittedNonces;
+
+/// Parent hash of the first block
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Maximum number of blocks sent in answer to a single sync request
+const MAX_SYNC_BLOCKS: usize = 64;
+
+/// Minimum delay between two sync requests
+const SYNC_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Input of the consensus event loop
+pub(crate) enum Event {
+    /// Message from a validator, or from the local node to itself
+    Message { from: u64, message: BftMessage },
+    /// Transaction submitted on this node, answered once it is committed
+    Submit {
+        transaction: Transaction,
+        reply: oneshot::Sender<TransactionResponse>,
+    },
+    /// State changes submitted on this node, answered once they are applied
+    SubmitStateChanges {
+        batch: StateChangeBatch,
+        reply: oneshot::Sender<TransactionResponse>,
+    },
+}
+
+/// Consensus settings of the local validator
+pub(crate) struct CoreConfig {
+    pub node_id: u64,
+    pub crypto: Arc<BlstCrypto>,
+    pub validators: ValidatorSet,
+    pub view_timeout: Duration,
+    pub max_block_size: usize,
+}
+
+/// Two-phase HotStuff state machine
+///
+/// Every height is decided in one or more views. The leader of a view proposes a
+/// block, a quorum of prepare votes locks it and a quorum of commit votes decides
+/// it. A validator locked on a block only votes for another one when the proposal
+/// carries a prepare certificate from a later view, which keeps conflicting blocks
+/// from being decided at the same height. When a view times out validators report
+/// their lock to the next leader, which re-proposes the most recent one.
+pub(crate) struct BftCore {
+    node_id: u64,
+    crypto: Arc<BlstCrypto>,
+    validators: ValidatorSet,
+    network: BftNetwork,
+    view_timeout: Duration,
+    max_block_size: usize,
+    /// Blocks decided so far, shared with the engine
+    committed: Arc<RwLock<Vec<CommittedBlock>>>,
+    /// Persists decided blocks and the votes cast at the current height
+    store: Arc<BftStore>,
+    /// Seals the transactions of every decided block into a chain block
+    sealer: Arc<BlockBuilder>,
+    /// State the decided state changes are applied to
+    state_storage: Option<Arc<dyn StateStorage>>,
+    chain: ChainState,
+    committed_txs: HashSet<Uuid>,
+    /// Next nonce of every sender, transactions replaying a spent nonce are refused
+    nonces: CommittedNonces,
+    confirmed_tx_sender: mpsc::Sender<Transaction>,
+    /// Index of the last confirmed transaction
+    log_index: u64,
+    /// Transactions waiting to be ordered
+    pending: VecDeque<Transaction>,
+    /// State changes waiting to be ordered
+    pending_state_changes: VecDeque<StateChangeBatch>,
+    pending_ids: HashSet<Uuid>,
+    waiters: HashMap<Uuid, Vec<oneshot::Sender<TransactionResponse>>>,
+    /// Height of the next block to decide
+    height: u64,
+    view: u64,
+    last_hash: String,
+    /// Decided blocks received ahead of the local height
+    future: BTreeMap<u64, CommittedBlock>,
+    last_sync: Option<Instant>,
+    deadline: Option<Instant>,
+    // State of the current height, reset on every decision
+    locked: Option<LockedBlock>,
+    blocks: HashMap<String, BftBlock>,
+    proposed: HashSet<u64>,
+    voted: HashSet<(Phase, u64)>,
+    votes: HashMap<(Phase, u64, String), HashMap<u64, SignedByValidator>>,
+    certified: HashSet<(Phase, u64)>,
+    new_views: HashMap<u64, HashMap<u64, Option<LockedBlock>>>,
+}
+
+impl BftCore {
+    /// Create the state machine, resuming after the blocks already decided
+    ///
+    /// Votes cast and the lock taken at the next height before a restart are
+    /// restored, so the validator does not vote against them.
+    pub(crate) fn new(
+        config: CoreConfig,
+        network: BftNetwork,
+        committed: Arc<RwLock<Vec<CommittedBlock>>>,
+        store: Arc<BftStore>,
+        sealer: Arc<BlockBuilder>,
+        state_storage: Option<Arc<dyn StateStorage>>,
+        confirmed_tx_sender: mpsc::Sender<Transaction>,
+    ) -> Result<Self> {
+        let chain = match sealer.store().latest() {
+            Some(block) => ChainState {
+                height: block.header.height,
+                last_hash: block.hash(),
+                pending: Vec::new(),
+            },
+            None => ChainState::default(),
+        };
+        let (height, last_hash) = {
+            let blocks = committed.read().unwrap();
+            let last_hash = blocks
+                .last()
+                .map(|c| c.block.hash())
+                .unwrap_or_else(|| GENESIS_HASH.to_string());
+            (blocks.len() as u64 + 1, last_hash)
+        };
+        // Replay the decided blocks to find the confirmed entries and the spent nonces
+        let mut committed_txs = HashSet::new();
+        let mut nonces = CommittedNonces::default();
+        let mut log_index = 0;
+        for c in committed.read().unwrap().iter() {
+            for batch in &c.block.state_changes {
+                if committed_txs.insert(batch.id) {
+                    log_index += 1;
+                }
+            }
+            for transaction in &c.block.transactions {
+                if committed_txs.insert(transaction.id) && nonces.spend(transaction).is_ok() {
+                    log_index += 1;
+                }
+            }
+        }
+        let safety = store
+            .load_safety()?
+            .filter(|safety| safety.height == height)
+            .unwrap_or_default();
+        if !safety.voted.is_empty() {
+            info!(
+                "Resuming BFT height {} in view {} after {} votes",
+                height,
+                safety.view,
+                safety.voted.len()
+            );
+        }
+
+        let mut core = Self {
+            node_id: config.node_id,
+            crypto: config.crypto,
+            validators: config.validators,
+            network,
+            view_timeout: config.view_timeout,
+            max_block_size: config.max_block_size,
+            committed,
+            store,
+            sealer,
+            state_storage,
+            chain,
+            log_index,
+            committed_txs,
+            nonces,
+            confirmed_tx_sender,
+            pending: VecDeque::new(),
+            pending_state_changes: VecDeque::new(),
+            pending_ids: HashSet::new(),
+            waiters: HashMap::new(),
+            height,
+            view: safety.view,
+            last_hash,
+            future: BTreeMap::new(),
+            last_sync: None,
+            deadline: None,
+            locked: safety.locked,
+            blocks: HashMap::new(),
+            proposed: HashSet::new(),
+            voted: safety.voted,
+            votes: HashMap::new(),
+            certified: HashSet::new(),
+            new_views: HashMap::new(),
+        };
+        core.reset_timer();
+        Ok(core)
+    }
+
+    /// Process events until the engine is stopped
+    pub(crate) async fn run(mut self, mut events: mpsc::UnboundedReceiver<Event>) {
+        loop {
+            let deadline = self.deadline;
+            tokio::select! {
+                event = events.recv() => match event {
+                    Some(event) => self.handle_event(event).await,
+                    None => break,
+                },
+                _ = wait_until(deadline) => self.on_timeout(),
+            }
+        }
+    }
+
+    async fn handle_event(&mut self, event: Event) {
+        match event {
+            Event::Submit { transaction, reply } => {
+                if self.committed_txs.contains(&transaction.id) {
+                    let _ = reply.send(TransactionResponse::success(transaction.id));
+                    return;
+                }
+                self.waiters.entry(transaction.id).or_default().push(reply);
+                self.network.broadcast(BftMessage::Transaction(transaction));
+            }
+            Event::SubmitStateChanges { batch, reply } => {
+                if self.committed_txs.contains(&batch.id) {
+                    let _ = reply.send(TransactionResponse::success(batch.id));
+                    return;
+                }
+                self.waiters.entry(batch.id).or_default().push(reply);
+                self.network.broadcast(BftMessage::StateChanges(batch));
+            }
+            Event::Message { from, message } => {
+                if let Err(e) = self.handle_message(from, message).await {
+                    debug!("Ignoring BFT message from validator {}: {}", from, e);
+                }
+            }
+        }
+    }
+
+    async fn handle_message(&mut self, from: u64, message: BftMessage) -> Result<()> {
+        match message {
+            BftMessage::Transaction(transaction) => self.on_transaction(transaction),
+            BftMessage::StateChanges(batch) => self.on_state_changes(batch),
+            BftMessage::Proposal {
+                view,
+                block,
+                justify,
+            } => self.on_proposal(from, view, block, justify),
+            BftMessage::Vote {
+                phase,
+                height,
+                view,
+                block_hash,
+                signature,
+            } => self.on_vote(from, phase, height, view, block_hash, signature),
+            BftMessage::PreCommit(certificate) => self.on_pre_commit(certificate),
+            BftMessage::Decide(committed) => self.on_decide(from, committed).await,
+            BftMessage::NewView {
+                height,
+                view,
+                locked,
+            } => self.on_new_view(from, height, view, locked),
+            BftMessage::SyncRequest { from_height } => {
+                self.send_committed(from, from_height);
+                Ok(())
+            }
+        }
+    }
+
+    fn on_transaction(&mut self, transaction: Transaction) -> Result<()> {
+        if self.committed_txs.contains(&transaction.id) || !self.pending_ids.insert(transaction.id)
+        {
+            return Ok(());
+        }
+        self.pending.push_back(transaction);
+        if self.deadline.is_none() {
+            self.reset_timer();
+        }
+        self.try_propose();
+        Ok(())
+    }
+
+    fn on_state_changes(&mut self, batch: StateChangeBatch) -> Result<()> {
+        if self.committed_txs.contains(&batch.id) || !self.pending_ids.insert(batch.id) {
+            return Ok(());
+        }
+        self.pending_state_changes.push_back(batch);
+        if self.deadline.is_none() {
+            self.reset_timer();
+        }
+        self.try_propose();
+        Ok(())
+    }
+
+    fn on_proposal(
+        &mut self,
+        from: u64,
+        view: u64,
+        block: BftBlock,
+        justify: Option<QuorumCertificate>,
+    ) -> Result<()> {
+        if block.height > self.height {
+            self.request_sync(from);
+            return Ok(());
+        }
+        if block.height < self.height || view < self.view {
+            return Ok(());
+        }
+        if from != self.validators.leader(self.height, view) {
+            bail!("Validator {} does not lead view {}", from, view);
+        }
+        if block.parent_hash != self.last_hash {
+            bail!("Proposal does not extend the last decided block");
+        }
+        if block.transactions.len() + block.state_changes.len() > self.max_block_size {
+            bail!("Proposal exceeds the maximum block size");
+        }
+        if block
+            .state_changes
+            .iter()
+            .map(|batch| batch.id)
+            .chain(block.transactions.iter().map(|tx| tx.id))
+            .any(|id| self.committed_txs.contains(&id))
+        {
+            bail!("Proposal contains committed transactions");
+        }
+
+        let hash = block.hash();
+        if let Some(certificate) = &justify {
+            if certificate.phase != Phase::Prepare
+                || certificate.height != self.height
+                || certificate.view >= view
+                || certificate.block_hash != hash
+            {
+                bail!("Proposal justification does not match the block");
+            }
+            certificate.verify(&self.validators)?;
+        }
+
+        let safe = match &self.locked {
+            None => true,
+            Some(locked) => {
+                locked.certificate.block_hash == hash
+                    || justify
+                        .as_ref()
+                        .is_some_and(|c| c.view > locked.certificate.view)
+            }
+        };
+        if !safe {
+            bail!(
+                "Proposal conflicts with the block locked in view {}",
+                self.locked.as_ref().map_or(0, |l| l.certificate.view)
+            );
+        }
+
+        if view > self.view {
+            self.enter_view(view);
+        }
+        if !self.voted.insert((Phase::Prepare, view)) {
+            return Ok(());
+        }
+        self.blocks.insert(hash.clone(), block);
+        self.vote(Phase::Prepare, view, hash)
+    }
+
+    fn on_vote(
+        &mut self,
+        from: u64,
+        phase: Phase,
+        height: u64,
+        view: u64,
+        block_hash: String,
+        signature: SignedByValidator,
+    ) -> Result<()> {
+        if height != self.height
+            || view != self.view
+            || self.validators.leader(height, view) != self.node_id
+            || self.certified.contains(&(phase, view))
+        {
+            return Ok(());
+        }
+        if signature.msg != vote_digest(phase, height, view, &block_hash)
+            || self.validators.public_key(from) != Some(&signature.signature.validator)
+        {
+            bail!("Vote is not signed by the sender");
+        }
+        if !BlstCrypto::verify(&signature)? {
+            bail!("Invalid vote signature");
+        }
+
+        let votes = self
+            .votes
+            .entry((phase, view, block_hash.clone()))
+            .or_default();
+        votes.insert(from, signature);
+        if votes.len() < self.validators.quorum() {
+            return Ok(());
+        }
+
+        let votes: Vec<SignedByValidator> = votes.values().cloned().collect();
+        let certificate = QuorumCertificate::aggregate(phase, height, view, block_hash, &votes)?;
+        self.certified.insert((phase, view));
+
+        match phase {
+            Phase::Prepare => self.network.broadcast(BftMessage::PreCommit(certificate)),
+            Phase::Commit => {
+                let block = self
+                    .blocks
+                    .get(&certificate.block_hash)
+                    .cloned()
+                    .ok_or_else(|| anyhow!("Unknown block {}", certificate.block_hash))?;
+                self.network
+                    .broadcast(BftMessage::Decide(CommittedBlock { block, certificate }));
+            }
+        }
+        Ok(())
+    }
+
+    fn on_pre_commit(&mut self, certificate: QuorumCertificate) -> Result<()> {
+        // Certificates of earlier views are ignored: the lock reported when leaving
+        // a view must not change afterwards.
+        if certificate.phase != Phase::Prepare
+            || certificate.height != self.height
+            || certificate.view != self.view
+        {
+            return Ok(());
+        }
+        certificate.verify(&self.validators)?;
+
+        let block = self
+            .blocks
+            .get(&certificate.block_hash)
+            .cloned()
+            .ok_or_else(|| anyhow!("Unknown block {}", certificate.block_hash))?;
+        if self
+            .locked
+            .as_ref()
+            .is_none_or(|l| l.certificate.view <= certificate.view)
+        {
+            self.locked = Some(LockedBlock {
+                block,
+                certificate: certificate.clone(),
+            });
+        }
+
+        if !self.voted.insert((Phase::Commit, certificate.view)) {
+            return Ok(());
+        }
+        self.vote(Phase::Commit, certificate.view, certificate.block_hash)
+    }
+
+    async fn on_decide(&mut self, from: u64, committed: CommittedBlock) -> Result<()> {
+        let height = committed.block.height;
+        if height < self.height {
+            return Ok(());
+        }
+
+        let certificate = &committed.certificate;
+        if certificate.phase != Phase::Commit
+            || certificate.height != height
+            || certificate.block_hash != committed.block.hash()
+        {
+            bail!("Certificate does not match the decided block");
+        }
+        certificate.verify(&self.validators)?;
+
+        if height > self.height {
+            self.future.insert(height, committed);
+            self.request_sync(from);
+            return Ok(());
+        }
+
+        self.commit(committed).await?;
+        while let Some(next) = self.future.remove(&self.height) {
+            self.commit(next).await?;
+        }
+        self.try_propose();
+        Ok(())
+    }
+
+    fn on_new_view(
+        &mut self,
+        from: u64,
+        height: u64,
+        view: u64,
+        locked: Option<LockedBlock>,
+    ) -> Result<()> {
+        if height < self.height {
+            // The sender missed a decision, help it catch up
+            self.send_committed(from, height);
+            return Ok(());
+        }
+        if height > self.height {
+            self.request_sync(from);
+            return Ok(());
+        }
+        if view < self.view || self.validators.leader(height, view) != self.node_id {
+            return Ok(());
+        }
+
+        if let Some(locked) = &locked {
+            let certificate = &locked.certificate;
+            if certificate.phase != Phase::Prepare
+                || certificate.height != height
+                || certificate.view >= view
+                || locked.block.height != height
+                || certificate.block_hash != locked.block.hash()
+            {
+                bail!("Reported lock does not match its certificate");
+            }
+            certificate.verify(&self.validators)?;
+        }
+
+        let reports = self.new_views.entry(view).or_default();
+        reports.insert(from, locked);
+        if reports.len() >= self.validators.quorum() && view > self.view {
+            self.enter_view(view);
+        }
+        self.try_propose();
+        Ok(())
+    }
+
+    fn on_timeout(&mut self) {
+        self.enter_view(self.view + 1);
+        warn!(
+            "BFT view timed out at height {}, moving to view {}",
+            self.height, self.view
+        );
+
+        let leader = self.validators.leader(self.height, self.view);
+        self.network.send(
+            leader,
+            BftMessage::NewView {
+                height: self.height,
+                view: self.view,
+                locked: self.locked.clone(),
+            },
+        );
+    }
+
+    /// Propose a block if this validator leads the current view and has something to propose
+    fn try_propose(&mut self) {
+        if self.validators.leader(self.height, self.view) != self.node_id
+            || self.proposed.contains(&self.view)
+        {
+            return;
+        }
+
+        let (block, justify) = if self.view == 0 {
+            if self.pending.is_empty() && self.pending_state_changes.is_empty() {
+                return;
+            }
+            (self.new_block(), None)
+        } else {
+            let Some(reports) = self.new_views.get(&self.view) else {
+                return;
+            };
+            if reports.len() < self.validators.quorum() {
+                return;
+            }
+            let highest = reports
+                .values()
+                .flatten()
+                .chain(self.locked.iter())
+                .max_by_key(|l| l.certificate.view)
+                .cloned();
+            match highest {
+                Some(locked) => (locked.block, Some(locked.certificate)),
+                None => (self.new_block(), None),
+            }
+        };
+
+        debug!(
+            "Proposing block with {} transactions and {} state changes at height {} view {}",
+            block.transactions.len(),
+            block.state_changes.len(),
+            self.height,
+            self.view
+        );
+        self.proposed.insert(self.view);
+        self.network.broadcast(BftMessage::Proposal {
+            view: self.view,
+            block,
+            justify,
+        });
+    }
+
+    fn new_block(&self) -> BftBlock {
+        let state_changes: Vec<StateChangeBatch> = self
+            .pending_state_changes
+            .iter()
+            .take(self.max_block_size)
+            .cloned()
+            .collect();
+        BftBlock {
+            height: self.height,
+            parent_hash: self.last_hash.clone(),
+            proposer: self.node_id,
+            timestamp: chrono::Utc::now(),
+            transactions: self
+                .pending
+                .iter()
+                .take(self.max_block_size - state_changes.len())
+                .cloned()
+                .collect(),
+            state_changes,
+        }
+    }
+
+    fn vote(&mut self, phase: Phase, view: u64, block_hash: String) -> Result<()> {
+        // The vote and the lock must be on disk before the vote leaves the node
+        self.store.save_safety(&SafetyState {
+            height: self.height,
+            view: self.view,
+            locked: self.locked.clone(),
+            voted: self.voted.clone(),
+        })?;
+        let signature = self
+            .crypto
+            .sign(&vote_digest(phase, self.height, view, &block_hash))?;
+        self.network.send(
+            self.validators.leader(self.height, view),
+            BftMessage::Vote {
+                phase,
+                height: self.height,
+                view,
+                block_hash,
+                signature,
+            },
+        );
+        Ok(())
+    }
+
+    /// Apply a decided block and move to the next height
+    async fn commit(&mut self, committed: CommittedBlock) -> Result<()> {
+        let hash = committed.block.hash();
+        if committed.block.parent_hash != self.last_hash {
+            bail!("Decided block {} does not extend {}", hash, self.last_hash);
+        }
+        info!(
+            "Decided block {} at height {} with {} transactions",
+            hash,
+            self.height,
+            committed.block.transactions.len()
+        );
+
+        let state_changes = committed.block.state_changes.clone();
+        let transactions = committed.block.transactions.clone();
+        let timestamp = committed.block.timestamp;
+        self.committed.write().unwrap().push(committed.clone());
+
+        // State changes go first so the sealed block carries the root they lead to.
+        // Applying them again after a crash writes the same values in the same order.
+        for batch in state_changes {
+            if !self.committed_txs.insert(batch.id) {
+                continue;
+            }
+            self.log_index += 1;
+            let response = self.apply_state_changes(batch.id, batch.operations);
+            for waiter in self.waiters.remove(&batch.id).unwrap_or_default() {
+                let _ = waiter.send(response.clone());
+            }
+        }
+
+        let mut confirmed = Vec::new();
+        for mut transaction in transactions {
+            if !self.committed_txs.insert(transaction.id) {
+                continue;
+            }
+            if let Err(e) = self.nonces.spend(&transaction) {
+                warn!("Refusing transaction at height {}: {}", self.height, e);
+                for waiter in self.waiters.remove(&transaction.id).unwrap_or_default() {
+                    let _ = waiter.send(TransactionResponse::error(e.clone()));
+                }
+                continue;
+            }
+            self.log_index += 1;
+            transaction.log_index = self.log_index;
+
+            for waiter in self.waiters.remove(&transaction.id).unwrap_or_default() {
+                let _ = waiter.send(TransactionResponse::success(transaction.id));
+            }
+            confirmed.push(transaction.clone());
+            if let Err(e) = self.confirmed_tx_sender.send(transaction).await {
+                warn!("Failed to publish confirmed transaction: {}", e);
+            }
+        }
+        // A block sealed right before a restart is decided again from the peers
+        let sealed = self.sealer.store().latest().is_some_and(|block| {
+            block
+                .transactions
+                .iter()
+                .map(|tx| tx.id)
+                .eq(confirmed.iter().map(|tx| tx.id))
+        });
+        if !confirmed.is_empty() && !sealed {
+            if let Err(e) = self
+                .sealer
+                .seal_transactions(&mut self.chain, confirmed, timestamp)
+            {
+                error!("Failed to seal block at height {}: {}", self.height, e);
+            }
+        }
+        // Recorded once sealed, a block decided but not sealed before a crash is synced again
+        if let Err(e) = self.store.append_committed(&committed) {
+            error!("Failed to persist decided block {}: {}", hash, e);
+        }
+        self.pending
+            .retain(|tx| !self.committed_txs.contains(&tx.id));
+        self.pending_state_changes
+            .retain(|batch| !self.committed_txs.contains(&batch.id));
+        self.pending_ids
+            .retain(|id| !self.committed_txs.contains(id));
+
+        self.height += 1;
+        self.view = 0;
+        self.last_hash = hash;
+        self.locked = None;
+        self.blocks.clear();
+        self.proposed.clear();
+        self.voted.clear();
+        self.votes.clear();
+        self.certified.clear();
+        self.new_views.clear();
+        self.reset_timer();
+        Ok(())
+    }
+
+    /// Apply decided state changes and record the root they lead to at the current log index
+    fn apply_state_changes(
+        &self,
+        id: Uuid,
+        operations: Vec<StateOperation>,
+    ) -> TransactionResponse {
+        let Some(storage) = &self.state_storage else {
+            return TransactionResponse::error(
+                "No state storage to apply state changes to".to_string(),
+            );
+        };
+        match storage.apply_operations(operations) {
+            Ok(diff) => {
+                debug!(
+                    "Applied {} state changes at log index {}, state root {}",
+                    diff.len(),
+                    self.log_index,
+                    diff.new_root
+                );
+                if let Err(e) = storage.record_log_root(self.log_index, &diff.new_root) {
+                    error!(
+                        "Failed to record the state root of log index {}: {}",
+                        self.log_index, e
+                    );
+                }
+                TransactionResponse::success(id)
+            }
+            Err(e) => {
+                error!(
+                    "Failed to apply state changes at log index {}: {}",
+                    self.log_index, e
+                );
+                TransactionResponse::error(e.to_string())
+            }
+        }
+    }
+
+    fn enter_view(&mut self, view: u64) {
+        self.view = view;
+        self.reset_timer();
+    }
+
+    /// Restart the view timer, it only runs while there is something to decide
+    fn reset_timer(&mut self) {
+        let busy = !self.pending.is_empty()
+            || !self.pending_state_changes.is_empty()
+            || self.locked.is_some()
+            || self.view > 0;
+        self.deadline = busy.then(|| {
+            // Back off linearly so validators with drifting clocks end up in the same view
+            Instant::now() + self.view_timeout * (self.view.min(8) as u32 + 1)
+        });
+    }
+
+    fn request_sync(&mut self, to: u64) {
+        if self
+            .last_sync
+            .is_some_and(|at| at.elapsed() < SYNC_INTERVAL)
+        {
+            return;
+        }
+        self.last_sync = Some(Instant::now());
+        self.network.send(
+            to,
+            BftMessage::SyncRequest {
+                from_height: self.height,
+            },
+        );
+    }
+
+    fn send_committed(&self, to: u64, from_height: u64) {
+        let blocks = self.committed.read().unwrap();
+        let start = from_height.saturating_sub(1) as usize;
+        for committed in blocks.iter().skip(start).take(MAX_SYNC_BLOCKS) {
+            self.network.send(to, BftMessage::Decide(committed.clone()));
+        }
+    }
+}
+
+async fn wait_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
@@ -0,0 +1,103 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tracing::error;
+
+use super::messages::{CommittedBlock, LockedBlock, Phase};
+
+const COMMITTED_FILE: &str = "committed.jsonl";
+const SAFETY_FILE: &str = "safety.json";
+
+/// Votes and lock of a validator at the height being decided
+///
+/// Persisted before every vote so that a restarted validator never votes twice
+/// in a view nor forgets the block it is locked on.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct SafetyState {
+    /// Height the votes were cast at
+    pub height: u64,
+    /// View the validator was in
+    pub view: u64,
+    /// Block locked by a prepare certificate
+    pub locked: Option<LockedBlock>,
+    /// Phases and views already voted in
+    pub voted: HashSet<(Phase, u64)>,
+}
+
+/// Decided blocks and voting state persisted under the BFT log path
+///
+/// Decided blocks are appended to `committed.jsonl`, the voting state of the
+/// current height is rewritten atomically in `safety.json`.
+pub(crate) struct BftStore {
+    path: PathBuf,
+}
+
+impl BftStore {
+    /// Open the store under `path`
+    pub(crate) fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        std::fs::create_dir_all(&path)
+            .with_context(|| format!("Failed to create BFT log directory {:?}", path))?;
+        Ok(Self { path })
+    }
+
+    /// Load the blocks decided by a previous run, in height order
+    pub(crate) fn load_committed(&self) -> Result<Vec<CommittedBlock>> {
+        let path = self.path.join(COMMITTED_FILE);
+        let mut blocks = Vec::new();
+        if !path.exists() {
+            return Ok(blocks);
+        }
+
+        let content = std::fs::read_to_string(&path)?;
+        for line in content.lines().filter(|line| !line.trim().is_empty()) {
+            match serde_json::from_str::<CommittedBlock>(line) {
+                Ok(block) => blocks.push(block),
+                Err(e) => {
+                    // Only the last line can be torn by a crash, the block is synced again
+                    error!("Ignoring unreadable decided block in {:?}: {}", path, e);
+                    break;
+                }
+            }
+        }
+        Ok(blocks)
+    }
+
+    /// Append a decided block
+    pub(crate) fn append_committed(&self, block: &CommittedBlock) -> Result<()> {
+        let mut line = serde_json::to_vec(block)?;
+        line.push(b'\n');
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path.join(COMMITTED_FILE))?;
+        file.write_all(&line)?;
+        file.sync_data()?;
+        Ok(())
+    }
+
+    /// Load the voting state, `None` before the first vote
+    pub(crate) fn load_safety(&self) -> Result<Option<SafetyState>> {
+        let path = self.path.join(SAFETY_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = std::fs::read(&path)?;
+        let state = serde_json::from_slice(&content)
+            .with_context(|| format!("Failed to parse BFT voting state {:?}", path))?;
+        Ok(Some(state))
+    }
+
+    /// Replace the voting state, through a temporary file so it is never torn
+    pub(crate) fn save_safety(&self, state: &SafetyState) -> Result<()> {
+        let path = self.path.join(SAFETY_FILE);
+        let tmp_path = path.with_extension("tmp");
+        let mut file = std::fs::File::create(&tmp_path)?;
+        file.write_all(&serde_json::to_vec(state)?)?;
+        file.sync_data()?;
+        std::fs::rename(&tmp_path, &path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,244 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use ethereum_types::H256;
+use mp_common::block::Block;
+use mp_common::types::Transaction;
+use mp_poc::bls::SignedAggregate;
+use mp_state::StateStorage;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use tracing::{info, warn};
+
+use crate::config::BlockConfig;
+
+const BLOCKS_FILE: &str = "blocks.jsonl";
+
+/// Signs the hash of every produced block with the node's PoC keys
+pub type BlockSigner = Arc<dyn Fn(&[u8]) -> Result<SignedAggregate> + Send + Sync>;
+
+/// Position of the chain and the transactions of the block being filled
+///
+/// Part of the replicated state machine, so every node cuts blocks at the same
+/// transactions.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChainState {
+    /// Height of the last sealed block
+    pub height: u64,
+    /// Hash of the last sealed block
+    pub last_hash: H256,
+    /// Confirmed transactions not sealed into a block yet
+    pub pending: Vec<Transaction>,
+}
+
+/// Sealed blocks indexed by height
+///
+/// Blocks are appended to `blocks.jsonl` when the store is backed by a
+/// directory. A node that caught up through a snapshot only holds the blocks
+/// sealed after it.
+pub struct BlockStore {
+    /// File the blocks are appended to, if persistent
+    path: Option<PathBuf>,
+    blocks: RwLock<BTreeMap<u64, Block>>,
+    /// Serializes writes to the file
+    file_lock: Mutex<()>,
+}
+
+impl BlockStore {
+    /// Open the store under `path`, loading the blocks sealed by a previous run
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        std::fs::create_dir_all(path.as_ref())?;
+        let path = path.as_ref().join(BLOCKS_FILE);
+
+        let mut blocks = BTreeMap::new();
+        if path.exists() {
+            let content = std::fs::read_to_string(&path)?;
+            for line in content.lines().filter(|l| !l.trim().is_empty()) {
+                // A torn last line from a crash mid-write is dropped, the block is
+                // sealed again when the log is replayed
+                match serde_json::from_str::<Block>(line) {
+                    Ok(block) => {
+                        blocks.insert(block.header.height, block);
+                    }
+                    Err(e) => warn!("Skipping unreadable block in {:?}: {}", path, e),
+                }
+            }
+        }
+
+        Ok(Self {
+            path: Some(path),
+            blocks: RwLock::new(blocks),
+            file_lock: Mutex::new(()),
+        })
+    }
+
+    /// Create a store that only keeps blocks in memory
+    pub fn in_memory() -> Self {
+        Self {
+            path: None,
+            blocks: RwLock::new(BTreeMap::new()),
+            file_lock: Mutex::new(()),
+        }
+    }
+
+    /// Add a sealed block, blocks at or below the latest height are ignored
+    pub fn append(&self, block: &Block) -> Result<()> {
+        if self
+            .latest_height()
+            .is_some_and(|height| block.header.height <= height)
+        {
+            return Ok(());
+        }
+
+        if let Some(path) = &self.path {
+            let _guard = self.file_lock.lock().unwrap();
+            let mut line = serde_json::to_vec(block)?;
+            line.push(b'\n');
+            let mut file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("Failed to open block file {:?}", path))?;
+            file.write_all(&line)?;
+            file.sync_data()?;
+        }
+
+        self.blocks
+            .write()
+            .unwrap()
+            .insert(block.header.height, block.clone());
+        Ok(())
+    }
+
+    /// Get the block at the given height
+    pub fn get(&self, height: u64) -> Option<Block> {
+        self.blocks.read().unwrap().get(&height).cloned()
+    }
+
+    /// Get the most recent block
+    pub fn latest(&self) -> Option<Block> {
+        self.blocks
+            .read()
+            .unwrap()
+            .last_key_value()
+            .map(|(_, block)| block.clone())
+    }
+
+    /// Get the height of the most recent block
+    pub fn latest_height(&self) -> Option<u64> {
+        self.blocks.read().unwrap().keys().next_back().copied()
+    }
+
+    /// Get up to `limit` blocks starting at height `from`
+    pub fn range(&self, from: u64, limit: usize) -> Vec<Block> {
+        self.blocks
+            .read()
+            .unwrap()
+            .range(from..)
+            .take(limit)
+            .map(|(_, block)| block.clone())
+            .collect()
+    }
+}
+
+/// Turns confirmed transactions into chained blocks
+pub struct BlockBuilder {
+    config: BlockConfig,
+    store: Arc<BlockStore>,
+    /// State storage the state root of each block is read from
+    state_storage: Option<Arc<dyn StateStorage>>,
+    signer: OnceLock<BlockSigner>,
+}
+
+impl BlockBuilder {
+    /// Create a builder appending to `store`
+    pub fn new(
+        config: BlockConfig,
+        store: Arc<BlockStore>,
+        state_storage: Option<Arc<dyn StateStorage>>,
+    ) -> Self {
+        Self {
+            config,
+            store,
+            state_storage,
+            signer: OnceLock::new(),
+        }
+    }
+
+    /// Sign produced blocks from now on, only the first signer is kept
+    pub fn set_signer(&self, signer: BlockSigner) {
+        if self.signer.set(signer).is_err() {
+            warn!("Block signer already set");
+        }
+    }
+
+    /// Get the store blocks are appended to
+    pub fn store(&self) -> Arc<BlockStore> {
+        self.store.clone()
+    }
+
+    /// Get the block production settings
+    pub fn config(&self) -> &BlockConfig {
+        &self.config
+    }
+
+    /// Add a confirmed transaction, sealing the block once it is full
+    pub fn push(&self, chain: &mut ChainState, transaction: Transaction) -> Result<Option<Block>> {
+        let timestamp = transaction.timestamp;
+        chain.pending.push(transaction);
+        if chain.pending.len() < self.config.max_transactions {
+            return Ok(None);
+        }
+        // The last transaction's timestamp keeps the block identical on every node
+        self.seal(chain, timestamp)
+    }
+
+    /// Seal the pending transactions into a block, if there are any
+    pub fn seal(&self, chain: &mut ChainState, timestamp: DateTime<Utc>) -> Result<Option<Block>> {
+        if chain.pending.is_empty() {
+            return Ok(None);
+        }
+        let transactions = std::mem::take(&mut chain.pending);
+        self.seal_transactions(chain, transactions, timestamp)
+            .map(Some)
+    }
+
+    /// Seal the given transactions into the next block
+    pub fn seal_transactions(
+        &self,
+        chain: &mut ChainState,
+        transactions: Vec<Transaction>,
+        timestamp: DateTime<Utc>,
+    ) -> Result<Block> {
+        let state_root = match &self.state_storage {
+            Some(storage) => storage.get_state_root()?,
+            None => String::new(),
+        };
+
+        let mut block = Block::new(
+            chain.height + 1,
+            chain.last_hash,
+            timestamp,
+            state_root,
+            transactions,
+        )?;
+        let hash = block.hash();
+        if let Some(signer) = self.signer.get() {
+            block.poc = Some(signer(hash.as_bytes())?);
+        }
+
+        self.store.append(&block)?;
+        chain.height = block.header.height;
+        chain.last_hash = hash;
+
+        info!(
+            "Sealed block {} at height {} with {} transactions",
+            hash,
+            block.header.height,
+            block.transactions.len()
+        );
+        Ok(block)
+    }
+}
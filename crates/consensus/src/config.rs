@@ -1,3 +1,4 @@
+use mp_poc::bls::ValidatorPublicKey;
 use serde::{Deserialize, Serialize};
 use std::net::SocketAddr;
 
@@ -15,6 +16,33 @@ pub struct ConsensusConfig {
 
     /// Raft-specific configuration
     pub raft: Option<RaftConfig>,
+
+    /// BFT-specific configuration
+    #[serde(default)]
+    pub bft: Option<BftConfig>,
+
+    /// Block production settings
+    #[serde(default)]
+    pub block: BlockConfig,
+}
+
+/// Block production settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockConfig {
+    /// Time after which pending transactions are sealed into a block, in milliseconds
+    pub interval: u64,
+
+    /// Number of transactions that seals a block right away
+    pub max_transactions: usize,
+}
+
+impl Default for BlockConfig {
+    fn default() -> Self {
+        Self {
+            interval: 1000,
+            max_transactions: 1000,
+        }
+    }
 }
 
 /// Information about a node in the consensus network
@@ -25,6 +53,10 @@ pub struct NodeInfo {
 
     /// Node address
     pub address: SocketAddr,
+
+    /// Address clients use to reach the node's REST API, used to redirect them to the leader
+    #[serde(default)]
+    pub rest_address: Option<String>,
 }
 
 /// Raft-specific configuration
@@ -44,4 +76,37 @@ pub struct RaftConfig {
 
     /// Path to store Raft logs
     pub log_path: String,
+
+    /// Join an existing cluster as a learner instead of bootstrapping a new one
+    #[serde(default)]
+    pub join: bool,
+}
+
+/// BFT-specific configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BftConfig {
+    /// Seed the validator's BLS key is derived from
+    pub key_seed: String,
+
+    /// Public keys of all validators, one per consensus node
+    pub validators: Vec<ValidatorInfo>,
+
+    /// Time a view may take before validators move to the next leader, in milliseconds
+    pub view_timeout: u64,
+
+    /// Maximum number of transactions in a block
+    pub max_block_size: usize,
+
+    /// Path to store decided blocks and votes
+    pub log_path: String,
+}
+
+/// Identity of a BFT validator
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ValidatorInfo {
+    /// Node ID
+    pub id: u64,
+
+    /// BLS public key used to verify the validator's votes
+    pub public_key: ValidatorPublicKey,
 }
@@ -1,8 +1,17 @@
+pub mod bft;
+pub mod block;
 pub mod config;
+pub mod membership;
+pub mod network;
+pub mod nonce;
 pub mod raft;
+pub mod storage;
 
 use anyhow::Result;
 use mp_common::types::{Transaction, TransactionResponse};
+use mp_state::diff::StateOperation;
+use mp_state::StateStorage;
+use std::sync::Arc;
 
 use tokio::sync::mpsc;
 
@@ -20,15 +29,65 @@ pub trait ConsensusEngine: Send + Sync {
 
     /// Get a channel for confirmed transactions
     async fn get_confirmed_tx_channel(&self) -> mpsc::Receiver<Transaction>;
+
+    /// Get the store of blocks sealed from confirmed transactions
+    fn blocks(&self) -> Arc<block::BlockStore>;
+
+    /// Sign sealed blocks with the node's PoC keys
+    fn set_block_signer(&self, signer: block::BlockSigner);
+
+    /// Get a handle for changing the cluster membership, if the engine supports it
+    fn membership(&self) -> Option<Arc<dyn ClusterMembership>> {
+        None
+    }
+
+    /// Get a handle for administering the replicated state, if the engine supports it
+    fn state_control(&self) -> Option<Arc<dyn StateControl>> {
+        None
+    }
+}
+
+/// Runtime changes to the set of nodes taking part in consensus
+#[async_trait::async_trait]
+pub trait ClusterMembership: Send + Sync {
+    /// Register a node and replicate the log to it without giving it a vote
+    async fn add_learner(&self, node: config::NodeInfo) -> Result<()>;
+
+    /// Turn a learner into a voting member
+    async fn promote_voter(&self, node_id: u64) -> Result<()>;
+
+    /// Remove a voting member from the cluster
+    async fn remove_voter(&self, node_id: u64) -> Result<()>;
+
+    /// Get the current membership as seen by this node
+    async fn status(&self) -> Result<membership::ClusterStatus>;
+}
+
+/// Cluster-wide changes to the state storage, decided through consensus
+#[async_trait::async_trait]
+pub trait StateControl: Send + Sync {
+    /// Revert the state of every node to `root`, undoing the diffs applied since
+    async fn revert_state(&self, root: String) -> Result<()>;
+
+    /// Apply the state changes of an execution on every node once committed
+    ///
+    /// The changes are applied on top of the state at their position in the log,
+    /// which may have moved on since the execution.
+    async fn commit_state_changes(&self, operations: Vec<StateOperation>) -> Result<()>;
 }
 
 /// Create a new consensus engine based on the configuration
 pub fn create_consensus_engine(
     config: config::ConsensusConfig,
+    state_storage: Option<Arc<dyn StateStorage>>,
 ) -> Result<Box<dyn ConsensusEngine>> {
     match config.engine_type.as_str() {
         "raft" => {
-            let engine = raft::RaftConsensusEngine::new(config)?;
+            let engine = raft::RaftConsensusEngine::new(config, state_storage)?;
+            Ok(Box::new(engine))
+        }
+        "bft" => {
+            let engine = bft::BftConsensusEngine::new(config, state_storage)?;
             Ok(Box::new(engine))
         }
         _ => Err(anyhow::anyhow!(
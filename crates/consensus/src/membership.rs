@@ -0,0 +1,173 @@
+use anyhow::{anyhow, Result};
+use async_raft::error::{ChangeConfigError, ClientWriteError};
+use async_raft::raft::ClientWriteRequest;
+use async_raft::NodeId;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use tokio::time::{self, Duration};
+use tracing::info;
+
+use crate::config::NodeInfo;
+use crate::network::PeerAddresses;
+use crate::raft::MpRaft;
+use crate::storage::RaftCommand;
+use crate::ClusterMembership;
+
+/// Time a new learner is given to catch up with the leader's log
+const LEARNER_SYNC_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Cluster membership as seen by the local node
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClusterStatus {
+    /// ID of the local node
+    pub node_id: u64,
+    /// ID of the current leader, if known
+    pub leader: Option<u64>,
+    /// Voting members of the cluster
+    pub voters: Vec<u64>,
+    /// Known addresses of all nodes, voters and learners
+    pub nodes: Vec<NodeInfo>,
+}
+
+impl ClusterStatus {
+    /// Get the address information of the current leader, if known
+    pub fn leader_info(&self) -> Option<&NodeInfo> {
+        let leader = self.leader?;
+        self.nodes.iter().find(|n| n.id == leader)
+    }
+
+    /// Check whether the local node is the current leader
+    pub fn is_leader(&self) -> bool {
+        self.leader == Some(self.node_id)
+    }
+}
+
+/// Membership management backed by a running Raft node
+///
+/// Changes must be made on the leader; node addresses are registered through
+/// the log so every node can reach the new member, the membership change
+/// itself is committed by Raft through joint consensus.
+pub struct RaftMembership {
+    node_id: NodeId,
+    raft: MpRaft,
+    peers: PeerAddresses,
+}
+
+impl RaftMembership {
+    /// Create a membership handle for the given Raft node
+    pub fn new(node_id: NodeId, raft: MpRaft, peers: PeerAddresses) -> Self {
+        Self {
+            node_id,
+            raft,
+            peers,
+        }
+    }
+
+    /// Voting members once any in-flight change has completed
+    fn voters(&self) -> HashSet<NodeId> {
+        let metrics = self.raft.metrics().borrow().clone();
+        metrics
+            .membership_config
+            .members_after_consensus
+            .unwrap_or(metrics.membership_config.members)
+    }
+
+    async fn change_voters(&self, voters: HashSet<NodeId>) -> Result<()> {
+        match self.raft.change_membership(voters).await {
+            Ok(()) | Err(ChangeConfigError::Noop) => Ok(()),
+            Err(ChangeConfigError::NodeNotLeader(leader)) => Err(anyhow!(
+                "Node {} is not the leader (leader: {:?}), membership change rejected",
+                self.node_id,
+                leader
+            )),
+            Err(e) => Err(anyhow!("Membership change failed: {}", e)),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl ClusterMembership for RaftMembership {
+    async fn add_learner(&self, node: NodeInfo) -> Result<()> {
+        let leader = self.raft.current_leader().await;
+        if leader != Some(self.node_id) {
+            return Err(anyhow!(
+                "Node {} is not the leader (leader: {:?}), learner {} rejected",
+                self.node_id,
+                leader,
+                node.id
+            ));
+        }
+        info!("Adding Raft learner {} at {}", node.id, node.address);
+
+        // The leader needs the address right away to start replicating, the
+        // rest of the cluster learns it once the registration is committed.
+        self.peers.write().unwrap().insert(node.id, node.clone());
+        match time::timeout(LEARNER_SYNC_TIMEOUT, self.raft.add_non_voter(node.id)).await {
+            Ok(Ok(())) | Ok(Err(ChangeConfigError::Noop)) => {}
+            Ok(Err(e)) => return Err(anyhow!("Failed to add learner {}: {}", node.id, e)),
+            Err(_) => {
+                return Err(anyhow!(
+                    "Learner {} did not catch up with the leader in time",
+                    node.id
+                ))
+            }
+        }
+
+        match self
+            .raft
+            .client_write(ClientWriteRequest::new(RaftCommand::AddNode(node)))
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(ClientWriteError::ForwardToLeader(_, leader)) => Err(anyhow!(
+                "Node {} lost leadership to {:?} while adding a learner",
+                self.node_id,
+                leader
+            )),
+            Err(ClientWriteError::RaftError(e)) => Err(anyhow!("Raft error: {}", e)),
+        }
+    }
+
+    async fn promote_voter(&self, node_id: u64) -> Result<()> {
+        if !self.peers.read().unwrap().contains_key(&node_id) {
+            return Err(anyhow!(
+                "Node {} is unknown, add it as a learner first",
+                node_id
+            ));
+        }
+
+        let mut voters = self.voters();
+        if !voters.insert(node_id) {
+            return Ok(());
+        }
+        info!("Promoting Raft node {} to voter", node_id);
+        self.change_voters(voters).await
+    }
+
+    async fn remove_voter(&self, node_id: u64) -> Result<()> {
+        let mut voters = self.voters();
+        if !voters.remove(&node_id) {
+            return Err(anyhow!("Node {} is not a voter", node_id));
+        }
+        if voters.is_empty() {
+            return Err(anyhow!("Cannot remove the last voter of the cluster"));
+        }
+        info!("Removing Raft voter {}", node_id);
+        self.change_voters(voters).await
+    }
+
+    async fn status(&self) -> Result<ClusterStatus> {
+        let mut voters: Vec<u64> = self.voters().into_iter().collect();
+        voters.sort_unstable();
+
+        let mut nodes: Vec<NodeInfo> = self.peers.read().unwrap().values().cloned().collect();
+        nodes.sort_by_key(|n| n.id);
+
+        Ok(ClusterStatus {
+            node_id: self.node_id,
+            leader: self.raft.current_leader().await,
+            voters,
+            nodes,
+        })
+    }
+}
@@ -0,0 +1,320 @@
+use anyhow::{anyhow, Result};
+use async_raft::error::ClientWriteError;
+use async_raft::raft::ClientWriteRequest;
+use async_raft::raft::{
+    AppendEntriesRequest, AppendEntriesResponse, InstallSnapshotRequest, InstallSnapshotResponse,
+    VoteRequest, VoteResponse,
+};
+use async_raft::{NodeId, RaftError, RaftNetwork};
+use mp_common::types::TransactionResponse;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tokio::time::{self, Duration};
+use tracing::{debug, error, info, warn};
+
+use crate::config::NodeInfo;
+use crate::raft::MpRaft;
+use crate::storage::RaftCommand;
+
+/// Upper bound for a single RPC frame, guards against corrupted length prefixes
+const MAX_FRAME_SIZE: usize = 64 * 1024 * 1024;
+
+/// Timeout for establishing a connection to a peer
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Timeout for sending a request to a peer and reading its response
+///
+/// Covers a forwarded command until the leader commits it.
+const RPC_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Addresses of the cluster nodes, shared by the network and the storage
+/// which records nodes added at runtime
+pub type PeerAddresses = Arc<RwLock<HashMap<NodeId, NodeInfo>>>;
+
+/// Build the address book from the configured nodes
+pub fn peer_addresses(nodes: &[NodeInfo]) -> PeerAddresses {
+    Arc::new(RwLock::new(
+        nodes.iter().map(|n| (n.id, n.clone())).collect(),
+    ))
+}
+
+/// Raft RPC requests exchanged between nodes
+#[derive(Debug, Serialize, Deserialize)]
+pub enum RaftRequest {
+    AppendEntries(AppendEntriesRequest<RaftCommand>),
+    InstallSnapshot(InstallSnapshotRequest),
+    Vote(VoteRequest),
+    /// Command submitted on a follower and forwarded to the leader
+    ClientWrite(RaftCommand),
+}
+
+/// Raft RPC responses exchanged between nodes
+#[derive(Debug, Serialize, Deserialize)]
+pub enum RaftResponse {
+    AppendEntries(AppendEntriesResponse),
+    InstallSnapshot(InstallSnapshotResponse),
+    Vote(VoteResponse),
+    ClientWrite(TransactionResponse),
+    Error(String),
+}
+
+/// Write a length-prefixed JSON frame
+pub(crate) async fn write_frame<T: Serialize>(stream: &mut TcpStream, message: &T) -> Result<()> {
+    let data = serde_json::to_vec(message)?;
+    let mut frame = Vec::with_capacity(4 + data.len());
+    frame.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&data);
+    stream.write_all(&frame).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+/// Read a length-prefixed JSON frame
+pub(crate) async fn read_frame<T: DeserializeOwned>(stream: &mut TcpStream) -> Result<T> {
+    let mut length_buffer = [0u8; 4];
+    stream.read_exact(&mut length_buffer).await?;
+    let length = u32::from_le_bytes(length_buffer) as usize;
+    if length > MAX_FRAME_SIZE {
+        return Err(anyhow!(
+            "Frame of {} bytes exceeds the maximum size",
+            length
+        ));
+    }
+
+    let mut data = vec![0u8; length];
+    stream.read_exact(&mut data).await?;
+    Ok(serde_json::from_slice(&data)?)
+}
+
+/// TCP implementation of the Raft network
+///
+/// Idle connections are kept per peer and reused, a connection that fails
+/// mid-request is dropped and re-established on the next RPC.
+pub struct TcpRaftNetwork {
+    /// Addresses of all nodes in the cluster
+    peers: PeerAddresses,
+    /// Idle connections per peer
+    connections: Mutex<HashMap<NodeId, Vec<TcpStream>>>,
+}
+
+impl TcpRaftNetwork {
+    /// Create a new network resolving nodes through the given address book
+    pub fn new(peers: PeerAddresses) -> Self {
+        Self {
+            peers,
+            connections: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn send(&self, target: NodeId, request: RaftRequest) -> Result<RaftResponse> {
+        let address = self
+            .peers
+            .read()
+            .unwrap()
+            .get(&target)
+            .map(|n| n.address)
+            .ok_or_else(|| anyhow!("Unknown Raft node: {}", target))?;
+
+        let idle = self
+            .connections
+            .lock()
+            .await
+            .get_mut(&target)
+            .and_then(|pool| pool.pop());
+        let mut stream = match idle {
+            Some(stream) => stream,
+            None => time::timeout(CONNECT_TIMEOUT, TcpStream::connect(address))
+                .await
+                .map_err(|_| anyhow!("Timed out connecting to node {} at {}", target, address))??,
+        };
+
+        // A stream timed out mid-request is dropped, it may still carry the late response
+        let response = time::timeout(RPC_TIMEOUT, async {
+            write_frame(&mut stream, &request).await?;
+            read_frame::<RaftResponse>(&mut stream).await
+        })
+        .await
+        .map_err(|_| anyhow!("Timed out waiting for node {} at {}", target, address))??;
+
+        self.connections
+            .lock()
+            .await
+            .entry(target)
+            .or_default()
+            .push(stream);
+
+        match response {
+            RaftResponse::Error(e) => Err(anyhow!("Node {} rejected RPC: {}", target, e)),
+            response => Ok(response),
+        }
+    }
+
+    /// Forward a client command to the leader and wait until it is committed
+    pub async fn forward_command(
+        &self,
+        leader: NodeId,
+        command: RaftCommand,
+    ) -> Result<TransactionResponse> {
+        match self.send(leader, RaftRequest::ClientWrite(command)).await? {
+            RaftResponse::ClientWrite(response) => Ok(response),
+            other => Err(anyhow!("Unexpected response to ClientWrite: {:?}", other)),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl RaftNetwork<RaftCommand> for TcpRaftNetwork {
+    async fn append_entries(
+        &self,
+        target: NodeId,
+        rpc: AppendEntriesRequest<RaftCommand>,
+    ) -> Result<AppendEntriesResponse> {
+        match self.send(target, RaftRequest::AppendEntries(rpc)).await? {
+            RaftResponse::AppendEntries(response) => Ok(response),
+            other => Err(anyhow!("Unexpected response to AppendEntries: {:?}", other)),
+        }
+    }
+
+    async fn install_snapshot(
+        &self,
+        target: NodeId,
+        rpc: InstallSnapshotRequest,
+    ) -> Result<InstallSnapshotResponse> {
+        match self.send(target, RaftRequest::InstallSnapshot(rpc)).await? {
+            RaftResponse::InstallSnapshot(response) => Ok(response),
+            other => Err(anyhow!(
+                "Unexpected response to InstallSnapshot: {:?}",
+                other
+            )),
+        }
+    }
+
+    async fn vote(&self, target: NodeId, rpc: VoteRequest) -> Result<VoteResponse> {
+        match self.send(target, RaftRequest::Vote(rpc)).await? {
+            RaftResponse::Vote(response) => Ok(response),
+            other => Err(anyhow!("Unexpected response to Vote: {:?}", other)),
+        }
+    }
+}
+
+/// Accept Raft RPCs from peers and dispatch them to the local Raft node
+///
+/// Commands forwarded by followers are only accepted from the hosts of the
+/// nodes in `peers`. Returns the address the server is bound to, which tells
+/// the port picked when `address` has port 0.
+pub async fn start_rpc_server(
+    address: SocketAddr,
+    raft: MpRaft,
+    peers: PeerAddresses,
+) -> Result<(JoinHandle<()>, SocketAddr)> {
+    let listener = TcpListener::bind(address).await?;
+    let address = listener.local_addr()?;
+    info!("Raft RPC server listening on {}", address);
+
+    let server = tokio::spawn(async move {
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    error!("Failed to accept Raft connection: {}", e);
+                    continue;
+                }
+            };
+            debug!("Accepted Raft connection from {}", peer);
+
+            let raft = raft.clone();
+            let peers = peers.clone();
+            tokio::spawn(async move {
+                if let Err(e) = handle_connection(stream, peer, raft, peers).await {
+                    debug!("Raft connection from {} closed: {}", peer, e);
+                }
+            });
+        }
+    });
+    Ok((server, address))
+}
+
+/// Whether `peer` connects from the host of a known cluster node
+fn is_cluster_host(peers: &PeerAddresses, peer: &SocketAddr) -> bool {
+    peers
+        .read()
+        .unwrap()
+        .values()
+        .any(|node| node.address.ip() == peer.ip())
+}
+
+async fn handle_connection(
+    mut stream: TcpStream,
+    peer: SocketAddr,
+    raft: MpRaft,
+    peers: PeerAddresses,
+) -> Result<()> {
+    loop {
+        let request = read_frame::<RaftRequest>(&mut stream).await?;
+        let response = match request {
+            RaftRequest::AppendEntries(rpc) => raft
+                .append_entries(rpc)
+                .await
+                .map(RaftResponse::AppendEntries),
+            RaftRequest::InstallSnapshot(rpc) => raft
+                .install_snapshot(rpc)
+                .await
+                .map(RaftResponse::InstallSnapshot),
+            RaftRequest::Vote(rpc) => raft.vote(rpc).await.map(RaftResponse::Vote),
+            // Reverts are only accepted from the admin interface of the leader
+            RaftRequest::ClientWrite(RaftCommand::RevertState(root)) => {
+                warn!("Refused forwarded state revert to root {}", root);
+                Ok(RaftResponse::Error(
+                    "State reverts are only accepted through the admin interface".to_string(),
+                ))
+            }
+            // Membership and block seals are only written by the leader itself
+            RaftRequest::ClientWrite(
+                command @ (RaftCommand::AddNode(_) | RaftCommand::SealBlock(_)),
+            ) => {
+                warn!("Refused forwarded command from {}: {:?}", peer, command);
+                Ok(RaftResponse::Error(
+                    "Only transactions and state changes are accepted from followers".to_string(),
+                ))
+            }
+            RaftRequest::ClientWrite(_) if !is_cluster_host(&peers, &peer) => {
+                warn!(
+                    "Refused forwarded command from {}, not a cluster node",
+                    peer
+                );
+                Ok(RaftResponse::Error(
+                    "Forwarded commands are only accepted from cluster nodes".to_string(),
+                ))
+            }
+            RaftRequest::ClientWrite(command) => {
+                // Forwarded writes are not forwarded again, so a stale view of the
+                // leader cannot bounce a command between nodes.
+                match raft.client_write(ClientWriteRequest::new(command)).await {
+                    Ok(response) => Ok(RaftResponse::ClientWrite(response.data)),
+                    Err(ClientWriteError::ForwardToLeader(_, leader)) => Ok(RaftResponse::Error(
+                        format!("Node is not the leader (leader: {:?})", leader),
+                    )),
+                    Err(ClientWriteError::RaftError(e)) => Err(e),
+                }
+            }
+        };
+
+        let response = match response {
+            Ok(response) => response,
+            Err(RaftError::ShuttingDown) => return Err(anyhow!("Raft is shutting down")),
+            Err(e) => {
+                warn!("Failed to handle Raft RPC: {}", e);
+                RaftResponse::Error(e.to_string())
+            }
+        };
+
+        write_frame(&mut stream, &response).await?;
+    }
+}
@@ -0,0 +1,54 @@
+//! Nonces of the committed transactions
+//!
+//! The pool of the node a transaction is submitted on checks its nonce, but a
+//! transaction captured on the way can still be submitted on another node.
+//! Every node therefore checks the nonces again when it applies the committed
+//! transactions, in log order, so they all refuse the same replays.
+
+use mp_common::types::Transaction;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Next nonce of every sender with committed transactions
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct CommittedNonces(BTreeMap<String, u64>);
+
+impl CommittedNonces {
+    /// Check a committed transaction and spend its nonce
+    ///
+    /// Contract calls must carry a sender and a nonce, a sender's nonces must
+    /// increase. Anonymous transactions of other types are not sequenced.
+    pub fn spend(&mut self, transaction: &Transaction) -> Result<(), String> {
+        let Some(sender) = &transaction.sender else {
+            if transaction.tx_type.is_request() {
+                return Err(format!(
+                    "Contract call {} carries no sender",
+                    transaction.id
+                ));
+            }
+            return Ok(());
+        };
+        let Some(nonce) = transaction.nonce else {
+            return Err(format!(
+                "Transaction {} of {} carries no nonce",
+                transaction.id, sender
+            ));
+        };
+
+        let next = self.next_nonce(sender);
+        if nonce < next {
+            return Err(format!(
+                "Nonce {} of {} was already used, the next nonce is {}",
+                nonce, sender, next
+            ));
+        }
+        self.0.insert(sender.clone(), nonce + 1);
+        Ok(())
+    }
+
+    /// Nonce the next committed transaction of `sender` must at least carry
+    pub fn next_nonce(&self, sender: &str) -> u64 {
+        self.0.get(sender).copied().unwrap_or_default()
+    }
+}
@@ -1,311 +1,194 @@
-use anyhow::Result;
-use mp_common::types::{Transaction, TransactionResponse};
-use std::collections::{HashMap, VecDeque};
+use anyhow::{anyhow, Result};
+use async_raft::config::SnapshotPolicy;
+use async_raft::error::ClientWriteError;
+use async_raft::raft::ClientWriteRequest;
+use async_raft::{Config, NodeId, Raft, RaftMetrics, State};
+use mp_common::types::{Transaction, TransactionResponse, TransactionStatus};
+use mp_state::diff::StateOperation;
+use mp_state::StateStorage;
+use std::collections::HashSet;
+use std::net::SocketAddr;
 use std::sync::{Arc, Mutex};
-use tokio::sync::{mpsc, RwLock};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
 use tokio::time::{self, Duration};
-use tracing::{debug, error, info};
-use uuid::Uuid;
-
-use crate::config::{ConsensusConfig, NodeInfo};
-use crate::ConsensusEngine;
-
-/// Simplified Raft node states
-#[derive(Debug, Clone, PartialEq, Eq)]
-enum NodeState {
-    Follower,
-    Candidate,
-    Leader,
-}
+use tracing::{debug, info, warn};
+
+use crate::block::{BlockSigner, BlockStore};
+use crate::config::{ConsensusConfig, NodeInfo, RaftConfig};
+use crate::membership::RaftMembership;
+use crate::network::{self, PeerAddresses, TcpRaftNetwork};
+use crate::storage::{RaftCommand, RaftStore};
+use crate::{ClusterMembership, ConsensusEngine, StateControl};
 
-/// A simplified implementation of the Raft consensus algorithm
+/// Raft node specialised for node transactions
+pub type MpRaft = Raft<RaftCommand, TransactionResponse, TcpRaftNetwork, RaftStore>;
+
+/// Raft consensus engine replicating transactions between cluster nodes over TCP
 pub struct RaftConsensusEngine {
     /// Node ID
     node_id: u64,
-    /// Current node state
-    state: Arc<RwLock<NodeState>>,
-    /// List of all nodes in the cluster
+    /// Nodes listed in the configuration
     nodes: Vec<NodeInfo>,
-    /// Current term
-    current_term: Arc<RwLock<u64>>,
-    /// Current leader ID
-    leader_id: Arc<RwLock<Option<u64>>>,
-    /// Log entries (simplified as a queue of transactions)
-    log: Arc<Mutex<VecDeque<Transaction>>>,
-    /// Last applied log index
-    last_applied: Arc<RwLock<u64>>,
-    /// Commit index
-    commit_index: Arc<RwLock<u64>>,
-    /// Channel for sending confirmed transactions to subscribers
-    confirmed_tx_sender: mpsc::Sender<Transaction>,
+    /// Addresses of all known nodes, including those added at runtime
+    peers: PeerAddresses,
+    /// Whether to join an existing cluster instead of bootstrapping one
+    join: bool,
+    /// Connections to the other nodes
+    network: Arc<TcpRaftNetwork>,
+    /// Raft runtime configuration
+    raft_config: Arc<Config>,
+    /// Log storage and state machine
+    storage: Arc<RaftStore>,
+    /// Running Raft node, set once the engine is started
+    raft: Option<MpRaft>,
+    /// Task accepting RPCs from peers
+    rpc_server: Option<JoinHandle<()>>,
+    /// Address the RPC server is bound to while the engine runs
+    listen_address: Option<SocketAddr>,
+    /// Task sealing blocks while this node leads
+    block_timer: Option<JoinHandle<()>>,
     /// Channel for receiving confirmed transactions
     confirmed_tx_receiver: Arc<Mutex<Option<mpsc::Receiver<Transaction>>>>,
-    /// Heartbeat interval in milliseconds
-    heartbeat_interval: u64,
-    /// Election timeout range in milliseconds
-    election_timeout_min: u64,
-    election_timeout_max: u64,
-    /// Transaction responses (transaction ID -> response)
-    responses: Arc<Mutex<HashMap<Uuid, TransactionResponse>>>,
-    /// Flag to indicate if the engine is running
-    running: Arc<RwLock<bool>>,
-    /// Store for transaction results to be propagated in heartbeats
-    transaction_results: Arc<RwLock<Vec<TransactionResponse>>>,
 }
 
 impl RaftConsensusEngine {
     /// Create a new Raft consensus engine
-    pub fn new(config: ConsensusConfig) -> Result<Self> {
+    ///
+    /// The log is persisted under `log_path`, snapshots capture `state_storage` when given.
+    pub fn new(
+        config: ConsensusConfig,
+        state_storage: Option<Arc<dyn StateStorage>>,
+    ) -> Result<Self> {
         let raft_config = config
             .raft
-            .ok_or_else(|| anyhow::anyhow!("Raft configuration is required"))?;
+            .as_ref()
+            .ok_or_else(|| anyhow!("Raft configuration is required"))?;
+
+        if !config.nodes.iter().any(|n| n.id == config.node_id) {
+            return Err(anyhow!(
+                "Node {} is not listed in the consensus nodes",
+                config.node_id
+            ));
+        }
 
         // Create channels for confirmed transactions
         let (confirmed_tx_sender, confirmed_tx_receiver) = mpsc::channel(1000);
+        let peers = network::peer_addresses(&config.nodes);
 
         Ok(Self {
             node_id: config.node_id,
-            state: Arc::new(RwLock::new(NodeState::Follower)),
+            raft_config: Arc::new(build_raft_config(raft_config)?),
+            storage: Arc::new(RaftStore::open(
+                config.node_id,
+                &raft_config.log_path,
+                state_storage,
+                peers.clone(),
+                confirmed_tx_sender,
+                config.block,
+            )?),
             nodes: config.nodes,
-            current_term: Arc::new(RwLock::new(0)),
-            leader_id: Arc::new(RwLock::new(None)),
-            log: Arc::new(Mutex::new(VecDeque::new())),
-            last_applied: Arc::new(RwLock::new(0)),
-            commit_index: Arc::new(RwLock::new(0)),
-            confirmed_tx_sender,
+            network: Arc::new(TcpRaftNetwork::new(peers.clone())),
+            peers,
+            join: raft_config.join,
+            raft: None,
+            rpc_server: None,
+            listen_address: None,
+            block_timer: None,
             confirmed_tx_receiver: Arc::new(Mutex::new(Some(confirmed_tx_receiver))),
-            heartbeat_interval: raft_config.heartbeat_interval,
-            election_timeout_min: raft_config.election_timeout_min,
-            election_timeout_max: raft_config.election_timeout_max,
-            responses: Arc::new(Mutex::new(HashMap::new())),
-            running: Arc::new(RwLock::new(false)),
-            transaction_results: Arc::new(RwLock::new(Vec::new())),
         })
     }
 
-    /// Start the heartbeat process (leader only)
-    async fn start_heartbeat(&self) {
-        // Clone all necessary values to avoid self reference in tokio::spawn
-        let running = self.running.clone();
-        let state = self.state.clone();
-        let heartbeat_interval = self.heartbeat_interval;
-        let transaction_results = self.transaction_results.clone();
-
-        // Clone the propagate_transaction_results method by creating a channel
-        let (tx_sender, mut tx_receiver) = mpsc::channel::<Vec<TransactionResponse>>(100);
-
-        // Spawn a task to receive results from heartbeat and propagate them
-        let propagate_task = {
-            let self_clone = self.clone();
-            tokio::spawn(async move {
-                while let Some(results) = tx_receiver.recv().await {
-                    if let Err(e) = self_clone.propagate_transaction_results(results).await {
-                        error!("Failed to propagate transaction results: {}", e);
-                    }
-                }
-            })
-        };
-
-        tokio::spawn(async move {
-            let interval_duration = Duration::from_millis(heartbeat_interval);
-            let mut interval = time::interval(interval_duration);
-
-            while *running.read().await {
-                interval.tick().await;
-
-                if *state.read().await == NodeState::Leader {
-                    debug!("Sending heartbeat to followers");
-
-                    let results = transaction_results.read().await;
-                    if !results.is_empty() {
-                        debug!(
-                            "Including {} transaction results in heartbeat",
-                            results.len()
-                        );
-                    }
-
-                    // Send results to propagate task instead of calling directly
-                    if !results.is_empty() {
-                        if let Err(e) = tx_sender.send(results.clone()).await {
-                            error!("Failed to send transaction results for propagation: {}", e);
-                        }
-                    }
-
-                    transaction_results.write().await.clear();
-                }
-            }
-
-            // Drop sender when done to terminate propagate task
-            drop(tx_sender);
-        });
-    }
-
-    /// Propagate transaction results to followers
-    async fn propagate_transaction_results(&self, results: Vec<TransactionResponse>) -> Result<()> {
-        // In a real implementation, we would send AppendEntries RPCs to all followers
-        // with the transaction results included
-        debug!(
-            "Propagating {} transaction results to followers",
-            results.len()
-        );
-
-        // Simulate propagation delay
-        time::sleep(Duration::from_millis(10)).await;
-
-        Ok(())
+    /// Get the running Raft node
+    fn raft(&self) -> Result<&MpRaft> {
+        self.raft
+            .as_ref()
+            .ok_or_else(|| anyhow!("Raft consensus engine is not running"))
     }
 
-    /// Start the election timeout process (follower/candidate only)
-    async fn start_election_timeout(&self) {
-        let running = self.running.clone();
-        let state = self.state.clone();
-        let current_term = self.current_term.clone();
-        let node_id = self.node_id;
-        let leader_id = self.leader_id.clone();
-        let min_timeout = self.election_timeout_min;
-        let max_timeout = self.election_timeout_max;
-
-        tokio::spawn(async move {
-            while *running.read().await {
-                // Only start election timeout if we're a follower or candidate
-                let current_state = state.read().await.clone();
-                if current_state == NodeState::Leader {
-                    time::sleep(Duration::from_millis(100)).await;
-                    continue;
-                }
-
-                // Random timeout between min and max
-                let timeout = rand::random::<u64>() % (max_timeout - min_timeout) + min_timeout;
-                let timeout_duration = Duration::from_millis(timeout);
-
-                // Wait for timeout
-                time::sleep(timeout_duration).await;
-
-                // Check if we're still a follower/candidate and should start an election
-                let current_state = state.read().await.clone();
-                if (current_state == NodeState::Follower || current_state == NodeState::Candidate)
-                    && *running.read().await
-                {
-                    // Get current term
-                    let current_term_value = *current_term.read().await;
-                    // Start election
-                    info!("Starting election for term {}", current_term_value + 1);
-
-                    // Increment term
-                    let mut term = current_term.write().await;
-                    *term += 1;
-
-                    // Become candidate
-                    *state.write().await = NodeState::Candidate;
-
-                    // Vote for self
-                    // In a real implementation, we would send RequestVote RPCs to all other nodes
-
-                    // For simplicity in this demo, always become leader after a timeout
-                    // In a real implementation, we would only become leader if we received votes from a majority
-                    time::sleep(Duration::from_millis(100)).await;
-                    if *state.read().await == NodeState::Candidate {
-                        info!("Node {} became leader for term {}", node_id, *term);
-                        *state.write().await = NodeState::Leader;
-                        *leader_id.write().await = Some(node_id);
-                    }
-                }
-            }
-        });
+    /// Get the ID of the local node
+    pub fn node_id(&self) -> u64 {
+        self.node_id
     }
 
-    /// Process a transaction (leader only)
-    async fn process_transaction(&self, tx: Transaction) -> Result<TransactionResponse> {
-        // Create initial response
-        let response = TransactionResponse::success(tx.id);
-
-        // Store response in responses map
-        {
-            let mut responses = self.responses.lock().unwrap();
-            responses.insert(tx.id, response.clone());
+    /// Get the ID of the current leader, if known
+    pub async fn current_leader(&self) -> Option<NodeId> {
+        match &self.raft {
+            Some(raft) => raft.current_leader().await,
+            None => None,
         }
+    }
 
-        // Add transaction to transaction_results for propagation in heartbeats
-        {
-            let mut transaction_results = self.transaction_results.write().await;
-            transaction_results.push(response.clone());
-        }
-
-        // CRITICAL FIX: Add transaction to log for processing
-        {
-            let mut log = self.log.lock().unwrap();
-            log.push_back(tx);
-
-            // Update commit index to ensure this transaction gets processed
-            let mut commit_index = self.commit_index.write().await;
-            *commit_index += 1;
-        }
+    /// Check whether this node is currently the leader
+    pub async fn is_leader(&self) -> bool {
+        self.current_leader().await == Some(self.node_id)
+    }
 
-        Ok(response)
+    /// Get a handle to the Raft metrics
+    pub fn metrics(&self) -> Option<watch::Receiver<RaftMetrics>> {
+        self.raft.as_ref().map(|raft| raft.metrics())
     }
 
-    /// Apply a committed transaction
-    async fn apply_transaction(&self, transaction: Transaction) -> Result<()> {
-        // Update last applied index
-        {
-            let mut last_applied = self.last_applied.write().await;
-            *last_applied = transaction.log_index;
-        }
+    /// Get the current Raft role of this node
+    pub fn state(&self) -> Option<State> {
+        self.metrics().map(|metrics| metrics.borrow().state)
+    }
 
-        // Send transaction to confirmed channel
-        if let Err(e) = self.confirmed_tx_sender.send(transaction).await {
-            error!("Failed to send confirmed transaction: {}", e);
-        }
+    /// Address the RPC server listens on, known once the engine is started
+    ///
+    /// With port 0 in the configuration this is where the picked port shows.
+    pub fn listen_address(&self) -> Option<SocketAddr> {
+        self.listen_address
+    }
 
-        Ok(())
+    /// Point this node at the address `node` listens on
+    ///
+    /// Only the local address book changes, nodes joining the cluster are
+    /// registered on every node through the membership instead.
+    pub fn register_peer(&self, node: NodeInfo) {
+        self.peers.write().unwrap().insert(node.id, node);
     }
 
-    /// Start the log applier process
-    async fn start_log_applier(&self) {
-        let running = self.running.clone();
-        let log = self.log.clone();
-        let last_applied = self.last_applied.clone();
-        let commit_index = self.commit_index.clone();
-        let confirmed_tx_sender = self.confirmed_tx_sender.clone();
-
-        tokio::spawn(async move {
-            while *running.read().await {
-                // Check if there are committed entries that haven't been applied
-                let last_applied_value = *last_applied.read().await;
-                let commit_index_value = *commit_index.read().await;
-
-                if last_applied_value < commit_index_value {
-                    // Apply all committed entries
-                    for i in (last_applied_value + 1)..=commit_index_value {
-                        // Get the transaction from the log
-                        let mut tx_clone = {
-                            let log_guard = log.lock().unwrap();
-                            if let Some(tx) = log_guard.get((i as usize).saturating_sub(1)) {
-                                tx.clone()
-                            } else {
-                                continue;
-                            }
-                        };
-
-                        // CRITICAL FIX: Set log_index before sending to confirmed channel
-                        tx_clone.log_index = i;
-
-                        // Update last applied index
-                        *last_applied.write().await = i;
-
-                        // Send transaction to confirmed channel
-                        info!("Sending confirmed transaction to executor: {}", tx_clone.id);
-                        if let Err(e) = confirmed_tx_sender.send(tx_clone).await {
-                            error!("Failed to send confirmed transaction: {}", e);
-                        }
-                    }
-                }
+    /// Configured address of the local node
+    fn local_address(&self) -> Result<SocketAddr> {
+        self.nodes
+            .iter()
+            .find(|n| n.id == self.node_id)
+            .map(|n| n.address)
+            .ok_or_else(|| anyhow!("Node {} is not listed in the consensus nodes", self.node_id))
+    }
+}
 
-                // Sleep for a short time
-                time::sleep(Duration::from_millis(10)).await;
+/// Seal the open block every block interval while this node leads
+///
+/// The seal goes through the log so every node cuts the block at the same entry.
+fn spawn_block_timer(node_id: NodeId, raft: MpRaft, storage: Arc<RaftStore>) -> JoinHandle<()> {
+    let interval = Duration::from_millis(storage.blocks().config().interval.max(1));
+    tokio::spawn(async move {
+        let mut ticker = time::interval(interval);
+        ticker.set_missed_tick_behavior(time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            if raft.current_leader().await != Some(node_id) || !storage.has_pending_block().await {
+                continue;
             }
-        });
-    }
+            let seal = RaftCommand::SealBlock(chrono::Utc::now());
+            if let Err(e) = raft.client_write(ClientWriteRequest::new(seal)).await {
+                debug!("Failed to seal block: {}", e);
+            }
+        }
+    })
+}
+
+/// Translate the node's Raft settings into an async-raft configuration
+fn build_raft_config(config: &RaftConfig) -> Result<Config> {
+    Config::build("mp-consensus".to_string())
+        .heartbeat_interval(config.heartbeat_interval)
+        .election_timeout_min(config.election_timeout_min)
+        .election_timeout_max(config.election_timeout_max)
+        .snapshot_policy(SnapshotPolicy::LogsSinceLast(config.snapshot_interval))
+        .validate()
+        .map_err(|e| anyhow!("Invalid Raft configuration: {}", e))
 }
 
 #[async_trait::async_trait]
@@ -314,20 +197,51 @@ impl ConsensusEngine for RaftConsensusEngine {
     async fn start(&mut self) -> Result<()> {
         info!("Starting Raft consensus engine (node ID: {})", self.node_id);
 
-        // Set running flag
-        *self.running.write().await = true;
+        if self.raft.is_some() {
+            warn!("Raft consensus engine already running");
+            return Ok(());
+        }
 
-        // Start as follower
-        *self.state.write().await = NodeState::Follower;
+        let raft = Raft::new(
+            self.node_id,
+            self.raft_config.clone(),
+            self.network.clone(),
+            self.storage.clone(),
+        );
 
-        // Start heartbeat process
-        self.start_heartbeat().await;
+        match network::start_rpc_server(self.local_address()?, raft.clone(), self.peers.clone())
+            .await
+        {
+            Ok((server, address)) => {
+                // Peers and the membership status see the port actually bound
+                if let Some(node) = self.peers.write().unwrap().get_mut(&self.node_id) {
+                    node.address = address;
+                }
+                self.rpc_server = Some(server);
+                self.listen_address = Some(address);
+            }
+            Err(e) => {
+                let _ = raft.shutdown().await;
+                return Err(e);
+            }
+        }
 
-        // Start election timeout process
-        self.start_election_timeout().await;
+        // A pristine cluster is bootstrapped with every configured node as a voter,
+        // nodes that already hold a log will simply reject the request. A joining
+        // node waits as a learner until the leader replicates the log to it.
+        if !self.join {
+            let members: HashSet<NodeId> = self.nodes.iter().map(|n| n.id).collect();
+            if let Err(e) = raft.initialize(members).await {
+                debug!("Skipping Raft cluster initialization: {}", e);
+            }
+        }
 
-        // Start log applier process
-        self.start_log_applier().await;
+        self.block_timer = Some(spawn_block_timer(
+            self.node_id,
+            raft.clone(),
+            self.storage.clone(),
+        ));
+        self.raft = Some(raft);
 
         info!("Raft consensus engine started");
         Ok(())
@@ -337,57 +251,65 @@ impl ConsensusEngine for RaftConsensusEngine {
     async fn stop(&mut self) -> Result<()> {
         info!("Stopping Raft consensus engine");
 
-        // Set running flag to false
-        *self.running.write().await = false;
-
-        // Wait for a short time to allow tasks to finish
-        time::sleep(Duration::from_millis(100)).await;
+        if let Some(timer) = self.block_timer.take() {
+            timer.abort();
+        }
+        if let Some(server) = self.rpc_server.take() {
+            // Wait for the listener to be dropped so the address can be reused right away
+            server.abort();
+            let _ = server.await;
+        }
+        self.listen_address = None;
+        if let Some(raft) = self.raft.take() {
+            raft.shutdown().await?;
+        }
 
         info!("Raft consensus engine stopped");
         Ok(())
     }
 
     /// Submit a transaction to the consensus engine
+    ///
+    /// On a follower the transaction is forwarded to the current leader.
     async fn submit_transaction(&self, transaction: Transaction) -> Result<TransactionResponse> {
         debug!(
             "Submitting transaction to Raft consensus: {:?}",
             transaction.id
         );
 
-        // Process the transaction - 完全重写，避免跨 .await 使用 MutexGuard
-        let response = TransactionResponse::success(transaction.id);
-
-        // Store response in responses map
+        let tx_id = transaction.id;
+        match self
+            .raft()?
+            .client_write(ClientWriteRequest::new(RaftCommand::Transaction(Box::new(
+                transaction,
+            ))))
+            .await
         {
-            let mut responses = self.responses.lock().unwrap();
-            responses.insert(transaction.id, response.clone());
-        }
-
-        // Add transaction to transaction_results for propagation in heartbeats
-        {
-            let mut transaction_results = self.transaction_results.write().await;
-            transaction_results.push(response.clone());
-        }
-
-        // CRITICAL FIX: Add transaction to log for processing
-        {
-            // 在这个作用域内使用 MutexGuard，并且不跨越 .await
-            let mut log = self.log.lock().unwrap();
-            log.push_back(transaction);
-        }
-
-        // Update commit index to ensure this transaction gets processed
-        {
-            let mut commit_index = self.commit_index.write().await;
-            *commit_index += 1;
+            Ok(response) => {
+                debug!("Transaction committed at log index {}", response.index);
+                Ok(response.data)
+            }
+            Err(ClientWriteError::ForwardToLeader(
+                RaftCommand::Transaction(transaction),
+                Some(leader),
+            )) if leader != self.node_id => {
+                debug!("Forwarding transaction {} to leader {}", tx_id, leader);
+                self.network
+                    .forward_command(leader, RaftCommand::Transaction(transaction))
+                    .await
+            }
+            Err(ClientWriteError::ForwardToLeader(_, leader)) => Err(anyhow!(
+                "Node {} is not the leader (leader: {:?}), transaction {} rejected",
+                self.node_id,
+                leader,
+                tx_id
+            )),
+            Err(ClientWriteError::RaftError(e)) => Err(anyhow!("Raft error: {}", e)),
         }
-
-        Ok(response)
     }
 
     /// Get a channel for confirmed transactions
     async fn get_confirmed_tx_channel(&self) -> mpsc::Receiver<Transaction> {
-        // 对 Mutex 使用 lock() 而不是 await
         let mut guard = self.confirmed_tx_receiver.lock().unwrap();
         if let Some(rx) = guard.take() {
             rx
@@ -397,45 +319,113 @@ impl ConsensusEngine for RaftConsensusEngine {
             rx
         }
     }
+
+    /// Get the store of sealed blocks
+    fn blocks(&self) -> Arc<BlockStore> {
+        self.storage.blocks().store()
+    }
+
+    /// Sign sealed blocks with the node's PoC keys
+    fn set_block_signer(&self, signer: BlockSigner) {
+        self.storage.blocks().set_signer(signer);
+    }
+
+    /// Get a handle for changing the cluster membership
+    fn membership(&self) -> Option<Arc<dyn ClusterMembership>> {
+        self.raft.as_ref().map(|raft| {
+            Arc::new(RaftMembership::new(
+                self.node_id,
+                raft.clone(),
+                self.peers.clone(),
+            )) as Arc<dyn ClusterMembership>
+        })
+    }
+
+    /// Get a handle for changing the state through the log
+    fn state_control(&self) -> Option<Arc<dyn StateControl>> {
+        self.raft.as_ref().map(|raft| {
+            Arc::new(RaftStateControl {
+                node_id: self.node_id,
+                raft: raft.clone(),
+                network: self.network.clone(),
+            }) as Arc<dyn StateControl>
+        })
+    }
 }
 
-impl Clone for RaftConsensusEngine {
-    fn clone(&self) -> Self {
-        // Clone the basic fields
-        let (new_tx_sender, mut new_tx_receiver) = mpsc::channel(1000);
-
-        // Clone the original sender's channel
-        let original_sender = self.confirmed_tx_sender.clone();
-
-        // 创建一个新通道而不是尝试克隆Receiver，因为Receiver不实现Clone
-        // 让我们创建一个新通道，并转发消息
-        let new_sender = new_tx_sender.clone();
-        tokio::spawn(async move {
-            while let Some(tx) = new_tx_receiver.recv().await {
-                if let Err(e) = original_sender.send(tx).await {
-                    error!("Failed to forward transaction from clone: {}", e);
-                    break;
-                }
+/// State administration backed by a running Raft node
+///
+/// Reverts and state changes are committed to the log like transactions, so every
+/// node changes its state at the same point and nodes replaying the log do so as well.
+struct RaftStateControl {
+    node_id: NodeId,
+    raft: MpRaft,
+    network: Arc<TcpRaftNetwork>,
+}
+
+#[async_trait::async_trait]
+impl StateControl for RaftStateControl {
+    async fn revert_state(&self, root: String) -> Result<()> {
+        info!("Submitting state revert to root {}", root);
+        match self
+            .raft
+            .client_write(ClientWriteRequest::new(RaftCommand::RevertState(root)))
+            .await
+        {
+            Ok(response) if response.data.status == TransactionStatus::Error => Err(anyhow!(
+                "State revert failed: {}",
+                response
+                    .data
+                    .result
+                    .as_ref()
+                    .and_then(|result| result.as_str())
+                    .unwrap_or("unknown error")
+            )),
+            Ok(_) => Ok(()),
+            Err(ClientWriteError::ForwardToLeader(_, leader)) => Err(anyhow!(
+                "Node {} is not the leader (leader: {:?}), state revert rejected",
+                self.node_id,
+                leader
+            )),
+            Err(ClientWriteError::RaftError(e)) => Err(anyhow!("Raft error: {}", e)),
+        }
+    }
+
+    async fn commit_state_changes(&self, operations: Vec<StateOperation>) -> Result<()> {
+        debug!("Submitting {} state changes", operations.len());
+        let command = RaftCommand::ApplyStateChanges(operations);
+        let response = match self
+            .raft
+            .client_write(ClientWriteRequest::new(command))
+            .await
+        {
+            Ok(response) => response.data,
+            Err(ClientWriteError::ForwardToLeader(command, Some(leader)))
+                if leader != self.node_id =>
+            {
+                debug!("Forwarding state changes to leader {}", leader);
+                self.network.forward_command(leader, command).await?
+            }
+            Err(ClientWriteError::ForwardToLeader(_, leader)) => {
+                return Err(anyhow!(
+                    "Node {} is not the leader (leader: {:?}), state changes rejected",
+                    self.node_id,
+                    leader
+                ))
             }
-        });
-
-        Self {
-            node_id: self.node_id,
-            state: self.state.clone(),
-            nodes: self.nodes.clone(),
-            current_term: self.current_term.clone(),
-            leader_id: self.leader_id.clone(),
-            log: self.log.clone(),
-            last_applied: self.last_applied.clone(),
-            commit_index: self.commit_index.clone(),
-            confirmed_tx_sender: new_sender,
-            confirmed_tx_receiver: Arc::new(Mutex::new(None)),
-            heartbeat_interval: self.heartbeat_interval,
-            election_timeout_min: self.election_timeout_min,
-            election_timeout_max: self.election_timeout_max,
-            responses: self.responses.clone(),
-            running: self.running.clone(),
-            transaction_results: self.transaction_results.clone(),
+            Err(ClientWriteError::RaftError(e)) => return Err(anyhow!("Raft error: {}", e)),
+        };
+
+        if response.status == TransactionStatus::Error {
+            return Err(anyhow!(
+                "Applying state changes failed: {}",
+                response
+                    .result
+                    .as_ref()
+                    .and_then(|result| result.as_str())
+                    .unwrap_or("unknown error")
+            ));
         }
+        Ok(())
     }
 }
@@ -0,0 +1,660 @@
+use anyhow::{Context, Result};
+use async_raft::raft::{Entry, EntryPayload, MembershipConfig};
+use async_raft::storage::{CurrentSnapshotData, HardState, InitialState};
+use async_raft::{AppData, NodeId, RaftStorage};
+use chrono::{DateTime, Utc};
+use mp_common::types::{Transaction, TransactionResponse};
+use mp_state::diff::{StateOperation, StateSnapshot};
+use mp_state::StateStorage;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{mpsc, RwLock};
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+use crate::block::{BlockBuilder, BlockStore, ChainState};
+use crate::config::{BlockConfig, NodeInfo};
+use crate::network::PeerAddresses;
+use crate::nonce::CommittedNonces;
+
+const HARD_STATE_FILE: &str = "hard_state.json";
+const LOG_FILE: &str = "log.jsonl";
+const STATE_MACHINE_FILE: &str = "state_machine.json";
+const SNAPSHOT_FILE: &str = "snapshot.json";
+/// Number of applied transaction IDs kept to recognize a transaction committed again
+const RECENT_TRANSACTIONS: usize = 4096;
+
+/// Error used to signal that Raft must shut down to protect the log
+#[derive(Debug, thiserror::Error)]
+#[error("Raft storage shutdown: {0}")]
+pub struct ShutdownError(pub String);
+
+/// Command replicated through the Raft log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum RaftCommand {
+    /// Transaction handed to the node once committed
+    Transaction(Box<Transaction>),
+    /// Register the address of a node joining the cluster
+    AddNode(NodeInfo),
+    /// Seal the pending transactions into a block, issued by the leader every block interval
+    SealBlock(DateTime<Utc>),
+    /// Revert the state storage of every node to the given state root
+    RevertState(String),
+    /// Apply state changes of an execution on top of the state of every node
+    ApplyStateChanges(Vec<StateOperation>),
+}
+
+impl AppData for RaftCommand {}
+
+/// State machine of the consensus layer
+///
+/// Applying a transaction means handing it to the node, so the state kept
+/// here is the index of the last applied entry, the addresses of nodes
+/// added to the cluster at runtime, the position of the block chain, the
+/// IDs of the latest transactions and the nonces of their senders.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StateMachine {
+    /// Index of the last applied log entry
+    pub last_applied_log: u64,
+    /// Nodes registered through the log
+    #[serde(default)]
+    pub nodes: BTreeMap<NodeId, NodeInfo>,
+    /// Block chain position and the transactions of the open block
+    #[serde(default)]
+    pub chain: ChainState,
+    /// State changes being written to the state storage, set until the entry is applied
+    #[serde(default)]
+    pub pending_state_change: Option<PendingStateChange>,
+    /// IDs of the latest applied transactions, oldest first
+    ///
+    /// A transaction resubmitted after its commit went unacknowledged, such as
+    /// a forwarded write that timed out, is committed again and skipped here.
+    #[serde(default)]
+    pub recent_transactions: VecDeque<Uuid>,
+    /// Next nonce of every sender, transactions replaying a spent nonce are refused
+    #[serde(default)]
+    pub nonces: CommittedNonces,
+}
+
+/// Log entry whose state changes may have reached the state storage
+///
+/// The state storage and the state machine file cannot be written atomically,
+/// so the entry is recorded before its changes are written. If the node stops
+/// in between, the state root tells whether the changes made it to the state.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingStateChange {
+    /// Index of the log entry
+    pub index: u64,
+    /// State root before the changes were written
+    pub prev_root: String,
+}
+
+/// Contents of a snapshot, the state machine plus a copy of the node state
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct SnapshotData {
+    state_machine: StateMachine,
+    state: Option<StateSnapshot>,
+}
+
+/// Snapshot of the state machine together with its log position
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RaftSnapshot {
+    index: u64,
+    term: u64,
+    membership: MembershipConfig,
+    data: Vec<u8>,
+}
+
+/// Raft storage persisted as JSON files under the configured log path
+///
+/// Log entries are appended to `log.jsonl` and rewritten atomically when
+/// the log is truncated or compacted, term and vote, the applied index and
+/// the latest snapshot each live in their own file.
+pub struct RaftStore {
+    /// ID of the local node
+    id: NodeId,
+    /// Directory holding the log, hard state and snapshot files
+    path: PathBuf,
+    /// Raft log entries by index
+    log: RwLock<BTreeMap<u64, Entry<RaftCommand>>>,
+    /// State machine
+    state_machine: RwLock<StateMachine>,
+    /// Current term and vote
+    hard_state: RwLock<Option<HardState>>,
+    /// Current snapshot
+    current_snapshot: RwLock<Option<RaftSnapshot>>,
+    /// State store captured in snapshots and restored when installing one
+    state_storage: Option<Arc<dyn StateStorage>>,
+    /// Address book updated when nodes are registered
+    peers: PeerAddresses,
+    /// Channel for sending confirmed transactions to subscribers
+    confirmed_tx_sender: mpsc::Sender<Transaction>,
+    /// Seals applied transactions into blocks stored next to the log
+    blocks: BlockBuilder,
+}
+
+impl RaftStore {
+    /// Open the storage under `path`, replaying anything persisted by a previous run
+    pub fn open(
+        id: NodeId,
+        path: impl AsRef<Path>,
+        state_storage: Option<Arc<dyn StateStorage>>,
+        peers: PeerAddresses,
+        confirmed_tx_sender: mpsc::Sender<Transaction>,
+        block_config: BlockConfig,
+    ) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        std::fs::create_dir_all(&path)
+            .with_context(|| format!("Failed to create Raft log directory {:?}", path))?;
+
+        let hard_state = load_json::<HardState>(&path.join(HARD_STATE_FILE))?;
+        let state_machine =
+            load_json::<StateMachine>(&path.join(STATE_MACHINE_FILE))?.unwrap_or_default();
+        let current_snapshot = load_json::<RaftSnapshot>(&path.join(SNAPSHOT_FILE))?;
+        let log = load_log(&path.join(LOG_FILE))?;
+        peers.write().unwrap().extend(state_machine.nodes.clone());
+        let blocks = BlockBuilder::new(
+            block_config,
+            Arc::new(BlockStore::open(&path)?),
+            state_storage.clone(),
+        );
+
+        if hard_state.is_some() {
+            info!(
+                "Recovered Raft storage from {:?}: {} log entries, last applied {}",
+                path,
+                log.len(),
+                state_machine.last_applied_log
+            );
+        }
+
+        Ok(Self {
+            id,
+            path,
+            log: RwLock::new(log),
+            state_machine: RwLock::new(state_machine),
+            hard_state: RwLock::new(hard_state),
+            current_snapshot: RwLock::new(current_snapshot),
+            state_storage,
+            peers,
+            confirmed_tx_sender,
+            blocks,
+        })
+    }
+
+    /// Get the index of the last applied log entry
+    pub async fn last_applied_log(&self) -> u64 {
+        self.state_machine.read().await.last_applied_log
+    }
+
+    /// Get the builder sealing applied transactions into blocks
+    pub fn blocks(&self) -> &BlockBuilder {
+        &self.blocks
+    }
+
+    /// Check whether applied transactions are waiting to be sealed into a block
+    pub async fn has_pending_block(&self) -> bool {
+        !self.state_machine.read().await.chain.pending.is_empty()
+    }
+
+    /// Hand a committed transaction to subscribers
+    async fn confirm(&self, index: u64, transaction: &Transaction) -> Transaction {
+        let mut transaction = transaction.clone();
+        transaction.log_index = index;
+
+        info!(
+            "Sending confirmed transaction to executor: {}",
+            transaction.id
+        );
+        if let Err(e) = self.confirmed_tx_sender.send(transaction.clone()).await {
+            error!("Failed to send confirmed transaction: {}", e);
+        }
+        transaction
+    }
+
+    /// Apply a committed command to the state machine
+    async fn apply(
+        &self,
+        state_machine: &mut StateMachine,
+        index: u64,
+        command: &RaftCommand,
+    ) -> Result<TransactionResponse> {
+        state_machine.last_applied_log = index;
+        let pending_state_change = state_machine.pending_state_change.take();
+        match command {
+            RaftCommand::Transaction(transaction) => {
+                if state_machine.recent_transactions.contains(&transaction.id) {
+                    debug!(
+                        "Transaction {} at log index {} was applied before",
+                        transaction.id, index
+                    );
+                    return Ok(TransactionResponse::success(transaction.id));
+                }
+                if let Err(e) = state_machine.nonces.spend(transaction) {
+                    warn!("Refusing transaction at log index {}: {}", index, e);
+                    return Ok(TransactionResponse::error(e));
+                }
+                state_machine.recent_transactions.push_back(transaction.id);
+                if state_machine.recent_transactions.len() > RECENT_TRANSACTIONS {
+                    state_machine.recent_transactions.pop_front();
+                }
+
+                let confirmed = self.confirm(index, transaction).await;
+                self.blocks.push(&mut state_machine.chain, confirmed)?;
+                Ok(TransactionResponse::success(transaction.id))
+            }
+            RaftCommand::AddNode(node) => {
+                info!("Registering Raft node {} at {}", node.id, node.address);
+                state_machine.nodes.insert(node.id, node.clone());
+                self.peers.write().unwrap().insert(node.id, node.clone());
+                Ok(TransactionResponse::success(Uuid::nil()))
+            }
+            RaftCommand::SealBlock(timestamp) => {
+                self.blocks.seal(&mut state_machine.chain, *timestamp)?;
+                Ok(TransactionResponse::success(Uuid::nil()))
+            }
+            RaftCommand::RevertState(root) => {
+                let storage = match &self.state_storage {
+                    Some(storage) => storage,
+                    None => {
+                        return Ok(TransactionResponse::error(
+                            "No state storage to revert".to_string(),
+                        ))
+                    }
+                };
+
+                warn!("Reverting state to root {} at log index {}", root, index);
+                Ok(match storage.revert_to(root) {
+                    Ok(()) => {
+                        storage.record_log_root(index, root)?;
+                        TransactionResponse::success(Uuid::nil())
+                    }
+                    Err(e) => {
+                        error!("Failed to revert state to root {}: {}", root, e);
+                        TransactionResponse::error(e.to_string())
+                    }
+                })
+            }
+            RaftCommand::ApplyStateChanges(operations) => {
+                let storage = match &self.state_storage {
+                    Some(storage) => storage,
+                    None => {
+                        return Ok(TransactionResponse::error(
+                            "No state storage to apply state changes to".to_string(),
+                        ))
+                    }
+                };
+
+                let prev_root = storage.get_state_root()?;
+                if let Some(pending) = pending_state_change {
+                    if pending.index == index && pending.prev_root != prev_root {
+                        info!(
+                            "State changes at log index {} were written before the restart, state root {}",
+                            index, prev_root
+                        );
+                        storage.record_log_root(index, &prev_root)?;
+                        return Ok(TransactionResponse::success(Uuid::nil()));
+                    }
+                }
+
+                // Record the entry before writing so it is not applied twice after a crash
+                let mut marker = state_machine.clone();
+                marker.last_applied_log = index - 1;
+                marker.pending_state_change = Some(PendingStateChange { index, prev_root });
+                self.save_state_machine(&marker).await?;
+
+                Ok(match storage.apply_operations(operations.clone()) {
+                    Ok(diff) => {
+                        debug!(
+                            "Applied {} state changes at log index {}, state root {}",
+                            diff.len(),
+                            index,
+                            diff.new_root
+                        );
+                        storage.record_log_root(index, &diff.new_root)?;
+                        TransactionResponse::success(Uuid::nil())
+                    }
+                    Err(e) => {
+                        error!(
+                            "Failed to apply state changes at log index {}: {}",
+                            index, e
+                        );
+                        TransactionResponse::error(e.to_string())
+                    }
+                })
+            }
+        }
+    }
+
+    /// Append entries to the log file
+    async fn append_to_log_file(&self, entries: &[Entry<RaftCommand>]) -> Result<()> {
+        let mut data = Vec::new();
+        for entry in entries {
+            serde_json::to_writer(&mut data, entry)?;
+            data.push(b'\n');
+        }
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path.join(LOG_FILE))
+            .await?;
+        file.write_all(&data).await?;
+        file.sync_data().await?;
+        Ok(())
+    }
+
+    /// Replace the log file with the given entries
+    async fn rewrite_log_file(&self, log: &BTreeMap<u64, Entry<RaftCommand>>) -> Result<()> {
+        let mut data = Vec::new();
+        for entry in log.values() {
+            serde_json::to_writer(&mut data, entry)?;
+            data.push(b'\n');
+        }
+        write_atomic(&self.path.join(LOG_FILE), &data).await
+    }
+
+    /// Persist the state machine
+    async fn save_state_machine(&self, state_machine: &StateMachine) -> Result<()> {
+        write_atomic(
+            &self.path.join(STATE_MACHINE_FILE),
+            &serde_json::to_vec(state_machine)?,
+        )
+        .await
+    }
+}
+
+/// Read a JSON file, returning `None` if it does not exist yet
+fn load_json<T: DeserializeOwned>(path: &Path) -> Result<Option<T>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let content = std::fs::read(path)?;
+    let value = serde_json::from_slice(&content)
+        .with_context(|| format!("Failed to parse Raft storage file {:?}", path))?;
+    Ok(Some(value))
+}
+
+/// Read the log file, later lines take precedence over earlier ones
+fn load_log(path: &Path) -> Result<BTreeMap<u64, Entry<RaftCommand>>> {
+    let mut log = BTreeMap::new();
+    if !path.exists() {
+        return Ok(log);
+    }
+
+    let content = std::fs::read_to_string(path)?;
+    for line in content.lines().filter(|line| !line.trim().is_empty()) {
+        match serde_json::from_str::<Entry<RaftCommand>>(line) {
+            Ok(entry) => {
+                log.insert(entry.index, entry);
+            }
+            Err(e) => {
+                // Only the last line can be torn by a crash during an append
+                error!("Ignoring unreadable Raft log entry in {:?}: {}", path, e);
+                break;
+            }
+        }
+    }
+    Ok(log)
+}
+
+/// Write a file through a temporary file so readers never see partial content
+async fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    let mut file = tokio::fs::File::create(&tmp_path).await?;
+    file.write_all(data).await?;
+    file.sync_data().await?;
+    tokio::fs::rename(&tmp_path, path).await?;
+    Ok(())
+}
+
+#[async_trait::async_trait]
+impl RaftStorage<RaftCommand, TransactionResponse> for RaftStore {
+    type Snapshot = Cursor<Vec<u8>>;
+    type ShutdownError = ShutdownError;
+
+    async fn get_membership_config(&self) -> Result<MembershipConfig> {
+        let log = self.log.read().await;
+        let membership = log.values().rev().find_map(|entry| match &entry.payload {
+            EntryPayload::ConfigChange(change) => Some(change.membership.clone()),
+            EntryPayload::SnapshotPointer(pointer) => Some(pointer.membership.clone()),
+            _ => None,
+        });
+        Ok(membership.unwrap_or_else(|| MembershipConfig::new_initial(self.id)))
+    }
+
+    async fn get_initial_state(&self) -> Result<InitialState> {
+        let membership = self.get_membership_config().await?;
+        let mut hard_state = self.hard_state.write().await;
+        match &*hard_state {
+            Some(state) => {
+                let log = self.log.read().await;
+                let (last_log_index, last_log_term) = log
+                    .values()
+                    .next_back()
+                    .map(|entry| (entry.index, entry.term))
+                    .unwrap_or((0, 0));
+                Ok(InitialState {
+                    last_log_index,
+                    last_log_term,
+                    last_applied_log: self.last_applied_log().await,
+                    hard_state: state.clone(),
+                    membership,
+                })
+            }
+            None => {
+                let initial = InitialState::new_initial(self.id);
+                write_atomic(
+                    &self.path.join(HARD_STATE_FILE),
+                    &serde_json::to_vec(&initial.hard_state)?,
+                )
+                .await?;
+                *hard_state = Some(initial.hard_state.clone());
+                Ok(initial)
+            }
+        }
+    }
+
+    async fn save_hard_state(&self, hs: &HardState) -> Result<()> {
+        let mut hard_state = self.hard_state.write().await;
+        write_atomic(&self.path.join(HARD_STATE_FILE), &serde_json::to_vec(hs)?)
+            .await
+            .map_err(|e| ShutdownError(format!("Failed to persist hard state: {}", e)))?;
+        *hard_state = Some(hs.clone());
+        Ok(())
+    }
+
+    async fn get_log_entries(&self, start: u64, stop: u64) -> Result<Vec<Entry<RaftCommand>>> {
+        if start > stop {
+            error!("Invalid log range: start {} > stop {}", start, stop);
+            return Ok(vec![]);
+        }
+        let log = self.log.read().await;
+        Ok(log
+            .range(start..stop)
+            .map(|(_, entry)| entry.clone())
+            .collect())
+    }
+
+    async fn delete_logs_from(&self, start: u64, stop: Option<u64>) -> Result<()> {
+        let mut log = self.log.write().await;
+        match stop {
+            Some(stop) => {
+                for index in start..stop {
+                    log.remove(&index);
+                }
+            }
+            None => {
+                log.split_off(&start);
+            }
+        }
+        self.rewrite_log_file(&log)
+            .await
+            .map_err(|e| ShutdownError(format!("Failed to rewrite Raft log: {}", e)))?;
+        Ok(())
+    }
+
+    async fn append_entry_to_log(&self, entry: &Entry<RaftCommand>) -> Result<()> {
+        let mut log = self.log.write().await;
+        self.append_to_log_file(std::slice::from_ref(entry))
+            .await
+            .map_err(|e| ShutdownError(format!("Failed to append to Raft log: {}", e)))?;
+        log.insert(entry.index, entry.clone());
+        Ok(())
+    }
+
+    async fn replicate_to_log(&self, entries: &[Entry<RaftCommand>]) -> Result<()> {
+        let mut log = self.log.write().await;
+        self.append_to_log_file(entries)
+            .await
+            .map_err(|e| ShutdownError(format!("Failed to append to Raft log: {}", e)))?;
+        for entry in entries {
+            log.insert(entry.index, entry.clone());
+        }
+        Ok(())
+    }
+
+    async fn apply_entry_to_state_machine(
+        &self,
+        index: &u64,
+        data: &RaftCommand,
+    ) -> Result<TransactionResponse> {
+        let mut state_machine = self.state_machine.write().await;
+        let response = self.apply(&mut state_machine, *index, data).await?;
+        self.save_state_machine(&state_machine).await?;
+        Ok(response)
+    }
+
+    async fn replicate_to_state_machine(&self, entries: &[(&u64, &RaftCommand)]) -> Result<()> {
+        let mut state_machine = self.state_machine.write().await;
+        for (index, data) in entries {
+            self.apply(&mut state_machine, **index, data).await?;
+        }
+        self.save_state_machine(&state_machine).await?;
+        Ok(())
+    }
+
+    async fn do_log_compaction(&self) -> Result<CurrentSnapshotData<Self::Snapshot>> {
+        let (data, last_applied_log) = {
+            let state_machine = self.state_machine.read().await;
+            let state = match &self.state_storage {
+                Some(storage) => Some(storage.export_snapshot()?),
+                None => None,
+            };
+            let data = SnapshotData {
+                state_machine: state_machine.clone(),
+                state,
+            };
+            (serde_json::to_vec(&data)?, state_machine.last_applied_log)
+        };
+
+        let membership = self.get_membership_config().await?;
+        let (term, bytes) = {
+            let mut log = self.log.write().await;
+            let term = log
+                .get(&last_applied_log)
+                .map(|entry| entry.term)
+                .ok_or_else(|| anyhow::anyhow!("Log entry {} not found", last_applied_log))?;
+            *log = log.split_off(&last_applied_log);
+            log.insert(
+                last_applied_log,
+                Entry::new_snapshot_pointer(
+                    last_applied_log,
+                    term,
+                    String::new(),
+                    membership.clone(),
+                ),
+            );
+
+            // The snapshot must be on disk before the entries it covers are dropped
+            let snapshot = RaftSnapshot {
+                index: last_applied_log,
+                term,
+                membership: membership.clone(),
+                data,
+            };
+            let bytes = serde_json::to_vec(&snapshot)?;
+            write_atomic(&self.path.join(SNAPSHOT_FILE), &bytes).await?;
+            self.rewrite_log_file(&log).await?;
+            *self.current_snapshot.write().await = Some(snapshot);
+            (term, bytes)
+        };
+
+        debug!("Compacted Raft log through index {}", last_applied_log);
+        Ok(CurrentSnapshotData {
+            term,
+            index: last_applied_log,
+            membership,
+            snapshot: Box::new(Cursor::new(bytes)),
+        })
+    }
+
+    async fn create_snapshot(&self) -> Result<(String, Box<Self::Snapshot>)> {
+        Ok((String::new(), Box::new(Cursor::new(Vec::new()))))
+    }
+
+    async fn finalize_snapshot_installation(
+        &self,
+        index: u64,
+        term: u64,
+        delete_through: Option<u64>,
+        id: String,
+        snapshot: Box<Self::Snapshot>,
+    ) -> Result<()> {
+        let snapshot: RaftSnapshot = serde_json::from_slice(snapshot.get_ref())?;
+        let data: SnapshotData = serde_json::from_slice(&snapshot.data)?;
+
+        if let (Some(storage), Some(state)) = (&self.state_storage, &data.state) {
+            storage.import_snapshot(state)?;
+            storage.record_log_root(index, &state.root)?;
+        }
+
+        write_atomic(
+            &self.path.join(SNAPSHOT_FILE),
+            &serde_json::to_vec(&snapshot)?,
+        )
+        .await?;
+
+        {
+            let mut log = self.log.write().await;
+            match delete_through {
+                Some(through) => *log = log.split_off(&(through + 1)),
+                None => log.clear(),
+            }
+            log.insert(
+                index,
+                Entry::new_snapshot_pointer(index, term, id, snapshot.membership.clone()),
+            );
+            self.rewrite_log_file(&log).await?;
+        }
+
+        let mut state_machine = self.state_machine.write().await;
+        *state_machine = data.state_machine;
+        self.peers
+            .write()
+            .unwrap()
+            .extend(state_machine.nodes.clone());
+        self.save_state_machine(&state_machine).await?;
+        *self.current_snapshot.write().await = Some(snapshot);
+
+        info!("Installed Raft snapshot at index {}", index);
+        Ok(())
+    }
+
+    async fn get_current_snapshot(&self) -> Result<Option<CurrentSnapshotData<Self::Snapshot>>> {
+        match &*self.current_snapshot.read().await {
+            Some(snapshot) => Ok(Some(CurrentSnapshotData {
+                term: snapshot.term,
+                index: snapshot.index,
+                membership: snapshot.membership.clone(),
+                snapshot: Box::new(Cursor::new(serde_json::to_vec(snapshot)?)),
+            })),
+            None => Ok(None),
+        }
+    }
+}
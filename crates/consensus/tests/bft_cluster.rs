@@ -0,0 +1,460 @@
+mod common;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use ethereum_types::H256;
+use mp_common::types::TransactionStatus;
+use mp_consensus::bft::{vote_digest, BftConsensusEngine, Phase, QuorumCertificate, ValidatorSet};
+use mp_consensus::ConsensusEngine;
+use mp_poc::bls::BlstCrypto;
+use mp_state::diff::StateOperation;
+use mp_state::StateStorage;
+use tokio::time::{sleep, Instant};
+use uuid::Uuid;
+
+use common::{
+    bft_config, call_transaction, connect_bft, expect_confirmed, key_seed, loopback_nodes,
+    sqlite_state_storage, test_transaction, validator_infos,
+};
+
+/// Check that the running engines decided the same chain with valid certificates
+fn assert_same_chain(engines: &[Option<BftConsensusEngine>]) {
+    let mut chains = engines.iter().flatten().map(|engine| {
+        let blocks = engine.committed_blocks();
+        for committed in &blocks {
+            assert_eq!(committed.certificate.phase, Phase::Commit);
+            assert_eq!(committed.certificate.block_hash, committed.block.hash());
+            committed.certificate.verify(engine.validators()).unwrap();
+        }
+
+        // Every decided block with confirmed transactions is sealed into a chain block linked
+        // to its parent, transactions refused when applied are left out
+        let chain = engine.blocks().range(1, usize::MAX);
+        let sealed: Vec<Vec<Uuid>> = chain
+            .iter()
+            .map(|block| block.transactions.iter().map(|tx| tx.id).collect())
+            .collect();
+        let decided: Vec<Vec<Uuid>> = blocks
+            .iter()
+            .map(|committed| {
+                let transactions = committed.block.transactions.iter().map(|tx| tx.id);
+                transactions
+                    .filter(|id| sealed.iter().flatten().any(|sealed| sealed == id))
+                    .collect::<Vec<_>>()
+            })
+            .filter(|ids| !ids.is_empty())
+            .collect();
+        assert_eq!(sealed, decided);
+        let mut parent = H256::zero();
+        for block in &chain {
+            assert_eq!(block.header.parent_hash, parent);
+            block.verify().unwrap();
+            parent = block.hash();
+        }
+
+        blocks
+            .iter()
+            .map(|committed| committed.block.hash())
+            .chain(chain.iter().map(|block| format!("{:x}", block.hash())))
+            .collect::<Vec<_>>()
+    });
+    let first = chains.next().unwrap();
+    for chain in chains {
+        assert_eq!(chain, first);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_four_validators_decide_and_tolerate_a_failure() {
+    let nodes = loopback_nodes(1..=4);
+
+    let data_dir = tempfile::tempdir().unwrap();
+    let mut engines = Vec::new();
+    let mut receivers = Vec::new();
+    for node in &nodes {
+        let mut engine =
+            BftConsensusEngine::new(bft_config(node.id, &nodes, data_dir.path()), None).unwrap();
+        receivers.push(engine.get_confirmed_tx_channel().await);
+        engine.start().await.unwrap();
+        engines.push(Some(engine));
+    }
+    connect_bft(engines.iter().flatten());
+
+    // Transactions submitted on any validator are decided everywhere in the same order
+    for (i, payload) in ["first", "second", "third"].iter().enumerate() {
+        let tx = test_transaction(payload);
+        engines[i]
+            .as_ref()
+            .unwrap()
+            .submit_transaction(tx.clone())
+            .await
+            .unwrap();
+        for rx in receivers.iter_mut() {
+            expect_confirmed(rx, &tx).await;
+        }
+    }
+    assert_same_chain(&engines);
+
+    // Stop the leader of the next height, the others time out and elect the next one
+    let height = engines[0].as_ref().unwrap().height() + 1;
+    let validators = engines[0].as_ref().unwrap().validators().clone();
+    let leader = validators.leader(height, 0);
+    let stopped = nodes.iter().position(|n| n.id == leader).unwrap();
+    engines[stopped].take().unwrap().stop().await.unwrap();
+
+    let submitter = (stopped + 1) % nodes.len();
+    for payload in ["after-failure", "after-failure-2"] {
+        let tx = test_transaction(payload);
+        engines[submitter]
+            .as_ref()
+            .unwrap()
+            .submit_transaction(tx.clone())
+            .await
+            .unwrap();
+        for (i, rx) in receivers.iter_mut().enumerate() {
+            if i != stopped {
+                expect_confirmed(rx, &tx).await;
+            }
+        }
+    }
+    assert_same_chain(&engines);
+
+    for engine in engines.iter_mut().filter_map(Option::take) {
+        let mut engine = engine;
+        engine.stop().await.unwrap();
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_decided_chain_survives_restart() {
+    let nodes = loopback_nodes(1..=4);
+    let data_dir = tempfile::tempdir().unwrap();
+    let start = |id: u64| {
+        let config = bft_config(id, &nodes, data_dir.path());
+        async move {
+            let mut engine = BftConsensusEngine::new(config, None).unwrap();
+            let rx = engine.get_confirmed_tx_channel().await;
+            engine.start().await.unwrap();
+            (engine, rx)
+        }
+    };
+
+    let mut engines = Vec::new();
+    let mut receivers = Vec::new();
+    for node in &nodes {
+        let (engine, rx) = start(node.id).await;
+        engines.push(Some(engine));
+        receivers.push(rx);
+    }
+    connect_bft(engines.iter().flatten());
+    for payload in ["first", "second"] {
+        let tx = test_transaction(payload);
+        engines[0]
+            .as_ref()
+            .unwrap()
+            .submit_transaction(tx.clone())
+            .await
+            .unwrap();
+        for rx in receivers.iter_mut() {
+            expect_confirmed(rx, &tx).await;
+        }
+    }
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while engines
+        .iter()
+        .flatten()
+        .any(|engine| engine.blocks().latest_height() < Some(2))
+    {
+        assert!(Instant::now() < deadline, "blocks were not sealed");
+        sleep(Duration::from_millis(50)).await;
+    }
+    for engine in engines.iter_mut().flatten() {
+        engine.stop().await.unwrap();
+    }
+    let height = engines[0].as_ref().unwrap().height();
+    let chain = engines[0].as_ref().unwrap().blocks().range(1, usize::MAX);
+    drop(engines);
+
+    // Restarted validators resume from the decided blocks and the sealed chain on disk
+    let mut engines = Vec::new();
+    let mut receivers = Vec::new();
+    for node in &nodes {
+        let (engine, rx) = start(node.id).await;
+        assert_eq!(engine.height(), height);
+        assert_eq!(
+            engine
+                .blocks()
+                .range(1, usize::MAX)
+                .iter()
+                .map(|block| block.hash())
+                .collect::<Vec<_>>(),
+            chain.iter().map(|block| block.hash()).collect::<Vec<_>>()
+        );
+        engines.push(Some(engine));
+        receivers.push(rx);
+    }
+    connect_bft(engines.iter().flatten());
+
+    let tx = test_transaction("after-restart");
+    engines[1]
+        .as_ref()
+        .unwrap()
+        .submit_transaction(tx.clone())
+        .await
+        .unwrap();
+    for rx in receivers.iter_mut() {
+        expect_confirmed(rx, &tx).await;
+    }
+    assert_eq!(engines[0].as_ref().unwrap().height(), height + 1);
+    assert_same_chain(&engines);
+
+    for engine in engines.iter_mut().filter_map(Option::take) {
+        let mut engine = engine;
+        engine.stop().await.unwrap();
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_state_changes_are_decided_and_applied_by_every_validator() {
+    let nodes = loopback_nodes(1..=4);
+
+    let data_dir = tempfile::tempdir().unwrap();
+    let mut engines = Vec::new();
+    let mut receivers = Vec::new();
+    let mut storages: Vec<Arc<dyn StateStorage>> = Vec::new();
+    for node in &nodes {
+        let node_dir = data_dir.path().join(format!("node{}", node.id));
+        let storage = sqlite_state_storage(&node_dir);
+        let mut engine = BftConsensusEngine::new(
+            bft_config(node.id, &nodes, &node_dir),
+            Some(storage.clone()),
+        )
+        .unwrap();
+        receivers.push(engine.get_confirmed_tx_channel().await);
+        engine.start().await.unwrap();
+        storages.push(storage);
+        engines.push(Some(engine));
+    }
+    connect_bft(engines.iter().flatten());
+
+    // State changes of an execution on any validator are decided and applied everywhere
+    let state_control = engines[1].as_ref().unwrap().state_control().unwrap();
+    state_control
+        .commit_state_changes(vec![
+            StateOperation::Insert {
+                key: "counter".to_string(),
+                value: "1".to_string(),
+            },
+            StateOperation::Insert {
+                key: "owner".to_string(),
+                value: "alice".to_string(),
+            },
+        ])
+        .await
+        .unwrap();
+
+    let expected = vec![
+        ("counter".to_string(), "1".to_string()),
+        ("owner".to_string(), "alice".to_string()),
+    ];
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while storages
+        .iter()
+        .any(|storage| storage.export_snapshot().unwrap().entries != expected)
+    {
+        assert!(
+            Instant::now() < deadline,
+            "state changes not applied on every validator"
+        );
+        sleep(Duration::from_millis(50)).await;
+    }
+    let root = storages[0].get_state_root().unwrap();
+    for storage in &storages {
+        assert_eq!(storage.get_state_root().unwrap(), root);
+        assert_eq!(storage.root_at_log_index(1).unwrap(), Some(root.clone()));
+    }
+
+    // Transactions decided afterwards come next in the log and seal the new root
+    let tx = test_transaction("after-state-changes");
+    engines[2]
+        .as_ref()
+        .unwrap()
+        .submit_transaction(tx.clone())
+        .await
+        .unwrap();
+    for rx in receivers.iter_mut() {
+        let confirmed = rx.recv().await.unwrap();
+        assert_eq!(confirmed.id, tx.id);
+        assert_eq!(confirmed.log_index, 2);
+    }
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while engines
+        .iter()
+        .flatten()
+        .any(|engine| engine.blocks().latest_height() < Some(1))
+    {
+        assert!(Instant::now() < deadline, "blocks were not sealed");
+        sleep(Duration::from_millis(50)).await;
+    }
+    for engine in engines.iter().flatten() {
+        assert_eq!(engine.blocks().latest().unwrap().header.state_root, root);
+    }
+    assert_same_chain(&engines);
+
+    assert!(state_control.revert_state(root).await.is_err());
+
+    for engine in engines.iter_mut().filter_map(Option::take) {
+        let mut engine = engine;
+        engine.stop().await.unwrap();
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_replayed_nonces_are_refused_by_every_validator() {
+    let nodes = loopback_nodes(1..=4);
+    let data_dir = tempfile::tempdir().unwrap();
+    let start = |id: u64| {
+        let config = bft_config(id, &nodes, data_dir.path());
+        async move {
+            let mut engine = BftConsensusEngine::new(config, None).unwrap();
+            let rx = engine.get_confirmed_tx_channel().await;
+            engine.start().await.unwrap();
+            (engine, rx)
+        }
+    };
+
+    let mut engines = Vec::new();
+    let mut receivers = Vec::new();
+    for node in &nodes {
+        let (engine, rx) = start(node.id).await;
+        engines.push(Some(engine));
+        receivers.push(rx);
+    }
+    connect_bft(engines.iter().flatten());
+
+    let first = call_transaction(Some("alice"), Some(0));
+    let response = engines[0]
+        .as_ref()
+        .unwrap()
+        .submit_transaction(first.clone())
+        .await
+        .unwrap();
+    assert_eq!(response.status, TransactionStatus::Success);
+    for rx in receivers.iter_mut() {
+        expect_confirmed(rx, &first).await;
+    }
+
+    // A spent nonce submitted on another validator, or a call without a sender, is decided
+    // but confirmed nowhere
+    for tx in [
+        call_transaction(Some("alice"), Some(0)),
+        call_transaction(None, None),
+    ] {
+        let response = engines[1]
+            .as_ref()
+            .unwrap()
+            .submit_transaction(tx)
+            .await
+            .unwrap();
+        assert_eq!(response.status, TransactionStatus::Error);
+    }
+    let next = call_transaction(Some("alice"), Some(1));
+    engines[2]
+        .as_ref()
+        .unwrap()
+        .submit_transaction(next.clone())
+        .await
+        .unwrap();
+    for rx in receivers.iter_mut() {
+        let confirmed = rx.recv().await.unwrap();
+        assert_eq!(confirmed.id, next.id);
+        assert_eq!(confirmed.log_index, 2);
+    }
+    for engine in engines.iter_mut().flatten() {
+        engine.stop().await.unwrap();
+    }
+    drop(engines);
+
+    // Restarted validators replay the decided blocks to the same nonces and log indices
+    let mut engines = Vec::new();
+    let mut receivers = Vec::new();
+    for node in &nodes {
+        let (engine, rx) = start(node.id).await;
+        engines.push(Some(engine));
+        receivers.push(rx);
+    }
+    connect_bft(engines.iter().flatten());
+
+    let replay = call_transaction(Some("alice"), Some(1));
+    let response = engines[3]
+        .as_ref()
+        .unwrap()
+        .submit_transaction(replay)
+        .await
+        .unwrap();
+    assert_eq!(response.status, TransactionStatus::Error);
+    let next = call_transaction(Some("alice"), Some(2));
+    engines[3]
+        .as_ref()
+        .unwrap()
+        .submit_transaction(next.clone())
+        .await
+        .unwrap();
+    for rx in receivers.iter_mut() {
+        let confirmed = rx.recv().await.unwrap();
+        assert_eq!(confirmed.id, next.id);
+        assert_eq!(confirmed.log_index, 3);
+    }
+    assert_same_chain(&engines);
+
+    for engine in engines.iter_mut().filter_map(Option::take) {
+        let mut engine = engine;
+        engine.stop().await.unwrap();
+    }
+}
+
+#[test]
+fn test_certificate_requires_quorum_of_validators() {
+    let validators = ValidatorSet::new(
+        validator_infos(1..=4)
+            .into_iter()
+            .map(|v| (v.id, v.public_key)),
+    );
+    assert_eq!(validators.quorum(), 3);
+
+    let block_hash = "ab".repeat(32);
+    let digest = vote_digest(Phase::Commit, 1, 0, &block_hash);
+    let votes = |seeds: Vec<String>| -> Vec<_> {
+        seeds
+            .into_iter()
+            .map(|seed| BlstCrypto::new(seed).unwrap().sign(&digest).unwrap())
+            .collect()
+    };
+    let certificate = |seeds: Vec<String>| {
+        QuorumCertificate::aggregate(Phase::Commit, 1, 0, block_hash.clone(), &votes(seeds))
+            .unwrap()
+    };
+
+    certificate((1..=3).map(key_seed).collect())
+        .verify(&validators)
+        .unwrap();
+    certificate((1..=4).map(key_seed).collect())
+        .verify(&validators)
+        .unwrap();
+
+    // Too few signers
+    assert!(certificate((1..=2).map(key_seed).collect())
+        .verify(&validators)
+        .is_err());
+
+    // A signer outside the validator set does not count towards the quorum
+    let mut seeds: Vec<String> = (1..=2).map(key_seed).collect();
+    seeds.push(key_seed(5));
+    assert!(certificate(seeds).verify(&validators).is_err());
+
+    // The signatures must cover the certified block
+    let mut forged = certificate((1..=3).map(key_seed).collect());
+    forged.block_hash = "cd".repeat(32);
+    assert!(forged.verify(&validators).is_err());
+}
@@ -0,0 +1,173 @@
+//! Helpers shared by the consensus integration tests
+#![allow(dead_code)]
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use mp_common::types::{Transaction, TransactionType};
+use mp_common::utils::create_transaction;
+use mp_consensus::bft::BftConsensusEngine;
+use mp_consensus::config::{BftConfig, ConsensusConfig, NodeInfo, RaftConfig, ValidatorInfo};
+use mp_consensus::raft::RaftConsensusEngine;
+use mp_poc::bls::BlstCrypto;
+use mp_state::config::StateConfig;
+use mp_state::{create_state_storage, StateStorage};
+use tokio::sync::mpsc;
+use tokio::time::{sleep, timeout, Instant};
+
+/// Nodes listening on a loopback port picked when their engine starts
+///
+/// Engines started from them learn where the others listen through
+/// [`connect_raft`] or [`connect_bft`].
+pub fn loopback_nodes(ids: impl IntoIterator<Item = u64>) -> Vec<NodeInfo> {
+    ids.into_iter()
+        .map(|id| NodeInfo {
+            id,
+            address: SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+            rest_address: None,
+        })
+        .collect()
+}
+
+pub fn raft_config(node_id: u64, nodes: &[NodeInfo], log_path: &Path) -> ConsensusConfig {
+    ConsensusConfig {
+        engine_type: "raft".to_string(),
+        node_id,
+        nodes: nodes.to_vec(),
+        raft: Some(RaftConfig {
+            heartbeat_interval: 50,
+            election_timeout_min: 300,
+            election_timeout_max: 600,
+            snapshot_interval: 10000,
+            log_path: log_path.to_string_lossy().to_string(),
+            join: false,
+        }),
+        bft: None,
+        block: Default::default(),
+    }
+}
+
+pub fn key_seed(id: u64) -> String {
+    format!("validator-{}", id)
+}
+
+pub fn validator_infos(ids: impl IntoIterator<Item = u64>) -> Vec<ValidatorInfo> {
+    ids.into_iter()
+        .map(|id| ValidatorInfo {
+            id,
+            public_key: BlstCrypto::new(key_seed(id))
+                .unwrap()
+                .validator_pubkey()
+                .clone(),
+        })
+        .collect()
+}
+
+pub fn bft_config(node_id: u64, nodes: &[NodeInfo], data_dir: &Path) -> ConsensusConfig {
+    ConsensusConfig {
+        engine_type: "bft".to_string(),
+        node_id,
+        nodes: nodes.to_vec(),
+        raft: None,
+        bft: Some(BftConfig {
+            key_seed: key_seed(node_id),
+            validators: validator_infos(nodes.iter().map(|n| n.id)),
+            view_timeout: 500,
+            max_block_size: 100,
+            log_path: data_dir
+                .join(format!("bft-{}", node_id))
+                .to_string_lossy()
+                .to_string(),
+        }),
+        block: Default::default(),
+    }
+}
+
+pub fn sqlite_state_storage(data_dir: &Path) -> Arc<dyn StateStorage> {
+    create_state_storage(StateConfig {
+        db_type: "sqlite".to_string(),
+        db_connection: data_dir.join("state.db").to_string_lossy().to_string(),
+        state_root_path: data_dir.join("state_root").to_string_lossy().to_string(),
+    })
+    .unwrap()
+}
+
+pub fn test_transaction(payload: &str) -> Transaction {
+    create_transaction(
+        TransactionType::StateChange,
+        payload.as_bytes().to_vec(),
+        None,
+        Default::default(),
+        Default::default(),
+    )
+}
+
+/// Contract call of `sender` numbered `nonce`
+pub fn call_transaction(sender: Option<&str>, nonce: Option<u64>) -> Transaction {
+    let mut transaction = create_transaction(
+        TransactionType::Request(Default::default(), "call".to_string()),
+        vec![],
+        sender.map(str::to_string),
+        Default::default(),
+        Default::default(),
+    );
+    transaction.nonce = nonce;
+    transaction
+}
+
+/// Point every started Raft engine at the address the others are bound to
+pub fn connect_raft<'a>(engines: impl IntoIterator<Item = &'a RaftConsensusEngine> + Clone) {
+    for engine in engines.clone() {
+        for peer in engines.clone() {
+            engine.register_peer(NodeInfo {
+                id: peer.node_id(),
+                address: peer.listen_address().expect("engine is not started"),
+                rest_address: None,
+            });
+        }
+    }
+}
+
+/// Point every started BFT validator at the address the others are bound to
+pub fn connect_bft<'a>(engines: impl IntoIterator<Item = &'a BftConsensusEngine> + Clone) {
+    for engine in engines.clone() {
+        for peer in engines.clone() {
+            engine.register_peer(NodeInfo {
+                id: peer.node_id(),
+                address: peer.listen_address().expect("engine is not started"),
+                rest_address: None,
+            });
+        }
+    }
+}
+
+/// Wait until one of the running engines reports itself as leader, returning its position
+pub async fn wait_for_leader<'a>(
+    engines: impl IntoIterator<Item = Option<&'a RaftConsensusEngine>> + Clone,
+) -> usize {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while Instant::now() < deadline {
+        for (i, engine) in engines.clone().into_iter().enumerate() {
+            if let Some(engine) = engine {
+                if engine.is_leader().await {
+                    return i;
+                }
+            }
+        }
+        sleep(Duration::from_millis(50)).await;
+    }
+    panic!("No leader elected within the deadline");
+}
+
+/// Check that the next confirmed transaction is `expected`
+pub async fn expect_confirmed(rx: &mut mpsc::Receiver<Transaction>, expected: &Transaction) {
+    let confirmed = timeout(Duration::from_secs(10), rx.recv())
+        .await
+        .expect("timed out waiting for confirmed transaction")
+        .expect("confirmed channel closed");
+    assert_eq!(confirmed.id, expected.id);
+    assert_eq!(confirmed.payload, expected.payload);
+    assert!(confirmed.log_index > 0);
+}
@@ -0,0 +1,171 @@
+mod common;
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use ethereum_types::H256;
+use mp_common::block::Block;
+use mp_common::types::Transaction;
+use mp_consensus::config::{BlockConfig, ConsensusConfig, NodeInfo};
+use mp_consensus::raft::RaftConsensusEngine;
+use mp_consensus::ConsensusEngine;
+use mp_poc::mock::MockPoC;
+use tokio::time::{sleep, Instant};
+
+use common::{connect_raft, loopback_nodes, raft_config, test_transaction, wait_for_leader};
+
+/// Blocks hold two transactions, the timer seals the ones left over
+fn cluster_config(node_id: u64, nodes: &[NodeInfo], log_path: &Path) -> ConsensusConfig {
+    ConsensusConfig {
+        block: BlockConfig {
+            interval: 200,
+            max_transactions: 2,
+        },
+        ..raft_config(node_id, nodes, log_path)
+    }
+}
+
+/// Wait until the engine has sealed blocks up to `height`
+async fn wait_for_height(engine: &RaftConsensusEngine, height: u64) -> Vec<Block> {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while engine.blocks().latest_height() < Some(height) {
+        assert!(Instant::now() < deadline, "block {} was not sealed", height);
+        sleep(Duration::from_millis(50)).await;
+    }
+    engine.blocks().range(1, usize::MAX)
+}
+
+/// Check that the blocks form a chain starting at `parent`
+fn assert_chain(blocks: &[Block], mut parent: H256) {
+    for block in blocks {
+        assert_eq!(block.header.parent_hash, parent);
+        block.verify().unwrap();
+        parent = block.hash();
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_blocks_are_sealed_identically_on_every_node() {
+    let nodes = loopback_nodes(1..=3);
+
+    let data_dir = tempfile::tempdir().unwrap();
+    let mut engines = Vec::new();
+    for node in &nodes {
+        let log_path = data_dir.path().join(format!("node{}", node.id));
+        let mut engine =
+            RaftConsensusEngine::new(cluster_config(node.id, &nodes, &log_path), None).unwrap();
+        engine.start().await.unwrap();
+        engines.push(engine);
+    }
+    connect_raft(&engines);
+    let leader = wait_for_leader(engines.iter().map(Some)).await;
+
+    // Two transactions fill a block, the third one is sealed by the leader's timer
+    let transactions: Vec<Transaction> = ["first", "second", "third"]
+        .iter()
+        .map(|payload| test_transaction(payload))
+        .collect();
+    for tx in &transactions {
+        engines[leader]
+            .submit_transaction(tx.clone())
+            .await
+            .unwrap();
+    }
+
+    let expected = wait_for_height(&engines[leader], 2).await;
+    assert_eq!(expected.len(), 2);
+    assert_eq!(expected[0].header.height, 1);
+    assert_eq!(expected[0].transactions.len(), 2);
+    assert_eq!(expected[1].transactions.len(), 1);
+    let ids: Vec<_> = expected
+        .iter()
+        .flat_map(|block| block.transactions.iter().map(|tx| tx.id))
+        .collect();
+    assert_eq!(ids, transactions.iter().map(|tx| tx.id).collect::<Vec<_>>());
+    assert_chain(&expected, H256::zero());
+
+    for engine in &engines {
+        let blocks = wait_for_height(engine, 2).await;
+        let hashes: Vec<H256> = blocks.iter().map(Block::hash).collect();
+        assert_eq!(hashes, expected.iter().map(Block::hash).collect::<Vec<_>>());
+    }
+
+    for engine in engines.iter_mut() {
+        engine.stop().await.unwrap();
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_signed_blocks_survive_restart() {
+    let nodes = loopback_nodes([1]);
+    let data_dir = tempfile::tempdir().unwrap();
+    let config = cluster_config(1, &nodes, data_dir.path());
+    let poc = Arc::new(MockPoC::new());
+
+    let mut engine = RaftConsensusEngine::new(config.clone(), None).unwrap();
+    let signer = poc.clone();
+    engine.set_block_signer(Arc::new(move |msg: &[u8]| signer.sign_aggregate(msg)));
+    engine.start().await.unwrap();
+    wait_for_leader([Some(&engine)]).await;
+    for payload in ["first", "second"] {
+        engine
+            .submit_transaction(test_transaction(payload))
+            .await
+            .unwrap();
+    }
+    let before = wait_for_height(&engine, 1).await;
+    assert!(before[0].poc.is_some());
+    engine.stop().await.unwrap();
+    drop(engine);
+
+    // The chain is reloaded from disk and continues from the last block
+    let mut engine = RaftConsensusEngine::new(config, None).unwrap();
+    assert_eq!(engine.blocks().latest_height(), Some(1));
+    engine.start().await.unwrap();
+    wait_for_leader([Some(&engine)]).await;
+    engine
+        .submit_transaction(test_transaction("third"))
+        .await
+        .unwrap();
+
+    let blocks = wait_for_height(&engine, 2).await;
+    assert_eq!(blocks[0].hash(), before[0].hash());
+    assert_chain(&blocks, H256::zero());
+
+    engine.stop().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_transactions_committed_again_are_skipped() {
+    let nodes = loopback_nodes([1]);
+    let data_dir = tempfile::tempdir().unwrap();
+    let mut engine =
+        RaftConsensusEngine::new(cluster_config(1, &nodes, data_dir.path()), None).unwrap();
+    let mut confirmed = engine.get_confirmed_tx_channel().await;
+    engine.start().await.unwrap();
+    wait_for_leader([Some(&engine)]).await;
+
+    // A retry of a commit that went unacknowledged reaches the log twice
+    let first = test_transaction("first");
+    let second = test_transaction("second");
+    for tx in [&first, &first, &second] {
+        engine.submit_transaction(tx.clone()).await.unwrap();
+    }
+
+    for expected in [&first, &second] {
+        assert_eq!(confirmed.recv().await.unwrap().id, expected.id);
+    }
+    assert!(confirmed.try_recv().is_err());
+
+    // The timer may seal the first one alone, either way it is sealed once
+    let blocks = wait_for_height(&engine, 1).await;
+    let ids: Vec<_> = blocks
+        .iter()
+        .flat_map(|block| block.transactions.iter().map(|tx| tx.id))
+        .collect();
+    assert_eq!(ids[0], first.id);
+    assert!(!ids[1..].contains(&first.id));
+
+    engine.stop().await.unwrap();
+}
@@ -0,0 +1,238 @@
+mod common;
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use mp_consensus::config::NodeInfo;
+use mp_consensus::network::{RaftRequest, RaftResponse};
+use mp_consensus::raft::RaftConsensusEngine;
+use mp_consensus::storage::RaftCommand;
+use mp_consensus::ConsensusEngine;
+use mp_state::diff::StateOperation;
+use mp_state::StateStorage;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpSocket;
+use tokio::time::{sleep, Instant};
+
+use common::{
+    connect_raft, expect_confirmed, loopback_nodes, raft_config, sqlite_state_storage,
+    test_transaction, wait_for_leader,
+};
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_three_node_replication_and_failover() {
+    let nodes = loopback_nodes(1..=3);
+
+    let data_dir = tempfile::tempdir().unwrap();
+    let mut engines = Vec::new();
+    let mut receivers = Vec::new();
+    for node in &nodes {
+        let log_path = data_dir.path().join(format!("node{}", node.id));
+        let mut engine =
+            RaftConsensusEngine::new(raft_config(node.id, &nodes, &log_path), None).unwrap();
+        receivers.push(engine.get_confirmed_tx_channel().await);
+        engine.start().await.unwrap();
+        engines.push(Some(engine));
+    }
+    connect_raft(engines.iter().flatten());
+
+    // A transaction committed by the leader is applied on every node
+    let leader = wait_for_leader(engines.iter().map(Option::as_ref)).await;
+    let tx = test_transaction("first");
+    engines[leader]
+        .as_ref()
+        .unwrap()
+        .submit_transaction(tx.clone())
+        .await
+        .unwrap();
+    for rx in receivers.iter_mut() {
+        expect_confirmed(rx, &tx).await;
+    }
+
+    // Writes submitted on a follower are forwarded to the leader
+    let follower = (leader + 1) % nodes.len();
+    let tx = test_transaction("forwarded");
+    engines[follower]
+        .as_ref()
+        .unwrap()
+        .submit_transaction(tx.clone())
+        .await
+        .unwrap();
+    for rx in receivers.iter_mut() {
+        expect_confirmed(rx, &tx).await;
+    }
+
+    // Kill the leader, one of the remaining nodes takes over and keeps committing
+    engines[leader].take().unwrap().stop().await.unwrap();
+    let new_leader = wait_for_leader(engines.iter().map(Option::as_ref)).await;
+    assert_ne!(new_leader, leader);
+
+    let tx = test_transaction("second");
+    engines[new_leader]
+        .as_ref()
+        .unwrap()
+        .submit_transaction(tx.clone())
+        .await
+        .unwrap();
+    for (i, rx) in receivers.iter_mut().enumerate() {
+        if i != leader {
+            expect_confirmed(rx, &tx).await;
+        }
+    }
+
+    for engine in engines.iter_mut().filter_map(Option::take) {
+        let mut engine = engine;
+        engine.stop().await.unwrap();
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_state_changes_committed_from_follower() {
+    let nodes = loopback_nodes(1..=3);
+
+    let data_dir = tempfile::tempdir().unwrap();
+    let mut engines = Vec::new();
+    let mut storages: Vec<Arc<dyn StateStorage>> = Vec::new();
+    for node in &nodes {
+        let node_dir = data_dir.path().join(format!("node{}", node.id));
+        let storage = sqlite_state_storage(&node_dir);
+        let mut engine = RaftConsensusEngine::new(
+            raft_config(node.id, &nodes, &node_dir.join("raft")),
+            Some(storage.clone()),
+        )
+        .unwrap();
+        engine.start().await.unwrap();
+        storages.push(storage);
+        engines.push(Some(engine));
+    }
+
+    connect_raft(engines.iter().flatten());
+
+    // State changes of an execution on a follower are committed through the leader
+    let leader = wait_for_leader(engines.iter().map(Option::as_ref)).await;
+    let follower = (leader + 1) % nodes.len();
+    engines[follower]
+        .as_ref()
+        .unwrap()
+        .state_control()
+        .unwrap()
+        .commit_state_changes(vec![
+            StateOperation::Insert {
+                key: "counter".to_string(),
+                value: "1".to_string(),
+            },
+            StateOperation::Insert {
+                key: "owner".to_string(),
+                value: "alice".to_string(),
+            },
+        ])
+        .await
+        .unwrap();
+
+    // Every node applies them and reaches the same state root
+    let expected = vec![
+        ("counter".to_string(), "1".to_string()),
+        ("owner".to_string(), "alice".to_string()),
+    ];
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while storages
+        .iter()
+        .any(|storage| storage.export_snapshot().unwrap().entries != expected)
+    {
+        assert!(
+            Instant::now() < deadline,
+            "state changes not applied on every node"
+        );
+        sleep(Duration::from_millis(50)).await;
+    }
+    let roots: Vec<String> = storages
+        .iter()
+        .map(|storage| storage.create_checkpoint().unwrap().new_root)
+        .collect();
+    assert!(roots.iter().all(|root| *root == roots[0]));
+    assert_eq!(roots[0], storages[0].get_state_root().unwrap());
+
+    // Every node maps the log entry that carried them to the same root
+    for storage in &storages {
+        assert_eq!(
+            storage.root_at_log_index(u64::MAX).unwrap().as_ref(),
+            Some(&roots[0])
+        );
+    }
+
+    for engine in engines.iter_mut().filter_map(Option::take) {
+        let mut engine = engine;
+        engine.stop().await.unwrap();
+    }
+}
+
+/// Forward `command` to the node at `address` over a connection from `host`
+async fn forward_from(host: Ipv4Addr, address: SocketAddr, command: RaftCommand) -> RaftResponse {
+    let socket = TcpSocket::new_v4().unwrap();
+    socket.bind(SocketAddr::from((host, 0))).unwrap();
+    let mut stream = socket.connect(address).await.unwrap();
+
+    let request = serde_json::to_vec(&RaftRequest::ClientWrite(command)).unwrap();
+    stream
+        .write_all(&(request.len() as u32).to_le_bytes())
+        .await
+        .unwrap();
+    stream.write_all(&request).await.unwrap();
+
+    let mut length = [0u8; 4];
+    stream.read_exact(&mut length).await.unwrap();
+    let mut response = vec![0u8; u32::from_le_bytes(length) as usize];
+    stream.read_exact(&mut response).await.unwrap();
+    serde_json::from_slice(&response).unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_forwarded_writes_are_only_accepted_from_cluster_nodes() {
+    let nodes = loopback_nodes([1]);
+    let data_dir = tempfile::tempdir().unwrap();
+    let storage = sqlite_state_storage(data_dir.path());
+    let mut engine = RaftConsensusEngine::new(
+        raft_config(1, &nodes, &data_dir.path().join("raft")),
+        Some(storage.clone()),
+    )
+    .unwrap();
+    engine.start().await.unwrap();
+    wait_for_leader([Some(&engine)]).await;
+    let address = engine.listen_address().unwrap();
+    let insert = |key: &str| {
+        RaftCommand::ApplyStateChanges(vec![StateOperation::Insert {
+            key: key.to_string(),
+            value: "1".to_string(),
+        }])
+    };
+
+    // A host that is not part of the cluster cannot write
+    let response = forward_from(Ipv4Addr::new(127, 0, 0, 2), address, insert("outsider")).await;
+    assert!(matches!(response, RaftResponse::Error(_)), "{:?}", response);
+
+    // Cluster nodes forward transactions and state changes only
+    let refused = [
+        RaftCommand::SealBlock(chrono::Utc::now()),
+        RaftCommand::AddNode(NodeInfo {
+            id: 9,
+            address: SocketAddr::from((Ipv4Addr::LOCALHOST, 9)),
+            rest_address: None,
+        }),
+    ];
+    for command in refused {
+        let response = forward_from(Ipv4Addr::LOCALHOST, address, command).await;
+        assert!(matches!(response, RaftResponse::Error(_)), "{:?}", response);
+    }
+    let response = forward_from(Ipv4Addr::LOCALHOST, address, insert("member")).await;
+    assert!(
+        matches!(response, RaftResponse::ClientWrite(_)),
+        "{:?}",
+        response
+    );
+
+    let entries = storage.export_snapshot().unwrap().entries;
+    assert_eq!(entries, vec![("member".to_string(), "1".to_string())]);
+
+    engine.stop().await.unwrap();
+}
@@ -0,0 +1,107 @@
+mod common;
+
+use mp_consensus::config::NodeInfo;
+use mp_consensus::raft::RaftConsensusEngine;
+use mp_consensus::ConsensusEngine;
+
+use common::{
+    connect_raft, expect_confirmed, loopback_nodes, raft_config, test_transaction, wait_for_leader,
+};
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_add_promote_and_remove_node() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let nodes = loopback_nodes(1..=3);
+
+    let mut engines = Vec::new();
+    let mut receivers = Vec::new();
+    for node in &nodes {
+        let log_path = data_dir.path().join(format!("node{}", node.id));
+        let mut engine =
+            RaftConsensusEngine::new(raft_config(node.id, &nodes, &log_path), None).unwrap();
+        receivers.push(engine.get_confirmed_tx_channel().await);
+        engine.start().await.unwrap();
+        engines.push(engine);
+    }
+    connect_raft(&engines);
+    let leader = wait_for_leader(engines.iter().map(Some)).await;
+    let tx = test_transaction("before-join");
+    engines[leader]
+        .submit_transaction(tx.clone())
+        .await
+        .unwrap();
+
+    // A new node knows the existing cluster and waits to be added
+    let mut joining_nodes: Vec<NodeInfo> = engines
+        .iter()
+        .map(|engine| NodeInfo {
+            id: engine.node_id(),
+            address: engine.listen_address().unwrap(),
+            rest_address: None,
+        })
+        .collect();
+    joining_nodes.extend(loopback_nodes([4]));
+    let mut config = raft_config(4, &joining_nodes, &data_dir.path().join("node4"));
+    config.raft.as_mut().unwrap().join = true;
+    let mut engine = RaftConsensusEngine::new(config, None).unwrap();
+    let mut new_rx = engine.get_confirmed_tx_channel().await;
+    engine.start().await.unwrap();
+    let new_node = NodeInfo {
+        id: 4,
+        address: engine.listen_address().unwrap(),
+        rest_address: None,
+    };
+    engines.push(engine);
+
+    // Membership changes are refused by followers
+    let follower = (leader + 1) % nodes.len();
+    let follower_membership = engines[follower].membership().unwrap();
+    assert!(follower_membership
+        .add_learner(new_node.clone())
+        .await
+        .is_err());
+
+    // The learner receives the existing log, including entries from before it joined
+    let membership = engines[leader].membership().unwrap();
+    membership.add_learner(new_node.clone()).await.unwrap();
+    expect_confirmed(&mut new_rx, &tx).await;
+    assert!(!membership.status().await.unwrap().voters.contains(&4));
+
+    membership.promote_voter(4).await.unwrap();
+    let status = membership.status().await.unwrap();
+    assert_eq!(status.voters, vec![1, 2, 3, 4]);
+    assert!(status.nodes.iter().any(|n| n.id == 4));
+
+    // Every node learned the new member's address through the log
+    let follower_status = follower_membership.status().await.unwrap();
+    assert!(follower_status
+        .nodes
+        .iter()
+        .any(|n| n.id == 4 && n.address == new_node.address));
+
+    let tx = test_transaction("after-promote");
+    engines[leader]
+        .submit_transaction(tx.clone())
+        .await
+        .unwrap();
+    expect_confirmed(&mut new_rx, &tx).await;
+
+    // Retire one of the original followers
+    let retired = nodes[follower].id;
+    membership.remove_voter(retired).await.unwrap();
+    let status = membership.status().await.unwrap();
+    assert!(!status.voters.contains(&retired));
+    assert_eq!(status.voters.len(), 3);
+    assert!(membership.remove_voter(retired).await.is_err());
+
+    let tx = test_transaction("after-remove");
+    engines[leader]
+        .submit_transaction(tx.clone())
+        .await
+        .unwrap();
+    expect_confirmed(&mut new_rx, &tx).await;
+
+    for mut engine in engines {
+        engine.stop().await.unwrap();
+    }
+}
@@ -0,0 +1,345 @@
+mod common;
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_raft::RaftStorage;
+use mp_common::types::{Transaction, TransactionStatus};
+use mp_consensus::config::ConsensusConfig;
+use mp_consensus::network::peer_addresses;
+use mp_consensus::raft::RaftConsensusEngine;
+use mp_consensus::storage::{RaftCommand, RaftStore};
+use mp_consensus::ConsensusEngine;
+use mp_state::diff::StateOperation;
+use mp_state::StateStorage;
+use tokio::sync::mpsc;
+use tokio::time::{sleep, timeout, Instant};
+
+use common::{
+    call_transaction, loopback_nodes, raft_config, sqlite_state_storage, test_transaction,
+};
+
+/// A node alone in its cluster, elected faster than in a cluster
+fn single_node_config(log_path: &Path, snapshot_interval: u64) -> ConsensusConfig {
+    let mut config = raft_config(1, &loopback_nodes([1]), log_path);
+    let raft = config.raft.as_mut().unwrap();
+    raft.election_timeout_min = 150;
+    raft.election_timeout_max = 300;
+    raft.snapshot_interval = snapshot_interval;
+    config
+}
+
+async fn start_engine(
+    config: ConsensusConfig,
+    state_storage: Option<Arc<dyn StateStorage>>,
+) -> (RaftConsensusEngine, mpsc::Receiver<Transaction>) {
+    let mut engine = RaftConsensusEngine::new(config, state_storage).unwrap();
+    let rx = engine.get_confirmed_tx_channel().await;
+    engine.start().await.unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !engine.is_leader().await {
+        assert!(Instant::now() < deadline, "node did not become leader");
+        sleep(Duration::from_millis(50)).await;
+    }
+    (engine, rx)
+}
+
+async fn submit_confirmed(
+    engine: &RaftConsensusEngine,
+    rx: &mut mpsc::Receiver<Transaction>,
+    payload: &str,
+) -> u64 {
+    let tx = test_transaction(payload);
+    engine.submit_transaction(tx.clone()).await.unwrap();
+    let confirmed = timeout(Duration::from_secs(5), rx.recv())
+        .await
+        .expect("timed out waiting for confirmed transaction")
+        .expect("confirmed channel closed");
+    assert_eq!(confirmed.id, tx.id);
+    confirmed.log_index
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_log_survives_restart() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let config = single_node_config(data_dir.path(), 10000);
+
+    let (mut engine, mut rx) = start_engine(config.clone(), None).await;
+    let mut last_index = 0;
+    for i in 0..3 {
+        last_index = submit_confirmed(&engine, &mut rx, &format!("tx-{}", i)).await;
+    }
+    let term = engine.metrics().unwrap().borrow().current_term;
+    engine.stop().await.unwrap();
+    drop(engine);
+
+    // The restarted node recovers its log and term and does not apply entries twice
+    let (mut engine, mut rx) = start_engine(config, None).await;
+    assert_eq!(engine.metrics().unwrap().borrow().current_term, term);
+    assert!(engine.metrics().unwrap().borrow().last_log_index > last_index);
+
+    let index = submit_confirmed(&engine, &mut rx, "after-restart").await;
+    assert!(index > last_index);
+    assert!(rx.try_recv().is_err());
+
+    engine.stop().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_replayed_nonces_are_refused_when_applied() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let config = single_node_config(data_dir.path(), 10000);
+
+    let (mut engine, mut rx) = start_engine(config.clone(), None).await;
+    let submit = |tx: Transaction| {
+        let engine = &engine;
+        async move { engine.submit_transaction(tx).await.unwrap().status }
+    };
+    let first = call_transaction(Some("alice"), Some(0));
+    assert_eq!(submit(first.clone()).await, TransactionStatus::Success);
+    assert_eq!(rx.recv().await.unwrap().id, first.id);
+
+    // Another transaction with a spent nonce, or a call without a sender, is not confirmed
+    let refused = [
+        call_transaction(Some("alice"), Some(0)),
+        call_transaction(Some("alice"), None),
+        call_transaction(None, None),
+    ];
+    for tx in refused {
+        assert_eq!(submit(tx).await, TransactionStatus::Error);
+    }
+    let next = call_transaction(Some("alice"), Some(1));
+    assert_eq!(submit(next.clone()).await, TransactionStatus::Success);
+    assert_eq!(rx.recv().await.unwrap().id, next.id);
+    engine.stop().await.unwrap();
+    drop(engine);
+
+    // The spent nonces are kept across restarts
+    let (mut engine, mut rx) = start_engine(config, None).await;
+    let replay = call_transaction(Some("alice"), Some(1));
+    let status = engine.submit_transaction(replay).await.unwrap().status;
+    assert_eq!(status, TransactionStatus::Error);
+    let next = call_transaction(Some("alice"), Some(2));
+    engine.submit_transaction(next.clone()).await.unwrap();
+    assert_eq!(rx.recv().await.unwrap().id, next.id);
+    assert!(rx.try_recv().is_err());
+
+    engine.stop().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_log_compaction_into_snapshot() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let log_path = data_dir.path().join("raft");
+    let state_storage = sqlite_state_storage(data_dir.path());
+    let config = single_node_config(&log_path, 5);
+
+    let (mut engine, mut rx) = start_engine(config.clone(), Some(state_storage.clone())).await;
+    let mut last_index = 0;
+    for i in 0..12 {
+        last_index = submit_confirmed(&engine, &mut rx, &format!("tx-{}", i)).await;
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !log_path.join("snapshot.json").exists() {
+        assert!(Instant::now() < deadline, "no snapshot was taken");
+        sleep(Duration::from_millis(50)).await;
+    }
+    engine.stop().await.unwrap();
+    drop(engine);
+
+    // The snapshot holds the state store and the log only keeps what it does not cover
+    let snapshot: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(log_path.join("snapshot.json")).unwrap()).unwrap();
+    let data: Vec<u8> = serde_json::from_value(snapshot["data"].clone()).unwrap();
+    let data: serde_json::Value = serde_json::from_slice(&data).unwrap();
+    assert_eq!(
+        data["state"]["root"].as_str().unwrap(),
+        state_storage.create_checkpoint().unwrap().new_root
+    );
+    let log_lines = std::fs::read_to_string(log_path.join("log.jsonl"))
+        .unwrap()
+        .lines()
+        .count() as u64;
+    assert!(log_lines < last_index);
+
+    let (mut engine, mut rx) = start_engine(config, Some(state_storage)).await;
+    let index = submit_confirmed(&engine, &mut rx, "after-restart").await;
+    assert!(index > last_index);
+    assert!(rx.try_recv().is_err());
+
+    engine.stop().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_state_revert_through_log() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let state_storage = sqlite_state_storage(data_dir.path());
+    let config = single_node_config(&data_dir.path().join("raft"), 10000);
+    let (mut engine, _rx) = start_engine(config, Some(state_storage.clone())).await;
+
+    let insert = |value: &str| StateOperation::Insert {
+        key: "contract".to_string(),
+        value: value.to_string(),
+    };
+    let good = state_storage
+        .apply_operations(vec![insert("good")])
+        .unwrap()
+        .new_root;
+    state_storage
+        .apply_operations(vec![insert("corrupted")])
+        .unwrap();
+
+    let state_control = engine.state_control().unwrap();
+    let index = engine.metrics().unwrap().borrow().last_applied;
+    state_control.revert_state(good.clone()).await.unwrap();
+    assert!(engine.metrics().unwrap().borrow().last_applied > index);
+    assert_eq!(state_storage.create_checkpoint().unwrap().new_root, good);
+    assert_eq!(
+        state_storage.export_snapshot().unwrap().entries,
+        vec![("contract".to_string(), "good".to_string())]
+    );
+
+    // Reverting to a root the state never went through fails on every node alike
+    assert!(state_control
+        .revert_state("unknown".to_string())
+        .await
+        .is_err());
+    assert_eq!(state_storage.create_checkpoint().unwrap().new_root, good);
+
+    engine.stop().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_proof_against_sealed_block() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let state_storage = sqlite_state_storage(data_dir.path());
+    let mut config = single_node_config(&data_dir.path().join("raft"), 10000);
+    config.block.max_transactions = 1;
+    let (mut engine, mut rx) = start_engine(config, Some(state_storage.clone())).await;
+    let state_control = engine.state_control().unwrap();
+    let insert = |value: &str| StateOperation::Insert {
+        key: "contract".to_string(),
+        value: value.to_string(),
+    };
+
+    state_control
+        .commit_state_changes(vec![insert("sealed")])
+        .await
+        .unwrap();
+    submit_confirmed(&engine, &mut rx, "seal").await;
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let block = loop {
+        if let Some(block) = engine.blocks().get(1) {
+            break block;
+        }
+        assert!(Instant::now() < deadline, "block was not sealed");
+        sleep(Duration::from_millis(50)).await;
+    };
+    let state_root = &block.header.state_root;
+    assert_eq!(*state_root, state_storage.get_state_root().unwrap());
+
+    // The state moves on, the block keeps proving what it sealed
+    state_control
+        .commit_state_changes(vec![insert("later")])
+        .await
+        .unwrap();
+    let proof = state_storage.get_proof_at("contract", state_root).unwrap();
+    assert_eq!(proof.value.as_deref(), Some("sealed"));
+    proof.verify_against(state_root).unwrap();
+    state_storage
+        .get_proof_at("missing", state_root)
+        .unwrap()
+        .verify_against(state_root)
+        .unwrap();
+
+    let current = state_storage.get_proof("contract").unwrap();
+    current.verify().unwrap();
+    assert!(current.verify_against(state_root).is_err());
+
+    engine.stop().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_state_changes_not_applied_twice_after_crash() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let log_path = data_dir.path().join("raft");
+    let state_storage = sqlite_state_storage(data_dir.path());
+    let (tx_sender, _rx) = mpsc::channel(10);
+    let open = || {
+        RaftStore::open(
+            1,
+            &log_path,
+            Some(state_storage.clone()),
+            peer_addresses(&[]),
+            tx_sender.clone(),
+            Default::default(),
+        )
+        .unwrap()
+    };
+    let insert = |value: &str| {
+        RaftCommand::ApplyStateChanges(vec![StateOperation::Insert {
+            key: "contract".to_string(),
+            value: value.to_string(),
+        }])
+    };
+
+    let store = open();
+    store
+        .apply_entry_to_state_machine(&1, &insert("first"))
+        .await
+        .unwrap();
+    let first = state_storage.get_state_root().unwrap();
+    let state_machine = std::fs::read(log_path.join("state_machine.json")).unwrap();
+    store
+        .apply_entry_to_state_machine(&2, &insert("second"))
+        .await
+        .unwrap();
+    let second = state_storage.get_state_root().unwrap();
+    drop(store);
+
+    // Crash after the state was written but before the entry was recorded as applied
+    let mut state_machine: serde_json::Value = serde_json::from_slice(&state_machine).unwrap();
+    state_machine["pending_state_change"] = serde_json::json!({
+        "index": 2,
+        "prev_root": first,
+    });
+    std::fs::write(
+        log_path.join("state_machine.json"),
+        serde_json::to_vec(&state_machine).unwrap(),
+    )
+    .unwrap();
+
+    let store = open();
+    assert_eq!(store.last_applied_log().await, 1);
+    store
+        .replicate_to_state_machine(&[(&2, &insert("second"))])
+        .await
+        .unwrap();
+    assert_eq!(store.last_applied_log().await, 2);
+    assert_eq!(state_storage.get_state_root().unwrap(), second);
+    assert_eq!(
+        state_storage
+            .diffs_since(&first, 10)
+            .unwrap()
+            .unwrap()
+            .len(),
+        1
+    );
+
+    // Entries after the recovered one are applied as usual
+    store
+        .replicate_to_state_machine(&[(&3, &insert("third"))])
+        .await
+        .unwrap();
+    assert_eq!(
+        state_storage
+            .diffs_since(&first, 10)
+            .unwrap()
+            .unwrap()
+            .len(),
+        2
+    );
+}
@@ -33,7 +33,7 @@ use uuid::Uuid;
 
 use bollard::container::{
     Config, CreateContainerOptions, InspectContainerOptions, ListContainersOptions,
-    RestartContainerOptions, StartContainerOptions,
+    RestartContainerOptions, StartContainerOptions, StatsOptions,
 };
 use dstack::compose::DockerCompose;
 use utils::{parse_container_inspect_response_port, port_bindings, tcp_port};
@@ -41,7 +41,7 @@ use utils::{parse_container_inspect_response_port, port_bindings, tcp_port};
 use crate::config::default_tappd_host;
 use crate::utils::string_to_uuid;
 use crate::ContainerDetail;
-use crate::{ContainerEnvironment, ContainerInfo, ContainerStatus};
+use crate::{ContainerEnvironment, ContainerInfo, ContainerStats, ContainerStatus};
 
 pub fn from_config(config: DockerCompose) -> Vec<(String, Config<String>)> {
     let mut configs = Vec::new();
@@ -609,6 +609,36 @@ impl ContainerEnvironment for DockerContainerEnvironment {
     async fn get_container_status(&self, vm_id: &Uuid) -> anyhow::Result<ContainerStatus> {
         self.get_container_status(vm_id).await
     }
+
+    async fn container_stats(&self, vm_id: &Uuid) -> anyhow::Result<Option<ContainerStats>> {
+        let container_name = {
+            let containers = self.containers.lock().await;
+            let vm_info = containers
+                .get(vm_id)
+                .ok_or(anyhow!("Contract {:?} not found", uuid_to_h128(vm_id)))?;
+            Self::generate_container_name(&vm_info.info.name)
+        };
+
+        let options = StatsOptions {
+            stream: false,
+            one_shot: true,
+        };
+        let stats = self
+            .docker
+            .stats(&container_name, Some(options))
+            .try_next()
+            .await?
+            .ok_or(anyhow!("No stats reported for container {}", container_name))?;
+
+        Ok(Some(ContainerStats {
+            cpu_time_ns: stats.cpu_stats.cpu_usage.total_usage,
+            memory_bytes: stats
+                .memory_stats
+                .max_usage
+                .or(stats.memory_stats.usage)
+                .unwrap_or_default(),
+        }))
+    }
 }
 
 #[async_trait::async_trait]
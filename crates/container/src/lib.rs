@@ -22,6 +22,7 @@ use std::fmt::Debug;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
 use tracing::{error, info, warn};
 use uuid::Uuid;
 
@@ -72,13 +73,28 @@ pub enum ContainerStatus {
     Error(String),
 }
 
+/// Resource counters of a running container
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContainerStats {
+    /// Total CPU time consumed since the container started, in nanoseconds
+    pub cpu_time_ns: u64,
+    /// Peak memory usage in bytes where reported, the current usage otherwise
+    pub memory_bytes: u64,
+}
+
 /// Container environment trait defining common operations for container management
 #[async_trait::async_trait]
 pub trait ContainerEnvironment: Send + Sync + Debug + 'static {
     async fn create_container(&self, req: AgentConfiguration) -> Result<ContainerInfo>;
 
     /// Execute a transaction in the container environment
-    async fn execute_transaction(&self, transaction: Transaction) -> Result<Transaction> {
+    ///
+    /// Calls to a contract are aborted with an error once `cancel` is cancelled.
+    async fn execute_transaction(
+        &self,
+        transaction: Transaction,
+        cancel: CancellationToken,
+    ) -> Result<Transaction> {
         info!("[DOCKER] Executing transaction: {:?}", transaction.id);
         match &transaction.tx_type {
             TransactionType::Request(id, path) => {
@@ -94,10 +110,12 @@ pub trait ContainerEnvironment: Send + Sync + Debug + 'static {
                     transaction.payload.clone(),
                     transaction.method.clone(),
                     transaction.header.clone(),
+                    &cancel,
                 )
                 .await
                 {
                     Ok(response) => handle_response(&transaction, response),
+                    Err(e) if cancel.is_cancelled() => Err(e),
                     Err(e) => {
                         error!("[DOCKER] Failed to execute API request: {}", e);
                         handle_internal_error(&transaction, e)
@@ -194,6 +212,11 @@ pub trait ContainerEnvironment: Send + Sync + Debug + 'static {
 
     /// Get all running containers
     async fn get_running_containers(&self) -> Result<Vec<ContainerDetail>>;
+
+    /// Get the resource counters of a container, `None` if the environment does not report them
+    async fn container_stats(&self, _vm_id: &Uuid) -> Result<Option<ContainerStats>> {
+        Ok(None)
+    }
 }
 
 /// Create a new container environment based on the configuration
@@ -238,6 +261,7 @@ async fn execute_api_request(
     payload: Vec<u8>,
     method: Method,
     headers: HeaderMap<HeaderValue>,
+    cancel: &CancellationToken,
 ) -> Result<ApiResponse> {
     // Log detailed request information
     info!("[DOCKER] ====== API Request Details ======");
@@ -255,13 +279,16 @@ async fn execute_api_request(
     // let target_url = format!("http://{}/{}", base_url, payload.path);
     info!("[DOCKER] Forwarding request to: {}", target_url);
     // Forward request to web2_style contract at port 8080
-    let response = client
+    let request = client
         .request(method, target_url)
         .headers(headers)
         .header("Content-Type", "application/json")
         .body(payload)
-        .send()
-        .await?;
+        .send();
+    let response = tokio::select! {
+        response = request => response?,
+        _ = cancel.cancelled() => return Err(anyhow::anyhow!("API request cancelled")),
+    };
     let status = response.status();
     let headers = response.headers().clone();
     // Log response details0
@@ -270,7 +297,10 @@ async fn execute_api_request(
     info!("[DOCKER] Response Headers: {:?}", headers);
 
     // Get response bytes and log them
-    let response_bytes = response.bytes().await?.to_vec();
+    let response_bytes = tokio::select! {
+        bytes = response.bytes() => bytes?.to_vec(),
+        _ = cancel.cancelled() => return Err(anyhow::anyhow!("API request cancelled")),
+    };
     info!(
         "[DOCKER] Response Body: {}",
         String::from_utf8_lossy(&response_bytes)
@@ -1,3 +1,5 @@
+use mp_executor_engine::handshake;
+use mp_executor_engine::protocol::DEFAULT_MAX_FRAME_SIZE;
 use mp_executor_engine::service::EngineRequest;
 use mp_executor_engine::{ComputerEngineWorker, Method, Params, Status};
 use std::net::SocketAddr;
@@ -8,7 +10,7 @@ use tokio::signal;
 use tokio::time::{sleep, Duration};
 use uuid::Uuid;
 
-async fn start_docker(server_id: usize, addr: SocketAddr) {
+async fn start_docker(server_id: usize, addr: SocketAddr, container_id: Uuid) {
     let listener = TcpListener::bind(addr)
         .await
         .expect("Failed to bind socket");
@@ -19,7 +21,7 @@ async fn start_docker(server_id: usize, addr: SocketAddr) {
 
     loop {
         match listener.accept().await {
-            Ok((stream, client_addr)) => {
+            Ok((mut stream, client_addr)) => {
                 connection_counter += 1;
                 println!(
                     "🔌 [Docker {}] 接受来自 {} 的连接 (第 {} 个连接)",
@@ -29,6 +31,15 @@ async fn start_docker(server_id: usize, addr: SocketAddr) {
                 let server_id = server_id.clone();
                 // 处理客户端连接
                 tokio::spawn(async move {
+                    // 应答节点的握手，帧使用 JSON 编码
+                    if let Err(e) =
+                        handshake::accept(&mut stream, container_id, &[], DEFAULT_MAX_FRAME_SIZE)
+                            .await
+                    {
+                        println!("❌ [Docker {}] 握手失败: {}", server_id, e);
+                        return;
+                    }
+
                     // 模拟服务器处理连接
                     let mut message_counter = 0;
                     let (mut reader, mut writer) = stream.into_split();
@@ -56,9 +67,9 @@ async fn start_docker(server_id: usize, addr: SocketAddr) {
 
                                                 // 创建响应
                                                 let response = Params {
-                                                    id: params.id, // 使用相同ID
-                                                    method: Method::Invoke,
-                                                    status: Status::RUNNING, // 设置为运行状态
+                                                    id: params.id,          // 使用相同ID
+                                                    method: Method::Finish, // 结束本次调用
+                                                    status: Status::STOP,
                                                     data: serde_json::json!({
                                                         "response": format!("✅ [Docker {}] 已收到消息 #{}", server_id, message_counter),
                                                         "server_id": server_id
@@ -188,7 +199,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut i = 1;
     for path in server_ids.iter() {
         let path = path.clone();
-        tasks.push(tokio::spawn(start_docker(i, path.0)));
+        tasks.push(tokio::spawn(start_docker(i, path.0, path.1)));
         i += 1;
     }
 
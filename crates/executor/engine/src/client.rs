@@ -0,0 +1,250 @@
+//! Container side of the engine protocol
+//!
+//! A contract implements [`Contract`] and hands it to [`serve`], which accepts
+//! the node's connections and runs every `Invoke` it receives, answering with
+//! `Finish`. During a call the contract reads and writes its state on the node
+//! through the [`StateClient`] it is given.
+//!
+//! Each connection opens with the handshake, answered as configured in
+//! [`ContainerConfig`].
+
+use std::{
+    collections::HashMap,
+    io,
+    sync::{Arc, Mutex as StdMutex},
+};
+
+use futures::channel::oneshot;
+use log::{error, warn};
+use serde_json::Value;
+use tokio::{
+    net::{tcp::OwnedWriteHalf, TcpListener, TcpStream},
+    sync::Mutex,
+};
+use uuid::Uuid;
+
+use crate::{
+    frame,
+    handshake::{self, Session},
+    protocol::{
+        Capability, Finish, GetState, Invocation, SetState, StateReply, DEFAULT_MAX_FRAME_SIZE,
+    },
+    Method, Params, Status,
+};
+
+/// How the container answers the handshake
+#[derive(Debug, Clone)]
+pub struct ContainerConfig {
+    /// ID the node knows the container by
+    pub container_id: Uuid,
+    /// Optional features the container agrees to if the node proposes them
+    pub capabilities: Vec<Capability>,
+    /// Largest frame the container accepts, in bytes
+    pub max_frame_size: u32,
+}
+
+impl ContainerConfig {
+    /// Container supporting every capability, with the default frame limit
+    pub fn new(container_id: Uuid) -> Self {
+        Self {
+            container_id,
+            capabilities: vec![Capability::ScaleCodec],
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+        }
+    }
+}
+
+/// Error reading or writing the state of a call
+#[derive(Debug, thiserror::Error)]
+pub enum StateError {
+    #[error("Connection to the node failed: {0}")]
+    Io(#[from] io::Error),
+    #[error("The node refused the state access: {0}")]
+    Refused(String),
+    #[error("The connection closed before the node answered")]
+    Closed,
+}
+
+/// Contract served over the engine protocol
+#[async_trait::async_trait]
+pub trait Contract: Send + Sync + 'static {
+    /// Execute a call, reading and writing the contract state through `state`
+    async fn invoke(&self, invocation: Invocation, state: &mut StateClient) -> Finish;
+}
+
+/// Access to the contract state on the node during a call
+///
+/// Keys are the contract's own, the node keeps them apart from the state of
+/// other contracts. Reads see the writes made earlier in the call.
+pub struct StateClient {
+    call: Uuid,
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+    session: Session,
+    replies: Replies,
+}
+
+type Replies = Arc<StdMutex<HashMap<Uuid, oneshot::Sender<StateReply>>>>;
+
+impl StateClient {
+    /// Read a value
+    pub async fn get(&mut self, key: &str) -> Result<Option<String>, StateError> {
+        let request = GetState {
+            key: key.to_string(),
+        };
+        let reply = self
+            .request(Method::Getstate, serde_json::to_value(request).unwrap())
+            .await?;
+        Ok(reply.value)
+    }
+
+    /// Write a value
+    pub async fn set(&mut self, key: &str, value: &str) -> Result<(), StateError> {
+        self.write(key, Some(value.to_string())).await
+    }
+
+    /// Delete a value
+    pub async fn delete(&mut self, key: &str) -> Result<(), StateError> {
+        self.write(key, None).await
+    }
+
+    async fn write(&mut self, key: &str, value: Option<String>) -> Result<(), StateError> {
+        let request = SetState {
+            key: key.to_string(),
+            value,
+        };
+        self.request(Method::Setstate, serde_json::to_value(request).unwrap())
+            .await?;
+        Ok(())
+    }
+
+    async fn request(&mut self, method: Method, data: Value) -> Result<StateReply, StateError> {
+        let (tx, rx) = oneshot::channel();
+        self.replies.lock().unwrap().insert(self.call, tx);
+        let params = Params {
+            id: self.call,
+            method,
+            status: Status::RUNNING,
+            data,
+        };
+        if let Err(e) = self.send(&params).await {
+            self.replies.lock().unwrap().remove(&self.call);
+            return Err(e.into());
+        }
+
+        let reply = rx.await.map_err(|_| StateError::Closed)?;
+        match reply.error {
+            Some(error) => Err(StateError::Refused(error)),
+            None => Ok(reply),
+        }
+    }
+
+    async fn send(&self, params: &Params) -> io::Result<()> {
+        let mut writer = self.writer.lock().await;
+        frame::write_frame(
+            &mut *writer,
+            params,
+            self.session.codec,
+            self.session.peer_max_frame_size,
+        )
+        .await
+    }
+}
+
+/// Accept the node's connections and serve the calls of `contract` on them
+pub async fn serve(
+    listener: TcpListener,
+    config: ContainerConfig,
+    contract: Arc<dyn Contract>,
+) -> io::Result<()> {
+    loop {
+        let (stream, address) = listener.accept().await?;
+        let config = config.clone();
+        let contract = contract.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_connection(stream, &config, contract).await {
+                warn!("Connection from node {} failed: {}", address, e);
+            }
+        });
+    }
+}
+
+/// Serve the calls the node sends over one connection, until it closes
+///
+/// Calls run concurrently, each answered with `Finish` once it is done.
+pub async fn serve_connection(
+    mut stream: TcpStream,
+    config: &ContainerConfig,
+    contract: Arc<dyn Contract>,
+) -> io::Result<()> {
+    let session = handshake::accept(
+        &mut stream,
+        config.container_id,
+        &config.capabilities,
+        config.max_frame_size,
+    )
+    .await?;
+    let (mut reader, writer) = stream.into_split();
+    let writer = Arc::new(Mutex::new(writer));
+    let replies = Replies::default();
+
+    loop {
+        let params =
+            match frame::read_frame(&mut reader, session.codec, config.max_frame_size).await {
+                Ok(params) => params,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            };
+        match params.method {
+            Method::Invoke => {
+                let mut state = StateClient {
+                    call: params.id,
+                    writer: writer.clone(),
+                    session,
+                    replies: replies.clone(),
+                };
+                let contract = contract.clone();
+                tokio::spawn(async move {
+                    let finish = match serde_json::from_value::<Invocation>(params.data) {
+                        Ok(invocation) => contract.invoke(invocation, &mut state).await,
+                        Err(e) => Finish::error(400, format!("Invalid invocation: {}", e)),
+                    };
+                    let mut params = Params {
+                        id: params.id,
+                        method: Method::Finish,
+                        status: Status::STOP,
+                        data: serde_json::to_value(finish).unwrap(),
+                    };
+                    let mut sent = state.send(&params).await;
+                    if let Err(e) = &sent {
+                        if e.kind() == io::ErrorKind::InvalidInput {
+                            // Output over the node's limit, fail the call instead
+                            params.data =
+                                serde_json::to_value(Finish::error(500, e.to_string())).unwrap();
+                            sent = state.send(&params).await;
+                        }
+                    }
+                    if let Err(e) = sent {
+                        error!("Failed to finish call {}: {}", params.id, e);
+                    }
+                });
+            }
+            Method::Getstate | Method::Setstate => {
+                let waiting = replies.lock().unwrap().remove(&params.id);
+                let Some(tx) = waiting else {
+                    warn!("Unexpected state reply for call {}", params.id);
+                    continue;
+                };
+                let reply = serde_json::from_value(params.data).unwrap_or_else(|e| StateReply {
+                    value: None,
+                    error: Some(format!("Invalid state reply: {}", e)),
+                });
+                let _ = tx.send(reply);
+            }
+            Method::Finish => warn!("The node should not finish call {}", params.id),
+        }
+    }
+
+    // Calls still waiting for the node fail with `StateError::Closed`
+    replies.lock().unwrap().clear();
+    Ok(())
+}
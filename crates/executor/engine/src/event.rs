@@ -8,3 +8,15 @@ pub enum Event {
     GetState { remote: Uuid, payload: Vec<u8> },
     Finish { remote: Uuid, payload: Vec<u8> },
 }
+
+impl Event {
+    /// Container the event comes from or goes to
+    pub fn remote(&self) -> Uuid {
+        match self {
+            Event::Invoke { remote, .. }
+            | Event::SetState { remote, .. }
+            | Event::GetState { remote, .. }
+            | Event::Finish { remote, .. } => *remote,
+        }
+    }
+}
@@ -0,0 +1,253 @@
+use std::io;
+
+use codec::{Decode, DecodeLimit, Encode};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{Map, Number, Value};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use uuid::Uuid;
+
+use crate::{protocol::Codec, Method, Params, Status};
+
+/// Deepest nesting of a SCALE-encoded value
+const MAX_DEPTH: u32 = 128;
+
+/// Encode a message as a frame, its body behind a little-endian u32 length
+///
+/// Fails if the frame is larger than `max_frame_size`, the limit of the peer.
+pub fn encode(params: &Params, codec: Codec, max_frame_size: u32) -> io::Result<Vec<u8>> {
+    let data = match codec {
+        Codec::Json => serde_json::to_vec(params).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("序列化数据失败: {}", e))
+        })?,
+        Codec::Scale => ScaleParams::from(params).encode(),
+    };
+    frame(data, max_frame_size)
+}
+
+/// Decode the body of a frame
+pub fn decode(data: &[u8], codec: Codec) -> io::Result<Params> {
+    match codec {
+        Codec::Json => serde_json::from_slice(data).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("解析数据失败: {}", e))
+        }),
+        Codec::Scale => ScaleParams::decode_with_depth_limit(MAX_DEPTH, &mut &data[..])
+            .map(Into::into)
+            .map_err(|e| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("解析数据失败: {}", e))
+            }),
+    }
+}
+
+/// Write a message as one frame and flush it
+pub async fn write_frame<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    params: &Params,
+    codec: Codec,
+    max_frame_size: u32,
+) -> io::Result<()> {
+    writer
+        .write_all(&encode(params, codec, max_frame_size)?)
+        .await?;
+    writer.flush().await
+}
+
+/// Read the next frame and decode its message
+///
+/// Frames larger than `max_frame_size` are refused before they are read.
+pub async fn read_frame<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    codec: Codec,
+    max_frame_size: u32,
+) -> io::Result<Params> {
+    decode(&read_body(reader, max_frame_size).await?, codec)
+}
+
+/// Write a handshake message as a JSON frame
+pub async fn write_json<W: AsyncWrite + Unpin, T: Serialize>(
+    writer: &mut W,
+    message: &T,
+    max_frame_size: u32,
+) -> io::Result<()> {
+    let data =
+        serde_json::to_vec(message).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    writer.write_all(&frame(data, max_frame_size)?).await?;
+    writer.flush().await
+}
+
+/// Read a handshake message from a JSON frame
+pub async fn read_json<R: AsyncRead + Unpin, T: DeserializeOwned>(
+    reader: &mut R,
+    max_frame_size: u32,
+) -> io::Result<T> {
+    let data = read_body(reader, max_frame_size).await?;
+    serde_json::from_slice(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn frame(data: Vec<u8>, max_frame_size: u32) -> io::Result<Vec<u8>> {
+    if data.len() > max_frame_size as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "帧大小 {} 字节超过对方上限 {} 字节",
+                data.len(),
+                max_frame_size
+            ),
+        ));
+    }
+    let mut frame = Vec::with_capacity(4 + data.len());
+    frame.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&data);
+    Ok(frame)
+}
+
+async fn read_body<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    max_frame_size: u32,
+) -> io::Result<Vec<u8>> {
+    let mut length = [0u8; 4];
+    reader.read_exact(&mut length).await?;
+    let length = u32::from_le_bytes(length);
+    if length > max_frame_size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("帧大小 {} 字节超过上限 {} 字节", length, max_frame_size),
+        ));
+    }
+    let mut data = vec![0u8; length as usize];
+    reader.read_exact(&mut data).await?;
+    Ok(data)
+}
+
+/// `Params` as encoded with SCALE
+#[derive(Encode, Decode)]
+struct ScaleParams {
+    id: [u8; 16],
+    method: Method,
+    status: Status,
+    data: ScaleValue,
+}
+
+/// JSON value as encoded with SCALE
+#[derive(Encode, Decode)]
+enum ScaleValue {
+    Null,
+    Bool(bool),
+    Unsigned(u64),
+    Signed(i64),
+    /// Bits of an `f64`
+    Float(u64),
+    String(String),
+    Array(Vec<ScaleValue>),
+    Object(Vec<(String, ScaleValue)>),
+}
+
+impl From<&Params> for ScaleParams {
+    fn from(params: &Params) -> Self {
+        Self {
+            id: params.id.into_bytes(),
+            method: params.method,
+            status: params.status,
+            data: (&params.data).into(),
+        }
+    }
+}
+
+impl From<ScaleParams> for Params {
+    fn from(params: ScaleParams) -> Self {
+        Self {
+            id: Uuid::from_bytes(params.id),
+            method: params.method,
+            status: params.status,
+            data: params.data.into(),
+        }
+    }
+}
+
+impl From<&Value> for ScaleValue {
+    fn from(value: &Value) -> Self {
+        match value {
+            Value::Null => ScaleValue::Null,
+            Value::Bool(value) => ScaleValue::Bool(*value),
+            Value::Number(number) => match (number.as_u64(), number.as_i64()) {
+                (Some(value), _) => ScaleValue::Unsigned(value),
+                (None, Some(value)) => ScaleValue::Signed(value),
+                _ => ScaleValue::Float(number.as_f64().unwrap_or_default().to_bits()),
+            },
+            Value::String(value) => ScaleValue::String(value.clone()),
+            Value::Array(values) => ScaleValue::Array(values.iter().map(Into::into).collect()),
+            Value::Object(values) => ScaleValue::Object(
+                values
+                    .iter()
+                    .map(|(key, value)| (key.clone(), value.into()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl From<ScaleValue> for Value {
+    fn from(value: ScaleValue) -> Self {
+        match value {
+            ScaleValue::Null => Value::Null,
+            ScaleValue::Bool(value) => Value::Bool(value),
+            ScaleValue::Unsigned(value) => Value::Number(value.into()),
+            ScaleValue::Signed(value) => Value::Number(value.into()),
+            ScaleValue::Float(bits) => Number::from_f64(f64::from_bits(bits))
+                .map(Value::Number)
+                .unwrap_or(Value::Null),
+            ScaleValue::String(value) => Value::String(value),
+            ScaleValue::Array(values) => Value::Array(values.into_iter().map(Into::into).collect()),
+            ScaleValue::Object(values) => Value::Object(
+                values
+                    .into_iter()
+                    .map(|(key, value)| (key, value.into()))
+                    .collect::<Map<_, _>>(),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::DEFAULT_MAX_FRAME_SIZE as DEFAULT_MAX;
+
+    fn params(data: Value) -> Params {
+        Params {
+            id: Uuid::new_v4(),
+            method: Method::Setstate,
+            status: Status::RUNNING,
+            data,
+        }
+    }
+
+    // SCALE 编码往返后数据不变
+    #[test]
+    fn test_scale_round_trip() {
+        let data = serde_json::json!({
+            "key": "count",
+            "value": null,
+            "nested": [true, 1, -2, 2.5, "文本", {"a": []}],
+        });
+        let sent = params(data.clone());
+        let frame = encode(&sent, Codec::Scale, DEFAULT_MAX).unwrap();
+        let received = decode(&frame[4..], Codec::Scale).unwrap();
+        assert_eq!(received.id, sent.id);
+        assert_eq!(received.method, Method::Setstate);
+        assert_eq!(received.data, data);
+    }
+
+    // 超过上限的帧既不发送也不读取
+    #[tokio::test]
+    async fn test_oversize_frames_are_refused() {
+        let sent = params(Value::String("x".repeat(64)));
+        let error = encode(&sent, Codec::Json, 32).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+
+        let frame = encode(&sent, Codec::Json, DEFAULT_MAX).unwrap();
+        let error = read_frame(&mut &frame[..], Codec::Json, 32)
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
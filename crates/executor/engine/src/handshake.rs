@@ -0,0 +1,125 @@
+//! Opening of a connection, see [`Hello`]
+
+use std::{io, time::Duration};
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use uuid::Uuid;
+
+use crate::{
+    frame,
+    protocol::{Capability, Codec, Hello, PROTOCOL_VERSION},
+};
+
+/// Time to wait for the hello of the other side
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What the handshake settled for a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Session {
+    /// Encoding of the frames
+    pub codec: Codec,
+    /// Largest frame the other side accepts, in bytes
+    pub peer_max_frame_size: u32,
+}
+
+/// Open a connection as the node, to the container `container`
+///
+/// Proposes `codec`, the container may fall back to JSON. Fails if the
+/// container is another one or speaks another protocol version.
+pub async fn connect<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    container: Uuid,
+    codec: Codec,
+    max_frame_size: u32,
+) -> io::Result<Session> {
+    let capabilities = match codec {
+        Codec::Json => vec![],
+        Codec::Scale => vec![Capability::ScaleCodec],
+    };
+    let hello = Hello {
+        version: PROTOCOL_VERSION,
+        container_id: container,
+        capabilities,
+        max_frame_size,
+    };
+
+    let answer: Hello = with_timeout(async {
+        frame::write_json(stream, &hello, max_frame_size).await?;
+        frame::read_json(stream, max_frame_size).await
+    })
+    .await?;
+    if answer.version != PROTOCOL_VERSION {
+        return Err(invalid(format!(
+            "容器的协议版本为 {}，节点为 {}",
+            answer.version, PROTOCOL_VERSION
+        )));
+    }
+    if answer.container_id != container {
+        return Err(invalid(format!(
+            "连接到的容器为 {}，期望 {}",
+            answer.container_id, container
+        )));
+    }
+
+    Ok(Session {
+        codec: session_codec(&answer.capabilities),
+        peer_max_frame_size: answer.max_frame_size,
+    })
+}
+
+/// Answer the node's hello as the container `container`
+///
+/// Agrees to the capabilities proposed by the node that are in `supported`.
+/// Fails after answering if the node speaks another protocol version.
+pub async fn accept<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    container: Uuid,
+    supported: &[Capability],
+    max_frame_size: u32,
+) -> io::Result<Session> {
+    let hello: Hello = with_timeout(frame::read_json(stream, max_frame_size)).await?;
+    let capabilities: Vec<_> = hello
+        .capabilities
+        .iter()
+        .filter(|capability| **capability != Capability::Unknown && supported.contains(capability))
+        .copied()
+        .collect();
+    let answer = Hello {
+        version: PROTOCOL_VERSION,
+        container_id: container,
+        capabilities,
+        max_frame_size,
+    };
+    with_timeout(frame::write_json(stream, &answer, hello.max_frame_size)).await?;
+    if hello.version != PROTOCOL_VERSION {
+        return Err(invalid(format!(
+            "节点的协议版本为 {}，容器为 {}",
+            hello.version, PROTOCOL_VERSION
+        )));
+    }
+
+    Ok(Session {
+        codec: session_codec(&answer.capabilities),
+        peer_max_frame_size: hello.max_frame_size,
+    })
+}
+
+fn session_codec(agreed: &[Capability]) -> Codec {
+    if agreed.contains(&Capability::ScaleCodec) {
+        Codec::Scale
+    } else {
+        Codec::Json
+    }
+}
+
+async fn with_timeout<T>(
+    handshake: impl std::future::Future<Output = io::Result<T>>,
+) -> io::Result<T> {
+    tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake)
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "握手超时"))?
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -1,5 +1,8 @@
+use event::Event;
 use futures::{channel::mpsc, StreamExt};
-use log::error;
+use handshake::Session;
+use log::{debug, error};
+use protocol::{Codec, StateReply, DEFAULT_MAX_FRAME_SIZE};
 use request_response::RequestResponses;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
@@ -21,22 +24,30 @@ use tokio::{
 };
 
 use uuid::Uuid;
-mod event;
+pub mod client;
+pub mod event;
+mod frame;
+pub mod handshake;
 mod out_events;
+pub mod protocol;
 mod request_response;
 pub mod service;
 
-#[derive(Debug, Deserialize, Serialize, Clone, Copy)]
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, codec::Encode, codec::Decode)]
 pub enum Status {
     STOP,
     RUNNING,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone, Copy)]
+#[derive(
+    Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, codec::Encode, codec::Decode,
+)]
 pub enum Method {
     Invoke,
     Setstate,
     Getstate,
+    /// 容器结束调用，携带调用结果
+    Finish,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -51,8 +62,14 @@ pub struct Params {
 pub struct ComputerEngineReader {
     reader: Arc<Mutex<ReadHalf<TcpStream>>>,
     to_local: mpsc::UnboundedSender<ReceiverMessage>,
+    // 连接断开时通知 worker
+    closed: mpsc::UnboundedSender<Uuid>,
     remote: Uuid,
     container_id: SocketAddr,
+    // 握手协商的帧编码
+    codec: Codec,
+    // 接收帧的大小上限
+    max_frame_size: u32,
     // 标记引擎是否正在运行
     running: Arc<AtomicBool>,
 }
@@ -65,14 +82,16 @@ impl ComputerEngineReader {
                 params,
             });
         }
+
+        // 连接已断开，由 worker 结束等待中的调用
+        self.running.store(false, Ordering::SeqCst);
+        let _ = self.closed.unbounded_send(self.remote);
     }
 }
 
 impl ComputerEngineReader {
     // 新的异步函数，用于读取消息
     pub async fn read_message(&mut self) -> Option<Params> {
-        use tokio::io::AsyncReadExt;
-
         // 检查引擎是否正在运行
         if !self.running.load(Ordering::SeqCst) {
             return None;
@@ -81,37 +100,14 @@ impl ComputerEngineReader {
         // 尝试获取读取锁
         let mut reader_guard = self.reader.lock().await;
 
-        // 读取消息长度（4字节）
-        let mut length_buffer = [0u8; 4];
-        match reader_guard.read_exact(&mut length_buffer).await {
-            Ok(_) => {
-                // 成功读取长度
-                let length = u32::from_le_bytes(length_buffer) as usize;
-
-                // 读取消息内容
-                let mut data_buffer = vec![0u8; length];
-                match reader_guard.read_exact(&mut data_buffer).await {
-                    Ok(_) => {
-                        // 成功读取数据，尝试解析
-                        match serde_json::from_slice::<Params>(&data_buffer) {
-                            Ok(params) => Some(params),
-                            Err(e) => {
-                                error!("解析数据失败: {}", e);
-                                None
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        if e.kind() != io::ErrorKind::WouldBlock {
-                            error!("读取消息内容失败: {}", e);
-                        }
-                        None
-                    }
-                }
-            }
+        // 读取长度前缀的消息并解析
+        match frame::read_frame(&mut *reader_guard, self.codec, self.max_frame_size).await {
+            Ok(params) => Some(params),
             Err(e) => {
-                if e.kind() != io::ErrorKind::WouldBlock {
-                    error!("读取消息长度失败: {}", e);
+                match e.kind() {
+                    io::ErrorKind::WouldBlock => {}
+                    io::ErrorKind::UnexpectedEof => debug!("连接已被对方关闭: {}", self.remote),
+                    _ => error!("读取消息失败: {}", e),
                 }
                 None
             }
@@ -122,6 +118,10 @@ impl ComputerEngineReader {
 pub struct ComputerEngineWriter {
     writer: Arc<Mutex<WriteHalf<TcpStream>>>,
     container_id: SocketAddr,
+    // 握手协商的帧编码
+    codec: Codec,
+    // 容器接收帧的大小上限
+    max_frame_size: u32,
     // 标记引擎是否正在运行
     running: Arc<AtomicBool>,
 }
@@ -138,22 +138,8 @@ impl ComputerEngineWriter {
             return Err(io::Error::new(io::ErrorKind::NotConnected, "连接已关闭"));
         }
 
-        // 尝试序列化数据
-        let data = match serde_json::to_vec(&params) {
-            Ok(data) => data,
-            Err(e) => {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    format!("序列化数据失败: {}", e),
-                ))
-            }
-        };
-
-        // 准备发送数据
-        let length = (data.len() as u32).to_le_bytes();
-        let mut combine = Vec::with_capacity(4 + data.len());
-        combine.extend_from_slice(&length);
-        combine.extend_from_slice(&data);
+        // 序列化为长度前缀的帧，超过容器上限的不发送
+        let combine = frame::encode(&params, self.codec, self.max_frame_size)?;
 
         // 获取写入器锁
         let mut writer = match self.writer.try_lock() {
@@ -199,6 +185,11 @@ pub struct ComputerEngineWorker {
     from_locals: mpsc::UnboundedReceiver<ReceiverMessage>,
     to_locals: mpsc::UnboundedSender<ReceiverMessage>,
     from_service: mpsc::UnboundedReceiver<ServiceToWorkerMsg>,
+    // 后台建立的连接经此交回 worker
+    to_worker: mpsc::UnboundedSender<ServiceToWorkerMsg>,
+    // 连接断开的容器
+    from_closed: mpsc::UnboundedReceiver<Uuid>,
+    to_closed: mpsc::UnboundedSender<Uuid>,
     service: Arc<EngineService>,
     request_responses: RequestResponses,
     // 自动重连标志
@@ -207,6 +198,10 @@ pub struct ComputerEngineWorker {
     max_reconnect_attempts: u32,
     // 重连间隔（毫秒）
     reconnect_interval_ms: u64,
+    // 握手时提议的帧编码
+    codec: Codec,
+    // 接收帧的大小上限
+    max_frame_size: u32,
     event_streams: out_events::Channels,
 }
 
@@ -214,17 +209,23 @@ impl ComputerEngineWorker {
     pub fn new(executor: Handle) -> io::Result<Self> {
         let (to_worker, from_service) = mpsc::unbounded();
         let (to_locals, from_locals) = mpsc::unbounded();
-        let service = Arc::new(EngineService::new(to_worker));
+        let (to_closed, from_closed) = mpsc::unbounded();
+        let service = Arc::new(EngineService::new(to_worker.clone()));
         Ok(Self {
             connections: HashMap::new(),
             executor,
             from_locals,
             to_locals,
             from_service,
+            to_worker,
+            from_closed,
+            to_closed,
             service,
             auto_reconnect: true,        // 默认启用自动重连
             max_reconnect_attempts: 5,   // 默认最大重连次数
             reconnect_interval_ms: 1000, // 默认重连间隔为1秒
+            codec: Codec::Json,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
             event_streams: out_events::Channels::new(),
             request_responses: RequestResponses::new(),
         })
@@ -240,28 +241,78 @@ impl ComputerEngineWorker {
                 remote,
                 request,
                 fallback_request,
+                state,
                 pending_response,
             } => {
-                if let Some(engine) = self.get_engine_mut(&remote) {
-                    engine
-                        .send(Params {
-                            id: request_id,
-                            method: Method::Invoke,
-                            status: Status::RUNNING,
-                            data: request,
-                        })
-                        .await
-                        .unwrap();
-                    self.request_responses.pending_requests(
-                        request_id,
-                        fallback_request,
-                        pending_response,
-                    );
-                } else {
-                    pending_response
-                        .send(Err(RequestFailure::NotConnected))
-                        .unwrap();
+                let Some(engine) = self.get_engine_mut(&remote) else {
+                    let _ = pending_response.send(Err(RequestFailure::NotConnected));
+                    return;
+                };
+                let params = Params {
+                    id: request_id,
+                    method: Method::Invoke,
+                    status: Status::RUNNING,
+                    data: request,
+                };
+                let payload = serde_json::to_vec(&params.data).unwrap_or_default();
+                if let Err(e) = engine.send(params).await {
+                    error!("发送调用到 {} 失败: {}", remote, e);
+                    let failure = match e.kind() {
+                        // 调用超过容器的帧上限，重连也无济于事
+                        io::ErrorKind::InvalidInput => {
+                            RequestFailure::InvalidRequest(e.to_string())
+                        }
+                        _ => RequestFailure::NotConnected,
+                    };
+                    let _ = pending_response.send(Err(failure));
+                    return;
+                }
+                self.event_streams.send(Event::Invoke { remote, payload });
+                self.request_responses.pending_requests(
+                    request_id,
+                    remote,
+                    fallback_request,
+                    state,
+                    pending_response,
+                );
+            }
+            ServiceToWorkerMsg::Connect {
+                remote,
+                address,
+                result,
+            } => {
+                // 在后台连接并握手，不阻塞其他调用
+                let to_worker = self.to_worker.clone();
+                let (codec, max_frame_size) = (self.codec, self.max_frame_size);
+                self.executor.spawn(async move {
+                    match open(remote, address, codec, max_frame_size).await {
+                        Ok((stream, session)) => {
+                            let _ = to_worker.unbounded_send(ServiceToWorkerMsg::Accept {
+                                remote,
+                                address,
+                                stream,
+                                session,
+                                result,
+                            });
+                        }
+                        Err(e) => {
+                            let _ = result.send(Err(e));
+                        }
+                    }
+                });
+            }
+            ServiceToWorkerMsg::Accept {
+                remote,
+                address,
+                stream,
+                session,
+                result,
+            } => {
+                let accepted = self.handle_accept(stream, remote, address, session);
+                if let Err(e) = &accepted {
+                    error!("接入容器 {} 失败: {}", address, e);
                 }
+                let _ = result.send(accepted);
             }
         }
     }
@@ -288,12 +339,25 @@ impl ComputerEngineWorker {
         self
     }
 
+    /// 设置握手时提议的帧编码，容器不支持时回退到 JSON
+    pub fn with_codec(mut self, codec: Codec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    /// 设置接收帧的大小上限（字节）
+    pub fn with_max_frame_size(mut self, max_frame_size: u32) -> Self {
+        self.max_frame_size = max_frame_size;
+        self
+    }
+
     /// 连接到指定容器ID的服务
     fn connect_to_container(
         &mut self,
         stream: TcpStream,
         remote: Uuid,
         container_id: SocketAddr,
+        session: Session,
     ) -> io::Result<()> {
         let (reader_half, writer_half) = tokio::io::split(stream);
         let reader = Arc::new(Mutex::new(reader_half));
@@ -304,13 +368,18 @@ impl ComputerEngineWorker {
             reader,
             container_id,
             remote,
+            codec: session.codec,
+            max_frame_size: self.max_frame_size,
             running: runner.clone(),
             to_local: self.to_locals.clone(),
+            closed: self.to_closed.clone(),
         };
 
         let writer_engine = ComputerEngineWriter {
             writer,
             container_id,
+            codec: session.codec,
+            max_frame_size: session.peer_max_frame_size,
             running: runner.clone(),
         };
 
@@ -325,8 +394,9 @@ impl ComputerEngineWorker {
         stream: TcpStream,
         remote: Uuid,
         addr: SocketAddr,
+        session: Session,
     ) -> io::Result<()> {
-        self.connect_to_container(stream, remote, addr)?;
+        self.connect_to_container(stream, remote, addr, session)?;
         Ok(())
     }
 
@@ -334,9 +404,9 @@ impl ComputerEngineWorker {
     pub async fn connect(&mut self, remote: Uuid, container_id: SocketAddr) -> io::Result<()> {
         println!("连接到容器: {:?}", container_id);
 
-        // 连接到容器
-        let stream = TcpStream::connect(container_id).await?;
-        self.handle_accept(stream, remote, container_id)?;
+        // 连接到容器并握手
+        let (stream, session) = open(remote, container_id, self.codec, self.max_frame_size).await?;
+        self.handle_accept(stream, remote, container_id, session)?;
         Ok(())
     }
 
@@ -360,9 +430,9 @@ impl ComputerEngineWorker {
         while attempts < max_attempts {
             attempts += 1;
 
-            match TcpStream::connect(container_id).await {
-                Ok(stream) => {
-                    return self.handle_accept(stream, *remote, container_id);
+            match open(*remote, container_id, self.codec, self.max_frame_size).await {
+                Ok((stream, session)) => {
+                    return self.handle_accept(stream, *remote, container_id, session);
                 }
                 Err(e) => {
                     if attempts < max_attempts {
@@ -401,19 +471,69 @@ impl ComputerEngineWorker {
     pub async fn next_action(&mut self) -> bool {
         futures::select! {
             receiver = self.from_locals.select_next_some() => {
-                self.handle_remote_message(receiver);
+                self.handle_remote_message(receiver).await;
             }
             msg = self.from_service.select_next_some() => {
                 self.handle_wortker_message(msg).await;
             }
+            remote = self.from_closed.select_next_some() => {
+                self.handle_closed(remote);
+            }
         }
 
         true
     }
 
     // 处理从远程接收的消息
-    fn handle_remote_message(&mut self, msg: ReceiverMessage) {
-        self.request_responses.on_request_response(msg.params);
+    async fn handle_remote_message(&mut self, msg: ReceiverMessage) {
+        let ReceiverMessage { remote, params } = msg;
+        let payload = serde_json::to_vec(&params.data).unwrap_or_default();
+        match params.method {
+            Method::Getstate | Method::Setstate => {
+                self.event_streams.send(match params.method {
+                    Method::Getstate => Event::GetState { remote, payload },
+                    _ => Event::SetState { remote, payload },
+                });
+
+                // 在调用进行中读写状态，应答发回同一连接
+                let reply = self.request_responses.on_state_request(&params);
+                if let Some(engine) = self.get_engine_mut(&remote) {
+                    if let Err(e) = engine.send(reply.clone()).await {
+                        error!("应答容器 {} 的状态请求失败: {}", remote, e);
+                        if e.kind() == io::ErrorKind::InvalidInput {
+                            // 值超过容器的帧上限，改为拒绝该请求
+                            let refused = StateReply {
+                                value: None,
+                                error: Some(e.to_string()),
+                            };
+                            let reply = Params {
+                                data: serde_json::to_value(refused).unwrap_or_default(),
+                                ..reply
+                            };
+                            let _ = engine.send(reply).await;
+                        }
+                    }
+                }
+            }
+            Method::Finish => {
+                self.event_streams.send(Event::Finish { remote, payload });
+                self.request_responses.on_request_response(params);
+            }
+            Method::Invoke => error!("容器 {} 不应发送 Invoke", remote),
+        }
+    }
+
+    // 连接断开：结束等待中的调用，移除连接
+    fn handle_closed(&mut self, remote: Uuid) {
+        if self
+            .get_engine(&remote)
+            .is_some_and(|engine| engine.is_running())
+        {
+            // 已经接入了新的连接
+            return;
+        }
+        self.connections.remove(&remote);
+        self.request_responses.on_disconnected(&remote);
     }
 
     pub async fn run(mut self) {
@@ -421,6 +541,18 @@ impl ComputerEngineWorker {
     }
 }
 
+/// 连接容器并完成握手
+async fn open(
+    remote: Uuid,
+    address: SocketAddr,
+    codec: Codec,
+    max_frame_size: u32,
+) -> io::Result<(TcpStream, Session)> {
+    let mut stream = TcpStream::connect(address).await?;
+    let session = handshake::connect(&mut stream, remote, codec, max_frame_size).await?;
+    Ok((stream, session))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,125 @@
+//! Messages of the engine protocol
+//!
+//! A connection opens with a [`Hello`] from each side, the node first, sent as
+//! JSON frames. They settle the [`Codec`] of the frames that follow and the
+//! largest frame each side accepts.
+//!
+//! A call is then a conversation under the ID of its `Invoke`:
+//!
+//! 1. The node sends `Invoke` with an [`Invocation`].
+//! 2. While executing, the container may send `Getstate` with a [`GetState`] or
+//!    `Setstate` with a [`SetState`], each answered by the node with a message of
+//!    the same method carrying a [`StateReply`].
+//! 3. The container closes the call with `Finish` carrying a [`Finish`].
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+/// Version of the protocol, both sides of a connection must speak the same
+pub const PROTOCOL_VERSION: u16 = 1;
+
+/// Largest frame accepted unless configured otherwise, in bytes
+pub const DEFAULT_MAX_FRAME_SIZE: u32 = 16 * 1024 * 1024;
+
+/// First message of each side of a connection
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Hello {
+    /// Protocol version of the sender
+    pub version: u16,
+    /// Container the node expects, or the container answering
+    pub container_id: Uuid,
+    /// Optional features the node supports, or the container agreed to
+    #[serde(default)]
+    pub capabilities: Vec<Capability>,
+    /// Largest frame the sender accepts, in bytes
+    pub max_frame_size: u32,
+}
+
+/// Optional protocol feature
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Capability {
+    /// Frames encoded with SCALE instead of JSON
+    ScaleCodec,
+    /// Feature of a newer version
+    #[serde(other)]
+    Unknown,
+}
+
+/// Encoding of the frames after the handshake
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Codec {
+    #[default]
+    Json,
+    /// Compact binary encoding, strings go unescaped
+    Scale,
+}
+
+/// Call of a contract, sent with `Invoke`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Invocation {
+    /// Path of the called contract method
+    pub path: String,
+    /// HTTP method of the request
+    pub method: String,
+    /// Request headers
+    #[serde(default)]
+    pub headers: Vec<(String, String)>,
+    /// Request body
+    #[serde(default)]
+    pub body: Vec<u8>,
+}
+
+/// Read of a state value, sent by the container with `Getstate`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GetState {
+    pub key: String,
+}
+
+/// Write of a state value, sent by the container with `Setstate`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SetState {
+    pub key: String,
+    /// New value, `None` deletes the key
+    pub value: Option<String>,
+}
+
+/// Answer of the node to `Getstate` and `Setstate`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StateReply {
+    /// Value read, always `None` for writes
+    #[serde(default)]
+    pub value: Option<String>,
+    /// Why the node refused the read or write
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Outcome of a call, sent by the container with `Finish`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Finish {
+    /// HTTP status of the response
+    pub status_code: u16,
+    /// Response body
+    pub output: Value,
+}
+
+impl Finish {
+    /// Successful outcome with `output`
+    pub fn ok(output: Value) -> Self {
+        Self {
+            status_code: 200,
+            output,
+        }
+    }
+
+    /// Failed outcome with an error message
+    pub fn error(status_code: u16, message: impl ToString) -> Self {
+        Self {
+            status_code,
+            output: serde_json::json!({ "error": message.to_string() }),
+        }
+    }
+}
@@ -1,15 +1,21 @@
-use std::{collections::HashMap, future::Future, pin::Pin, time::Instant};
+use std::{collections::HashMap, future::Future, pin::Pin, sync::Arc, time::Instant};
 
 use futures::{channel::oneshot, stream};
 use serde_json::Value;
 use uuid::Uuid;
 
-use crate::{service::RequestFailure, Params};
+use crate::{
+    protocol::{GetState, SetState, StateReply},
+    service::{ContractState, RequestFailure},
+    Method, Params, Status,
+};
 
 pub struct PendingRequest {
     started_at: Instant,
+    remote: Uuid,
     response_tx: Option<oneshot::Sender<Result<Value, RequestFailure>>>,
     fallback_request: Option<Vec<u8>>,
+    state: Option<Arc<dyn ContractState>>,
 }
 
 pub struct RequestResponses {
@@ -34,19 +40,80 @@ impl RequestResponses {
         }
     }
 
+    /// Serve a state read or write of a call in progress, returning the reply to send
+    pub fn on_state_request(&mut self, params: &Params) -> Params {
+        let state = self
+            .pending_requests
+            .get(&params.id)
+            .and_then(|request| request.state.clone());
+        let reply = match state {
+            Some(state) => serve_state(state.as_ref(), params.method, params.data.clone()),
+            None => Err(format!("No call {} with state in progress", params.id)),
+        };
+        let reply = reply.unwrap_or_else(|error| StateReply {
+            value: None,
+            error: Some(error),
+        });
+
+        Params {
+            id: params.id,
+            method: params.method,
+            status: Status::RUNNING,
+            data: serde_json::to_value(reply).unwrap_or_default(),
+        }
+    }
+
+    /// Fail the calls waiting on a remote whose connection closed
+    pub fn on_disconnected(&mut self, remote: &Uuid) {
+        self.pending_requests.retain(|_, request| {
+            if request.remote != *remote {
+                return true;
+            }
+            if let Some(tx) = request.response_tx.take() {
+                let _ = tx.send(Err(RequestFailure::Refused));
+            }
+            false
+        });
+    }
+
     pub fn pending_requests(
         &mut self,
         request_id: Uuid,
+        remote: Uuid,
         fallback_request: Option<Vec<u8>>,
+        state: Option<Arc<dyn ContractState>>,
         pending_response: oneshot::Sender<Result<Value, RequestFailure>>,
     ) {
         self.pending_requests.insert(
             request_id,
             PendingRequest {
                 started_at: Instant::now(),
+                remote,
                 response_tx: Some(pending_response),
                 fallback_request,
+                state,
             },
         );
     }
 }
+
+fn serve_state(
+    state: &dyn ContractState,
+    method: Method,
+    data: Value,
+) -> Result<StateReply, String> {
+    match method {
+        Method::Getstate => {
+            let request: GetState = serde_json::from_value(data).map_err(|e| e.to_string())?;
+            Ok(StateReply {
+                value: state.get(&request.key)?,
+                error: None,
+            })
+        }
+        _ => {
+            let request: SetState = serde_json::from_value(data).map_err(|e| e.to_string())?;
+            state.set(&request.key, request.value)?;
+            Ok(StateReply::default())
+        }
+    }
+}
@@ -1,13 +1,22 @@
-use std::{pin::Pin, sync::Arc};
+use std::{io, net::SocketAddr, pin::Pin, sync::Arc};
 
-use crate::{event::Event, out_events};
+use crate::{
+    event::Event,
+    handshake::Session,
+    out_events,
+    protocol::{Finish, Invocation},
+};
 use futures::{
     channel::{mpsc, oneshot},
-    Stream,
+    future, Stream, StreamExt,
 };
 use serde_json::Value;
+use tokio::net::TcpStream;
 use uuid::Uuid;
 
+/// Number of unprocessed events after which an event stream warns
+const EVENT_QUEUE_WARNING: usize = 100_000;
+
 /// Error in a request.
 #[derive(Debug, thiserror::Error)]
 #[allow(missing_docs)]
@@ -20,6 +29,19 @@ pub enum RequestFailure {
     Refused,
     #[error("The remote replied, but the local node is no longer interested in the response.")]
     Obsolete,
+    #[error("The remote replied with an invalid response: {0}")]
+    InvalidResponse(String),
+    #[error("The request cannot be sent: {0}")]
+    InvalidRequest(String),
+}
+
+/// State a contract reads and writes while the node serves its call
+pub trait ContractState: Send + Sync {
+    /// Read a value
+    fn get(&self, key: &str) -> Result<Option<String>, String>;
+
+    /// Write a value, `None` deletes it
+    fn set(&self, key: &str, value: Option<String>) -> Result<(), String>;
 }
 
 pub enum ServiceToWorkerMsg {
@@ -29,8 +51,22 @@ pub enum ServiceToWorkerMsg {
         remote: Uuid,
         request: Value,
         fallback_request: Option<Vec<u8>>,
+        state: Option<Arc<dyn ContractState>>,
         pending_response: oneshot::Sender<Result<Value, RequestFailure>>,
     },
+    /// Connect and handshake in the background, then hand the stream over with `Accept`
+    Connect {
+        remote: Uuid,
+        address: SocketAddr,
+        result: oneshot::Sender<io::Result<()>>,
+    },
+    Accept {
+        remote: Uuid,
+        address: SocketAddr,
+        stream: TcpStream,
+        session: Session,
+        result: oneshot::Sender<io::Result<()>>,
+    },
 }
 
 pub struct EngineService {
@@ -41,6 +77,41 @@ impl EngineService {
     pub fn new(to_worker: mpsc::UnboundedSender<ServiceToWorkerMsg>) -> Self {
         Self { to_worker }
     }
+
+    /// Connect to a container while the worker runs, replacing any previous connection
+    ///
+    /// Returns once the handshake is done and requests go to the new connection.
+    pub async fn connect(&self, remote: Uuid, address: SocketAddr) -> io::Result<()> {
+        let stopped = || io::Error::new(io::ErrorKind::NotConnected, "worker stopped");
+        let (result, rx) = oneshot::channel();
+        self.to_worker
+            .unbounded_send(ServiceToWorkerMsg::Connect {
+                remote,
+                address,
+                result,
+            })
+            .map_err(|_| stopped())?;
+        rx.await.map_err(|_| stopped())?
+    }
+
+    fn send_request(
+        &self,
+        request_id: Uuid,
+        remote: &Uuid,
+        request: Value,
+        fallback_request: Option<Vec<u8>>,
+        state: Option<Arc<dyn ContractState>>,
+        tx: oneshot::Sender<Result<Value, RequestFailure>>,
+    ) {
+        let _ = self.to_worker.unbounded_send(ServiceToWorkerMsg::Request {
+            request_id,
+            remote: *remote,
+            request,
+            fallback_request,
+            state,
+            pending_response: tx,
+        });
+    }
 }
 
 #[async_trait::async_trait]
@@ -69,13 +140,33 @@ impl EngineRequest for EngineService {
         fallback_request: Option<Vec<u8>>,
         tx: oneshot::Sender<Result<Value, RequestFailure>>,
     ) {
-        let _ = self.to_worker.unbounded_send(ServiceToWorkerMsg::Request {
-            request_id,
-            remote: remote.clone(),
-            request,
-            fallback_request,
-            pending_response: tx,
-        });
+        self.send_request(request_id, remote, request, fallback_request, None, tx);
+    }
+
+    async fn invoke(
+        &self,
+        request_id: Uuid,
+        remote: &Uuid,
+        invocation: Invocation,
+        state: Arc<dyn ContractState>,
+    ) -> Result<Finish, RequestFailure> {
+        let request = serde_json::to_value(invocation)
+            .map_err(|e| RequestFailure::InvalidResponse(e.to_string()))?;
+        let (tx, rx) = oneshot::channel();
+        self.send_request(request_id, remote, request, None, Some(state), tx);
+        let output = rx.await.map_err(|_| RequestFailure::NotConnected)??;
+        serde_json::from_value(output).map_err(|e| RequestFailure::InvalidResponse(e.to_string()))
+    }
+}
+
+impl EventStream for EngineService {
+    fn event_stream(&self, remote: &Uuid) -> Pin<Box<dyn Stream<Item = Event> + Send>> {
+        let remote = *remote;
+        let (tx, rx) = out_events::channel(remote, EVENT_QUEUE_WARNING);
+        let _ = self
+            .to_worker
+            .unbounded_send(ServiceToWorkerMsg::EventStream(tx));
+        Box::pin(rx.filter(move |event| future::ready(event.remote() == remote)))
     }
 }
 
@@ -110,6 +201,15 @@ pub trait EngineRequest {
         fallback_request: Option<Vec<u8>>,
         tx: oneshot::Sender<Result<Value, RequestFailure>>,
     );
+
+    /// Call a contract, serving its state reads and writes from `state` until it finishes
+    async fn invoke(
+        &self,
+        request_id: Uuid,
+        remote: &Uuid,
+        invocation: Invocation,
+        state: Arc<dyn ContractState>,
+    ) -> Result<Finish, RequestFailure>;
 }
 
 #[async_trait::async_trait]
@@ -137,4 +237,14 @@ where
     ) {
         T::start_request(self, request_id, remote, request, fallback_request, tx);
     }
+
+    async fn invoke(
+        &self,
+        request_id: Uuid,
+        remote: &Uuid,
+        invocation: Invocation,
+        state: Arc<dyn ContractState>,
+    ) -> Result<Finish, RequestFailure> {
+        T::invoke(self, request_id, remote, invocation, state).await
+    }
 }
@@ -1,10 +1,18 @@
 use anyhow::Result;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::mpsc;
 use tokio::task::JoinHandle;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
-use crate::core::{ExecutionEngine, ExecutionRequest, ExecutionResult};
+use crate::config::ExecutionTimeouts;
+use crate::core::{ExecutionEngine, ExecutionFailure, ExecutionRequest, ExecutionResult};
+use crate::error::ExecutionError;
+use crate::parallel::{BatchResult, ParallelExecutor};
+use crate::scheduler::{lane_of, Scheduler, SchedulerMetrics};
+
+/// Time a cancelled execution gets to stop before its worker moves on
+const CANCEL_GRACE_PERIOD: Duration = Duration::from_secs(1);
 
 /// Cross-process communication bridge for execution
 pub struct ExecutionBridge {
@@ -15,13 +23,24 @@ pub struct ExecutionBridge {
     /// Channel for receiving execution requests
     request_rx: mpsc::Receiver<ExecutionRequest>,
     queue_size: usize,
+    /// Deadlines after which executions are cancelled
+    timeouts: ExecutionTimeouts,
+    /// Number of executions run at once
+    worker_count: usize,
+    /// Queues the requests of every contract for the workers
+    scheduler: Arc<Scheduler>,
     /// Worker handles
     workers: Vec<JoinHandle<()>>,
 }
 
 impl ExecutionBridge {
     /// Create a new execution bridge
-    pub fn new(engine: Arc<dyn ExecutionEngine>, worker_count: usize, queue_size: usize) -> Self {
+    pub fn new(
+        engine: Arc<dyn ExecutionEngine>,
+        worker_count: usize,
+        queue_size: usize,
+        timeouts: ExecutionTimeouts,
+    ) -> Self {
         let (request_tx, request_rx) = mpsc::channel(queue_size);
 
         Self {
@@ -29,67 +48,62 @@ impl ExecutionBridge {
             request_tx,
             request_rx,
             queue_size,
+            timeouts,
+            worker_count,
+            scheduler: Arc::new(Scheduler::new(worker_count)),
             workers: Vec::with_capacity(worker_count),
         }
     }
 
     /// Start the execution bridge
-    pub async fn start(&mut self, worker_count: usize) -> Result<mpsc::Receiver<ExecutionResult>> {
+    ///
+    /// Requests to the same contract execute one at a time in the order they
+    /// were sent, on the worker owning the contract unless another one is idle.
+    /// Every request gets a result or a failure back. The next request of a contract
+    /// does not wait for the state changes of the previous one to be committed, so
+    /// committed transactions are executed through [`Self::execute_batch`] instead.
+    pub async fn start(
+        &mut self,
+        worker_count: usize,
+    ) -> Result<mpsc::Receiver<Result<ExecutionResult, ExecutionFailure>>> {
         info!("Starting execution bridge with {} workers", worker_count);
 
         // Create result channel
         let (result_tx, result_rx) = mpsc::channel(self.queue_size);
+        let scheduler = Arc::new(Scheduler::new(worker_count));
+        self.scheduler = scheduler.clone();
+        self.worker_count = worker_count;
 
-        // Create multiple channels for worker distribution
-        let mut worker_channels = Vec::with_capacity(worker_count);
-        for i in 0..worker_count {
-            let (tx, rx) = mpsc::channel::<ExecutionRequest>(100);
-            info!("Created worker channel {} with capacity 100", i);
-            worker_channels.push((tx, rx));
-        }
-
-        // Forward messages from the main channel to workers in a round-robin fashion
-        let worker_senders = worker_channels
-            .iter()
-            .map(|(tx, _)| tx.clone())
-            .collect::<Vec<_>>();
         let mut main_rx =
             std::mem::replace(&mut self.request_rx, mpsc::channel::<ExecutionRequest>(1).1);
 
-        // Spawn a task that distributes incoming requests across workers
+        // Spawn a task that queues incoming requests for the workers
+        let queue = scheduler.clone();
         tokio::spawn(async move {
-            let mut current_worker = 0;
             while let Some(req) = main_rx.recv().await {
+                let lane = lane_of(&req.transaction_type);
                 info!(
-                    "Distributing request for module {:?} to worker {}",
-                    req.transaction_type, current_worker
+                    "Queueing request for module {:?} on worker {}",
+                    req.transaction_type,
+                    queue.worker_of(&lane)
                 );
-                // Round-robin distribution
-                if let Err(e) = worker_senders[current_worker].send(req).await {
-                    error!(
-                        "Failed to forward execution request to worker {}: {}",
-                        current_worker, e
-                    );
-                }
-                current_worker = (current_worker + 1) % worker_senders.len();
+                queue.push(req);
             }
             info!("Main request channel closed, distributor task ending");
+            queue.close();
         });
 
         // Start worker tasks
         for i in 0..worker_count {
             let engine = self.engine.clone();
+            let timeouts = self.timeouts.clone();
             let result_tx = result_tx.clone();
-            // Take ownership of the receiver - Receivers can't be cloned
-            let mut worker_rx = std::mem::replace(
-                &mut worker_channels[i].1,
-                mpsc::channel::<ExecutionRequest>(1).1,
-            );
+            let scheduler = scheduler.clone();
 
             let handle = tokio::spawn(async move {
                 info!("Worker {} started", i);
 
-                while let Some(mut request) = worker_rx.recv().await {
+                while let Some((lane, mut request)) = scheduler.next(i).await {
                     debug!(
                         "Worker {} processing request for module {:?}",
                         i, request.transaction_type
@@ -102,7 +116,10 @@ impl ExecutionBridge {
                     );
 
                     // 执行请求，使用可变引用，以保留 result_sender 用于主动通知
-                    match engine.execute(&mut request).await {
+                    let timeout = timeouts.timeout_of(&request.transaction_type);
+                    let outcome = match execute_with_timeout(engine.as_ref(), &mut request, timeout)
+                        .await
+                    {
                         Ok(result) => {
                             // Add debug logging to print the execution result
                             info!(
@@ -111,18 +128,22 @@ impl ExecutionBridge {
                                 request.tx_hash,
                                 serde_json::to_string_pretty(&result.output).unwrap_or_default()
                             );
-
-                            if let Err(e) = result_tx.send(result).await {
-                                error!("Failed to send execution result: {}", e);
-                            }
+                            Ok(result)
                         }
                         Err(e) => {
                             error!(
                                 "Worker {} execution error for tx {}: {}",
                                 i, request.tx_hash, e
                             );
+                            Err(ExecutionFailure::new(&request, &e))
                         }
+                    };
+
+                    // The next request of the contract waits until this outcome is out
+                    if let Err(e) = result_tx.send(outcome).await {
+                        error!("Failed to send execution result: {}", e);
                     }
+                    scheduler.complete(&lane);
                 }
 
                 info!("Worker {} channel closed, worker stopping", i);
@@ -134,6 +155,25 @@ impl ExecutionBridge {
         Ok(result_rx)
     }
 
+    /// Requests waiting per contract, and how much work was stolen
+    pub fn metrics(&self) -> SchedulerMetrics {
+        self.scheduler.metrics()
+    }
+
+    /// Execute a batch of transactions in log order, in parallel where they do not conflict
+    ///
+    /// The results are returned in the order of `requests`, as if they had been
+    /// executed one after the other.
+    pub async fn execute_batch(&self, requests: Vec<ExecutionRequest>) -> Vec<BatchResult> {
+        ParallelExecutor::new(
+            self.engine.clone(),
+            self.timeouts.clone(),
+            self.worker_count,
+        )
+        .execute_batch(requests)
+        .await
+    }
+
     /// Get a sender for execution requests
     pub fn get_request_sender(&self) -> mpsc::Sender<ExecutionRequest> {
         self.request_tx.clone()
@@ -149,3 +189,29 @@ impl ExecutionBridge {
         Ok(())
     }
 }
+
+/// Execute a request, cancelling it once `timeout` elapses
+///
+/// A cancelled execution gets a grace period to stop cooperatively, it is
+/// dropped if it does not.
+pub(crate) async fn execute_with_timeout(
+    engine: &dyn ExecutionEngine,
+    request: &mut ExecutionRequest,
+    timeout: Duration,
+) -> Result<ExecutionResult, ExecutionError> {
+    let cancel = request.cancel.clone();
+    let tx_hash = request.tx_hash;
+    let execution = engine.execute(request);
+    tokio::pin!(execution);
+
+    tokio::select! {
+        result = &mut execution => result,
+        _ = tokio::time::sleep(timeout) => {
+            cancel.cancel();
+            if tokio::time::timeout(CANCEL_GRACE_PERIOD, &mut execution).await.is_err() {
+                warn!("Execution of tx {} did not stop after cancellation", tx_hash);
+            }
+            Err(ExecutionError::Timeout(timeout.as_millis() as u64))
+        }
+    }
+}
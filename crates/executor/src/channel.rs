@@ -0,0 +1,145 @@
+use anyhow::Result;
+use mp_common::types::TransactionType;
+use mp_common::utils::h128_to_uuid;
+use mp_common::H128;
+use mp_executor_engine::protocol::{Finish, Invocation};
+use mp_executor_engine::service::{ContractState, EngineRequest, EngineService, RequestFailure};
+use mp_executor_engine::ComputerEngineWorker;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::runtime::Handle;
+use uuid::Uuid;
+
+use crate::config::StateChannelConfig;
+use crate::core::ExecutionRequest;
+use crate::error::ExecutionError;
+use crate::state::{contract_key, CopyOnWriteState};
+
+/// Calls contract containers over the engine protocol
+///
+/// The container reads and writes the contract state through the node while it
+/// executes, so every value it sees is recorded in the read set of the call.
+pub struct EngineChannel {
+    service: Arc<EngineService>,
+}
+
+impl EngineChannel {
+    /// Start the engine worker on the current runtime
+    pub fn new(config: &StateChannelConfig) -> Result<Self> {
+        let worker = ComputerEngineWorker::new(Handle::current())?
+            .with_codec(config.codec)
+            .with_max_frame_size(config.max_frame_size);
+        let service = worker.service().clone();
+        tokio::spawn(worker.run());
+        Ok(Self { service })
+    }
+
+    /// Call a contract served at `address`, its state reads and writes going to `state`
+    ///
+    /// Returns the outcome of the call and the state it left behind. Connects to
+    /// the container on first use, and again once the connection dropped.
+    pub async fn invoke(
+        &self,
+        contract: H128,
+        address: SocketAddr,
+        request: &ExecutionRequest,
+        state: CopyOnWriteState,
+    ) -> Result<(Finish, CopyOnWriteState), ExecutionError> {
+        let TransactionType::Request(_, path) = &request.transaction_type else {
+            return Err(ExecutionError::InvalidInput(format!(
+                "tx {} does not call a contract",
+                request.tx_hash
+            )));
+        };
+        let invocation = Invocation {
+            path: path.clone(),
+            method: request.method.to_string(),
+            headers: request
+                .header
+                .iter()
+                .filter_map(|(name, value)| {
+                    Some((name.to_string(), value.to_str().ok()?.to_string()))
+                })
+                .collect(),
+            body: request.input.clone(),
+        };
+        let remote = h128_to_uuid(&contract);
+        let call = Arc::new(CallState {
+            contract,
+            state: Mutex::new(Some(state)),
+        });
+
+        let outcome = tokio::select! {
+            outcome = self.call(remote, address, invocation, call.clone()) => outcome,
+            _ = request.cancel.cancelled() => Err(ExecutionError::ExecutionError(format!(
+                "Call of contract {:?} cancelled",
+                contract
+            ))),
+        };
+        // Late state requests of the call are refused from now on
+        let state = call.state.lock().unwrap().take();
+        let state =
+            state.ok_or_else(|| ExecutionError::InternalError("Call state taken".into()))?;
+        Ok((outcome?, state))
+    }
+
+    async fn call(
+        &self,
+        remote: Uuid,
+        address: SocketAddr,
+        invocation: Invocation,
+        state: Arc<CallState>,
+    ) -> Result<Finish, ExecutionError> {
+        let outcome = self
+            .service
+            .invoke(Uuid::new_v4(), &remote, invocation.clone(), state.clone())
+            .await;
+        let outcome = match outcome {
+            // Nothing was sent, the call can go over a new connection
+            Err(RequestFailure::NotConnected) => {
+                self.service.connect(remote, address).await.map_err(|e| {
+                    ExecutionError::CommunicationError(format!(
+                        "Failed to connect to {}: {}",
+                        address, e
+                    ))
+                })?;
+                self.service
+                    .invoke(Uuid::new_v4(), &remote, invocation, state)
+                    .await
+            }
+            outcome => outcome,
+        };
+        outcome.map_err(|e| match e {
+            // The call exceeds the frame limit of the container
+            RequestFailure::InvalidRequest(message) => ExecutionError::InvalidInput(message),
+            e => ExecutionError::CommunicationError(format!("{} ({})", e, address)),
+        })
+    }
+}
+
+/// State of a contract call, shared with the engine worker until the call is over
+struct CallState {
+    contract: H128,
+    state: Mutex<Option<CopyOnWriteState>>,
+}
+
+impl ContractState for CallState {
+    fn get(&self, key: &str) -> Result<Option<String>, String> {
+        let mut state = self.state.lock().unwrap();
+        let state = state.as_mut().ok_or("The call is over")?;
+        state
+            .read(&contract_key(&self.contract, key))
+            .map_err(|e| e.to_string())
+    }
+
+    fn set(&self, key: &str, value: Option<String>) -> Result<(), String> {
+        let mut state = self.state.lock().unwrap();
+        let state = state.as_mut().ok_or("The call is over")?;
+        let key = contract_key(&self.contract, key);
+        match value {
+            Some(value) => state.write(key, value),
+            None => state.delete(key),
+        }
+        Ok(())
+    }
+}
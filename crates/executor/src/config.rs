@@ -1,7 +1,14 @@
+use mp_common::types::TransactionType;
+use mp_common::H128;
 use mp_container::ContainerEnvironment;
+use mp_executor_engine::protocol::{Codec, DEFAULT_MAX_FRAME_SIZE};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 
+use crate::metering::{GasSchedule, ResourceLimits};
 use crate::ExecutionEngineType;
 
 /// Execution engine configuration
@@ -13,6 +20,18 @@ pub struct ExecutorConfig {
     pub max_concurrent_requests: usize,
     /// Enable detailed execution tracing
     pub enable_tracing: bool,
+    /// Gas price of every metered resource
+    #[serde(default)]
+    pub gas: GasSchedule,
+    /// Resource limits applied to every execution
+    #[serde(default)]
+    pub limits: ResourceLimits,
+    /// Deadlines after which executions are cancelled
+    #[serde(default)]
+    pub timeouts: ExecutionTimeouts,
+    /// Contracts called over the engine protocol
+    #[serde(default)]
+    pub state_channel: StateChannelConfig,
 
     /// Type of execution engine to use
     #[serde(skip)]
@@ -31,9 +50,78 @@ impl Default for ExecutorConfig {
             worker_threads: 4,
             max_concurrent_requests: 100,
             enable_tracing: false,
+            gas: GasSchedule::default(),
+            limits: ResourceLimits::default(),
+            timeouts: ExecutionTimeouts::default(),
+            state_channel: StateChannelConfig::default(),
             engine_type: ExecutionEngineType::default(),
             container_environment: None,
             compute_environment: None,
         }
     }
 }
+
+/// Execution deadlines, per contract or for every execution
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExecutionTimeouts {
+    /// Deadline of executions in milliseconds, unless the contract has its own
+    pub default_ms: u64,
+    /// Deadlines in milliseconds of calls to specific contracts
+    pub contracts: HashMap<H128, u64>,
+}
+
+impl Default for ExecutionTimeouts {
+    fn default() -> Self {
+        Self {
+            default_ms: 30_000,
+            contracts: HashMap::new(),
+        }
+    }
+}
+
+impl ExecutionTimeouts {
+    /// Deadline of an execution of the given transaction type
+    pub fn timeout_of(&self, transaction_type: &TransactionType) -> Duration {
+        let timeout_ms = match transaction_type {
+            TransactionType::Request(contract, _) => self.contracts.get(contract).copied(),
+            _ => None,
+        };
+        Duration::from_millis(timeout_ms.unwrap_or(self.default_ms))
+    }
+}
+
+/// Contracts called over the engine protocol instead of HTTP
+///
+/// Their containers read and write the contract state on the node while they
+/// execute, rather than reporting state changes in their response.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StateChannelConfig {
+    /// Port every contract container serves the protocol on, unless the contract has an address
+    pub port: Option<u16>,
+    /// Addresses serving the protocol for specific contracts
+    pub contracts: HashMap<H128, SocketAddr>,
+    /// Frame encoding proposed to containers, which may fall back to JSON
+    pub codec: Codec,
+    /// Largest frame accepted from containers, in bytes
+    pub max_frame_size: u32,
+}
+
+impl Default for StateChannelConfig {
+    fn default() -> Self {
+        Self {
+            port: None,
+            contracts: HashMap::new(),
+            codec: Codec::Json,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+        }
+    }
+}
+
+impl StateChannelConfig {
+    /// Whether any contract is called over the engine protocol
+    pub fn is_enabled(&self) -> bool {
+        self.port.is_some() || !self.contracts.is_empty()
+    }
+}
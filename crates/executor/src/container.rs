@@ -1,21 +1,29 @@
 use anyhow; // Remove anyhow::anyhow import
 use async_trait::async_trait;
-use mp_common::types::Transaction;
+use http::{HeaderMap, HeaderValue};
+use mp_common::types::{Transaction, TransactionType};
 use mp_common::utils::h128_to_uuid;
+use mp_common::TransactionResponse;
 use mp_common::H128;
-use mp_container::ContainerEnvironment;
+use mp_container::{ContainerEnvironment, ContainerStats, ContainerStatus};
+use mp_state::StateStorage;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::Mutex;
 use tracing::{error, info, warn};
 use uuid::Uuid;
 
+use crate::channel::EngineChannel;
 use crate::config::ExecutorConfig;
 use crate::core::{ExecutionEngine, ExecutionRequest, ExecutionResult};
 use crate::error::ExecutionError;
 use crate::metadata::ExecutionMetadata;
-use crate::state::StateDiff;
+use crate::metering::{Resource, ResourceUsage};
+use crate::replay;
+use crate::state::{contract_key, CopyOnWriteState, StateObserver};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ContainerInfo {
@@ -25,15 +33,27 @@ pub struct ContainerInfo {
     pub tags: Vec<String>,
 }
 
+/// State change reported by a contract in the `state_diffs` of its response
+#[derive(Debug, Deserialize)]
+struct ContractStateChange {
+    key: String,
+    new_value: Option<String>,
+}
+
 /// Container-based execution engine implementation
 ///
 /// This execution engine uses a container environment (such as Docker)
 /// to execute transactions in isolated containers. It manages the lifecycle of
-/// containers and handles communication with them.
+/// containers and handles communication with them. The state changes contracts
+/// report are turned into a diff against `state_storage`, when given.
+/// Contracts configured for the engine protocol are called over `channel`
+/// instead, reading and writing that state while they execute.
 pub struct ContainerExecutionEngine {
     config: ExecutorConfig,
     container_env: Arc<dyn ContainerEnvironment>,
     containers: Mutex<HashMap<H128, ContainerInfo>>,
+    state_storage: Option<Arc<dyn StateStorage>>,
+    channel: Option<EngineChannel>,
 }
 
 impl ContainerExecutionEngine {
@@ -41,14 +61,240 @@ impl ContainerExecutionEngine {
     pub fn new(
         config: ExecutorConfig,
         container_env: Arc<dyn ContainerEnvironment>,
+        state_storage: Option<Arc<dyn StateStorage>>,
     ) -> Result<Self, anyhow::Error> {
+        let channel = if config.state_channel.is_enabled() {
+            Some(EngineChannel::new(&config.state_channel)?)
+        } else {
+            None
+        };
         Ok(Self {
             config,
             container_env,
             containers: Mutex::new(HashMap::new()),
+            state_storage,
+            channel,
         })
     }
 
+    /// Node state a request executes against, before its own changes
+    fn base_state(&self, request: &ExecutionRequest) -> Result<CopyOnWriteState, ExecutionError> {
+        let state = match (&self.state_storage, &request.state_root) {
+            (Some(storage), Some(root)) => {
+                CopyOnWriteState::from_storage_at(storage.clone(), root.clone())
+            }
+            (Some(storage), None) => CopyOnWriteState::from_storage(storage.clone())
+                .map_err(|e| ExecutionError::StateError(e.to_string()))?,
+            (None, _) => CopyOnWriteState::new(String::new()),
+        };
+        Ok(state.with_pending(request.pending_writes.clone()))
+    }
+
+    /// Apply the state changes reported by a contract on top of the node state
+    ///
+    /// Keys are prefixed with the contract address, so a contract only ever changes
+    /// its own state. Changes leaving a value as it is are dropped.
+    fn contract_state(
+        &self,
+        request: &ExecutionRequest,
+        changes: &[serde_json::Value],
+    ) -> Result<CopyOnWriteState, ExecutionError> {
+        let contract = match &request.transaction_type {
+            TransactionType::Request(contract, _) if !changes.is_empty() => contract,
+            _ => return Ok(CopyOnWriteState::new(String::new())),
+        };
+
+        let mut state = self.base_state(request)?;
+        for change in changes {
+            let change: ContractStateChange = serde_json::from_value(change.clone())
+                .map_err(|e| ExecutionError::StateError(format!("Invalid state change: {}", e)))?;
+            let key = contract_key(contract, &change.key);
+            let current = state
+                .read(&key)
+                .map_err(|e| ExecutionError::StateError(e.to_string()))?;
+            if current == change.new_value {
+                continue;
+            }
+            match change.new_value {
+                Some(value) => state.write(key, value),
+                None => state.delete(key),
+            }
+        }
+        Ok(state)
+    }
+
+    /// Record a request executed against the current node state, for later replays
+    ///
+    /// Executions pinned to a past state root are replays themselves and leave
+    /// the record alone.
+    fn record_execution(&self, request: &ExecutionRequest, result: &ExecutionResult) {
+        let Some(storage) = &self.state_storage else {
+            return;
+        };
+        if request.state_root.is_some() || !request.transaction_type.is_request() {
+            return;
+        }
+        let recorded = replay::execution_record(request, result)
+            .and_then(|record| storage.record_execution(&record));
+        if let Err(e) = recorded {
+            warn!(
+                "Failed to record execution of tx {}: {}",
+                request.tx_hash, e
+            );
+        }
+    }
+
+    /// Resource counters of the container serving a request, if they are metered
+    async fn container_stats(&self, transaction_type: &TransactionType) -> Option<ContainerStats> {
+        if !self.config.limits.limits_container_stats() {
+            return None;
+        }
+        let TransactionType::Request(contract, _) = transaction_type else {
+            return None;
+        };
+
+        match self
+            .container_env
+            .container_stats(&h128_to_uuid(contract))
+            .await
+        {
+            Ok(stats) => stats,
+            Err(e) => {
+                warn!("Failed to read container stats of {:?}: {}", contract, e);
+                None
+            }
+        }
+    }
+
+    /// Resolve once the container stats of a running call go over a limit
+    ///
+    /// Never resolves when neither CPU time nor memory are limited.
+    async fn enforce_limits(
+        &self,
+        transaction_type: &TransactionType,
+        stats_before: Option<ContainerStats>,
+        started: Instant,
+    ) -> ExecutionError {
+        let limits = &self.config.limits;
+        if !limits.limits_container_stats() {
+            return std::future::pending().await;
+        }
+        let interval = Duration::from_millis(limits.monitor_interval_ms.max(1));
+        let mut peak_memory = stats_before.map_or(0, |stats| stats.memory_bytes);
+
+        loop {
+            tokio::time::sleep(interval).await;
+            let mut usage = ResourceUsage {
+                wall_time_ms: started.elapsed().as_millis() as u64,
+                ..Default::default()
+            };
+            if let (Some(before), Some(now)) =
+                (stats_before, self.container_stats(transaction_type).await)
+            {
+                peak_memory = peak_memory.max(now.memory_bytes);
+                usage.cpu_time_ns = now.cpu_time_ns.saturating_sub(before.cpu_time_ns);
+                usage.memory_bytes = peak_memory;
+            }
+            // Gas does not change while the call runs, it is checked before and after
+            if let Err(exceeded) = limits.check(&usage, 0) {
+                return exceeded;
+            }
+        }
+    }
+
+    /// Call the contract or container operation of a request
+    ///
+    /// Returns the response with the state the call left behind, and the
+    /// response headers.
+    async fn call_contract(
+        &self,
+        request: &ExecutionRequest,
+    ) -> Result<
+        (
+            TransactionResponse,
+            CopyOnWriteState,
+            HeaderMap<HeaderValue>,
+        ),
+        ExecutionError,
+    > {
+        if let (Some(channel), TransactionType::Request(contract, _)) =
+            (&self.channel, &request.transaction_type)
+        {
+            if let Some(address) = self.channel_address(contract).await? {
+                let state = self.base_state(request)?;
+                let (finish, state) = channel.invoke(*contract, address, request, state).await?;
+                let mut output: TransactionResponse = serde_json::from_value(finish.output)
+                    .map_err(|e| ExecutionError::CommunicationError(e.to_string()))?;
+                if !output.state_diffs.is_empty() {
+                    warn!(
+                        "Contract {:?} reported state changes over the engine protocol, ignored",
+                        contract
+                    );
+                }
+                output.status_code.get_or_insert(finish.status_code as u32);
+                return Ok((output, state, HeaderMap::new()));
+            }
+        }
+
+        // Create an API request transaction
+        let api_request = self.create_api_request(request)?;
+
+        // Execute the transaction in the container environment
+        info!(
+            "Executing transaction in container environment: {:?}",
+            api_request.id
+        );
+        let api_response = self
+            .container_env
+            .execute_transaction(api_request, request.cancel.clone())
+            .await
+            .map_err(|e| {
+                error!(
+                    "ContainerExecutionEngine: Failed to execute transaction in container: {}",
+                    e
+                );
+                ExecutionError::ExecutionError(format!("Failed to execute transaction: {}", e))
+            })?;
+        info!("ContainerExecutionEngine: Successfully executed transaction in container");
+
+        // Parse the API response
+        let output =
+            serde_json::from_slice::<TransactionResponse>(&api_response.payload).map_err(|e| {
+                error!(
+                    "ContainerExecutionEngine: Failed to parse API response: {}",
+                    e
+                );
+                ExecutionError::InternalError(e.to_string())
+            })?;
+        info!("ContainerExecutionEngine: Successfully parsed API response");
+        let state = self.contract_state(request, &output.state_diffs)?;
+        Ok((output, state, api_response.header))
+    }
+
+    /// Address serving the engine protocol for a contract, `None` if it is called over HTTP
+    async fn channel_address(&self, contract: &H128) -> Result<Option<SocketAddr>, ExecutionError> {
+        let config = &self.config.state_channel;
+        if let Some(address) = config.contracts.get(contract) {
+            return Ok(Some(*address));
+        }
+        let Some(port) = config.port else {
+            return Ok(None);
+        };
+
+        let container = self
+            .container_env
+            .get_container(&h128_to_uuid(contract))
+            .await
+            .map_err(|e| ExecutionError::ModuleNotFound(e.to_string()))?;
+        if container.info.status != ContainerStatus::Running {
+            return Err(ExecutionError::ExecutionError(format!(
+                "Container of contract {:?} is not running",
+                contract
+            )));
+        }
+        Ok(Some(SocketAddr::new(container.info.address.ip(), port)))
+    }
+
     /// Create an API request transaction for execution
     fn create_api_request(
         &self,
@@ -79,68 +325,78 @@ impl ExecutionEngine for ContainerExecutionEngine {
             request.tx_hash, request.transaction_type
         );
 
-        // Create an API request transaction
-        let api_request = self.create_api_request(&request)?;
-
-        // Execute the transaction in the container environment
-        info!(
-            "Executing transaction in container environment: {:?}",
-            api_request.id
-        );
+        // The request alone may already cost more gas than allowed
+        let mut usage = ResourceUsage {
+            payload_bytes: request.input.len() as u64,
+            ..Default::default()
+        };
+        self.config
+            .limits
+            .check(&usage, self.config.gas.gas(&usage))?;
 
-        match self.container_env.execute_transaction(api_request).await {
-            Ok(api_response) => {
-                info!("ContainerExecutionEngine: Successfully executed transaction in container");
-
-                // Parse the API response
-                match serde_json::from_slice(&api_response.payload) {
-                    Ok(output) => {
-                        info!("ContainerExecutionEngine: Successfully parsed API response");
-                        // For now, we just create an empty state diff and basic metadata
-                        // In a real implementation, we would track state changes during execution
-                        let state_diff = StateDiff::default();
-                        let metadata = ExecutionMetadata {
-                            tx_hash: request.tx_hash,
-                            executed_at: chrono::Utc::now(),
-                            gas_used: 1000, // Simulated gas usage
-                        };
-
-                        // Create the execution result
-                        let result = ExecutionResult {
-                            input: request.input.clone(),
-                            output,
-                            state_diff,
-                            metadata,
-                            headers: api_response.header.clone(),
-                        };
-
-                        info!(
-                            "EXECUTOR - Returning execution result with output: {}",
-                            serde_json::to_string_pretty(&result.output).unwrap_or_default()
-                        );
-
-                        Ok(result)
-                    }
-                    Err(e) => {
-                        error!(
-                            "ContainerExecutionEngine: Failed to parse API response: {}",
-                            e
-                        );
-                        Err(ExecutionError::InternalError(e.to_string()))
-                    }
+        let stats_before = self.container_stats(&request.transaction_type).await;
+        let started = Instant::now();
+        let call = async {
+            match self.config.limits.max_wall_time_ms {
+                Some(limit) => {
+                    tokio::time::timeout(Duration::from_millis(limit), self.call_contract(request))
+                        .await
+                        .map_err(|_| ExecutionError::ResourceLimitExceeded {
+                            resource: Resource::WallTime,
+                            used: started.elapsed().as_millis() as u64,
+                            limit,
+                        })
                 }
+                None => Ok(self.call_contract(request).await),
             }
-            Err(e) => {
-                error!(
-                    "ContainerExecutionEngine: Failed to execute transaction in container: {}",
-                    e
-                );
-                Err(ExecutionError::ExecutionError(format!(
-                    "Failed to execute transaction: {}",
-                    e
-                )))
+        };
+        let monitor = self.enforce_limits(&request.transaction_type, stats_before, started);
+        let outcome = tokio::select! {
+            outcome = call => outcome?,
+            exceeded = monitor => {
+                warn!("Execution of tx {} aborted: {}", request.tx_hash, exceeded);
+                return Err(exceeded);
             }
+        };
+        usage.wall_time_ms = started.elapsed().as_millis() as u64;
+        if let (Some(before), Some(after)) = (
+            stats_before,
+            self.container_stats(&request.transaction_type).await,
+        ) {
+            usage.cpu_time_ns = after.cpu_time_ns.saturating_sub(before.cpu_time_ns);
+            usage.memory_bytes = before.memory_bytes.max(after.memory_bytes);
         }
+
+        let (output, state, headers) = outcome?;
+        usage.outbound_requests = output.outbound_requests;
+        usage.state_bytes_read = state.bytes_read();
+        usage.state_bytes_written = state.bytes_written();
+        let gas_used = self.config.gas.gas(&usage);
+        self.config.limits.check(&usage, gas_used)?;
+        let metadata = ExecutionMetadata {
+            tx_hash: request.tx_hash,
+            executed_at: chrono::Utc::now(),
+            gas_used,
+            resource_usage: usage,
+        };
+
+        // Create the execution result
+        let result = ExecutionResult {
+            input: request.input.clone(),
+            output,
+            state_diff: state.get_state_diff(),
+            read_set: state.read_set().clone(),
+            metadata,
+            headers,
+        };
+
+        info!(
+            "EXECUTOR - Returning execution result with output: {}",
+            serde_json::to_string_pretty(&result.output).unwrap_or_default()
+        );
+        self.record_execution(request, &result);
+
+        Ok(result)
     }
 
     async fn start(&self, module_id: &Uuid) -> Result<(), anyhow::Error> {
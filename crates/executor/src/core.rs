@@ -5,11 +5,13 @@ use mp_common::types::TransactionType;
 use mp_common::TransactionResponse;
 use mp_poc::bls::SignedAggregate;
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
-use crate::error::ExecutionError;
+use crate::error::{ExecutionError, ExecutionErrorKind};
 use crate::metadata::ExecutionMetadata;
-use crate::state::StateDiff;
+use crate::state::{StateDiff, StateValues};
 
 /// Execution request payload
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -26,6 +28,15 @@ pub struct ExecutionRequest {
     /// HTTP headers
     #[serde(skip)]
     pub header: HeaderMap<HeaderValue>,
+    /// Cancelled when the execution is no longer awaited, engines stop promptly
+    #[serde(skip)]
+    pub cancel: CancellationToken,
+    /// Writes of preceding transactions not yet in the node state
+    #[serde(skip)]
+    pub pending_writes: Arc<StateValues>,
+    /// State root to execute against, the current node state when unset
+    #[serde(skip)]
+    pub state_root: Option<String>,
 }
 
 /// Execution result structure
@@ -36,6 +47,9 @@ pub struct ExecutionResult {
     pub output: TransactionResponse,
     /// State changes caused by execution
     pub state_diff: StateDiff,
+    /// State values the execution read, as it saw them
+    #[serde(default)]
+    pub read_set: StateValues,
     /// Blockchain-related metadata
     pub metadata: ExecutionMetadata,
     /// HTTP headers
@@ -43,6 +57,45 @@ pub struct ExecutionResult {
     pub headers: HeaderMap<HeaderValue>,
 }
 
+/// Execution that failed, sent back in place of its result
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExecutionFailure {
+    pub input: Vec<u8>,
+    /// Kind of the error the execution failed with
+    pub kind: ExecutionErrorKind,
+    /// Message of the error
+    pub message: String,
+    /// Blockchain-related metadata
+    pub metadata: ExecutionMetadata,
+}
+
+impl ExecutionFailure {
+    /// Failure of `request` with `error`
+    pub fn new(request: &ExecutionRequest, error: &ExecutionError) -> Self {
+        Self {
+            input: request.input.clone(),
+            kind: error.kind(),
+            message: error.to_string(),
+            metadata: ExecutionMetadata::new(request.tx_hash),
+        }
+    }
+
+    /// HTTP status reported to clients for the failure
+    pub fn status_code(&self) -> u16 {
+        self.kind.status_code()
+    }
+
+    /// JSON recorded and signed for the failure in place of an output
+    pub fn output(&self) -> serde_json::Value {
+        serde_json::json!({
+            "error": {
+                "kind": self.kind,
+                "message": self.message,
+            }
+        })
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ExecutionResponse {
     pub result: ExecutionResult,
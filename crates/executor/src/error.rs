@@ -1,5 +1,8 @@
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use crate::metering::Resource;
+
 /// Error types for the execution engine
 #[derive(Debug, Error)]
 pub enum ExecutionError {
@@ -15,13 +18,17 @@ pub enum ExecutionError {
     #[error("Invalid input format: {0}")]
     InvalidInput(String),
 
-    /// Execution timed out
+    /// Execution timed out and was cancelled
     #[error("Execution timed out after {0}ms")]
-    ExecutionTimeout(u64),
+    Timeout(u64),
 
     /// Resource limit exceeded
-    #[error("Resource limit exceeded: {0}")]
-    ResourceLimitExceeded(String),
+    #[error("Resource limit exceeded: {resource} used {used}, limit {limit}")]
+    ResourceLimitExceeded {
+        resource: Resource,
+        used: u64,
+        limit: u64,
+    },
 
     /// Internal execution error
     #[error("Execution error: {0}")]
@@ -39,3 +46,54 @@ pub enum ExecutionError {
     #[error("Internal error: {0}")]
     InternalError(String),
 }
+
+/// Kind of an execution error, without its details
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExecutionErrorKind {
+    ModuleNotFound,
+    HandlerNotFound,
+    InvalidInput,
+    Timeout,
+    ResourceLimitExceeded,
+    ExecutionError,
+    StateError,
+    CommunicationError,
+    InternalError,
+}
+
+impl ExecutionErrorKind {
+    /// HTTP status reported to clients for errors of this kind
+    pub fn status_code(&self) -> u16 {
+        match self {
+            ExecutionErrorKind::ModuleNotFound | ExecutionErrorKind::HandlerNotFound => 404,
+            ExecutionErrorKind::InvalidInput => 400,
+            ExecutionErrorKind::Timeout => 504,
+            _ => 500,
+        }
+    }
+}
+
+impl ExecutionError {
+    /// Kind of the error
+    pub fn kind(&self) -> ExecutionErrorKind {
+        match self {
+            ExecutionError::ModuleNotFound(_) => ExecutionErrorKind::ModuleNotFound,
+            ExecutionError::HandlerNotFound(_) => ExecutionErrorKind::HandlerNotFound,
+            ExecutionError::InvalidInput(_) => ExecutionErrorKind::InvalidInput,
+            ExecutionError::Timeout(_) => ExecutionErrorKind::Timeout,
+            ExecutionError::ResourceLimitExceeded { .. } => {
+                ExecutionErrorKind::ResourceLimitExceeded
+            }
+            ExecutionError::ExecutionError(_) => ExecutionErrorKind::ExecutionError,
+            ExecutionError::StateError(_) => ExecutionErrorKind::StateError,
+            ExecutionError::CommunicationError(_) => ExecutionErrorKind::CommunicationError,
+            ExecutionError::InternalError(_) => ExecutionErrorKind::InternalError,
+        }
+    }
+
+    /// HTTP status reported to clients for the error
+    pub fn status_code(&self) -> u16 {
+        self.kind().status_code()
+    }
+}
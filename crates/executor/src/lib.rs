@@ -1,10 +1,15 @@
 pub mod bridge;
+pub mod channel;
 // pub mod compute; // 保留旧模块，稍后将被弃用
 pub mod config;
 pub mod container; // 新的容器模块
 pub mod core;
 pub mod error;
 pub mod metadata;
+pub mod metering;
+pub mod parallel;
+pub mod replay;
+pub mod scheduler;
 pub mod state;
 
 use anyhow::Result;
@@ -12,6 +17,7 @@ use anyhow::Result;
 use crate::container::ContainerExecutionEngine;
 use crate::core::ExecutionEngine;
 pub use mp_container::utils;
+use mp_state::StateStorage;
 use std::sync::Arc;
 use tracing::{info, warn};
 
@@ -31,15 +37,22 @@ impl Default for ExecutionEngineType {
 }
 
 /// Create a new execution engine based on the configuration
+///
+/// State changes of executions are diffed against `state_storage` when given.
 pub async fn create_execution_engine(
     config: config::ExecutorConfig,
+    state_storage: Option<Arc<dyn StateStorage>>,
 ) -> Result<Arc<dyn ExecutionEngine>> {
     match config.engine_type {
         ExecutionEngineType::Network => {
             if let Some(container_env) = &config.container_environment {
                 // Create a container-based execution engine
                 info!("Creating container-based execution engine");
-                let engine = ContainerExecutionEngine::new(config.clone(), container_env.clone())?;
+                let engine = ContainerExecutionEngine::new(
+                    config.clone(),
+                    container_env.clone(),
+                    state_storage,
+                )?;
 
                 Ok(Arc::new(engine))
             } else {
@@ -2,6 +2,8 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::metering::ResourceUsage;
+
 /// Execution metadata for blockchain integration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ExecutionMetadata {
@@ -11,6 +13,9 @@ pub struct ExecutionMetadata {
     pub executed_at: DateTime<Utc>,
     /// Gas used by execution
     pub gas_used: u64,
+    /// Resources the gas was charged for
+    #[serde(default)]
+    pub resource_usage: ResourceUsage,
 }
 
 impl ExecutionMetadata {
@@ -20,6 +25,7 @@ impl ExecutionMetadata {
             tx_hash,
             executed_at: Utc::now(),
             gas_used: 0,
+            resource_usage: ResourceUsage::default(),
         }
     }
 
@@ -0,0 +1,193 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::error::ExecutionError;
+
+const NANOS_PER_MILLI: u64 = 1_000_000;
+/// Interval at which a running call is checked against the limits, unless configured otherwise
+pub const DEFAULT_MONITOR_INTERVAL_MS: u64 = 100;
+
+/// Resources consumed by one execution
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResourceUsage {
+    /// Bytes of the request payload
+    #[serde(default)]
+    pub payload_bytes: u64,
+    /// CPU time the contract container spent on the call, in nanoseconds
+    pub cpu_time_ns: u64,
+    /// Memory used by the contract container, in bytes
+    pub memory_bytes: u64,
+    /// Bytes of node state read while diffing the reported changes
+    pub state_bytes_read: u64,
+    /// Bytes of node state written by the call
+    pub state_bytes_written: u64,
+    /// Outbound requests the contract made through the node proxy
+    pub outbound_requests: u64,
+    /// Wall time of the call, in milliseconds
+    pub wall_time_ms: u64,
+}
+
+/// Metered resource, named in limit errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Resource {
+    /// Gas charged for the whole execution
+    Gas,
+    /// Container CPU time
+    CpuTime,
+    /// Container memory
+    Memory,
+    /// Bytes of state written
+    StateWrites,
+    /// Outbound proxied requests
+    OutboundRequests,
+    /// Wall time of the call
+    WallTime,
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Resource::Gas => "gas",
+            Resource::CpuTime => "CPU time (ms)",
+            Resource::Memory => "memory (bytes)",
+            Resource::StateWrites => "state writes (bytes)",
+            Resource::OutboundRequests => "outbound requests",
+            Resource::WallTime => "wall time (ms)",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Gas price of the deterministic parts of an execution
+///
+/// Only the request and the state it reads and writes are priced, so the same
+/// call costs the same gas on every node. CPU time, memory, wall time and
+/// outbound requests vary between runs, they are bounded by [`ResourceLimits`]
+/// instead.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GasSchedule {
+    /// Flat cost of every execution
+    pub base: u64,
+    /// Cost per byte of request payload
+    pub per_payload_byte: u64,
+    /// Cost per byte of state read
+    pub per_state_byte_read: u64,
+    /// Cost per byte of state written
+    pub per_state_byte_written: u64,
+}
+
+impl Default for GasSchedule {
+    fn default() -> Self {
+        Self {
+            base: 1000,
+            per_payload_byte: 1,
+            per_state_byte_read: 1,
+            per_state_byte_written: 5,
+        }
+    }
+}
+
+impl GasSchedule {
+    /// Gas charged for the given usage, saturating at `u64::MAX`
+    pub fn gas(&self, usage: &ResourceUsage) -> u64 {
+        [
+            (1, self.base),
+            (usage.payload_bytes, self.per_payload_byte),
+            (usage.state_bytes_read, self.per_state_byte_read),
+            (usage.state_bytes_written, self.per_state_byte_written),
+        ]
+        .into_iter()
+        .fold(0u64, |gas, (units, price)| {
+            gas.saturating_add(units.saturating_mul(price))
+        })
+    }
+}
+
+/// Limits applied to every execution, unset limits are not enforced
+///
+/// An execution over any limit fails and its state changes are discarded.
+/// Calls whose payload alone costs more gas than allowed are not made. Wall
+/// time, container CPU time and memory are checked while the call runs, which
+/// is aborted once one of them is over its limit. The contract container itself
+/// keeps running. State access and outbound requests are reported with the
+/// response and checked, with the gas they cost, once the call returns.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ResourceLimits {
+    /// Maximum gas charged for one execution
+    pub max_gas: Option<u64>,
+    /// Maximum container CPU time in milliseconds
+    pub max_cpu_time_ms: Option<u64>,
+    /// Maximum container memory in bytes
+    pub max_memory_bytes: Option<u64>,
+    /// Maximum bytes of state written
+    pub max_state_bytes_written: Option<u64>,
+    /// Maximum outbound proxied requests
+    pub max_outbound_requests: Option<u64>,
+    /// Maximum wall time in milliseconds, the call is aborted once it is reached
+    pub max_wall_time_ms: Option<u64>,
+    /// Interval in milliseconds at which the usage of a running call is checked
+    pub monitor_interval_ms: u64,
+}
+
+impl Default for ResourceLimits {
+    fn default() -> Self {
+        Self {
+            max_gas: None,
+            max_cpu_time_ms: None,
+            max_memory_bytes: None,
+            max_state_bytes_written: None,
+            max_outbound_requests: None,
+            max_wall_time_ms: None,
+            monitor_interval_ms: DEFAULT_MONITOR_INTERVAL_MS,
+        }
+    }
+}
+
+impl ResourceLimits {
+    /// Whether CPU time or memory are limited, which requires container stats
+    pub fn limits_container_stats(&self) -> bool {
+        self.max_cpu_time_ms.is_some() || self.max_memory_bytes.is_some()
+    }
+
+    /// Check an execution's usage and gas against the limits
+    pub fn check(&self, usage: &ResourceUsage, gas: u64) -> Result<(), ExecutionError> {
+        let checks = [
+            (
+                Resource::WallTime,
+                usage.wall_time_ms,
+                self.max_wall_time_ms,
+            ),
+            (
+                Resource::CpuTime,
+                usage.cpu_time_ns.div_ceil(NANOS_PER_MILLI),
+                self.max_cpu_time_ms,
+            ),
+            (Resource::Memory, usage.memory_bytes, self.max_memory_bytes),
+            (
+                Resource::StateWrites,
+                usage.state_bytes_written,
+                self.max_state_bytes_written,
+            ),
+            (
+                Resource::OutboundRequests,
+                usage.outbound_requests,
+                self.max_outbound_requests,
+            ),
+            (Resource::Gas, gas, self.max_gas),
+        ];
+
+        for (resource, used, limit) in checks {
+            if let Some(limit) = limit.filter(|limit| used > *limit) {
+                return Err(ExecutionError::ResourceLimitExceeded {
+                    resource,
+                    used,
+                    limit,
+                });
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,138 @@
+use futures::future::join_all;
+use mp_common::types::TransactionType;
+use mp_common::H128;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tracing::{debug, info};
+
+use crate::bridge::execute_with_timeout;
+use crate::config::ExecutionTimeouts;
+use crate::core::{ExecutionEngine, ExecutionRequest, ExecutionResult};
+use crate::error::ExecutionError;
+use crate::state::StateValues;
+
+/// Outcome of one transaction of a batch
+pub type BatchResult = Result<ExecutionResult, ExecutionError>;
+
+/// Optimistic parallel executor for batches of transactions in log order
+///
+/// Transactions run concurrently on the state as of the start of the batch,
+/// except calls to the same contract, which run one after the other in log order
+/// since they share keys. Results are then validated in log order against the
+/// read sets: a transaction that read a key written by a preceding transaction
+/// of the batch is executed again on top of every preceding write. The results
+/// and their state diffs therefore match a sequential execution.
+pub struct ParallelExecutor {
+    engine: Arc<dyn ExecutionEngine>,
+    timeouts: ExecutionTimeouts,
+    parallelism: usize,
+}
+
+impl ParallelExecutor {
+    /// Create an executor running at most `parallelism` executions at once
+    pub fn new(
+        engine: Arc<dyn ExecutionEngine>,
+        timeouts: ExecutionTimeouts,
+        parallelism: usize,
+    ) -> Self {
+        Self {
+            engine,
+            timeouts,
+            parallelism: parallelism.max(1),
+        }
+    }
+
+    /// Execute a batch, returning the results in the order of `requests`
+    pub async fn execute_batch(&self, requests: Vec<ExecutionRequest>) -> Vec<BatchResult> {
+        let permits = Semaphore::new(self.parallelism);
+        let runs = lanes(&requests).into_iter().map(|lane| {
+            let requests = &requests;
+            let permits = &permits;
+            async move {
+                // Later calls of a lane see the writes of the earlier ones
+                let mut lane_writes = StateValues::new();
+                let mut results = Vec::with_capacity(lane.len());
+                for index in lane {
+                    let mut request = requests[index].clone();
+                    request.pending_writes = Arc::new(lane_writes.clone());
+                    let result = {
+                        let _permit = permits.acquire().await.expect("Semaphore is never closed");
+                        self.execute(&mut request).await
+                    };
+                    if let Ok(result) = &result {
+                        lane_writes.extend(result.state_diff.write_set());
+                    }
+                    results.push((index, result));
+                }
+                results
+            }
+        });
+
+        let mut results: Vec<Option<BatchResult>> = requests.iter().map(|_| None).collect();
+        for (index, result) in join_all(runs).await.into_iter().flatten() {
+            results[index] = Some(result);
+        }
+
+        // Validate in log order, executing again what read stale values
+        let mut committed = StateValues::new();
+        let mut reexecuted = 0;
+        for (index, slot) in results.iter_mut().enumerate() {
+            let mut result = slot
+                .take()
+                .expect("Every transaction of a lane is executed");
+            if matches!(&result, Ok(executed) if !is_valid(&executed.read_set, &committed)) {
+                debug!(
+                    "Executing tx {} again after a conflict",
+                    requests[index].tx_hash
+                );
+                let mut request = requests[index].clone();
+                request.pending_writes = Arc::new(committed.clone());
+                result = self.execute(&mut request).await;
+                reexecuted += 1;
+            }
+            if let Ok(executed) = &result {
+                committed.extend(executed.state_diff.write_set());
+            }
+            *slot = Some(result);
+        }
+
+        info!(
+            "Executed a batch of {} transactions, {} executed again after conflicts",
+            requests.len(),
+            reexecuted
+        );
+        results.into_iter().flatten().collect()
+    }
+
+    async fn execute(&self, request: &mut ExecutionRequest) -> BatchResult {
+        let timeout = self.timeouts.timeout_of(&request.transaction_type);
+        execute_with_timeout(self.engine.as_ref(), request, timeout).await
+    }
+}
+
+/// Group the requests into lanes that may run concurrently, calls to one contract share a lane
+fn lanes(requests: &[ExecutionRequest]) -> Vec<Vec<usize>> {
+    let mut lanes: Vec<Vec<usize>> = Vec::new();
+    let mut contract_lanes: HashMap<H128, usize> = HashMap::new();
+    for (index, request) in requests.iter().enumerate() {
+        match request.transaction_type {
+            TransactionType::Request(contract, _) => {
+                let lane = *contract_lanes.entry(contract).or_insert_with(|| {
+                    lanes.push(Vec::new());
+                    lanes.len() - 1
+                });
+                lanes[lane].push(index);
+            }
+            _ => lanes.push(vec![index]),
+        }
+    }
+    lanes
+}
+
+/// Whether every value in the read set is still the latest one
+fn is_valid(read_set: &StateValues, committed: &StateValues) -> bool {
+    read_set
+        .iter()
+        .all(|(key, value)| committed.get(key).is_none_or(|latest| latest == value))
+}
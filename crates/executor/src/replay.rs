@@ -0,0 +1,287 @@
+use anyhow::{anyhow, Result};
+use http::header::{HeaderName, AUTHORIZATION};
+use http::{HeaderMap, HeaderValue, Method};
+use mp_common::types::{Transaction, TransactionType};
+use mp_poc::generator;
+use mp_state::transactions::{ExecutionRecord, TransactionQuery};
+use mp_state::StateStorage;
+use std::sync::Arc;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::bridge::execute_with_timeout;
+use crate::config::ExecutionTimeouts;
+use crate::core::{ExecutionEngine, ExecutionRequest, ExecutionResult};
+use crate::error::ExecutionError;
+
+/// Request headers carrying client credentials, never recorded
+const CREDENTIAL_HEADERS: [&str; 1] = ["x-api-key"];
+
+/// Hex-encoded PoC root the node signs for an execution result
+pub fn poc_root(result: &ExecutionResult) -> Result<String> {
+    let output = serde_json::to_vec(&result.output.output)?;
+    let root = generator::generate_root(vec![(result.input.clone(), output)])
+        .map_err(|e| anyhow!("Failed to generate PoC root: {:?}", e))?;
+    Ok(format!("{:x}", root))
+}
+
+/// Record of an execution, what a replay needs to run it again and compare its outcome
+///
+/// Credentials are left out of the recorded headers.
+pub fn execution_record(
+    request: &ExecutionRequest,
+    result: &ExecutionResult,
+) -> Result<ExecutionRecord> {
+    let headers = request
+        .header
+        .iter()
+        .filter(|(name, _)| *name != AUTHORIZATION && !CREDENTIAL_HEADERS.contains(&name.as_str()))
+        .map(|(name, value)| Ok((name.to_string(), value.to_str()?.to_string())))
+        .collect::<Result<_>>()?;
+
+    Ok(ExecutionRecord {
+        tx_id: request.tx_hash,
+        method: request.method.to_string(),
+        headers,
+        state_root: result.state_diff.prev_root.clone(),
+        output: result.output.output.clone(),
+        poc_root: poc_root(result)?,
+    })
+}
+
+/// Transactions of the history to replay
+#[derive(Debug, Clone)]
+pub enum ReplaySelection {
+    /// Transactions within an inclusive range of log indices
+    LogIndices { from: u64, to: u64 },
+    /// Transactions with the given IDs
+    Transactions(Vec<Uuid>),
+}
+
+/// Outcome of replaying a transaction
+#[derive(Debug)]
+pub enum ReplayOutcome {
+    /// Same output and PoC root as recorded
+    Matched,
+    /// The output or the PoC root differ from the recorded ones
+    Diverged {
+        recorded: ExecutionRecord,
+        output: serde_json::Value,
+        poc_root: String,
+    },
+    /// No execution of the transaction was recorded, it was not replayed
+    NotRecorded,
+    /// Not a request, re-run without comparison if it changes containers
+    Unchecked,
+    /// The replay failed to execute
+    Failed(ExecutionError),
+}
+
+/// Replay of one transaction
+#[derive(Debug)]
+pub struct ReplayReport {
+    /// ID of the replayed transaction
+    pub tx_id: Uuid,
+    /// Index of the transaction in the Raft log
+    pub log_index: u64,
+    /// How the replay compares with the recorded execution
+    pub outcome: ReplayOutcome,
+}
+
+impl ReplayReport {
+    /// Whether the replay contradicts the recorded execution
+    pub fn is_mismatch(&self) -> bool {
+        matches!(
+            self.outcome,
+            ReplayOutcome::Diverged { .. } | ReplayOutcome::Failed(_)
+        )
+    }
+}
+
+/// Re-executes recorded transactions and compares them with their recorded executions
+///
+/// Requests run against the node state root they originally read, which
+/// `storage` must have gone through. Containers are recreated from the same
+/// images by re-running the recorded lifecycle transactions, so the engine
+/// must not share its container environment with a live node.
+pub struct Replayer {
+    engine: Arc<dyn ExecutionEngine>,
+    storage: Arc<dyn StateStorage>,
+    timeouts: ExecutionTimeouts,
+}
+
+impl Replayer {
+    /// Create a replayer of the transactions recorded in `storage`
+    pub fn new(
+        engine: Arc<dyn ExecutionEngine>,
+        storage: Arc<dyn StateStorage>,
+        timeouts: ExecutionTimeouts,
+    ) -> Self {
+        Self {
+            engine,
+            storage,
+            timeouts,
+        }
+    }
+
+    /// Recorded transactions matching `selection`, in log order
+    pub fn select(&self, selection: &ReplaySelection) -> Result<Vec<Transaction>> {
+        let mut transactions = match selection {
+            ReplaySelection::LogIndices { from, to } => self.history(*from, *to)?,
+            ReplaySelection::Transactions(ids) => ids
+                .iter()
+                .map(|id| {
+                    self.storage
+                        .get_transaction(id)?
+                        .ok_or_else(|| anyhow!("Transaction {} is not in the history", id))
+                })
+                .collect::<Result<_>>()?,
+        };
+        transactions.sort_by_key(|transaction| transaction.log_index);
+        Ok(transactions)
+    }
+
+    /// Re-run the container lifecycle transactions recorded before `log_index`
+    ///
+    /// Returns the number of transactions re-run.
+    pub async fn restore_containers(&self, log_index: u64) -> Result<usize> {
+        let Some(last) = log_index.checked_sub(1) else {
+            return Ok(0);
+        };
+        let lifecycle: Vec<_> = self
+            .history(0, last)?
+            .into_iter()
+            .filter(|transaction| changes_containers(&transaction.tx_type))
+            .collect();
+        for transaction in &lifecycle {
+            self.execute(transaction, Method::default(), HeaderMap::new(), None)
+                .await
+                .map_err(|e| anyhow!("Failed to re-run tx {}: {}", transaction.id, e))?;
+        }
+        Ok(lifecycle.len())
+    }
+
+    /// Replay a transaction and compare the outcome with its recorded execution
+    pub async fn replay(&self, transaction: &Transaction) -> Result<ReplayReport> {
+        let outcome = if transaction.tx_type.is_request() {
+            match self.storage.get_execution(&transaction.id)? {
+                Some(recorded) => self.replay_request(transaction, recorded).await,
+                None => ReplayOutcome::NotRecorded,
+            }
+        } else if changes_containers(&transaction.tx_type) {
+            match self
+                .execute(transaction, Method::default(), HeaderMap::new(), None)
+                .await
+            {
+                Ok(_) => ReplayOutcome::Unchecked,
+                Err(e) => ReplayOutcome::Failed(e),
+            }
+        } else {
+            ReplayOutcome::Unchecked
+        };
+
+        Ok(ReplayReport {
+            tx_id: transaction.id,
+            log_index: transaction.log_index,
+            outcome,
+        })
+    }
+
+    async fn replay_request(
+        &self,
+        transaction: &Transaction,
+        recorded: ExecutionRecord,
+    ) -> ReplayOutcome {
+        let (method, header) = match recorded_request(&recorded) {
+            Ok(request) => request,
+            Err(e) => return ReplayOutcome::Failed(ExecutionError::InvalidInput(e.to_string())),
+        };
+        let state_root = (!recorded.state_root.is_empty()).then(|| recorded.state_root.clone());
+
+        let result = match self.execute(transaction, method, header, state_root).await {
+            Ok(result) => result,
+            Err(e) => return ReplayOutcome::Failed(e),
+        };
+        let poc_root = match poc_root(&result) {
+            Ok(root) => root,
+            Err(e) => return ReplayOutcome::Failed(ExecutionError::InternalError(e.to_string())),
+        };
+
+        if result.output.output == recorded.output && poc_root == recorded.poc_root {
+            ReplayOutcome::Matched
+        } else {
+            warn!("Replay of tx {} diverged from its record", transaction.id);
+            ReplayOutcome::Diverged {
+                recorded,
+                output: result.output.output,
+                poc_root,
+            }
+        }
+    }
+
+    async fn execute(
+        &self,
+        transaction: &Transaction,
+        method: Method,
+        header: HeaderMap<HeaderValue>,
+        state_root: Option<String>,
+    ) -> Result<ExecutionResult, ExecutionError> {
+        info!(
+            "Replaying tx {} at log index {}",
+            transaction.id, transaction.log_index
+        );
+        let mut request = ExecutionRequest {
+            transaction_type: transaction.tx_type.clone(),
+            input: transaction.payload.clone(),
+            tx_hash: transaction.id,
+            method,
+            header,
+            cancel: Default::default(),
+            pending_writes: Default::default(),
+            state_root,
+        };
+        let timeout = self.timeouts.timeout_of(&request.transaction_type);
+        execute_with_timeout(self.engine.as_ref(), &mut request, timeout).await
+    }
+
+    /// Recorded transactions within an inclusive range of log indices, oldest first
+    fn history(&self, from: u64, to: u64) -> Result<Vec<Transaction>> {
+        let mut query = TransactionQuery {
+            min_log_index: Some(from),
+            max_log_index: Some(to),
+            ..Default::default()
+        };
+        let mut transactions = Vec::new();
+        loop {
+            let page = self.storage.query_transactions(&query)?;
+            transactions.extend(page.transactions);
+            match page.next_cursor {
+                Some(cursor) => query.cursor = Some(cursor),
+                None => break,
+            }
+        }
+        transactions.reverse();
+        Ok(transactions)
+    }
+}
+
+/// HTTP method and headers of a recorded request
+fn recorded_request(recorded: &ExecutionRecord) -> Result<(Method, HeaderMap<HeaderValue>)> {
+    let method = recorded.method.parse()?;
+    let mut header = HeaderMap::new();
+    for (name, value) in &recorded.headers {
+        header.append(HeaderName::try_from(name.as_str())?, value.parse()?);
+    }
+    Ok((method, header))
+}
+
+/// Whether a transaction creates, starts, stops or removes a container
+fn changes_containers(transaction_type: &TransactionType) -> bool {
+    matches!(
+        transaction_type,
+        TransactionType::CreateContainer
+            | TransactionType::StartContainer
+            | TransactionType::StopContainer
+            | TransactionType::RemoveContainer
+    )
+}
@@ -0,0 +1,203 @@
+use mp_common::types::TransactionType;
+use mp_common::H128;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+use tokio::sync::Notify;
+
+use crate::core::ExecutionRequest;
+
+/// Requests executed one at a time in arrival order, the contract they call or
+/// `None` for the transactions not calling a contract
+pub type Lane = Option<H128>;
+
+/// Lane of a request
+pub fn lane_of(transaction_type: &TransactionType) -> Lane {
+    match transaction_type {
+        TransactionType::Request(contract, _) => Some(*contract),
+        _ => None,
+    }
+}
+
+/// Snapshot of the requests waiting in the scheduler
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SchedulerMetrics {
+    /// Requests waiting per contract, not counting the ones executing
+    pub queue_depths: BTreeMap<H128, usize>,
+    /// Requests waiting that do not call a contract
+    pub other_queue_depth: usize,
+    /// Requests executing
+    pub running: usize,
+    /// Requests a worker took from another worker's contracts
+    pub stolen: u64,
+}
+
+/// Hands out requests to workers, keeping the requests of a lane in order
+///
+/// Each lane belongs to a worker chosen by hashing it, and at most one request
+/// of a lane executes at a time. A worker with none of its own lanes ready
+/// takes work from the lanes of the others.
+pub struct Scheduler {
+    state: Mutex<SchedulerState>,
+    /// Wakes up an idle worker
+    wakeups: Vec<Notify>,
+}
+
+#[derive(Default)]
+struct SchedulerState {
+    /// Waiting requests of every lane with any
+    pending: HashMap<Lane, VecDeque<ExecutionRequest>>,
+    /// Lanes with waiting requests, per owning worker
+    ready: Vec<VecDeque<Lane>>,
+    /// Lanes with a request executing
+    running: HashSet<Lane>,
+    /// Workers waiting for requests
+    idle: Vec<bool>,
+    /// No more requests are coming
+    closed: bool,
+    stolen: u64,
+}
+
+impl Scheduler {
+    /// Create a scheduler for `workers` workers, numbered from 0
+    pub fn new(workers: usize) -> Self {
+        let workers = workers.max(1);
+        Self {
+            state: Mutex::new(SchedulerState {
+                ready: vec![VecDeque::new(); workers],
+                idle: vec![false; workers],
+                ..Default::default()
+            }),
+            wakeups: (0..workers).map(|_| Notify::new()).collect(),
+        }
+    }
+
+    /// Worker owning a lane
+    pub fn worker_of(&self, lane: &Lane) -> usize {
+        let mut hasher = DefaultHasher::new();
+        lane.hash(&mut hasher);
+        (hasher.finish() % self.wakeups.len() as u64) as usize
+    }
+
+    /// Queue a request behind the waiting requests of its lane
+    pub fn push(&self, request: ExecutionRequest) {
+        let lane = lane_of(&request.transaction_type);
+        let owner = self.worker_of(&lane);
+        let mut state = self.state.lock().unwrap();
+        let queue = state.pending.entry(lane).or_default();
+        queue.push_back(request);
+        if queue.len() == 1 {
+            state.ready[owner].push_back(lane);
+        }
+
+        // Prefer the owner, any idle worker takes the request otherwise
+        let worker = if state.idle[owner] {
+            Some(owner)
+        } else {
+            state.idle.iter().position(|idle| *idle)
+        };
+        if let Some(worker) = worker {
+            state.idle[worker] = false;
+            self.wakeups[worker].notify_one();
+        }
+    }
+
+    /// Wait for the next request `worker` may execute
+    ///
+    /// Returns `None` once the scheduler is closed and drained. The lane of the
+    /// returned request stays blocked until it is completed.
+    pub async fn next(&self, worker: usize) -> Option<(Lane, ExecutionRequest)> {
+        loop {
+            {
+                let mut state = self.state.lock().unwrap();
+                if let Some(taken) = state.take(worker) {
+                    state.idle[worker] = false;
+                    return Some(taken);
+                }
+                if state.closed && state.pending.is_empty() {
+                    return None;
+                }
+                state.idle[worker] = true;
+            }
+            self.wakeups[worker].notified().await;
+        }
+    }
+
+    /// Unblock a lane once its request is done
+    pub fn complete(&self, lane: &Lane) {
+        let mut state = self.state.lock().unwrap();
+        state.running.remove(lane);
+        if state.closed && state.pending.is_empty() {
+            self.wake_idle(&mut state);
+        }
+    }
+
+    /// Stop accepting requests, workers stop once the waiting ones are done
+    pub fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        self.wake_idle(&mut state);
+    }
+
+    /// Snapshot of the waiting requests
+    pub fn metrics(&self) -> SchedulerMetrics {
+        let state = self.state.lock().unwrap();
+        let mut metrics = SchedulerMetrics {
+            running: state.running.len(),
+            stolen: state.stolen,
+            ..Default::default()
+        };
+        for (lane, queue) in &state.pending {
+            match lane {
+                Some(contract) => {
+                    metrics.queue_depths.insert(*contract, queue.len());
+                }
+                None => metrics.other_queue_depth = queue.len(),
+            }
+        }
+        metrics
+    }
+
+    fn wake_idle(&self, state: &mut SchedulerState) {
+        for (worker, idle) in state.idle.iter_mut().enumerate() {
+            if std::mem::take(idle) {
+                self.wakeups[worker].notify_one();
+            }
+        }
+    }
+}
+
+impl SchedulerState {
+    /// Take the first request of a lane that is not blocked, own lanes first
+    fn take(&mut self, worker: usize) -> Option<(Lane, ExecutionRequest)> {
+        let workers = self.ready.len();
+        for offset in 0..workers {
+            let owner = (worker + offset) % workers;
+            let Some(position) = self.ready[owner]
+                .iter()
+                .position(|lane| !self.running.contains(lane))
+            else {
+                continue;
+            };
+
+            // Requeue the lane behind the others so that one busy contract does not starve them
+            let lane = self.ready[owner].remove(position)?;
+            let queue = self.pending.get_mut(&lane)?;
+            let request = queue.pop_front()?;
+            if queue.is_empty() {
+                self.pending.remove(&lane);
+            } else {
+                self.ready[owner].push_back(lane);
+            }
+
+            self.running.insert(lane);
+            if offset > 0 {
+                self.stolen += 1;
+            }
+            return Some((lane, request));
+        }
+        None
+    }
+}
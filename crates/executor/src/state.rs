@@ -1,5 +1,19 @@
+use mp_common::H128;
+use mp_state::StateStorage;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+/// Values by key, `None` for deleted or absent keys
+pub type StateValues = BTreeMap<String, Option<String>>;
+
+/// Key of a contract's state value in the node state
+///
+/// Keys are prefixed with the contract address, so a contract only ever reaches
+/// its own state.
+pub fn contract_key(contract: &H128, key: &str) -> String {
+    format!("{:?}/{}", contract, key)
+}
 
 /// State operation types
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -50,6 +64,17 @@ impl StateDiff {
         self.new_root = format!("root_{}", self.operations.len());
     }
 
+    /// Final value of every key the diff changes
+    pub fn write_set(&self) -> StateValues {
+        self.operations
+            .iter()
+            .map(|operation| match operation {
+                StateOperation::Insert { key, value } => (key.clone(), Some(value.clone())),
+                StateOperation::Delete { key } => (key.clone(), None),
+            })
+            .collect()
+    }
+
     /// Convert from a map of modified values
     pub fn from_modifications(
         prev_root: String,
@@ -57,6 +82,8 @@ impl StateDiff {
     ) -> Self {
         let mut diff = Self::new(prev_root);
 
+        // Ordered by key so the same modifications always yield the same diff
+        let modifications: BTreeMap<String, Option<String>> = modifications.into_iter().collect();
         for (key, value_opt) in modifications {
             match value_opt {
                 Some(value) => diff.insert(key, value),
@@ -68,6 +95,15 @@ impl StateDiff {
     }
 }
 
+impl From<StateOperation> for mp_state::diff::StateOperation {
+    fn from(operation: StateOperation) -> Self {
+        match operation {
+            StateOperation::Insert { key, value } => Self::Insert { key, value },
+            StateOperation::Delete { key } => Self::Delete { key },
+        }
+    }
+}
+
 /// State observer trait for tracking changes
 pub trait StateObserver: Send + Sync {
     /// Get the current state diff
@@ -75,9 +111,18 @@ pub trait StateObserver: Send + Sync {
 }
 
 /// Default implementation using copy-on-write
+///
+/// Reads go through the own modifications, then the pending writes of preceding
+/// transactions, then the base state. The values read from outside the own
+/// modifications make up the read set.
 pub struct CopyOnWriteState {
     base_root: String,
+    base: Option<Arc<dyn StateStorage>>,
+    pending: Arc<StateValues>,
     modifications: HashMap<String, Option<String>>,
+    reads: StateValues,
+    bytes_read: u64,
+    bytes_written: u64,
 }
 
 impl CopyOnWriteState {
@@ -85,26 +130,90 @@ impl CopyOnWriteState {
     pub fn new(base_root: String) -> Self {
         Self {
             base_root,
+            base: None,
+            pending: Arc::default(),
             modifications: HashMap::new(),
+            reads: StateValues::new(),
+            bytes_read: 0,
+            bytes_written: 0,
         }
     }
 
-    /// Read a value
-    pub fn read(&self, key: &str) -> Option<String> {
-        // In a real implementation, we'd check the modifications first,
-        // then fall back to the base state
-        self.modifications.get(key).cloned().unwrap_or(None)
+    /// Create a copy-on-write state over the current state of `storage`
+    ///
+    /// Reads keep seeing the state as of creation, even if `storage` moves on.
+    pub fn from_storage(storage: Arc<dyn StateStorage>) -> anyhow::Result<Self> {
+        Ok(Self {
+            base_root: storage.create_checkpoint()?.new_root,
+            base: Some(storage),
+            pending: Arc::default(),
+            modifications: HashMap::new(),
+            reads: StateValues::new(),
+            bytes_read: 0,
+            bytes_written: 0,
+        })
+    }
+
+    /// Create a copy-on-write state over the state of `storage` at `root`
+    ///
+    /// Reads fail if `storage` never went through `root`.
+    pub fn from_storage_at(storage: Arc<dyn StateStorage>, root: String) -> Self {
+        Self {
+            base: Some(storage),
+            ..Self::new(root)
+        }
+    }
+
+    /// Read the writes of preceding transactions not yet in the base state
+    pub fn with_pending(mut self, pending: Arc<StateValues>) -> Self {
+        self.pending = pending;
+        self
+    }
+
+    /// Read a value, own modifications first, then pending writes, then the base state
+    pub fn read(&mut self, key: &str) -> anyhow::Result<Option<String>> {
+        let value = match self.modifications.get(key) {
+            Some(value) => value.clone(),
+            None => {
+                let value = match (self.pending.get(key), &self.base) {
+                    (Some(value), _) => value.clone(),
+                    (None, Some(base)) => base.get_at(key, &self.base_root)?,
+                    (None, None) => None,
+                };
+                self.reads.entry(key.to_string()).or_insert(value.clone());
+                value
+            }
+        };
+        self.bytes_read += (key.len() + value.as_ref().map_or(0, String::len)) as u64;
+        Ok(value)
+    }
+
+    /// Values read from outside the own modifications, as first seen
+    pub fn read_set(&self) -> &StateValues {
+        &self.reads
     }
 
     /// Write a value
     pub fn write(&mut self, key: String, value: String) {
+        self.bytes_written += (key.len() + value.len()) as u64;
         self.modifications.insert(key, Some(value));
     }
 
     /// Delete a value
     pub fn delete(&mut self, key: String) {
+        self.bytes_written += key.len() as u64;
         self.modifications.insert(key, None);
     }
+
+    /// Bytes of keys and values read so far
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    /// Bytes of keys and values written or deleted so far
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
 }
 
 impl StateObserver for CopyOnWriteState {
@@ -0,0 +1,275 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use mp_common::types::TransactionType;
+use mp_common::{TransactionResponse, H128};
+use mp_executor::bridge::ExecutionBridge;
+use mp_executor::config::ExecutionTimeouts;
+use mp_executor::core::{ExecutionEngine, ExecutionRequest, ExecutionResult};
+use mp_executor::error::{ExecutionError, ExecutionErrorKind};
+use mp_executor::metadata::ExecutionMetadata;
+use mp_executor::scheduler::Scheduler;
+use uuid::Uuid;
+
+const SLOW: H128 = H128([1; 16]);
+const FAST: H128 = H128([2; 16]);
+
+/// Engine that takes one second for the slow contract and answers the others at once
+#[derive(Default)]
+struct SleepyEngine {
+    cancelled: AtomicUsize,
+}
+
+#[async_trait]
+impl ExecutionEngine for SleepyEngine {
+    async fn execute(
+        &self,
+        request: &mut ExecutionRequest,
+    ) -> Result<ExecutionResult, ExecutionError> {
+        if matches!(request.transaction_type, TransactionType::Request(contract, _) if contract == SLOW)
+        {
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_secs(1)) => {}
+                _ = request.cancel.cancelled() => {
+                    self.cancelled.fetch_add(1, Ordering::SeqCst);
+                    return Err(ExecutionError::ExecutionError("cancelled".to_string()));
+                }
+            }
+        }
+
+        Ok(ExecutionResult {
+            input: request.input.clone(),
+            output: TransactionResponse::default(),
+            state_diff: Default::default(),
+            read_set: Default::default(),
+            metadata: ExecutionMetadata::new(request.tx_hash),
+            headers: Default::default(),
+        })
+    }
+
+    async fn start(&self, _module_id: &Uuid) -> Result<()> {
+        Ok(())
+    }
+
+    async fn stop(&self, _module_id: &Uuid) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn request(contract: H128) -> ExecutionRequest {
+    ExecutionRequest {
+        transaction_type: TransactionType::Request(contract, "run".to_string()),
+        input: vec![],
+        tx_hash: Uuid::new_v4(),
+        method: Default::default(),
+        header: Default::default(),
+        cancel: Default::default(),
+        pending_writes: Default::default(),
+        state_root: None,
+    }
+}
+
+#[test]
+fn test_contract_timeouts_override_default() {
+    let timeouts: ExecutionTimeouts = serde_json::from_value(serde_json::json!({
+        "default_ms": 500,
+        "contracts": { format!("{:?}", SLOW): 50 },
+    }))
+    .unwrap();
+
+    let call = |contract| TransactionType::Request(contract, String::new());
+    assert_eq!(timeouts.timeout_of(&call(SLOW)), Duration::from_millis(50));
+    assert_eq!(timeouts.timeout_of(&call(FAST)), Duration::from_millis(500));
+    assert_eq!(
+        timeouts.timeout_of(&TransactionType::ListContainers),
+        Duration::from_millis(500)
+    );
+}
+
+#[test]
+fn test_timeout_maps_to_gateway_timeout() {
+    assert_eq!(ExecutionError::Timeout(100).status_code(), 504);
+}
+
+#[tokio::test]
+async fn test_hung_execution_is_cancelled_and_frees_the_worker() {
+    let engine = Arc::new(SleepyEngine::default());
+    let timeouts = ExecutionTimeouts {
+        default_ms: 5_000,
+        contracts: HashMap::from([(SLOW, 50)]),
+    };
+    let mut bridge = ExecutionBridge::new(engine.clone(), 1, 10, timeouts);
+    let mut results = bridge.start(1).await.unwrap();
+    let requests = bridge.get_request_sender();
+
+    // The single worker gives up on the slow call and serves the next one
+    let slow = request(SLOW);
+    let slow_hash = slow.tx_hash;
+    let cancel = slow.cancel.clone();
+    requests.send(slow).await.unwrap();
+    let fast = request(FAST);
+    let fast_hash = fast.tx_hash;
+    requests.send(fast).await.unwrap();
+
+    // The hung call is answered with its failure instead of being dropped
+    let failure = results.recv().await.unwrap().unwrap_err();
+    assert_eq!(failure.metadata.tx_hash, slow_hash);
+    assert_eq!(failure.kind, ExecutionErrorKind::Timeout);
+    assert_eq!(failure.status_code(), 504);
+    assert_eq!(failure.output()["error"]["kind"], "timeout");
+    let result = tokio::time::timeout(Duration::from_millis(500), results.recv())
+        .await
+        .expect("worker still busy with the hung execution")
+        .unwrap()
+        .unwrap();
+    assert_eq!(result.metadata.tx_hash, fast_hash);
+    assert!(cancel.is_cancelled());
+    assert_eq!(engine.cancelled.load(Ordering::SeqCst), 1);
+}
+
+/// Engine taking 20ms per call, logging the calls of every contract as they start
+#[derive(Default)]
+struct LoggingEngine {
+    calls: Mutex<HashMap<H128, Vec<u8>>>,
+    running: Mutex<HashMap<H128, usize>>,
+    overlapped: AtomicUsize,
+    max_running: AtomicUsize,
+    total_running: AtomicUsize,
+}
+
+#[async_trait]
+impl ExecutionEngine for LoggingEngine {
+    async fn execute(
+        &self,
+        request: &mut ExecutionRequest,
+    ) -> Result<ExecutionResult, ExecutionError> {
+        let TransactionType::Request(contract, _) = request.transaction_type else {
+            unreachable!()
+        };
+        self.calls
+            .lock()
+            .unwrap()
+            .entry(contract)
+            .or_default()
+            .push(request.input[0]);
+        let overlapping = {
+            let mut running = self.running.lock().unwrap();
+            let count = running.entry(contract).or_default();
+            *count += 1;
+            *count > 1
+        };
+        if overlapping {
+            self.overlapped.fetch_add(1, Ordering::SeqCst);
+        }
+        let total = self.total_running.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max_running.fetch_max(total, Ordering::SeqCst);
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        self.total_running.fetch_sub(1, Ordering::SeqCst);
+        *self.running.lock().unwrap().get_mut(&contract).unwrap() -= 1;
+        Ok(ExecutionResult {
+            input: request.input.clone(),
+            output: TransactionResponse::default(),
+            state_diff: Default::default(),
+            read_set: Default::default(),
+            metadata: ExecutionMetadata::new(request.tx_hash),
+            headers: Default::default(),
+        })
+    }
+
+    async fn start(&self, _module_id: &Uuid) -> Result<()> {
+        Ok(())
+    }
+
+    async fn stop(&self, _module_id: &Uuid) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn numbered(contract: H128, number: u8) -> ExecutionRequest {
+    ExecutionRequest {
+        input: vec![number],
+        ..request(contract)
+    }
+}
+
+#[tokio::test]
+async fn test_calls_to_a_contract_keep_their_order() {
+    let engine = Arc::new(LoggingEngine::default());
+    let mut bridge = ExecutionBridge::new(engine.clone(), 4, 100, ExecutionTimeouts::default());
+    let mut results = bridge.start(4).await.unwrap();
+    let requests = bridge.get_request_sender();
+
+    let contracts = [H128([1; 16]), H128([2; 16]), H128([3; 16])];
+    let mut sent = HashMap::new();
+    for number in 0..10 {
+        for contract in contracts {
+            let request = numbered(contract, number);
+            sent.insert(request.tx_hash, contract);
+            requests.send(request).await.unwrap();
+        }
+    }
+
+    // Results of a contract come back in the order its calls were sent
+    let mut answered: HashMap<H128, Vec<u8>> = HashMap::new();
+    for _ in 0..30 {
+        let result = results.recv().await.unwrap().unwrap();
+        answered
+            .entry(sent[&result.metadata.tx_hash])
+            .or_default()
+            .push(result.input[0]);
+    }
+
+    let expected: Vec<u8> = (0..10).collect();
+    for contract in contracts {
+        assert_eq!(engine.calls.lock().unwrap()[&contract], expected);
+        assert_eq!(answered[&contract], expected);
+    }
+    assert_eq!(engine.overlapped.load(Ordering::SeqCst), 0);
+    assert!(engine.max_running.load(Ordering::SeqCst) > 1);
+}
+
+#[tokio::test]
+async fn test_idle_workers_take_over_busy_workers_contracts() {
+    let engine = Arc::new(LoggingEngine::default());
+    let mut bridge = ExecutionBridge::new(engine.clone(), 2, 100, ExecutionTimeouts::default());
+    let mut results = bridge.start(2).await.unwrap();
+    let requests = bridge.get_request_sender();
+
+    // Two contracts owned by the same worker
+    let scheduler = Scheduler::new(2);
+    let owner = scheduler.worker_of(&Some(H128([0; 16])));
+    let contracts: Vec<H128> = (0..=u8::MAX)
+        .map(|byte| H128([byte; 16]))
+        .filter(|contract| scheduler.worker_of(&Some(*contract)) == owner)
+        .take(2)
+        .collect();
+
+    for number in 0..5 {
+        for contract in &contracts {
+            requests.send(numbered(*contract, number)).await.unwrap();
+        }
+    }
+
+    // The other worker picks up one of the contracts while the owner is busy
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    let metrics = bridge.metrics();
+    assert_eq!(metrics.running, 2);
+    assert_eq!(metrics.queue_depths.values().sum::<usize>(), 8);
+    // As served by the admin interface, keyed by contract
+    let json = serde_json::to_value(&metrics).unwrap();
+    assert_eq!(json["queue_depths"].as_object().unwrap().len(), 2);
+
+    for _ in 0..10 {
+        results.recv().await.unwrap().unwrap();
+    }
+    assert_eq!(engine.overlapped.load(Ordering::SeqCst), 0);
+    assert_eq!(engine.max_running.load(Ordering::SeqCst), 2);
+    assert!(bridge.metrics().stolen > 0);
+    assert!(bridge.metrics().queue_depths.is_empty());
+}
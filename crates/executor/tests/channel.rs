@@ -0,0 +1,255 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use mp_common::types::TransactionType;
+use mp_common::utils::h128_to_uuid;
+use mp_common::H128;
+use mp_executor::channel::EngineChannel;
+use mp_executor::config::StateChannelConfig;
+use mp_executor::core::ExecutionRequest;
+use mp_executor::error::ExecutionError;
+use mp_executor::state::{contract_key, CopyOnWriteState, StateObserver, StateOperation};
+use mp_executor_engine::client::{serve, ContainerConfig, Contract, StateClient};
+use mp_executor_engine::handshake;
+use mp_executor_engine::protocol::{Codec, Finish, Invocation, DEFAULT_MAX_FRAME_SIZE};
+use std::net::SocketAddr;
+use tokio::io::AsyncReadExt;
+use tokio::net::{TcpListener, TcpStream};
+use uuid::Uuid;
+
+const COUNTER: H128 = H128([7; 16]);
+
+/// Mock contract keeping a counter in the node state
+struct Counter;
+
+#[async_trait]
+impl Contract for Counter {
+    async fn invoke(&self, invocation: Invocation, state: &mut StateClient) -> Finish {
+        match invocation.path.as_str() {
+            "increment" => {
+                let count: u64 = match state.get("count").await {
+                    Ok(count) => count.map_or(0, |count| count.parse().unwrap()),
+                    Err(e) => return Finish::error(500, e),
+                };
+                if let Err(e) = state.set("count", &(count + 1).to_string()).await {
+                    return Finish::error(500, e);
+                }
+                // The call sees its own write
+                let count = state.get("count").await.unwrap();
+                Finish::ok(serde_json::json!({
+                    "count": count,
+                    "by": String::from_utf8_lossy(&invocation.body),
+                }))
+            }
+            "reset" => match state.delete("count").await {
+                Ok(()) => Finish::ok(serde_json::json!({})),
+                Err(e) => Finish::error(500, e),
+            },
+            path => Finish::error(404, format!("No method {}", path)),
+        }
+    }
+}
+
+fn request(path: &str, input: &str) -> ExecutionRequest {
+    ExecutionRequest {
+        transaction_type: TransactionType::Request(COUNTER, path.to_string()),
+        input: input.as_bytes().to_vec(),
+        tx_hash: Uuid::new_v4(),
+        method: http::Method::POST,
+        header: Default::default(),
+        cancel: Default::default(),
+        pending_writes: Default::default(),
+        state_root: None,
+    }
+}
+
+/// Serve the counter with `config`, returning its address
+async fn counter(config: ContainerConfig) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(serve(listener, config, Arc::new(Counter)));
+    address
+}
+
+fn channel(codec: Codec) -> EngineChannel {
+    EngineChannel::new(&StateChannelConfig {
+        codec,
+        ..Default::default()
+    })
+    .unwrap()
+}
+
+#[tokio::test]
+async fn test_contract_reads_and_writes_node_state_during_a_call() {
+    let address = counter(ContainerConfig::new(h128_to_uuid(&COUNTER))).await;
+    let channel = channel(Codec::Json);
+
+    // The counter lives under the contract's prefix in the node state
+    let key = contract_key(&COUNTER, "count");
+    let pending = [(key.clone(), Some("41".to_string()))]
+        .into_iter()
+        .collect();
+    let state = CopyOnWriteState::new("root".to_string()).with_pending(Arc::new(pending));
+    let (finish, state) = channel
+        .invoke(COUNTER, address, &request("increment", "alice"), state)
+        .await
+        .unwrap();
+    assert_eq!(finish.status_code, 200);
+    assert_eq!(finish.output["count"], "42");
+    assert_eq!(finish.output["by"], "alice");
+    assert_eq!(state.read_set().get(&key), Some(&Some("41".to_string())));
+    let diff = state.get_state_diff();
+    assert!(matches!(
+        diff.operations.as_slice(),
+        [StateOperation::Insert { key: written, value }] if *written == key && value == "42"
+    ));
+
+    // Later calls reuse the connection
+    let state = CopyOnWriteState::new("root".to_string());
+    let (_, state) = channel
+        .invoke(COUNTER, address, &request("reset", ""), state)
+        .await
+        .unwrap();
+    assert!(matches!(
+        state.get_state_diff().operations.as_slice(),
+        [StateOperation::Delete { key: deleted }] if *deleted == key
+    ));
+
+    let state = CopyOnWriteState::new("root".to_string());
+    let (finish, _) = channel
+        .invoke(COUNTER, address, &request("missing", ""), state)
+        .await
+        .unwrap();
+    assert_eq!(finish.status_code, 404);
+}
+
+#[tokio::test]
+async fn test_call_fails_when_the_container_drops_the_connection() {
+    // Container reading the invocation, then going away without finishing it
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let id = h128_to_uuid(&COUNTER);
+            if handshake::accept(&mut stream, id, &[], DEFAULT_MAX_FRAME_SIZE)
+                .await
+                .is_ok()
+            {
+                let mut buffer = [0u8; 4];
+                let _ = stream.read_exact(&mut buffer).await;
+            }
+        }
+    });
+
+    let channel = channel(Codec::Json);
+    let state = CopyOnWriteState::new("root".to_string());
+    let outcome = tokio::time::timeout(
+        Duration::from_secs(5),
+        channel.invoke(COUNTER, address, &request("increment", ""), state),
+    )
+    .await
+    .expect("call still waiting for the closed connection");
+    assert!(matches!(
+        outcome,
+        Err(ExecutionError::CommunicationError(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_handshake_agrees_on_the_codec_both_sides_support() {
+    let id = h128_to_uuid(&COUNTER);
+    let scale = counter(ContainerConfig::new(id)).await;
+    let json_only = counter(ContainerConfig {
+        capabilities: vec![],
+        ..ContainerConfig::new(id)
+    })
+    .await;
+
+    let mut stream = TcpStream::connect(scale).await.unwrap();
+    let session = handshake::connect(&mut stream, id, Codec::Scale, 1024)
+        .await
+        .unwrap();
+    assert_eq!(session.codec, Codec::Scale);
+    assert_eq!(session.peer_max_frame_size, DEFAULT_MAX_FRAME_SIZE);
+
+    let mut stream = TcpStream::connect(scale).await.unwrap();
+    let session = handshake::connect(&mut stream, id, Codec::Json, 1024)
+        .await
+        .unwrap();
+    assert_eq!(session.codec, Codec::Json);
+
+    let mut stream = TcpStream::connect(json_only).await.unwrap();
+    let session = handshake::connect(&mut stream, id, Codec::Scale, 1024)
+        .await
+        .unwrap();
+    assert_eq!(session.codec, Codec::Json);
+}
+
+#[tokio::test]
+async fn test_contract_calls_over_the_scale_codec() {
+    let address = counter(ContainerConfig::new(h128_to_uuid(&COUNTER))).await;
+    let channel = channel(Codec::Scale);
+
+    let pending = [(contract_key(&COUNTER, "count"), Some("6".to_string()))]
+        .into_iter()
+        .collect();
+    let state = CopyOnWriteState::new("root".to_string()).with_pending(Arc::new(pending));
+    let (finish, _) = channel
+        .invoke(
+            COUNTER,
+            address,
+            &request("increment", "bob \u{1F600}"),
+            state,
+        )
+        .await
+        .unwrap();
+    assert_eq!(finish.status_code, 200);
+    assert_eq!(finish.output["count"], "7");
+    assert_eq!(finish.output["by"], "bob \u{1F600}");
+}
+
+#[tokio::test]
+async fn test_connection_refused_to_another_container() {
+    let address = counter(ContainerConfig::new(Uuid::new_v4())).await;
+    let channel = channel(Codec::Json);
+
+    let state = CopyOnWriteState::new("root".to_string());
+    let outcome = channel
+        .invoke(COUNTER, address, &request("increment", ""), state)
+        .await;
+    assert!(matches!(
+        outcome,
+        Err(ExecutionError::CommunicationError(message)) if message.contains("期望")
+    ));
+}
+
+#[tokio::test]
+async fn test_call_over_the_container_frame_limit_is_invalid_input() {
+    let address = counter(ContainerConfig {
+        max_frame_size: 1024,
+        ..ContainerConfig::new(h128_to_uuid(&COUNTER))
+    })
+    .await;
+    let channel = channel(Codec::Json);
+
+    let state = CopyOnWriteState::new("root".to_string());
+    let outcome = channel
+        .invoke(
+            COUNTER,
+            address,
+            &request("increment", &"x".repeat(4096)),
+            state,
+        )
+        .await;
+    assert!(matches!(outcome, Err(ExecutionError::InvalidInput(_))));
+
+    // The connection stays usable for calls within the limit
+    let state = CopyOnWriteState::new("root".to_string());
+    let (finish, _) = channel
+        .invoke(COUNTER, address, &request("increment", "carol"), state)
+        .await
+        .unwrap();
+    assert_eq!(finish.output["count"], "1");
+}
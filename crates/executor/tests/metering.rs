@@ -0,0 +1,225 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use dstack::types::AgentConfiguration;
+use mp_common::types::{Transaction, TransactionType};
+use mp_common::H128;
+use mp_container::{
+    ContainerDetail, ContainerEnvironment, ContainerInfo, ContainerStats, ContainerStatus,
+};
+use mp_executor::config::ExecutorConfig;
+use mp_executor::container::ContainerExecutionEngine;
+use mp_executor::core::{ExecutionEngine, ExecutionRequest};
+use mp_executor::error::ExecutionError;
+use mp_executor::metering::{GasSchedule, Resource, ResourceLimits, ResourceUsage};
+use mp_executor::state::CopyOnWriteState;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+fn usage() -> ResourceUsage {
+    ResourceUsage {
+        payload_bytes: 30,
+        cpu_time_ns: 2_500_000,
+        memory_bytes: 3 * 1024 * 1024 + 1,
+        state_bytes_read: 10,
+        state_bytes_written: 20,
+        outbound_requests: 2,
+        wall_time_ms: 7,
+    }
+}
+
+#[test]
+fn test_gas_follows_schedule() {
+    let schedule = GasSchedule {
+        base: 100,
+        per_payload_byte: 2,
+        per_state_byte_read: 1,
+        per_state_byte_written: 3,
+    };
+    assert_eq!(schedule.gas(&usage()), 100 + 60 + 10 + 60);
+    assert_eq!(schedule.gas(&ResourceUsage::default()), 100);
+
+    // What varies between runs of the same call is never charged
+    let slower = ResourceUsage {
+        cpu_time_ns: 1_000_000_000,
+        memory_bytes: 1 << 30,
+        outbound_requests: 9,
+        wall_time_ms: 1000,
+        ..usage()
+    };
+    assert_eq!(schedule.gas(&slower), schedule.gas(&usage()));
+
+    let expensive = GasSchedule {
+        per_state_byte_written: u64::MAX,
+        ..schedule
+    };
+    assert_eq!(expensive.gas(&usage()), u64::MAX);
+}
+
+#[test]
+fn test_limits_reject_excess_usage() {
+    let limits = ResourceLimits::default();
+    assert!(limits.check(&usage(), u64::MAX).is_ok());
+
+    let limits = ResourceLimits {
+        max_gas: Some(1000),
+        max_state_bytes_written: Some(20),
+        ..Default::default()
+    };
+    assert!(limits.check(&usage(), 1000).is_ok());
+    match limits.check(&usage(), 1001) {
+        Err(ExecutionError::ResourceLimitExceeded {
+            resource: Resource::Gas,
+            used: 1001,
+            limit: 1000,
+        }) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    let limits = ResourceLimits {
+        max_cpu_time_ms: Some(2),
+        ..Default::default()
+    };
+    assert!(limits.limits_container_stats());
+    match limits.check(&usage(), 0) {
+        Err(ExecutionError::ResourceLimitExceeded {
+            resource: Resource::CpuTime,
+            used: 3,
+            limit: 2,
+        }) => {}
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn test_state_access_is_counted() {
+    let mut state = CopyOnWriteState::new(String::new());
+    state.write("key".to_string(), "value".to_string());
+    state.delete("gone".to_string());
+    assert_eq!(state.bytes_written(), 12);
+
+    assert_eq!(state.read("key").unwrap(), Some("value".to_string()));
+    assert_eq!(state.read("missing").unwrap(), None);
+    assert_eq!(state.bytes_read(), 15);
+}
+
+/// Container whose calls never return, burning 50ms of CPU time between stats reads
+#[derive(Debug, Default)]
+struct SpinningContainer {
+    cpu_time_ns: AtomicU64,
+}
+
+#[async_trait]
+impl ContainerEnvironment for SpinningContainer {
+    async fn create_container(&self, _req: AgentConfiguration) -> Result<ContainerInfo> {
+        bail!("not supported")
+    }
+
+    async fn execute_transaction(
+        &self,
+        _transaction: Transaction,
+        _cancel: CancellationToken,
+    ) -> Result<Transaction> {
+        std::future::pending().await
+    }
+
+    async fn start_container(&self, _vm_id: &Uuid) -> Result<ContainerInfo> {
+        bail!("not supported")
+    }
+
+    async fn stop_container(&self, _vm_id: &Uuid) -> Result<()> {
+        Ok(())
+    }
+
+    async fn remove_container(&self, _vm_id: &Uuid) -> Result<()> {
+        Ok(())
+    }
+
+    async fn get_container(&self, _vm_id: &Uuid) -> Result<ContainerDetail> {
+        bail!("not supported")
+    }
+
+    async fn get_container_status(&self, _vm_id: &Uuid) -> Result<ContainerStatus> {
+        Ok(ContainerStatus::Running)
+    }
+
+    async fn get_running_containers(&self) -> Result<Vec<ContainerDetail>> {
+        Ok(Vec::new())
+    }
+
+    async fn container_stats(&self, _vm_id: &Uuid) -> Result<Option<ContainerStats>> {
+        Ok(Some(ContainerStats {
+            cpu_time_ns: self.cpu_time_ns.fetch_add(50_000_000, Ordering::SeqCst),
+            memory_bytes: 1024 * 1024,
+        }))
+    }
+}
+
+fn spinning_call() -> ExecutionRequest {
+    ExecutionRequest {
+        transaction_type: TransactionType::Request(H128::from_low_u64_be(1), "spin".to_string()),
+        input: Vec::new(),
+        tx_hash: Uuid::new_v4(),
+        method: http::Method::POST,
+        header: Default::default(),
+        cancel: Default::default(),
+        pending_writes: Default::default(),
+        state_root: None,
+    }
+}
+
+/// Engine over a spinning container, monitoring calls every 10ms
+fn engine_with(limits: ResourceLimits) -> ContainerExecutionEngine {
+    let config = ExecutorConfig {
+        limits: ResourceLimits {
+            monitor_interval_ms: 10,
+            ..limits
+        },
+        ..Default::default()
+    };
+    ContainerExecutionEngine::new(config, Arc::new(SpinningContainer::default()), None).unwrap()
+}
+
+#[tokio::test]
+async fn test_running_calls_are_killed_over_their_limits() {
+    // No wall time limit is set, the call is killed for its CPU time alone
+    let engine = engine_with(ResourceLimits {
+        max_cpu_time_ms: Some(120),
+        ..Default::default()
+    });
+    let result = tokio::time::timeout(Duration::from_secs(5), engine.execute(&mut spinning_call()))
+        .await
+        .expect("call over its CPU limit was not killed");
+    match result {
+        Err(ExecutionError::ResourceLimitExceeded {
+            resource: Resource::CpuTime,
+            used: 150,
+            limit: 120,
+        }) => {}
+        other => panic!("unexpected result: {:?}", other.map(|_| ())),
+    }
+
+    // A request whose payload alone costs more gas than allowed is never called
+    let engine = engine_with(ResourceLimits {
+        max_gas: Some(1005),
+        ..Default::default()
+    });
+    let mut call = ExecutionRequest {
+        input: vec![0; 10],
+        ..spinning_call()
+    };
+    let result = tokio::time::timeout(Duration::from_secs(5), engine.execute(&mut call))
+        .await
+        .expect("call over its gas limit was made");
+    match result {
+        Err(ExecutionError::ResourceLimitExceeded {
+            resource: Resource::Gas,
+            used: 1010,
+            limit: 1005,
+        }) => {}
+        other => panic!("unexpected result: {:?}", other.map(|_| ())),
+    }
+}
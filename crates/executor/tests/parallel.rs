@@ -0,0 +1,142 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use mp_common::types::TransactionType;
+use mp_common::{TransactionResponse, H128};
+use mp_executor::config::ExecutionTimeouts;
+use mp_executor::core::{ExecutionEngine, ExecutionRequest, ExecutionResult};
+use mp_executor::error::ExecutionError;
+use mp_executor::metadata::ExecutionMetadata;
+use mp_executor::parallel::ParallelExecutor;
+use mp_executor::state::{CopyOnWriteState, StateObserver, StateValues};
+use uuid::Uuid;
+
+/// Engine incrementing the counter named by the input, taking 50ms per call
+#[derive(Default)]
+struct CounterEngine {
+    executions: AtomicUsize,
+    running: AtomicUsize,
+    max_running: AtomicUsize,
+}
+
+#[async_trait]
+impl ExecutionEngine for CounterEngine {
+    async fn execute(
+        &self,
+        request: &mut ExecutionRequest,
+    ) -> Result<ExecutionResult, ExecutionError> {
+        self.executions.fetch_add(1, Ordering::SeqCst);
+        let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max_running.fetch_max(running, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        self.running.fetch_sub(1, Ordering::SeqCst);
+
+        let key = String::from_utf8(request.input.clone()).unwrap();
+        let mut state =
+            CopyOnWriteState::new(String::new()).with_pending(request.pending_writes.clone());
+        let count: u64 = state
+            .read(&key)
+            .unwrap()
+            .map_or(0, |value| value.parse().unwrap());
+        state.write(key, (count + 1).to_string());
+
+        Ok(ExecutionResult {
+            input: request.input.clone(),
+            output: TransactionResponse::default(),
+            state_diff: state.get_state_diff(),
+            read_set: state.read_set().clone(),
+            metadata: ExecutionMetadata::new(request.tx_hash),
+            headers: Default::default(),
+        })
+    }
+
+    async fn start(&self, _module_id: &Uuid) -> Result<()> {
+        Ok(())
+    }
+
+    async fn stop(&self, _module_id: &Uuid) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn increment(contract: u8, key: &str) -> ExecutionRequest {
+    ExecutionRequest {
+        transaction_type: TransactionType::Request(H128([contract; 16]), "increment".to_string()),
+        input: key.as_bytes().to_vec(),
+        tx_hash: Uuid::new_v4(),
+        method: Default::default(),
+        header: Default::default(),
+        cancel: Default::default(),
+        pending_writes: Default::default(),
+        state_root: None,
+    }
+}
+
+fn writes(results: &[Result<ExecutionResult, ExecutionError>]) -> Vec<StateValues> {
+    results
+        .iter()
+        .map(|result| result.as_ref().unwrap().state_diff.write_set())
+        .collect()
+}
+
+fn counter(key: &str, count: u64) -> StateValues {
+    StateValues::from([(key.to_string(), Some(count.to_string()))])
+}
+
+#[tokio::test]
+async fn test_independent_contracts_run_in_parallel() {
+    let engine = Arc::new(CounterEngine::default());
+    let executor = ParallelExecutor::new(engine.clone(), ExecutionTimeouts::default(), 4);
+
+    let requests: Vec<_> = (0..8u8)
+        .map(|i| increment(i % 4, &format!("contract{}/count", i % 4)))
+        .collect();
+    let hashes: Vec<_> = requests.iter().map(|request| request.tx_hash).collect();
+    let started = Instant::now();
+    let results = executor.execute_batch(requests).await;
+
+    // Calls to one contract run in log order and see each other's writes
+    let expected: Vec<_> = (0..8u64)
+        .map(|i| counter(&format!("contract{}/count", i % 4), i / 4 + 1))
+        .collect();
+    assert_eq!(writes(&results), expected);
+    let result_hashes: Vec<_> = results
+        .iter()
+        .map(|result| result.as_ref().unwrap().metadata.tx_hash)
+        .collect();
+    assert_eq!(result_hashes, hashes);
+
+    assert_eq!(engine.executions.load(Ordering::SeqCst), 8);
+    assert_eq!(engine.max_running.load(Ordering::SeqCst), 4);
+    assert!(started.elapsed() < Duration::from_millis(300));
+}
+
+#[tokio::test]
+async fn test_conflicts_are_executed_again_in_log_order() {
+    let engine = Arc::new(CounterEngine::default());
+    let executor = ParallelExecutor::new(engine.clone(), ExecutionTimeouts::default(), 4);
+
+    // Two contracts share a key, the later call read it before the earlier wrote it
+    let results = executor
+        .execute_batch(vec![
+            increment(1, "shared"),
+            increment(2, "shared"),
+            increment(3, "other"),
+            increment(1, "shared"),
+        ])
+        .await;
+
+    assert_eq!(
+        writes(&results),
+        vec![
+            counter("shared", 1),
+            counter("shared", 2),
+            counter("other", 1),
+            counter("shared", 3),
+        ]
+    );
+    assert_eq!(engine.executions.load(Ordering::SeqCst), 6);
+}
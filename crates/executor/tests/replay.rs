@@ -0,0 +1,295 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use mp_common::types::{Transaction, TransactionType};
+use mp_common::utils::create_transaction;
+use mp_common::{TransactionResponse, H128};
+use mp_consensus::config::{ConsensusConfig, NodeInfo, RaftConfig};
+use mp_consensus::raft::RaftConsensusEngine;
+use mp_consensus::ConsensusEngine;
+use mp_executor::config::ExecutionTimeouts;
+use mp_executor::core::{ExecutionEngine, ExecutionRequest, ExecutionResult};
+use mp_executor::error::ExecutionError;
+use mp_executor::metadata::ExecutionMetadata;
+use mp_executor::replay::{execution_record, ReplayOutcome, ReplaySelection, Replayer};
+use mp_executor::state::StateDiff;
+use mp_state::config::StateConfig;
+use mp_state::{create_state_storage, StateStorage};
+use tokio::time::{sleep, timeout, Instant};
+use uuid::Uuid;
+
+/// Engine echoing the method, headers and input, optionally with a run counter
+#[derive(Default)]
+struct EchoEngine {
+    counts_runs: bool,
+    runs: AtomicU64,
+}
+
+#[async_trait]
+impl ExecutionEngine for EchoEngine {
+    async fn execute(
+        &self,
+        request: &mut ExecutionRequest,
+    ) -> Result<ExecutionResult, ExecutionError> {
+        let run = self.runs.fetch_add(1, Ordering::SeqCst);
+        let mut output = serde_json::json!({
+            "method": request.method.as_str(),
+            "content_type": request.header.get("content-type").map(|v| v.to_str().unwrap()),
+            "echo": String::from_utf8_lossy(&request.input),
+        });
+        if self.counts_runs {
+            output["run"] = run.into();
+        }
+
+        Ok(ExecutionResult {
+            input: request.input.clone(),
+            output: TransactionResponse {
+                output,
+                ..Default::default()
+            },
+            state_diff: StateDiff::new(request.state_root.clone().unwrap_or_default()),
+            read_set: Default::default(),
+            metadata: ExecutionMetadata::new(request.tx_hash),
+            headers: Default::default(),
+        })
+    }
+
+    async fn start(&self, _module_id: &Uuid) -> Result<()> {
+        Ok(())
+    }
+
+    async fn stop(&self, _module_id: &Uuid) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn open(dir: &Path) -> Arc<dyn StateStorage> {
+    let storage = create_state_storage(StateConfig {
+        db_type: "sqlite".to_string(),
+        db_connection: dir.join("state.db").to_string_lossy().to_string(),
+        state_root_path: dir.join("state_root").to_string_lossy().to_string(),
+    })
+    .unwrap();
+    storage.start().unwrap();
+    storage
+}
+
+/// Contract call of alice, numbered by its log index
+fn call(input: &str, log_index: u64) -> Transaction {
+    let mut header = http::HeaderMap::new();
+    header.insert("content-type", "text/plain".parse().unwrap());
+    header.insert("x-api-key", "secret".parse().unwrap());
+    let mut transaction = create_transaction(
+        TransactionType::Request(H128::from_low_u64_be(1), "echo".to_string()),
+        input.as_bytes().to_vec(),
+        Some("alice".to_string()),
+        http::Method::POST,
+        header,
+    );
+    transaction.nonce = Some(log_index);
+    transaction.log_index = log_index;
+    transaction
+}
+
+/// Execute the transactions the way the node does, recording them in the history
+async fn run_live(
+    engine: &dyn ExecutionEngine,
+    storage: &dyn StateStorage,
+    history: &[Transaction],
+) {
+    for transaction in history {
+        let mut request = ExecutionRequest {
+            transaction_type: transaction.tx_type.clone(),
+            input: transaction.payload.clone(),
+            tx_hash: transaction.id,
+            method: transaction.method.clone(),
+            header: transaction.header.clone(),
+            cancel: Default::default(),
+            pending_writes: Default::default(),
+            state_root: None,
+        };
+        let result = engine.execute(&mut request).await.unwrap();
+        let record = execution_record(&request, &result).unwrap();
+        assert!(record.headers.iter().all(|(name, _)| name != "x-api-key"));
+        storage.record_execution(&record).unwrap();
+        storage.apply_transaction(transaction.clone()).unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_replay_matches_recorded_executions() {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = open(dir.path());
+    let engine = Arc::new(EchoEngine::default());
+    let history = [call("a", 1), call("b", 2), call("c", 3), call("d", 4)];
+    run_live(engine.as_ref(), storage.as_ref(), &history).await;
+
+    let replayer = Replayer::new(engine, storage.clone(), ExecutionTimeouts::default());
+    let selected = replayer
+        .select(&ReplaySelection::LogIndices { from: 2, to: 3 })
+        .unwrap();
+    assert_eq!(
+        selected.iter().map(|tx| tx.id).collect::<Vec<_>>(),
+        vec![history[1].id, history[2].id]
+    );
+
+    // Replays get the recorded method and headers back, credentials aside
+    for transaction in &selected {
+        let report = replayer.replay(transaction).await.unwrap();
+        assert!(
+            matches!(report.outcome, ReplayOutcome::Matched),
+            "{:?}",
+            report
+        );
+    }
+
+    // Transactions picked by ID are replayed in log order
+    let selected = replayer
+        .select(&ReplaySelection::Transactions(vec![
+            history[3].id,
+            history[0].id,
+        ]))
+        .unwrap();
+    assert_eq!(selected[0].id, history[0].id);
+    assert_eq!(selected[1].id, history[3].id);
+    assert!(replayer
+        .select(&ReplaySelection::Transactions(vec![Uuid::new_v4()]))
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_replay_reports_divergence() {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = open(dir.path());
+    let engine = Arc::new(EchoEngine {
+        counts_runs: true,
+        ..Default::default()
+    });
+    let recorded = call("a", 1);
+    run_live(engine.as_ref(), storage.as_ref(), &[recorded.clone()]).await;
+    let unrecorded = call("b", 2);
+    storage.apply_transaction(unrecorded.clone()).unwrap();
+
+    let replayer = Replayer::new(engine, storage.clone(), ExecutionTimeouts::default());
+    let report = replayer.replay(&recorded).await.unwrap();
+    assert!(report.is_mismatch());
+    let ReplayOutcome::Diverged {
+        recorded: record,
+        output,
+        poc_root,
+    } = report.outcome
+    else {
+        panic!("Replay should diverge, got {:?}", report.outcome);
+    };
+    assert_eq!(record.output["run"], 0);
+    assert_eq!(output["run"], 1);
+    assert_ne!(poc_root, record.poc_root);
+
+    let report = replayer.replay(&unrecorded).await.unwrap();
+    assert!(matches!(report.outcome, ReplayOutcome::NotRecorded));
+    assert!(!report.is_mismatch());
+}
+
+fn single_node_config(log_path: &Path) -> ConsensusConfig {
+    // The node binds a free port when it starts
+    let address = "127.0.0.1:0".parse().unwrap();
+    ConsensusConfig {
+        engine_type: "raft".to_string(),
+        node_id: 1,
+        nodes: vec![NodeInfo {
+            id: 1,
+            address,
+            rest_address: None,
+        }],
+        raft: Some(RaftConfig {
+            heartbeat_interval: 50,
+            election_timeout_min: 150,
+            election_timeout_max: 300,
+            snapshot_interval: 10000,
+            log_path: log_path.to_string_lossy().to_string(),
+            join: false,
+        }),
+        bft: None,
+        block: Default::default(),
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_replay_from_a_node_written_history() {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = open(dir.path());
+    let engine = Arc::new(EchoEngine::default());
+    let mut consensus = RaftConsensusEngine::new(
+        single_node_config(&dir.path().join("raft")),
+        Some(storage.clone()),
+    )
+    .unwrap();
+    let mut confirmed = consensus.get_confirmed_tx_channel().await;
+    consensus.start().await.unwrap();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !consensus.is_leader().await {
+        assert!(Instant::now() < deadline, "node did not become leader");
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    // The history is written from the committed stream, with the log indices consensus assigned
+    let submitted: Vec<_> = ["a", "b", "c"]
+        .into_iter()
+        .enumerate()
+        .map(|(nonce, input)| Transaction {
+            nonce: Some(nonce as u64),
+            ..call(input, 0)
+        })
+        .collect();
+    for transaction in &submitted {
+        consensus
+            .submit_transaction(transaction.clone())
+            .await
+            .unwrap();
+    }
+    let mut committed = Vec::new();
+    for _ in &submitted {
+        let transaction = timeout(Duration::from_secs(5), confirmed.recv())
+            .await
+            .expect("timed out waiting for confirmed transaction")
+            .expect("confirmed channel closed");
+        run_live(engine.as_ref(), storage.as_ref(), &[transaction.clone()]).await;
+        committed.push(transaction);
+    }
+    consensus.stop().await.unwrap();
+    drop(consensus);
+    drop(storage);
+
+    // The replay subcommand reopens the database the node wrote
+    let storage = open(dir.path());
+    let replayer = Replayer::new(engine, storage, ExecutionTimeouts::default());
+    let selected = replayer
+        .select(&ReplaySelection::LogIndices {
+            from: committed[0].log_index,
+            to: committed[2].log_index,
+        })
+        .unwrap();
+    assert_eq!(
+        selected.iter().map(|tx| tx.id).collect::<Vec<_>>(),
+        submitted.iter().map(|tx| tx.id).collect::<Vec<_>>()
+    );
+    assert!(selected
+        .iter()
+        .zip(&committed)
+        .all(
+            |(selected, committed)| selected.log_index == committed.log_index
+                && selected.log_index > 0
+        ));
+    for transaction in &selected {
+        let report = replayer.replay(transaction).await.unwrap();
+        assert!(
+            matches!(report.outcome, ReplayOutcome::Matched),
+            "{:?}",
+            report
+        );
+    }
+}
@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 
+use crate::nonce::DEFAULT_MAX_QUEUED_PER_SENDER;
+
 /// Transaction pool configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MempoolConfig {
@@ -14,4 +16,12 @@ pub struct MempoolConfig {
 
     /// Transaction timeout in seconds
     pub tx_timeout: u64,
+
+    /// Transactions of a sender held for missing nonces
+    #[serde(default = "default_max_queued_per_sender")]
+    pub max_queued_per_sender: usize,
+}
+
+fn default_max_queued_per_sender() -> usize {
+    DEFAULT_MAX_QUEUED_PER_SENDER
 }
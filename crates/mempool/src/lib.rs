@@ -1,5 +1,6 @@
 // pub mod api;
 pub mod config;
+pub mod nonce;
 pub mod pool;
 
 use anyhow::Result;
@@ -8,7 +9,7 @@ use mp_common::types::{Transaction, TransactionResponse};
 use mp_consensus::ConsensusEngine;
 use mp_poc::bls::SignedAggregate;
 use std::sync::Arc;
-use tokio::sync::mpsc;
+use tokio::sync::oneshot;
 use uuid::Uuid;
 
 /// Transaction pool interface
@@ -21,10 +22,34 @@ pub trait TransactionPool: Send + Sync {
     fn stop(&self) -> Result<()>;
 
     /// Submit a transaction to the pool
+    ///
+    /// Fails with a [`nonce::NonceError`] if the nonce of the sender is refused.
     async fn submit_transaction(&self, transaction: Transaction) -> Result<TransactionResponse>;
 
-    /// Get a channel for pending transactions
-    async fn get_pending_tx_channel(&self) -> mpsc::Receiver<Transaction>;
+    /// Get notified if the pool gives up on a transaction before consensus orders it
+    ///
+    /// Resolves with the failure of a transaction replaced, whose nonce a peer
+    /// used or that consensus kept refusing. Once consensus ordered the
+    /// transaction the sender is dropped, its outcome comes from its execution.
+    async fn watch_rejection(&self, tx_id: Uuid) -> oneshot::Receiver<TransactionStatusWithProof>;
+
+    /// Take note of a transaction a peer gossiped
+    ///
+    /// The node it was submitted on hands it to consensus and executes it, the
+    /// pool only takes its nonce so the sender cannot reuse it here.
+    async fn add_remote_transaction(&self, transaction: Transaction) -> Result<()>;
+
+    /// Check whether a transaction was submitted through this pool
+    ///
+    /// The node executes the committed transactions it submitted, the others are
+    /// executed by the node their client waits on.
+    async fn has_transaction(&self, tx_id: &Uuid) -> bool;
+
+    /// Get a transaction submitted through this pool that has no outcome yet
+    ///
+    /// Unlike the copy consensus delivers, it keeps the HTTP method and headers
+    /// the client sent, which are not part of the replicated transaction.
+    async fn get_transaction(&self, tx_id: &Uuid) -> Option<Transaction>;
 
     /// Get the status of a transaction
     async fn get_transaction_status(&self, tx_id: &Uuid) -> Result<TransactionStatusWithProof>;
@@ -37,8 +62,23 @@ pub trait TransactionPool: Send + Sync {
         signed_aggregate: SignedAggregate,
     ) -> Result<()>;
 
+    /// Record the failure of a transaction with the HTTP status to report
+    async fn update_transaction_failure(
+        &self,
+        tx_id: &Uuid,
+        error: serde_json::Value,
+        status_code: u16,
+        signed_aggregate: SignedAggregate,
+    ) -> Result<()>;
+
     async fn get_transaction_proof(&self, tx_id: &Uuid) -> Option<serde_json::Value>;
 
+    /// Nonce the next transaction of `sender` must carry
+    async fn next_nonce(&self, sender: &str) -> u64;
+
+    /// Resume the nonces of a sender the pool has not seen yet at `next`
+    async fn seed_nonce(&self, sender: &str, next: u64);
+
     /// Get the result of a transaction
     async fn get_transaction_result(
         &self,
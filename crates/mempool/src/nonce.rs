@@ -0,0 +1,242 @@
+//! Per-sender nonces of the transactions in the pool
+//!
+//! A sender numbers its transactions from 0. The pool dispatches them in that
+//! order: a transaction whose nonce is ahead of the next one waits until the
+//! gap is filled, and a nonce already dispatched is refused, so a captured
+//! transaction cannot be submitted again. Until it is dispatched, a
+//! transaction can be replaced by one with the same nonce and a higher fee.
+//! Transactions of a sender must carry a nonce, and contract calls a sender.
+//! Other anonymous transactions are not sequenced.
+
+use std::collections::{BTreeMap, HashMap};
+
+use mp_common::types::Transaction;
+use uuid::Uuid;
+
+/// Transactions waiting for missing nonces, per sender, unless configured otherwise
+pub const DEFAULT_MAX_QUEUED_PER_SENDER: usize = 64;
+
+/// Why a transaction was refused for its nonce
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum NonceError {
+    #[error("Nonce {nonce} of {sender} was already used, the next nonce is {next}")]
+    Stale {
+        sender: String,
+        nonce: u64,
+        next: u64,
+    },
+    #[error("Transaction {pending} with nonce {nonce} of {sender} is pending with fee {fee}, a replacement must offer more")]
+    Underpriced {
+        sender: String,
+        nonce: u64,
+        pending: Uuid,
+        fee: u64,
+    },
+    #[error("{sender} already has {max} transactions waiting for missing nonces")]
+    TooManyQueued { sender: String, max: usize },
+    #[error("Transactions of {sender} must carry a nonce, the next nonce is {next}")]
+    Missing { sender: String, next: u64 },
+    #[error("Contract calls must carry a sender and a nonce")]
+    Anonymous,
+}
+
+impl NonceError {
+    /// HTTP status to report to the client
+    pub fn status_code(&self) -> u16 {
+        match self {
+            NonceError::Stale { .. } | NonceError::Underpriced { .. } => 409,
+            NonceError::TooManyQueued { .. } => 429,
+            NonceError::Missing { .. } | NonceError::Anonymous => 400,
+        }
+    }
+}
+
+/// What the pool does with an admitted transaction
+#[derive(Debug)]
+pub enum Admission {
+    /// Dispatch these transactions in order, the admitted one first, then
+    /// those its nonce unblocked
+    Ready(Vec<Transaction>),
+    /// Hold the transaction until the nonces before it arrive
+    Queued,
+    /// The transaction takes the place of `replaced`, in the dispatch queue if
+    /// `ready` and among the held transactions otherwise
+    Replaced { replaced: Uuid, ready: bool },
+}
+
+/// Nonces of one sender
+#[derive(Debug, Default)]
+struct SenderNonces {
+    /// Nonce the next ready transaction must carry
+    next: u64,
+    /// ID and fee of the ready transactions not dispatched yet
+    ready: BTreeMap<u64, (Uuid, u64)>,
+    /// Transactions held for a missing nonce
+    queued: BTreeMap<u64, Transaction>,
+}
+
+/// Nonces of every sender with transactions in the pool
+#[derive(Debug)]
+pub struct NonceTracker {
+    senders: HashMap<String, SenderNonces>,
+    max_queued_per_sender: usize,
+}
+
+impl NonceTracker {
+    pub fn new(max_queued_per_sender: usize) -> Self {
+        Self {
+            senders: HashMap::new(),
+            max_queued_per_sender,
+        }
+    }
+
+    /// Check the nonce of a submitted transaction
+    pub fn admit(&mut self, transaction: &Transaction) -> Result<Admission, NonceError> {
+        let Some(sender) = &transaction.sender else {
+            if transaction.tx_type.is_request() {
+                return Err(NonceError::Anonymous);
+            }
+            return Ok(Admission::Ready(vec![transaction.clone()]));
+        };
+        let Some(nonce) = transaction.nonce else {
+            return Err(NonceError::Missing {
+                sender: sender.clone(),
+                next: self.next_nonce(sender),
+            });
+        };
+        let nonces = self.senders.entry(sender.clone()).or_default();
+
+        if nonce < nonces.next {
+            let Some((pending, fee)) = nonces.ready.get(&nonce).copied() else {
+                return Err(NonceError::Stale {
+                    sender: sender.clone(),
+                    nonce,
+                    next: nonces.next,
+                });
+            };
+            check_fee(sender, nonce, pending, fee, transaction)?;
+            nonces
+                .ready
+                .insert(nonce, (transaction.id, transaction.fee));
+            return Ok(Admission::Replaced {
+                replaced: pending,
+                ready: true,
+            });
+        }
+
+        if nonce > nonces.next {
+            if let Some(pending) = nonces.queued.get(&nonce) {
+                let replaced = pending.id;
+                check_fee(sender, nonce, replaced, pending.fee, transaction)?;
+                nonces.queued.insert(nonce, transaction.clone());
+                return Ok(Admission::Replaced {
+                    replaced,
+                    ready: false,
+                });
+            }
+            if nonces.queued.len() >= self.max_queued_per_sender {
+                return Err(NonceError::TooManyQueued {
+                    sender: sender.clone(),
+                    max: self.max_queued_per_sender,
+                });
+            }
+            nonces.queued.insert(nonce, transaction.clone());
+            return Ok(Admission::Queued);
+        }
+
+        // The expected nonce, it unblocks the transactions held right after it
+        let mut ready = vec![transaction.clone()];
+        nonces.next += 1;
+        while let Some(unblocked) = nonces.queued.remove(&nonces.next) {
+            ready.push(unblocked);
+            nonces.next += 1;
+        }
+        for transaction in &ready {
+            nonces.ready.insert(
+                transaction.nonce.unwrap_or_default(),
+                (transaction.id, transaction.fee),
+            );
+        }
+        Ok(Admission::Ready(ready))
+    }
+
+    /// Record that a ready transaction left the pool for execution, it can no longer be replaced
+    pub fn dispatched(&mut self, transaction: &Transaction) {
+        let (Some(sender), Some(nonce)) = (&transaction.sender, transaction.nonce) else {
+            return;
+        };
+        if let Some(nonces) = self.senders.get_mut(sender) {
+            nonces.ready.remove(&nonce);
+        }
+    }
+
+    /// Record a transaction submitted on another node, its nonce counts as dispatched
+    ///
+    /// Returns the transactions of the sender this node can no longer dispatch,
+    /// their nonce being taken, and those the new next nonce made ready.
+    pub fn used_elsewhere(&mut self, transaction: &Transaction) -> (Vec<Uuid>, Vec<Transaction>) {
+        let (Some(sender), Some(nonce)) = (&transaction.sender, transaction.nonce) else {
+            return (Vec::new(), Vec::new());
+        };
+        let nonces = self.senders.entry(sender.clone()).or_default();
+
+        // Local transactions ready or held with a nonce up to the remote one
+        let mut stale = Vec::new();
+        let kept = nonces.ready.split_off(&(nonce + 1));
+        stale.extend(nonces.ready.values().map(|(id, _)| *id));
+        nonces.ready = kept;
+        let kept = nonces.queued.split_off(&(nonce + 1));
+        stale.extend(nonces.queued.values().map(|tx| tx.id));
+        nonces.queued = kept;
+        if nonce < nonces.next {
+            return (stale, Vec::new());
+        }
+
+        nonces.next = nonce + 1;
+        let mut ready = Vec::new();
+        while let Some(unblocked) = nonces.queued.remove(&nonces.next) {
+            nonces
+                .ready
+                .insert(nonces.next, (unblocked.id, unblocked.fee));
+            ready.push(unblocked);
+            nonces.next += 1;
+        }
+        (stale, ready)
+    }
+
+    /// Start the nonces of a sender seen for the first time at `next`
+    ///
+    /// Used to resume from the nonce recorded before a restart, a sender with
+    /// transactions in the pool keeps its nonces.
+    pub fn seed(&mut self, sender: &str, next: u64) {
+        self.senders
+            .entry(sender.to_string())
+            .or_insert_with(|| SenderNonces {
+                next,
+                ..Default::default()
+            });
+    }
+
+    /// Nonce the next transaction of `sender` must carry to be dispatched
+    pub fn next_nonce(&self, sender: &str) -> u64 {
+        self.senders.get(sender).map_or(0, |nonces| nonces.next)
+    }
+}
+
+fn check_fee(
+    sender: &str,
+    nonce: u64,
+    pending: Uuid,
+    fee: u64,
+    replacement: &Transaction,
+) -> Result<(), NonceError> {
+    if replacement.fee > fee {
+        return Ok(());
+    }
+    Err(NonceError::Underpriced {
+        sender: sender.to_string(),
+        nonce,
+        pending,
+        fee,
+    })
+}
@@ -5,15 +5,22 @@ use mp_poc::bls::SignedAggregate;
 use mp_poc::PoC;
 use std::collections::{HashMap, VecDeque};
 use std::sync::Arc;
-use tokio::sync::{mpsc, Mutex};
-use tracing::{debug, error, info};
+use std::time::Duration;
+use tokio::sync::{oneshot, Mutex};
+use tracing::{debug, error, info, warn};
 use uuid;
 use uuid::Uuid;
 
 use crate::config::MempoolConfig;
+use crate::nonce::{Admission, NonceTracker};
 use crate::TransactionPool;
 use mp_common::types::TransactionStatusWithProof;
 
+/// Interval at which the queue is checked while it is empty
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// Delay before handing a transaction consensus refused to it again
+const SUBMIT_RETRY_INTERVAL: Duration = Duration::from_millis(200);
+
 /// Basic transaction pool implementation
 pub struct BasicTransactionPool {
     config: MempoolConfig,
@@ -21,15 +28,17 @@ pub struct BasicTransactionPool {
     transaction_map: Arc<Mutex<HashMap<Uuid, Transaction>>>,
     transaction_results: Arc<Mutex<HashMap<Uuid, TransactionResponse>>>,
     transaction_proof: Arc<Mutex<HashMap<Uuid, serde_json::Value>>>,
+    /// Locked before `pending_transactions` when both are needed
+    nonces: Arc<Mutex<NonceTracker>>,
+    /// Clients to tell when a transaction fails before consensus orders it
+    rejection_watchers: Arc<Mutex<HashMap<Uuid, oneshot::Sender<TransactionStatusWithProof>>>>,
     consensus_engine: Arc<Box<dyn ConsensusEngine>>,
-    tx_sender: mpsc::Sender<Transaction>,
-    tx_receiver: Mutex<Option<mpsc::Receiver<Transaction>>>,
 }
 
 impl BasicTransactionPool {
     /// Create a new basic transaction pool
     pub fn new(config: MempoolConfig, consensus_engine: Box<dyn ConsensusEngine>) -> Result<Self> {
-        let (tx_sender, tx_receiver) = mpsc::channel(1000);
+        let nonces = NonceTracker::new(config.max_queued_per_sender);
 
         Ok(Self {
             config,
@@ -37,64 +46,108 @@ impl BasicTransactionPool {
             transaction_map: Arc::new(Mutex::new(HashMap::new())),
             transaction_results: Arc::new(Mutex::new(HashMap::new())),
             transaction_proof: Arc::new(Mutex::new(HashMap::new())),
+            nonces: Arc::new(Mutex::new(nonces)),
+            rejection_watchers: Arc::new(Mutex::new(HashMap::new())),
             consensus_engine: Arc::new(consensus_engine),
-            tx_sender,
-            tx_receiver: Mutex::new(Some(tx_receiver)),
         })
     }
 
-    /// Process pending transactions
+    /// Fail a transaction that will not be handed to consensus
+    async fn reject(&self, tx_id: Uuid, error: String, status_code: u16) {
+        let error = serde_json::json!({ "error": error });
+        self.transaction_map.lock().await.remove(&tx_id);
+        self.transaction_results.lock().await.insert(
+            tx_id,
+            TransactionResponse {
+                tx_id,
+                status: TransactionStatus::Error,
+                result: Some(error.clone()),
+                status_code: Some(status_code),
+            },
+        );
+        if let Some(watcher) = self.rejection_watchers.lock().await.remove(&tx_id) {
+            let _ = watcher.send(TransactionStatusWithProof::Failed(
+                error,
+                status_code,
+                None,
+                None,
+            ));
+        }
+    }
+
+    /// Hand pending transactions to consensus, in the order the pool releases them
+    ///
+    /// Consensus answers once a transaction is committed, its execution follows
+    /// from the committed stream. A transaction consensus cannot take yet is
+    /// retried ahead of the others, so the nonces of a sender keep their order,
+    /// until it is older than the transaction timeout.
     async fn process_transactions(&self) {
         info!("Starting transaction processing loop");
+        let tx_timeout = chrono::Duration::seconds(self.config.tx_timeout as i64);
 
         loop {
-            // Get a transaction from the queue
+            // Get a pending transaction, its nonce can no longer be replaced
             let tx = {
-                let mut queue = self.pending_transactions.lock().await;
-                queue.pop_front()
+                let mut nonces = self.nonces.lock().await;
+                let tx = self.pending_transactions.lock().await.pop_front();
+                if let Some(transaction) = &tx {
+                    nonces.dispatched(transaction);
+                }
+                tx
+            };
+            let Some(transaction) = tx else {
+                tokio::time::sleep(POLL_INTERVAL).await;
+                continue;
             };
 
-            if let Some(transaction) = tx {
-                let tx_id = transaction.id;
-                debug!("Processing transaction: {}", tx_id);
+            let tx_id = transaction.id;
+            debug!("Processing transaction: {}", tx_id);
 
-                // Update transaction status to processing
-                {
-                    let mut results = self.transaction_results.lock().await;
-                    if let Some(response) = results.get_mut(&transaction.id) {
-                        response.status = TransactionStatus::Processing;
-                    }
+            // Update transaction status to processing
+            {
+                let mut results = self.transaction_results.lock().await;
+                if let Some(response) = results.get_mut(&tx_id) {
+                    response.status = TransactionStatus::Processing;
                 }
+            }
 
-                // Submit to consensus engine
-                match self
-                    .consensus_engine
-                    .submit_transaction(transaction.clone())
-                    .await
-                {
-                    Ok(response) => {
-                        info!("Transaction submitted for execution: {}", tx_id);
-                    }
-                    Err(e) => {
-                        error!("Failed to submit transaction to consensus: {}", e);
-
-                        // Update transaction status to failed
-                        let mut results = self.transaction_results.lock().await;
-                        if let Some(response) = results.get_mut(&tx_id) {
-                            response.status = TransactionStatus::Error;
-                            response.result = Some(
-                                serde_json::json!({"error": format!("Failed to submit transaction: {}", e)}),
-                            );
-                        }
-
-                        // Put it back in the queue for retry
-                        let mut queue = self.pending_transactions.lock().await;
-                        queue.push_back(transaction);
-                    }
+            // Submit to consensus engine
+            match self
+                .consensus_engine
+                .submit_transaction(transaction.clone())
+                .await
+            {
+                Ok(response) if response.status == TransactionStatus::Error => {
+                    // Every node refuses it when applying the log, such as a replayed nonce
+                    let error = response
+                        .result
+                        .as_ref()
+                        .and_then(|result| result.as_str())
+                        .unwrap_or("Transaction refused by consensus")
+                        .to_string();
+                    warn!("Transaction {} refused by consensus: {}", tx_id, error);
+                    self.reject(tx_id, error, 409).await;
+                }
+                Ok(_) => {
+                    info!("Transaction {} committed through consensus", tx_id);
+                    self.rejection_watchers.lock().await.remove(&tx_id);
+                }
+                Err(e) if chrono::Utc::now() - transaction.timestamp < tx_timeout => {
+                    warn!(
+                        "Failed to submit transaction {} to consensus, retrying: {}",
+                        tx_id, e
+                    );
+                    self.pending_transactions
+                        .lock()
+                        .await
+                        .push_front(transaction);
+                    tokio::time::sleep(SUBMIT_RETRY_INTERVAL).await;
+                }
+                Err(e) => {
+                    error!("Failed to submit transaction {} to consensus: {}", tx_id, e);
+                    self.reject(tx_id, format!("Failed to submit transaction: {}", e), 503)
+                        .await;
                 }
-            } else {
-                // No transactions to process, wait a bit
-                tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
             }
         }
     }
@@ -103,59 +156,10 @@ impl BasicTransactionPool {
 #[async_trait::async_trait]
 impl TransactionPool for BasicTransactionPool {
     async fn start(&self) -> Result<()> {
-        info!("Starting transaction pool with execute-then-consensus model");
-
-        // 创建交易处理循环
-        let pending_transactions = self.pending_transactions.clone();
-        let _transaction_map = self.transaction_map.clone();
-        let transaction_results = self.transaction_results.clone();
-        let tx_sender = self.tx_sender.clone();
-
-        // Start processing thread
-        tokio::spawn(async move {
-            info!("Transaction pool processing thread started");
-            loop {
-                tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
-
-                // Get a pending transaction
-                let tx = {
-                    let mut queue = pending_transactions.lock().await;
-                    queue.pop_front()
-                };
-
-                if let Some(transaction) = tx {
-                    let tx_id = transaction.id;
-                    info!("Processing pending transaction: {}", tx_id);
-
-                    // Send to transaction channel for execution
-                    if let Err(e) = tx_sender.send(transaction.clone()).await {
-                        error!("Failed to send transaction to channel: {}", e);
-                        continue;
-                    }
-
-                    // Update transaction status to processing
-                    {
-                        let mut results = transaction_results.lock().await;
-                        if !results.contains_key(&tx_id) {
-                            // Create initial response
-                            results.insert(
-                                tx_id.clone(),
-                                mp_common::types::TransactionResponse {
-                                    tx_id: tx_id.clone(),
-                                    status: TransactionStatus::Processing,
-                                    result: None,
-                                },
-                            );
-                        } else if let Some(response) = results.get_mut(&tx_id) {
-                            response.status = TransactionStatus::Processing;
-                        }
-                    }
+        info!("Starting transaction pool, transactions are executed once committed");
 
-                    // Keep the transaction in the map for status tracking
-                    info!("Transaction {} sent for execution", tx_id);
-                }
-            }
-        });
+        let pool = self.clone();
+        tokio::spawn(async move { pool.process_transactions().await });
 
         // // Start the API server
         // if let Some(api_address) = &self.config.api_address {
@@ -207,58 +211,124 @@ impl TransactionPool for BasicTransactionPool {
     async fn submit_transaction(&self, transaction: Transaction) -> Result<TransactionResponse> {
         info!("Submitting transaction: {:?}", transaction.id);
 
-        // Add transaction to pending queue
-        {
-            let mut queue = self.pending_transactions.lock().await;
-            queue.push_back(transaction.clone());
-        }
-
-        // Store transaction in map
-        {
-            let mut map = self.transaction_map.lock().await;
-            map.insert(transaction.id, transaction.clone());
-        }
-
-        // Create initial response
         let response = TransactionResponse {
             tx_id: transaction.id,
             status: TransactionStatus::Pending,
             result: None,
+            status_code: None,
         };
 
-        // Store the initial response
-        let mut results = self.transaction_results.lock().await;
-        results.insert(transaction.id, response.clone());
-
-        // Also add to transaction_map if not present, to ensure it can be found by get_transaction_status
-        let mut tx_map = self.transaction_map.lock().await;
-        if !tx_map.contains_key(&transaction.id) {
-            // Create a placeholder transaction entry with the ID
-            // This ensures the transaction can be found by the client
-            // let placeholder_tx = Transaction {
-            //     id: transaction.id,
-            //     tx_type: mp_common::types::TransactionType::StateChange,
-            //     payload: vec![],
-            //     timestamp: chrono::Utc::now(),
-            //     sender: Some("".to_string()),
-            //     log_index: 0,
-            // };
-            // 直接使用 tx_id 作为键，因为它已经是 String
-            tx_map.insert(transaction.id, transaction.clone());
+        // Check the nonce of the sender, then queue what it made ready
+        let admission = {
+            let mut nonces = self.nonces.lock().await;
+            let admission = match nonces.admit(&transaction) {
+                Ok(admission) => admission,
+                Err(e) => {
+                    self.rejection_watchers.lock().await.remove(&transaction.id);
+                    return Err(e.into());
+                }
+            };
+
+            // Known before it is queued, consensus may commit it before this returns
+            self.transaction_map
+                .lock()
+                .await
+                .insert(transaction.id, transaction.clone());
+            self.transaction_results
+                .lock()
+                .await
+                .insert(transaction.id, response.clone());
+
+            let mut queue = self.pending_transactions.lock().await;
+            match &admission {
+                Admission::Ready(ready) => queue.extend(ready.iter().cloned()),
+                Admission::Replaced {
+                    replaced,
+                    ready: true,
+                } => {
+                    if let Some(pending) = queue.iter_mut().find(|tx| tx.id == *replaced) {
+                        *pending = transaction.clone();
+                    }
+                }
+                Admission::Replaced { ready: false, .. } | Admission::Queued => {}
+            }
+            admission
+        };
+        match admission {
+            Admission::Queued => info!(
+                "MEMPOOL - Transaction {} waits for the earlier nonces of its sender",
+                transaction.id
+            ),
+            Admission::Replaced { replaced, .. } => {
+                info!(
+                    "MEMPOOL - Transaction {} replaced by {}",
+                    replaced, transaction.id
+                );
+                self.reject(
+                    replaced,
+                    format!("Replaced by transaction {}", transaction.id),
+                    409,
+                )
+                .await;
+            }
+            Admission::Ready(_) => {}
+        }
+
+        Ok(response)
+    }
+
+    async fn add_remote_transaction(&self, transaction: Transaction) -> Result<()> {
+        if self.has_transaction(&transaction.id).await {
+            return Ok(());
+        }
+        debug!("Transaction {} was submitted on a peer", transaction.id);
+
+        let (stale, ready) = {
+            let mut nonces = self.nonces.lock().await;
+            let (stale, ready) = nonces.used_elsewhere(&transaction);
+            let mut queue = self.pending_transactions.lock().await;
+            queue.retain(|tx| !stale.contains(&tx.id));
+            queue.extend(ready.iter().cloned());
+            (stale, ready)
+        };
+        if !ready.is_empty() {
             info!(
-                "MEMPOOL - Added placeholder transaction for {}",
+                "MEMPOOL - {} held transactions released by the nonce of {}",
+                ready.len(),
                 transaction.id
             );
         }
+        for tx_id in stale {
+            info!(
+                "MEMPOOL - Transaction {} dropped, its nonce was used by {} on a peer",
+                tx_id, transaction.id
+            );
+            self.reject(
+                tx_id,
+                format!("Nonce used by transaction {}", transaction.id),
+                409,
+            )
+            .await;
+        }
+        Ok(())
+    }
 
-        Ok(response)
+    async fn watch_rejection(&self, tx_id: Uuid) -> oneshot::Receiver<TransactionStatusWithProof> {
+        let (sender, receiver) = oneshot::channel();
+        let mut watchers = self.rejection_watchers.lock().await;
+        // Clients that went away wait no more
+        watchers.retain(|_, watcher| !watcher.is_closed());
+        watchers.insert(tx_id, sender);
+        receiver
     }
 
-    async fn get_pending_tx_channel(&self) -> mpsc::Receiver<Transaction> {
-        let mut rx_guard = self.tx_receiver.lock().await;
-        rx_guard
-            .take()
-            .expect("Pending transaction channel already taken")
+    async fn has_transaction(&self, tx_id: &Uuid) -> bool {
+        self.transaction_map.lock().await.contains_key(tx_id)
+            || self.transaction_results.lock().await.contains_key(tx_id)
+    }
+
+    async fn get_transaction(&self, tx_id: &Uuid) -> Option<Transaction> {
+        self.transaction_map.lock().await.get(tx_id).cloned()
     }
 
     async fn get_transaction_status(&self, tx_id: &Uuid) -> Result<TransactionStatusWithProof> {
@@ -294,7 +364,7 @@ impl TransactionPool for BasicTransactionPool {
 
                         return Ok(TransactionStatusWithProof::Failed(
                             response.result.clone().unwrap_or_default(),
-                            500,
+                            response.status_code.unwrap_or(500),
                             None,
                             poc,
                         ));
@@ -386,6 +456,7 @@ impl TransactionPool for BasicTransactionPool {
                 tx_id: *tx_id,
                 status: TransactionStatus::Success,
                 result: Some(result),
+                status_code: None,
             };
 
             // Store the result in the transaction_results map
@@ -416,6 +487,34 @@ impl TransactionPool for BasicTransactionPool {
         }
     }
 
+    /// Record the failure of a transaction, signed like a result
+    async fn update_transaction_failure(
+        &self,
+        tx_id: &Uuid,
+        error: serde_json::Value,
+        status_code: u16,
+        signed_aggregate: SignedAggregate,
+    ) -> Result<()> {
+        info!("MEMPOOL - Transaction {} failed: {}", tx_id, error);
+
+        let poc: PoC = signed_aggregate.try_into()?;
+        self.transaction_results.lock().await.insert(
+            *tx_id,
+            TransactionResponse {
+                tx_id: *tx_id,
+                status: TransactionStatus::Error,
+                result: Some(error),
+                status_code: Some(status_code),
+            },
+        );
+        self.transaction_proof
+            .lock()
+            .await
+            .insert(*tx_id, serde_json::json!(poc));
+        self.transaction_map.lock().await.remove(tx_id);
+        Ok(())
+    }
+
     /// Get the result of a transaction
     async fn get_transaction_result(
         &self,
@@ -447,6 +546,14 @@ impl TransactionPool for BasicTransactionPool {
     async fn get_transaction_proof(&self, tx_id: &Uuid) -> Option<serde_json::Value> {
         self.transaction_proof.lock().await.get(tx_id).cloned()
     }
+
+    async fn next_nonce(&self, sender: &str) -> u64 {
+        self.nonces.lock().await.next_nonce(sender)
+    }
+
+    async fn seed_nonce(&self, sender: &str, next: u64) {
+        self.nonces.lock().await.seed(sender, next);
+    }
 }
 
 impl Clone for BasicTransactionPool {
@@ -458,8 +565,8 @@ impl Clone for BasicTransactionPool {
             transaction_results: Arc::clone(&self.transaction_results),
             consensus_engine: Arc::clone(&self.consensus_engine),
             transaction_proof: Arc::clone(&self.transaction_proof),
-            tx_sender: self.tx_sender.clone(),
-            tx_receiver: Mutex::new(None), // The receiver can't be cloned
+            nonces: Arc::clone(&self.nonces),
+            rejection_watchers: Arc::clone(&self.rejection_watchers),
         }
     }
 }
@@ -0,0 +1,98 @@
+use std::time::Duration;
+
+use mp_common::types::TransactionType;
+use mp_common::utils::create_transaction;
+use mp_consensus::config::{BlockConfig, ConsensusConfig, NodeInfo, RaftConfig};
+use mp_consensus::raft::RaftConsensusEngine;
+use mp_consensus::ConsensusEngine;
+use mp_mempool::config::MempoolConfig;
+use mp_mempool::pool::BasicTransactionPool;
+use mp_mempool::TransactionPool;
+use tokio::time::{sleep, timeout, Instant};
+
+fn single_node_config(log_path: &std::path::Path) -> ConsensusConfig {
+    // The node binds a free port when it starts
+    let address = "127.0.0.1:0".parse().unwrap();
+    ConsensusConfig {
+        engine_type: "raft".to_string(),
+        node_id: 1,
+        nodes: vec![NodeInfo {
+            id: 1,
+            address,
+            rest_address: None,
+        }],
+        raft: Some(RaftConfig {
+            heartbeat_interval: 50,
+            election_timeout_min: 150,
+            election_timeout_max: 300,
+            snapshot_interval: 10000,
+            log_path: log_path.to_string_lossy().to_string(),
+            join: false,
+        }),
+        bft: None,
+        block: BlockConfig {
+            interval: 60_000,
+            max_transactions: 2,
+        },
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_pool_submissions_are_sealed_into_blocks() {
+    let data_dir = tempfile::tempdir().unwrap();
+    let mut engine = RaftConsensusEngine::new(single_node_config(data_dir.path()), None).unwrap();
+    let mut confirmed = engine.get_confirmed_tx_channel().await;
+    engine.start().await.unwrap();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !engine.is_leader().await {
+        assert!(Instant::now() < deadline, "node did not become leader");
+        sleep(Duration::from_millis(50)).await;
+    }
+    let blocks = engine.blocks();
+
+    let config = MempoolConfig {
+        max_transactions: 100,
+        api_address: None,
+        max_tx_size: 1024,
+        tx_timeout: 60,
+        max_queued_per_sender: 8,
+    };
+    let pool = BasicTransactionPool::new(config, Box::new(engine)).unwrap();
+    pool.start().await.unwrap();
+
+    let mut submitted = Vec::new();
+    for payload in ["first", "second", "third", "fourth"] {
+        let transaction = create_transaction(
+            TransactionType::StateChange,
+            payload.as_bytes().to_vec(),
+            None,
+            http::Method::POST,
+            Default::default(),
+        );
+        submitted.push(transaction.id);
+        pool.submit_transaction(transaction).await.unwrap();
+    }
+    for id in &submitted {
+        let transaction = timeout(Duration::from_secs(5), confirmed.recv())
+            .await
+            .expect("timed out waiting for confirmed transaction")
+            .expect("confirmed channel closed");
+        assert_eq!(transaction.id, *id);
+    }
+
+    // Every two committed transactions seal a block chained to the previous one
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while blocks.latest_height() < Some(2) {
+        assert!(Instant::now() < deadline, "blocks were not sealed");
+        sleep(Duration::from_millis(50)).await;
+    }
+    let chain = blocks.range(1, 10);
+    assert_eq!(chain.len(), 2);
+    let sealed: Vec<_> = chain
+        .iter()
+        .flat_map(|block| block.transactions.iter().map(|tx| tx.id))
+        .collect();
+    assert_eq!(sealed, submitted);
+    assert_eq!(chain[1].header.parent_hash, chain[0].header.hash());
+    assert_eq!(chain[0].header.height, 1);
+}
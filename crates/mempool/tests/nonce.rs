@@ -0,0 +1,472 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use mp_common::types::{
+    Transaction, TransactionResponse, TransactionStatusWithProof, TransactionType,
+};
+use mp_common::utils::create_transaction;
+use mp_consensus::block::{BlockSigner, BlockStore};
+use mp_consensus::ConsensusEngine;
+use mp_mempool::config::MempoolConfig;
+use mp_mempool::nonce::{Admission, NonceError, NonceTracker};
+use mp_mempool::pool::BasicTransactionPool;
+use mp_mempool::TransactionPool;
+use tokio::sync::mpsc;
+
+fn transaction(sender: Option<&str>, nonce: Option<u64>, fee: u64) -> Transaction {
+    let mut transaction = create_transaction(
+        TransactionType::StateChange,
+        vec![],
+        sender.map(str::to_string),
+        http::Method::POST,
+        Default::default(),
+    );
+    transaction.nonce = nonce;
+    transaction.fee = fee;
+    transaction
+}
+
+fn nonces(ready: &[Transaction]) -> Vec<Option<u64>> {
+    ready.iter().map(|tx| tx.nonce).collect()
+}
+
+#[test]
+fn test_gapped_nonces_wait_for_the_missing_ones() {
+    let mut tracker = NonceTracker::new(8);
+    let first = transaction(Some("alice"), Some(0), 0);
+    let Admission::Ready(ready) = tracker.admit(&first).unwrap() else {
+        panic!("first nonce not ready");
+    };
+    assert_eq!(nonces(&ready), vec![Some(0)]);
+
+    assert!(matches!(
+        tracker.admit(&transaction(Some("alice"), Some(3), 0)),
+        Ok(Admission::Queued)
+    ));
+    assert!(matches!(
+        tracker.admit(&transaction(Some("alice"), Some(2), 0)),
+        Ok(Admission::Queued)
+    ));
+    assert_eq!(tracker.next_nonce("alice"), 1);
+
+    // Filling the gap releases the held transactions in order
+    let Admission::Ready(ready) = tracker
+        .admit(&transaction(Some("alice"), Some(1), 0))
+        .unwrap()
+    else {
+        panic!("missing nonce not ready");
+    };
+    assert_eq!(nonces(&ready), vec![Some(1), Some(2), Some(3)]);
+    assert_eq!(tracker.next_nonce("alice"), 4);
+
+    // Senders are numbered independently
+    assert_eq!(tracker.next_nonce("bob"), 0);
+    assert!(matches!(
+        tracker.admit(&transaction(Some("bob"), Some(0), 0)),
+        Ok(Admission::Ready(_))
+    ));
+}
+
+#[test]
+fn test_dispatched_nonces_cannot_be_reused() {
+    let mut tracker = NonceTracker::new(8);
+    let first = transaction(Some("alice"), Some(0), 0);
+    tracker.admit(&first).unwrap();
+    tracker.dispatched(&first);
+
+    // A captured copy, even with a higher fee
+    let replay = Transaction {
+        fee: 100,
+        ..first.clone()
+    };
+    assert_eq!(
+        tracker.admit(&replay).unwrap_err(),
+        NonceError::Stale {
+            sender: "alice".to_string(),
+            nonce: 0,
+            next: 1,
+        }
+    );
+    assert_eq!(tracker.admit(&replay).unwrap_err().status_code(), 409);
+}
+
+#[test]
+fn test_pending_nonces_are_replaced_by_a_higher_fee() {
+    let mut tracker = NonceTracker::new(8);
+    let ready = transaction(Some("alice"), Some(0), 5);
+    let queued = transaction(Some("alice"), Some(2), 5);
+    tracker.admit(&ready).unwrap();
+    tracker.admit(&queued).unwrap();
+
+    for pending in [&ready, &queued] {
+        let same_fee = transaction(Some("alice"), pending.nonce, 5);
+        assert!(matches!(
+            tracker.admit(&same_fee),
+            Err(NonceError::Underpriced { pending: id, fee: 5, .. }) if id == pending.id
+        ));
+    }
+
+    let replacement = transaction(Some("alice"), Some(0), 6);
+    assert!(matches!(
+        tracker.admit(&replacement),
+        Ok(Admission::Replaced { replaced, ready: true }) if replaced == ready.id
+    ));
+    let replacement = transaction(Some("alice"), Some(2), 6);
+    assert!(matches!(
+        tracker.admit(&replacement),
+        Ok(Admission::Replaced { replaced, ready: false }) if replaced == queued.id
+    ));
+
+    // The replacement is the one released once the gap is filled
+    let Admission::Ready(ready) = tracker
+        .admit(&transaction(Some("alice"), Some(1), 0))
+        .unwrap()
+    else {
+        panic!("missing nonce not ready");
+    };
+    assert_eq!(ready[1].id, replacement.id);
+}
+
+#[test]
+fn test_queued_transactions_are_capped_per_sender() {
+    let mut tracker = NonceTracker::new(2);
+    tracker
+        .admit(&transaction(Some("alice"), Some(1), 0))
+        .unwrap();
+    tracker
+        .admit(&transaction(Some("alice"), Some(2), 0))
+        .unwrap();
+    let error = tracker
+        .admit(&transaction(Some("alice"), Some(3), 0))
+        .unwrap_err();
+    assert!(matches!(error, NonceError::TooManyQueued { max: 2, .. }));
+    assert_eq!(error.status_code(), 429);
+}
+
+#[test]
+fn test_anonymous_transactions_are_not_sequenced() {
+    let mut tracker = NonceTracker::new(0);
+    for _ in 0..2 {
+        assert!(matches!(
+            tracker.admit(&transaction(None, Some(5), 0)),
+            Ok(Admission::Ready(ready)) if ready.len() == 1
+        ));
+    }
+
+    // Contract calls cannot be anonymous, they could be replayed
+    let mut call = transaction(None, Some(5), 0);
+    call.tx_type = TransactionType::Request(Default::default(), "call".to_string());
+    assert_eq!(tracker.admit(&call).unwrap_err(), NonceError::Anonymous);
+
+    // A sender cannot leave its transactions unnumbered
+    assert_eq!(
+        tracker
+            .admit(&transaction(Some("alice"), None, 0))
+            .unwrap_err(),
+        NonceError::Missing {
+            sender: "alice".to_string(),
+            next: 0
+        }
+    );
+    assert_eq!(tracker.next_nonce("alice"), 0);
+}
+
+#[test]
+fn test_seeded_nonces_resume_where_the_sender_stopped() {
+    let mut tracker = NonceTracker::new(8);
+    tracker.seed("alice", 3);
+    assert!(matches!(
+        tracker.admit(&transaction(Some("alice"), Some(2), 0)),
+        Err(NonceError::Stale { next: 3, .. })
+    ));
+    assert!(matches!(
+        tracker.admit(&transaction(Some("alice"), Some(3), 0)),
+        Ok(Admission::Ready(_))
+    ));
+
+    // Seeding again does not move a sender the pool already knows
+    tracker.seed("alice", 0);
+    assert_eq!(tracker.next_nonce("alice"), 4);
+}
+
+/// Consensus engine recording the transactions the pool hands to it
+struct RecordingConsensus(mpsc::UnboundedSender<Transaction>);
+
+#[async_trait::async_trait]
+impl ConsensusEngine for RecordingConsensus {
+    async fn start(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn submit_transaction(&self, transaction: Transaction) -> Result<TransactionResponse> {
+        let tx_id = transaction.id;
+        self.0.send(transaction)?;
+        Ok(TransactionResponse::success(tx_id))
+    }
+
+    async fn get_confirmed_tx_channel(&self) -> mpsc::Receiver<Transaction> {
+        mpsc::channel(1).1
+    }
+
+    fn blocks(&self) -> Arc<BlockStore> {
+        Arc::new(BlockStore::in_memory())
+    }
+
+    fn set_block_signer(&self, _signer: BlockSigner) {}
+}
+
+#[tokio::test]
+async fn test_pool_dispatches_the_transactions_of_a_sender_in_nonce_order() {
+    let config = MempoolConfig {
+        max_transactions: 100,
+        api_address: None,
+        max_tx_size: 1024,
+        tx_timeout: 60,
+        max_queued_per_sender: 8,
+    };
+    let (committed, mut dispatched) = mpsc::unbounded_channel();
+    let pool = BasicTransactionPool::new(config, Box::new(RecordingConsensus(committed))).unwrap();
+    pool.start().await.unwrap();
+
+    let second = transaction(Some("alice"), Some(1), 0);
+    let outbid = transaction(Some("alice"), Some(1), 0);
+    let third = transaction(Some("alice"), Some(1), 1);
+    let first = transaction(Some("alice"), Some(0), 0);
+    pool.submit_transaction(second.clone()).await.unwrap();
+    let error = pool.submit_transaction(outbid).await.unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<NonceError>(),
+        Some(NonceError::Underpriced { .. })
+    ));
+    pool.submit_transaction(third.clone()).await.unwrap();
+    assert!(matches!(
+        pool.get_transaction_status(&second.id).await.unwrap(),
+        TransactionStatusWithProof::Failed(_, 409, _, _)
+    ));
+    assert!(matches!(
+        pool.get_transaction_status(&third.id).await.unwrap(),
+        TransactionStatusWithProof::Pending
+    ));
+    pool.submit_transaction(first.clone()).await.unwrap();
+    assert_eq!(pool.next_nonce("alice").await, 2);
+
+    for expected in [&first, &third] {
+        let tx = tokio::time::timeout(Duration::from_secs(5), dispatched.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(tx.id, expected.id);
+    }
+
+    // Once dispatched, a nonce is spent
+    let error = pool.submit_transaction(first).await.unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<NonceError>(),
+        Some(NonceError::Stale { next: 2, .. })
+    ));
+}
+
+#[tokio::test]
+async fn test_pool_fails_transactions_consensus_refuses_past_the_timeout() {
+    let config = MempoolConfig {
+        max_transactions: 100,
+        api_address: None,
+        max_tx_size: 1024,
+        tx_timeout: 0,
+        max_queued_per_sender: 8,
+    };
+    // The engine refuses everything once nobody records what it commits
+    let (committed, _) = mpsc::unbounded_channel();
+    let pool = BasicTransactionPool::new(config, Box::new(RecordingConsensus(committed))).unwrap();
+    pool.start().await.unwrap();
+
+    let tx = transaction(Some("alice"), Some(0), 0);
+    pool.submit_transaction(tx.clone()).await.unwrap();
+    assert!(pool.has_transaction(&tx.id).await);
+    let status = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            match pool.get_transaction_status(&tx.id).await.unwrap() {
+                status @ TransactionStatusWithProof::Failed(..) => break status,
+                _ => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        }
+    })
+    .await
+    .unwrap();
+    assert!(matches!(
+        status,
+        TransactionStatusWithProof::Failed(_, 503, _, _)
+    ));
+}
+
+#[tokio::test]
+async fn test_gossiped_transactions_take_nonces_without_being_dispatched() {
+    let config = MempoolConfig {
+        max_transactions: 100,
+        api_address: None,
+        max_tx_size: 1024,
+        tx_timeout: 60,
+        max_queued_per_sender: 8,
+    };
+    let (committed, mut dispatched) = mpsc::unbounded_channel();
+    let pool = BasicTransactionPool::new(config, Box::new(RecordingConsensus(committed))).unwrap();
+    pool.start().await.unwrap();
+
+    let held = transaction(Some("alice"), Some(2), 0);
+    let conflicting = transaction(Some("bob"), Some(1), 0);
+    pool.submit_transaction(held.clone()).await.unwrap();
+    pool.submit_transaction(conflicting.clone()).await.unwrap();
+
+    // Nonces used on a peer release the transactions waiting for them
+    let remote = [
+        transaction(Some("alice"), Some(0), 0),
+        transaction(Some("alice"), Some(1), 0),
+    ];
+    for tx in &remote {
+        pool.add_remote_transaction(tx.clone()).await.unwrap();
+        assert!(!pool.has_transaction(&tx.id).await);
+    }
+    assert_eq!(pool.next_nonce("alice").await, 3);
+    let tx = tokio::time::timeout(Duration::from_secs(5), dispatched.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(tx.id, held.id);
+
+    // A local transaction whose nonce a peer used is dropped
+    pool.add_remote_transaction(transaction(Some("bob"), Some(1), 0))
+        .await
+        .unwrap();
+    assert!(matches!(
+        pool.get_transaction_status(&conflicting.id).await.unwrap(),
+        TransactionStatusWithProof::Failed(_, 409, _, _)
+    ));
+
+    // The gossiped transactions cannot be submitted here again
+    let error = pool
+        .submit_transaction(remote[0].clone())
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<NonceError>(),
+        Some(NonceError::Stale { next: 3, .. })
+    ));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(dispatched.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn test_rejection_watchers_learn_of_failures_before_ordering() {
+    let config = MempoolConfig {
+        max_transactions: 100,
+        api_address: None,
+        max_tx_size: 1024,
+        tx_timeout: 60,
+        max_queued_per_sender: 8,
+    };
+    let (committed, mut dispatched) = mpsc::unbounded_channel();
+    let pool = BasicTransactionPool::new(config, Box::new(RecordingConsensus(committed))).unwrap();
+    pool.seed_nonce("alice", 5).await;
+    pool.start().await.unwrap();
+
+    // Held for nonce 5, then outbid
+    let replaced = transaction(Some("alice"), Some(6), 0);
+    let replaced_rejection = pool.watch_rejection(replaced.id).await;
+    pool.submit_transaction(replaced.clone()).await.unwrap();
+    pool.submit_transaction(transaction(Some("alice"), Some(6), 1))
+        .await
+        .unwrap();
+    assert!(matches!(
+        tokio::time::timeout(Duration::from_secs(5), replaced_rejection)
+            .await
+            .unwrap(),
+        Ok(TransactionStatusWithProof::Failed(_, 409, _, _))
+    ));
+
+    // An ordered transaction is never rejected, its watcher is let go
+    let ordered = transaction(Some("alice"), Some(5), 0);
+    let ordered_rejection = pool.watch_rejection(ordered.id).await;
+    pool.submit_transaction(ordered.clone()).await.unwrap();
+    let tx = tokio::time::timeout(Duration::from_secs(5), dispatched.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(tx.id, ordered.id);
+    assert!(
+        tokio::time::timeout(Duration::from_secs(5), ordered_rejection)
+            .await
+            .unwrap()
+            .is_err()
+    );
+
+    // A refused submission is not watched either
+    let stale = transaction(Some("alice"), Some(0), 0);
+    let stale_rejection = pool.watch_rejection(stale.id).await;
+    assert!(pool.submit_transaction(stale).await.is_err());
+    assert!(stale_rejection.await.is_err());
+}
+
+/// Consensus engine refusing every transaction when applying it, as for a replayed nonce
+struct RefusingConsensus;
+
+#[async_trait::async_trait]
+impl ConsensusEngine for RefusingConsensus {
+    async fn start(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn stop(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn submit_transaction(&self, _transaction: Transaction) -> Result<TransactionResponse> {
+        Ok(TransactionResponse::error(
+            "Nonce 0 of alice was already used, the next nonce is 1".to_string(),
+        ))
+    }
+
+    async fn get_confirmed_tx_channel(&self) -> mpsc::Receiver<Transaction> {
+        mpsc::channel(1).1
+    }
+
+    fn blocks(&self) -> Arc<BlockStore> {
+        Arc::new(BlockStore::in_memory())
+    }
+
+    fn set_block_signer(&self, _signer: BlockSigner) {}
+}
+
+#[tokio::test]
+async fn test_transactions_refused_by_consensus_fail_with_a_conflict() {
+    let config = MempoolConfig {
+        max_transactions: 100,
+        api_address: None,
+        max_tx_size: 1024,
+        tx_timeout: 60,
+        max_queued_per_sender: 8,
+    };
+    let pool = BasicTransactionPool::new(config, Box::new(RefusingConsensus)).unwrap();
+    pool.start().await.unwrap();
+
+    let tx = transaction(Some("alice"), Some(0), 0);
+    let rejection = pool.watch_rejection(tx.id).await;
+    pool.submit_transaction(tx.clone()).await.unwrap();
+    match tokio::time::timeout(Duration::from_secs(5), rejection)
+        .await
+        .unwrap()
+    {
+        Ok(TransactionStatusWithProof::Failed(error, 409, _, _)) => {
+            assert_eq!(
+                error["error"],
+                "Nonce 0 of alice was already used, the next nonce is 1"
+            );
+        }
+        status => panic!("unexpected status {:?}", status),
+    }
+    assert!(pool.get_transaction(&tx.id).await.is_none());
+}
@@ -7,6 +7,52 @@ pub struct NetworkConfig {
     /// Listen address
     pub listen_address: SocketAddr,
 
-    /// Bootstrap nodes
+    /// Bootstrap nodes, as multiaddrs ending with the node's peer ID
+    /// (e.g. `/ip4/10.0.0.1/tcp/9000/p2p/12D3KooW...`)
     pub bootstrap_nodes: Vec<String>,
+
+    /// File holding the node's network key, created on first start.
+    /// A fresh key is generated on every start when unset.
+    #[serde(default)]
+    pub key_path: Option<String>,
+
+    /// Discover peers on the local network through mDNS
+    #[serde(default)]
+    pub enable_mdns: bool,
+
+    /// Catch up with the state of connected peers on startup
+    #[serde(default)]
+    pub state_sync: bool,
+
+    /// Peer scoring and banning
+    #[serde(default)]
+    pub reputation: ReputationConfig,
+}
+
+/// Peer reputation configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReputationConfig {
+    /// Score at or below which a peer is banned
+    pub ban_threshold: i32,
+
+    /// Ban duration in seconds
+    pub ban_duration: u64,
+
+    /// Maximum number of gossip messages accepted from a peer per second
+    pub max_messages_per_second: u32,
+
+    /// Points a penalised peer recovers every second, up to a neutral score
+    pub recovery_per_second: i32,
+}
+
+impl Default for ReputationConfig {
+    fn default() -> Self {
+        Self {
+            ban_threshold: -100,
+            ban_duration: 600,
+            max_messages_per_second: 100,
+            recovery_per_second: 1,
+        }
+    }
 }
@@ -1,77 +1,70 @@
-use anyhow::Result;
-use std::collections::HashSet;
-use tracing::{debug, info, warn};
+use anyhow::{anyhow, Result};
+use libp2p::kad::store::MemoryStore;
+use libp2p::kad::{Kademlia, KademliaConfig};
+use libp2p::multiaddr::Protocol;
+use libp2p::{Multiaddr, PeerId};
+use std::time::Duration;
+use tracing::info;
 
 use crate::config::NetworkConfig;
 
 /// Service for node discovery
+///
+/// Peers are found through a Kademlia DHT seeded with the bootstrap nodes
+/// and, when enabled, through mDNS on the local network.
 pub struct DiscoveryService {
-    config: NetworkConfig,
-    #[allow(dead_code)]
-    peers: HashSet<String>,
-    running: bool,
+    bootstrap_nodes: Vec<(PeerId, Multiaddr)>,
 }
 
 impl DiscoveryService {
     /// Create a new discovery service
-    pub fn new(config: NetworkConfig) -> Self {
-        let mut peers = HashSet::new();
-
-        // Add bootstrap nodes to initial peer list
-        for node in &config.bootstrap_nodes {
-            peers.insert(node.clone());
-        }
-
-        Self {
-            config,
-            peers,
-            running: false,
-        }
-    }
-
-    /// Start the discovery service
-    pub fn start(&mut self) -> Result<()> {
-        if self.running {
-            warn!("Discovery service already running");
-            return Ok(());
-        }
+    pub fn new(config: &NetworkConfig) -> Result<Self> {
+        let bootstrap_nodes = config
+            .bootstrap_nodes
+            .iter()
+            .map(|node| parse_bootstrap_node(node))
+            .collect::<Result<Vec<_>>>()?;
 
         info!(
-            "Starting discovery service with {} bootstrap nodes",
-            self.config.bootstrap_nodes.len()
+            "Discovery service seeded with {} bootstrap nodes",
+            bootstrap_nodes.len()
         );
-
-        // In a real implementation, we would start a libp2p discovery service
-        // For now, we just log the bootstrap nodes
-        for node in &self.config.bootstrap_nodes {
-            debug!("Bootstrap node: {}", node);
-        }
-
-        self.running = true;
-        Ok(())
+        Ok(Self { bootstrap_nodes })
     }
 
-    /// Stop the discovery service
-    pub fn stop(&mut self) -> Result<()> {
-        if !self.running {
-            warn!("Discovery service not running");
-            return Ok(());
-        }
-
-        info!("Stopping discovery service");
-        self.running = false;
-        Ok(())
+    /// Get the bootstrap nodes
+    pub fn bootstrap_nodes(&self) -> &[(PeerId, Multiaddr)] {
+        &self.bootstrap_nodes
     }
 
-    /// Get the current list of peers
-    #[allow(dead_code)]
-    pub fn get_peers(&self) -> Vec<String> {
-        self.peers.iter().cloned().collect()
+    /// Create the Kademlia behaviour with the bootstrap nodes in its routing table
+    pub fn kademlia(&self, local_peer_id: PeerId) -> Kademlia<MemoryStore> {
+        let mut config = KademliaConfig::default();
+        config.set_query_timeout(Duration::from_secs(30));
+
+        let mut kademlia =
+            Kademlia::with_config(local_peer_id, MemoryStore::new(local_peer_id), config);
+        for (peer_id, address) in &self.bootstrap_nodes {
+            kademlia.add_address(peer_id, address.clone());
+        }
+        kademlia
     }
+}
 
-    /// Add a peer to the list
-    #[allow(dead_code)]
-    pub fn add_peer(&mut self, peer: String) {
-        self.peers.insert(peer);
+/// Split a bootstrap multiaddr into the peer ID and the address to dial
+fn parse_bootstrap_node(node: &str) -> Result<(PeerId, Multiaddr)> {
+    let mut address: Multiaddr = node
+        .parse()
+        .map_err(|e| anyhow!("Invalid bootstrap node {}: {}", node, e))?;
+    match address.pop() {
+        Some(Protocol::P2p(hash)) => {
+            let peer_id = PeerId::from_multihash(hash)
+                .map_err(|_| anyhow!("Invalid peer ID in bootstrap node {}", node))?;
+            Ok((peer_id, address))
+        }
+        _ => Err(anyhow!(
+            "Bootstrap node {} must end with /p2p/<peer id>",
+            node
+        )),
     }
 }
@@ -3,17 +3,31 @@
 pub mod config;
 mod discovery;
 mod protocol;
+pub mod reputation;
+pub mod state_sync;
+mod swarm;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use libp2p::{identity, Multiaddr, PeerId};
 use mp_common::types::Transaction;
-use std::sync::{Arc, Mutex};
+use mp_state::StateStorage;
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
 use tokio::sync::mpsc;
-use tracing::{debug, error, info, warn};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
 
 use discovery::DiscoveryService;
 use protocol::ProtocolHandler;
+use reputation::{PeerInfo, PeerReputation};
+use swarm::{Command, SwarmState};
+
+pub use protocol::TRANSACTION_TOPIC;
 
 /// Network interface
+#[async_trait::async_trait]
 pub trait Network: Send + Sync {
     /// Start the network
     fn start(&self) -> Result<()>;
@@ -27,42 +41,126 @@ pub trait Network: Send + Sync {
     /// Get a channel for received transactions
     fn get_received_tx_channel(&self) -> mpsc::Receiver<Transaction>;
 
+    /// Get the peer ID of this node
+    fn local_peer_id(&self) -> String;
+
+    /// Get the addresses the node listens on, including its peer ID
+    ///
+    /// Known once the swarm bound them, which tells the port picked when the
+    /// node is configured with port 0. These are the addresses to bootstrap from.
+    fn listen_addresses(&self) -> Vec<String>;
+
+    /// Get the peer IDs of the connected peers
+    fn peers(&self) -> Vec<String>;
+
+    /// Get the reputation of connected, penalised and banned peers
+    fn peer_list(&self) -> Vec<PeerInfo>;
+
+    /// Catch up with the state of a connected peer, returning the resulting state root
+    ///
+    /// Peers are tried in turn until one of them brings the local state up to its own.
+    async fn sync_state(&self) -> Result<String>;
+
     /// Clone this network instance
     fn clone(&self) -> Arc<dyn Network>;
 }
 
 /// Create a new network based on the configuration
-pub fn create_network(config: config::NetworkConfig) -> Result<Arc<dyn Network>> {
+///
+/// Peers gossiping transactions with payloads above `max_tx_size` bytes are penalised.
+/// State sync requests from peers are served from `state_storage` when given.
+pub fn create_network(
+    config: config::NetworkConfig,
+    max_tx_size: usize,
+    state_storage: Option<Arc<dyn StateStorage>>,
+) -> Result<Arc<dyn Network>> {
     info!("Creating libp2p network service");
-    Ok(Arc::new(Libp2pNetwork::new(config)?))
+    Ok(Arc::new(Libp2pNetwork::new(
+        config,
+        max_tx_size,
+        state_storage,
+    )?))
+}
+
+/// Load the node's network key from `key_path`, generating and saving it on first use
+pub fn load_or_create_keypair(key_path: &Path) -> Result<identity::Keypair> {
+    if key_path.exists() {
+        let bytes = std::fs::read(key_path)?;
+        return identity::Keypair::from_protobuf_encoding(&bytes)
+            .map_err(|e| anyhow!("Invalid network key in {}: {}", key_path.display(), e));
+    }
+
+    let keypair = identity::Keypair::generate_ed25519();
+    if let Some(parent) = key_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let bytes = keypair
+        .to_protobuf_encoding()
+        .map_err(|e| anyhow!("Failed to encode network key: {}", e))?;
+    std::fs::write(key_path, bytes)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(key_path, std::fs::Permissions::from_mode(0o600))?;
+    }
+
+    info!("Generated network key in {}", key_path.display());
+    Ok(keypair)
+}
+
+/// Handle to the running swarm task
+struct SwarmHandle {
+    commands: mpsc::UnboundedSender<Command>,
+    task: JoinHandle<()>,
 }
 
 /// Libp2p-based network implementation
 struct Libp2pNetwork {
     config: config::NetworkConfig,
-    discovery: Arc<Mutex<DiscoveryService>>,
+    keypair: identity::Keypair,
+    discovery: Arc<DiscoveryService>,
     protocol: Arc<Mutex<ProtocolHandler>>,
-    running: Arc<Mutex<bool>>,
+    reputation: Arc<Mutex<PeerReputation>>,
+    swarm: Arc<Mutex<Option<SwarmHandle>>>,
+    peers: Arc<RwLock<HashSet<PeerId>>>,
+    listen_addresses: Arc<RwLock<Vec<Multiaddr>>>,
+    state_storage: Option<Arc<dyn StateStorage>>,
     tx_sender: mpsc::Sender<Transaction>,
-    tx_receiver: tokio::sync::Mutex<Option<mpsc::Receiver<Transaction>>>,
+    tx_receiver: Mutex<Option<mpsc::Receiver<Transaction>>>,
 }
 
 impl Libp2pNetwork {
     /// Create a new libp2p network
-    fn new(config: config::NetworkConfig) -> Result<Self> {
+    fn new(
+        config: config::NetworkConfig,
+        max_tx_size: usize,
+        state_storage: Option<Arc<dyn StateStorage>>,
+    ) -> Result<Self> {
         let (tx_sender, tx_receiver) = mpsc::channel(1000);
 
+        let keypair = match &config.key_path {
+            Some(path) => load_or_create_keypair(Path::new(path))?,
+            None => identity::Keypair::generate_ed25519(),
+        };
+        info!("Local peer ID: {}", keypair.public().to_peer_id());
+
         Ok(Self {
-            config: config.clone(),
-            discovery: Arc::new(Mutex::new(DiscoveryService::new(config.clone()))),
-            protocol: Arc::new(Mutex::new(ProtocolHandler::new(config))),
-            running: Arc::new(Mutex::new(false)),
+            discovery: Arc::new(DiscoveryService::new(&config)?),
+            reputation: Arc::new(Mutex::new(PeerReputation::new(config.reputation.clone()))),
+            config,
+            keypair,
+            protocol: Arc::new(Mutex::new(ProtocolHandler::new(max_tx_size))),
+            swarm: Arc::new(Mutex::new(None)),
+            peers: Arc::new(RwLock::new(HashSet::new())),
+            listen_addresses: Arc::new(RwLock::new(Vec::new())),
+            state_storage,
             tx_sender,
-            tx_receiver: tokio::sync::Mutex::new(Some(tx_receiver)),
+            tx_receiver: Mutex::new(Some(tx_receiver)),
         })
     }
 }
 
+#[async_trait::async_trait]
 impl Network for Libp2pNetwork {
     fn start(&self) -> Result<()> {
         info!(
@@ -70,85 +168,151 @@ impl Network for Libp2pNetwork {
             self.config.listen_address
         );
 
-        let mut running = self.running.lock().unwrap();
-        if *running {
+        let mut handle = self.swarm.lock().unwrap();
+        if handle.is_some() {
             warn!("Network service already running");
             return Ok(());
         }
 
-        // Start discovery service
-        if let Err(e) = self.discovery.lock().unwrap().start() {
-            error!("Failed to start discovery service: {}", e);
-            return Err(anyhow::anyhow!("Failed to start discovery service"));
-        }
-
-        // Start protocol handler
-        if let Err(e) = self.protocol.lock().unwrap().start() {
-            error!("Failed to start protocol handler: {}", e);
-            return Err(anyhow::anyhow!("Failed to start protocol handler"));
-        }
+        let swarm = swarm::build_swarm(
+            &self.keypair,
+            self.config.listen_address,
+            &self.discovery,
+            self.config.enable_mdns,
+            self.protocol.lock().unwrap().max_message_size(),
+        )?;
+        let (commands, command_receiver) = mpsc::unbounded_channel();
+        let state = SwarmState {
+            protocol: self.protocol.clone(),
+            reputation: self.reputation.clone(),
+            tx_sender: self.tx_sender.clone(),
+            peers: self.peers.clone(),
+            listen_addresses: self.listen_addresses.clone(),
+            bootstrap_peers: self
+                .discovery
+                .bootstrap_nodes()
+                .iter()
+                .map(|(peer_id, _)| *peer_id)
+                .collect(),
+            state: self.state_storage.clone(),
+        };
+        let task = tokio::spawn(swarm::run(swarm, command_receiver, state));
+        *handle = Some(SwarmHandle { commands, task });
 
-        *running = true;
         info!("Network service started");
-
         Ok(())
     }
 
     fn stop(&self) -> Result<()> {
         info!("Stopping libp2p network service");
 
-        let mut running = self.running.lock().unwrap();
-        if !*running {
-            warn!("Network service not running");
-            return Ok(());
-        }
-
-        // Stop protocol handler
-        if let Err(e) = self.protocol.lock().unwrap().stop() {
-            error!("Failed to stop protocol handler: {}", e);
-        }
-
-        // Stop discovery service
-        if let Err(e) = self.discovery.lock().unwrap().stop() {
-            error!("Failed to stop discovery service: {}", e);
+        match self.swarm.lock().unwrap().take() {
+            Some(handle) => {
+                handle.task.abort();
+                self.peers.write().unwrap().clear();
+                self.listen_addresses.write().unwrap().clear();
+                info!("Network service stopped");
+            }
+            None => warn!("Network service not running"),
         }
 
-        *running = false;
-        info!("Network service stopped");
-
         Ok(())
     }
 
     fn broadcast_transaction(&self, transaction: Transaction) -> Result<()> {
         debug!("Broadcasting transaction: {:?}", transaction.id);
 
-        let running = self.running.lock().unwrap();
-        if !*running {
-            return Err(anyhow::anyhow!("Network service not running"));
-        }
+        let handle = self.swarm.lock().unwrap();
+        let handle = handle
+            .as_ref()
+            .ok_or_else(|| anyhow!("Network service not running"))?;
 
-        // Forward to protocol handler
-        self.protocol
+        let data = self
+            .protocol
             .lock()
             .unwrap()
-            .broadcast_transaction(transaction)
+            .broadcast_transaction(&transaction)?;
+        handle
+            .commands
+            .send(Command::Publish(data))
+            .map_err(|_| anyhow!("Network service stopped"))
     }
 
     fn get_received_tx_channel(&self) -> mpsc::Receiver<Transaction> {
-        let mut rx_guard = self.tx_receiver.blocking_lock();
-        rx_guard
+        self.tx_receiver
+            .lock()
+            .unwrap()
             .take()
             .expect("Received transaction channel already taken")
     }
 
+    fn local_peer_id(&self) -> String {
+        self.keypair.public().to_peer_id().to_string()
+    }
+
+    fn listen_addresses(&self) -> Vec<String> {
+        let peer_id = self.keypair.public().to_peer_id();
+        self.listen_addresses
+            .read()
+            .unwrap()
+            .iter()
+            .map(|address| format!("{}/p2p/{}", address, peer_id))
+            .collect()
+    }
+
+    fn peers(&self) -> Vec<String> {
+        self.peers
+            .read()
+            .unwrap()
+            .iter()
+            .map(|peer| peer.to_string())
+            .collect()
+    }
+
+    fn peer_list(&self) -> Vec<PeerInfo> {
+        let connected = self.peers.read().unwrap();
+        self.reputation
+            .lock()
+            .unwrap()
+            .peer_list(&connected, Instant::now())
+    }
+
+    async fn sync_state(&self) -> Result<String> {
+        let storage = self
+            .state_storage
+            .as_ref()
+            .ok_or_else(|| anyhow!("State sync requires a state storage"))?;
+        let commands = self
+            .swarm
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|handle| handle.commands.clone())
+            .ok_or_else(|| anyhow!("Network service not running"))?;
+
+        let peers: Vec<PeerId> = self.peers.read().unwrap().iter().copied().collect();
+        for peer in peers {
+            match state_sync::sync_from(storage, peer, &commands).await {
+                Ok(root) => return Ok(root),
+                Err(e) => warn!("State sync with {} failed: {}", peer, e),
+            }
+        }
+        Err(anyhow!("No connected peer could serve the state"))
+    }
+
     fn clone(&self) -> Arc<dyn Network> {
         Arc::new(Libp2pNetwork {
             config: self.config.clone(),
+            keypair: self.keypair.clone(),
             discovery: self.discovery.clone(),
             protocol: self.protocol.clone(),
-            running: self.running.clone(),
+            reputation: self.reputation.clone(),
+            swarm: self.swarm.clone(),
+            peers: self.peers.clone(),
+            listen_addresses: self.listen_addresses.clone(),
+            state_storage: self.state_storage.clone(),
             tx_sender: self.tx_sender.clone(),
-            tx_receiver: tokio::sync::Mutex::new(None),
+            tx_receiver: Mutex::new(None),
         })
     }
 }
@@ -1,97 +1,92 @@
 use anyhow::Result;
+use libp2p::gossipsub::IdentTopic;
 use mp_common::types::Transaction;
-use std::collections::HashMap;
-use tracing::{debug, info, warn};
+use std::collections::{HashSet, VecDeque};
+use tracing::debug;
 use uuid::Uuid;
 
-use crate::config::NetworkConfig;
+use crate::reputation::Misbehaviour;
+
+/// Gossipsub topic transactions are broadcast on
+pub const TRANSACTION_TOPIC: &str = "mp/transactions/1";
+
+/// Number of transaction IDs remembered to drop duplicates
+const SEEN_CAPACITY: usize = 100_000;
+
+/// Get the transaction topic
+pub fn transaction_topic() -> IdentTopic {
+    IdentTopic::new(TRANSACTION_TOPIC)
+}
 
 /// Handler for network protocol
+///
+/// Encodes broadcast transactions and drops the ones already seen, whether
+/// they were sent by this node or received from a peer.
 pub struct ProtocolHandler {
-    config: NetworkConfig,
-    running: bool,
-    pending_transactions: HashMap<Uuid, Transaction>,
+    max_tx_size: usize,
+    seen: HashSet<Uuid>,
+    order: VecDeque<Uuid>,
 }
 
 impl ProtocolHandler {
-    /// Create a new protocol handler
-    pub fn new(config: NetworkConfig) -> Self {
+    /// Create a new protocol handler accepting payloads of up to `max_tx_size` bytes
+    pub fn new(max_tx_size: usize) -> Self {
         Self {
-            config,
-            running: false,
-            pending_transactions: HashMap::new(),
+            max_tx_size,
+            seen: HashSet::new(),
+            order: VecDeque::new(),
         }
     }
 
-    /// Start the protocol handler
-    pub fn start(&mut self) -> Result<()> {
-        if self.running {
-            warn!("Protocol handler already running");
-            return Ok(());
-        }
-
-        info!(
-            "Starting protocol handler on {}",
-            self.config.listen_address
-        );
-
-        // In a real implementation, we would start a libp2p protocol handler
-        // For now, we just set the running flag
-
-        self.running = true;
-        Ok(())
-    }
-
-    /// Stop the protocol handler
-    pub fn stop(&mut self) -> Result<()> {
-        if !self.running {
-            warn!("Protocol handler not running");
-            return Ok(());
-        }
-
-        info!("Stopping protocol handler");
-        self.running = false;
-        Ok(())
+    /// Maximum size of an encoded transaction message
+    ///
+    /// JSON spells payload bytes out as numbers, which takes up to four bytes each.
+    pub fn max_message_size(&self) -> usize {
+        self.max_tx_size.saturating_mul(4).saturating_add(64 * 1024)
     }
 
-    /// Broadcast a transaction to the network
-    pub fn broadcast_transaction(&mut self, tx: Transaction) -> Result<()> {
-        if !self.running {
-            return Err(anyhow::anyhow!("Protocol handler not running"));
-        }
-
+    /// Encode a transaction for broadcasting
+    pub fn broadcast_transaction(&mut self, tx: &Transaction) -> Result<Vec<u8>> {
         debug!("Broadcasting transaction: {:?}", tx.id);
-
-        // Store transaction in pending map
-        self.pending_transactions.insert(tx.id, tx.clone());
-
-        // In a real implementation, we would broadcast the transaction to peers
-        // For now, we just log it
-
-        Ok(())
+        self.remember(tx.id);
+        Ok(serde_json::to_vec(tx)?)
     }
 
-    /// Handle a received transaction
-    #[allow(dead_code)]
-    pub fn handle_transaction(&mut self, tx: Transaction) -> Result<()> {
-        if !self.running {
-            return Err(anyhow::anyhow!("Protocol handler not running"));
+    /// Decode a received message, returning the misbehaviour if it is not a new valid transaction
+    pub fn handle_message(
+        &mut self,
+        data: &[u8],
+    ) -> std::result::Result<Transaction, Misbehaviour> {
+        let tx: Transaction =
+            serde_json::from_slice(data).map_err(|_| Misbehaviour::UndecodableMessage)?;
+        if tx.payload.len() > self.max_tx_size {
+            debug!(
+                "Rejecting transaction {:?} with a {} byte payload",
+                tx.id,
+                tx.payload.len()
+            );
+            return Err(Misbehaviour::OversizedPayload);
         }
-
-        debug!("Received transaction: {:?}", tx.id);
-
-        // Check if we've already seen this transaction
-        if self.pending_transactions.contains_key(&tx.id) {
+        if !self.remember(tx.id) {
             debug!("Ignoring duplicate transaction: {:?}", tx.id);
-            return Ok(());
+            return Err(Misbehaviour::DuplicateMessage);
         }
 
-        // Store transaction in pending map
-        self.pending_transactions.insert(tx.id, tx.clone());
-
-        // In a real implementation, we would validate and forward the transaction
-        // For now, we just log it
+        debug!("Received transaction: {:?}", tx.id);
+        Ok(tx)
+    }
 
-        Ok(())
+    /// Record a transaction ID, returning false if it was already known
+    fn remember(&mut self, id: Uuid) -> bool {
+        if !self.seen.insert(id) {
+            return false;
+        }
+        self.order.push_back(id);
+        if self.order.len() > SEEN_CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        true
     }
 }
@@ -0,0 +1,192 @@
+use libp2p::PeerId;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::config::ReputationConfig;
+
+/// Misbehaviour a peer is penalised for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Misbehaviour {
+    /// Message that does not decode into a transaction
+    UndecodableMessage,
+    /// Transaction payload above the mempool's maximum transaction size
+    OversizedPayload,
+    /// Transaction that was already received
+    DuplicateMessage,
+    /// Message rejected by gossipsub validation, such as a bad signature
+    InvalidSignature,
+    /// Messages sent above the per-peer rate limit
+    RateLimited,
+    /// State sync response that does not chain up with the requested state
+    InvalidStateSync,
+}
+
+impl Misbehaviour {
+    /// Score deducted for this misbehaviour
+    pub fn penalty(self) -> i32 {
+        match self {
+            Misbehaviour::UndecodableMessage => 20,
+            Misbehaviour::OversizedPayload => 20,
+            Misbehaviour::DuplicateMessage => 2,
+            Misbehaviour::InvalidSignature => 50,
+            Misbehaviour::RateLimited => 5,
+            Misbehaviour::InvalidStateSync => 50,
+        }
+    }
+}
+
+/// Reputation of a peer as reported by the admin API
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerInfo {
+    /// Peer ID
+    pub peer_id: String,
+    /// Whether the peer is currently connected
+    pub connected: bool,
+    /// Current score, 0 for well-behaved peers
+    pub score: i32,
+    /// Seconds until the ban is lifted, if the peer is banned
+    pub banned_for: Option<u64>,
+}
+
+#[derive(Debug)]
+struct PeerState {
+    score: i32,
+    banned_until: Option<Instant>,
+    /// Start of the current rate limiting window
+    window_start: Instant,
+    /// Messages received in the current window
+    messages: u32,
+}
+
+/// Scores peers on the messages they send and bans the ones that fall below the threshold
+///
+/// Scores start at 0, drop with every misbehaviour and recover over time. A
+/// banned peer keeps its score until the ban expires, after which it starts over.
+pub struct PeerReputation {
+    config: ReputationConfig,
+    peers: HashMap<PeerId, PeerState>,
+    last_recovery: Instant,
+}
+
+impl PeerReputation {
+    /// Create a new reputation tracker
+    pub fn new(config: ReputationConfig) -> Self {
+        Self {
+            config,
+            peers: HashMap::new(),
+            last_recovery: Instant::now(),
+        }
+    }
+
+    fn state(&mut self, peer: PeerId, now: Instant) -> &mut PeerState {
+        self.peers.entry(peer).or_insert(PeerState {
+            score: 0,
+            banned_until: None,
+            window_start: now,
+            messages: 0,
+        })
+    }
+
+    /// Count a message from `peer`, returning false if it exceeds the rate limit
+    pub fn allow_message(&mut self, peer: PeerId, now: Instant) -> bool {
+        let limit = self.config.max_messages_per_second;
+        let state = self.state(peer, now);
+        if now.duration_since(state.window_start) >= Duration::from_secs(1) {
+            state.window_start = now;
+            state.messages = 0;
+        }
+        state.messages += 1;
+        state.messages <= limit
+    }
+
+    /// Penalise a peer, returning true if this got it banned
+    pub fn penalize(&mut self, peer: PeerId, misbehaviour: Misbehaviour, now: Instant) -> bool {
+        let threshold = self.config.ban_threshold;
+        let ban_duration = Duration::from_secs(self.config.ban_duration);
+        let state = self.state(peer, now);
+        if state.banned_until.is_some() {
+            return false;
+        }
+
+        state.score = state.score.saturating_sub(misbehaviour.penalty());
+        if state.score > threshold {
+            return false;
+        }
+
+        state.banned_until = Some(now + ban_duration);
+        warn!(
+            "Banning peer {} for {}s (score {}, last offence: {:?})",
+            peer,
+            ban_duration.as_secs(),
+            state.score,
+            misbehaviour
+        );
+        true
+    }
+
+    /// Check whether a peer is banned
+    pub fn is_banned(&self, peer: &PeerId, now: Instant) -> bool {
+        self.peers
+            .get(peer)
+            .and_then(|state| state.banned_until)
+            .is_some_and(|until| until > now)
+    }
+
+    /// Get the score of a peer
+    pub fn score(&self, peer: &PeerId) -> i32 {
+        self.peers.get(peer).map_or(0, |state| state.score)
+    }
+
+    /// Recover scores for the time elapsed and lift expired bans, returning the unbanned peers
+    pub fn refresh(&mut self, now: Instant) -> Vec<PeerId> {
+        let elapsed = now.saturating_duration_since(self.last_recovery).as_secs();
+        if elapsed > 0 {
+            self.last_recovery += Duration::from_secs(elapsed);
+        }
+        let recovery = self
+            .config
+            .recovery_per_second
+            .saturating_mul(elapsed.min(i32::MAX as u64) as i32);
+
+        let mut unbanned = Vec::new();
+        self.peers.retain(|peer, state| {
+            match state.banned_until {
+                Some(until) if until <= now => {
+                    info!("Ban of peer {} expired", peer);
+                    unbanned.push(*peer);
+                    state.banned_until = None;
+                    state.score = 0;
+                }
+                Some(_) => return true,
+                None => state.score = state.score.saturating_add(recovery).min(0),
+            }
+            // Forget neutral peers once their rate limiting window is over
+            state.score < 0 || now.duration_since(state.window_start) < Duration::from_secs(1)
+        });
+        unbanned
+    }
+
+    /// List the given connected peers along with every peer that has a reputation
+    pub fn peer_list(&self, connected: &HashSet<PeerId>, now: Instant) -> Vec<PeerInfo> {
+        let mut peers: Vec<PeerInfo> = connected
+            .iter()
+            .chain(self.peers.keys().filter(|peer| !connected.contains(*peer)))
+            .map(|peer| {
+                let state = self.peers.get(peer);
+                PeerInfo {
+                    peer_id: peer.to_string(),
+                    connected: connected.contains(peer),
+                    score: state.map_or(0, |state| state.score),
+                    banned_for: state
+                        .and_then(|state| state.banned_until)
+                        .filter(|until| *until > now)
+                        .map(|until| until.duration_since(now).as_secs().max(1)),
+                }
+            })
+            .collect();
+        peers.sort_by(|a, b| a.peer_id.cmp(&b.peer_id));
+        peers
+    }
+}
@@ -0,0 +1,350 @@
+use anyhow::{anyhow, Result};
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use libp2p::request_response::{Codec, ProtocolName};
+use libp2p::PeerId;
+use mp_state::diff::{StateDiff, StateSnapshot};
+use mp_state::{proof, StateStorage, EMPTY_STATE_ROOT};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{debug, info, warn};
+
+use crate::reputation::Misbehaviour;
+use crate::swarm::Command;
+
+/// Protocol name of the state sync request/response exchange
+pub const STATE_SYNC_PROTOCOL: &str = "/mp/state-sync/1";
+
+/// Upper bound for a single state sync message, checkpoints carry the whole state
+const MAX_MESSAGE_SIZE: usize = 512 * 1024 * 1024;
+
+/// Number of diffs requested at once
+pub(crate) const DIFF_BATCH_SIZE: u32 = 256;
+
+/// Request sent to a peer to catch up with its state
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum StateSyncRequest {
+    /// Full state at the peer's latest checkpoint
+    Checkpoint,
+    /// Diffs the peer applied after reaching `root`
+    DiffsSince { root: String, limit: u32 },
+}
+
+/// Answer to a [`StateSyncRequest`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum StateSyncResponse {
+    /// Full state, its root is the peer's checkpoint
+    Checkpoint(StateSnapshot),
+    /// Diffs following the requested root, empty once `head` is reached
+    Diffs { head: String, diffs: Vec<StateDiff> },
+    /// The peer never went through the requested root
+    UnknownRoot(String),
+    /// The peer cannot serve state
+    Unavailable(String),
+}
+
+/// Protocol identifier for the request/response behaviour
+#[derive(Debug, Clone)]
+pub struct StateSyncProtocol;
+
+impl ProtocolName for StateSyncProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        STATE_SYNC_PROTOCOL.as_bytes()
+    }
+}
+
+/// Length-prefixed JSON codec for state sync messages
+#[derive(Debug, Clone, Default)]
+pub struct StateSyncCodec;
+
+#[async_trait::async_trait]
+impl Codec for StateSyncCodec {
+    type Protocol = StateSyncProtocol;
+    type Request = StateSyncRequest;
+    type Response = StateSyncResponse;
+
+    async fn read_request<T>(
+        &mut self,
+        _: &StateSyncProtocol,
+        io: &mut T,
+    ) -> io::Result<Self::Request>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_message(io).await
+    }
+
+    async fn read_response<T>(
+        &mut self,
+        _: &StateSyncProtocol,
+        io: &mut T,
+    ) -> io::Result<Self::Response>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_message(io).await
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &StateSyncProtocol,
+        io: &mut T,
+        request: Self::Request,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_message(io, &request).await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &StateSyncProtocol,
+        io: &mut T,
+        response: Self::Response,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_message(io, &response).await
+    }
+}
+
+async fn read_message<T, M>(io: &mut T) -> io::Result<M>
+where
+    T: AsyncRead + Unpin + Send,
+    M: DeserializeOwned,
+{
+    let mut length_buffer = [0u8; 4];
+    io.read_exact(&mut length_buffer).await?;
+    let length = u32::from_le_bytes(length_buffer) as usize;
+    if length > MAX_MESSAGE_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "State sync message of {} bytes exceeds the maximum size",
+                length
+            ),
+        ));
+    }
+
+    let mut data = vec![0u8; length];
+    io.read_exact(&mut data).await?;
+    serde_json::from_slice(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+async fn write_message<T, M>(io: &mut T, message: &M) -> io::Result<()>
+where
+    T: AsyncWrite + Unpin + Send,
+    M: Serialize,
+{
+    let data = serde_json::to_vec(message)?;
+    io.write_all(&(data.len() as u32).to_le_bytes()).await?;
+    io.write_all(&data).await?;
+    io.close().await
+}
+
+/// Answer a state sync request from the local state storage
+pub(crate) fn handle_request(
+    storage: Option<&Arc<dyn StateStorage>>,
+    request: StateSyncRequest,
+) -> StateSyncResponse {
+    let storage = match storage {
+        Some(storage) => storage,
+        None => return StateSyncResponse::Unavailable("No state storage".to_string()),
+    };
+
+    let response = match request {
+        StateSyncRequest::Checkpoint => {
+            storage.export_snapshot().map(StateSyncResponse::Checkpoint)
+        }
+        StateSyncRequest::DiffsSince { root, limit } => {
+            let limit = limit.min(DIFF_BATCH_SIZE) as usize;
+            storage.diffs_since(&root, limit).and_then(|diffs| {
+                Ok(match diffs {
+                    Some(diffs) => StateSyncResponse::Diffs {
+                        head: storage.create_checkpoint()?.new_root,
+                        diffs,
+                    },
+                    None => StateSyncResponse::UnknownRoot(root),
+                })
+            })
+        }
+    };
+
+    response.unwrap_or_else(|e| {
+        warn!("Failed to answer state sync request: {}", e);
+        StateSyncResponse::Unavailable(e.to_string())
+    })
+}
+
+/// Check that the diffs chain up from `root`, returning the root they lead to
+pub fn verify_diff_chain(root: &str, diffs: &[StateDiff]) -> Result<String> {
+    let mut current = root;
+    for (i, diff) in diffs.iter().enumerate() {
+        if diff.prev_root != current {
+            return Err(anyhow!(
+                "Diff {} starts at root {} instead of {}",
+                i,
+                diff.prev_root,
+                current
+            ));
+        }
+        current = &diff.new_root;
+    }
+    Ok(current.to_string())
+}
+
+/// Check that the entries of a checkpoint lead to its root
+pub fn verify_snapshot(snapshot: &StateSnapshot) -> Result<()> {
+    let root = proof::state_root(
+        snapshot
+            .entries
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str())),
+    )?;
+    if root != snapshot.root {
+        return Err(anyhow!(
+            "Checkpoint claims state root {} but its entries lead to {}",
+            snapshot.root,
+            root
+        ));
+    }
+    Ok(())
+}
+
+/// Apply diffs received from a peer on top of `root`
+///
+/// The state root is recomputed after each diff. If a diff does not lead to the
+/// root it claims, the diffs of the batch already applied are rolled back.
+fn apply_diffs(storage: &Arc<dyn StateStorage>, root: &str, diffs: &[StateDiff]) -> Result<()> {
+    for diff in diffs {
+        let applied = storage.apply_diff(diff).and_then(|_| {
+            let state_root = storage.get_state_root()?;
+            if state_root != diff.new_root {
+                return Err(anyhow!(
+                    "Diff claims state root {} but the state is at {}",
+                    diff.new_root,
+                    state_root
+                ));
+            }
+            Ok(())
+        });
+        if let Err(e) = applied {
+            storage.revert_to(root)?;
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+/// Catch up with `peer`: fetch its checkpoint if needed, then apply its diffs until caught up
+///
+/// Checkpoints and diffs whose content does not lead to the state root they
+/// claim are rejected and the peer is penalized.
+pub(crate) async fn sync_from(
+    storage: &Arc<dyn StateStorage>,
+    peer: PeerId,
+    commands: &mpsc::UnboundedSender<Command>,
+) -> Result<String> {
+    let request = |request: StateSyncRequest| async move {
+        let (reply, response) = oneshot::channel();
+        commands
+            .send(Command::StateSync {
+                peer,
+                request,
+                reply,
+            })
+            .map_err(|_| anyhow!("Network service stopped"))?;
+        response
+            .await
+            .map_err(|_| anyhow!("Network service stopped"))?
+    };
+    let reject = |e: anyhow::Error| {
+        let _ = commands.send(Command::Penalize(peer, Misbehaviour::InvalidStateSync));
+        e
+    };
+
+    let mut root = storage.create_checkpoint()?.new_root;
+    // A fresh node starts from the checkpoint rather than replaying the whole history
+    let mut checkpoint_needed = root == EMPTY_STATE_ROOT;
+    loop {
+        if checkpoint_needed {
+            match request(StateSyncRequest::Checkpoint).await? {
+                StateSyncResponse::Checkpoint(snapshot) => {
+                    info!(
+                        "Importing checkpoint {} with {} entries from {}",
+                        snapshot.root,
+                        snapshot.entries.len(),
+                        peer
+                    );
+                    if snapshot.root != root {
+                        verify_snapshot(&snapshot).map_err(reject)?;
+                        storage.import_snapshot(&snapshot)?;
+                        root = snapshot.root;
+                    }
+                }
+                StateSyncResponse::Unavailable(e) => {
+                    return Err(anyhow!("Peer {} cannot serve state: {}", peer, e))
+                }
+                other => {
+                    return Err(reject(anyhow!(
+                        "Unexpected checkpoint response: {:?}",
+                        other
+                    )))
+                }
+            }
+            checkpoint_needed = false;
+        }
+
+        let response = request(StateSyncRequest::DiffsSince {
+            root: root.clone(),
+            limit: DIFF_BATCH_SIZE,
+        })
+        .await?;
+        match response {
+            StateSyncResponse::Diffs { head, diffs } if diffs.is_empty() => {
+                if head != root {
+                    return Err(reject(anyhow!(
+                        "Peer {} reports head {} but has no diffs from {}",
+                        peer,
+                        head,
+                        root
+                    )));
+                }
+                info!("State caught up with {} at root {}", peer, root);
+                return Ok(root);
+            }
+            StateSyncResponse::Diffs { diffs, .. } => {
+                let new_root = verify_diff_chain(&root, &diffs).map_err(reject)?;
+                debug!(
+                    "Applying {} diffs from {} up to {}",
+                    diffs.len(),
+                    peer,
+                    new_root
+                );
+                apply_diffs(storage, &root, &diffs).map_err(reject)?;
+                root = new_root;
+            }
+            StateSyncResponse::UnknownRoot(_) if root != EMPTY_STATE_ROOT => {
+                info!(
+                    "Peer {} does not know root {}, fetching its checkpoint",
+                    peer, root
+                );
+                checkpoint_needed = true;
+            }
+            StateSyncResponse::UnknownRoot(_) => {
+                return Err(reject(anyhow!(
+                    "Peer {} does not know the empty state",
+                    peer
+                )))
+            }
+            StateSyncResponse::Unavailable(e) => {
+                return Err(anyhow!("Peer {} cannot serve state: {}", peer, e))
+            }
+            other => return Err(reject(anyhow!("Unexpected diffs response: {:?}", other))),
+        }
+    }
+}
@@ -0,0 +1,515 @@
+use anyhow::{anyhow, Result};
+use futures::StreamExt;
+use libp2p::core::upgrade;
+use libp2p::gossipsub::{
+    self, MessageAcceptance, MessageAuthenticity, PeerScoreParams, PeerScoreThresholds,
+    TopicScoreParams, ValidationMode,
+};
+use libp2p::kad::store::MemoryStore;
+use libp2p::kad::{Kademlia, KademliaEvent};
+use libp2p::multiaddr::Protocol;
+use libp2p::request_response::{self, ProtocolSupport, RequestId};
+use libp2p::swarm::behaviour::toggle::Toggle;
+use libp2p::swarm::dial_opts::{DialOpts, PeerCondition};
+use libp2p::swarm::{NetworkBehaviour, SwarmBuilder, SwarmEvent};
+use libp2p::{dns, identify, identity, mdns, noise, tcp, yamux};
+use libp2p::{Multiaddr, PeerId, Swarm, Transport};
+use mp_common::types::Transaction;
+use mp_state::StateStorage;
+use std::collections::{hash_map::DefaultHasher, HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
+use tokio::time;
+use tracing::{debug, info, warn};
+
+use crate::discovery::DiscoveryService;
+use crate::protocol::{self, ProtocolHandler};
+use crate::reputation::{Misbehaviour, PeerReputation};
+use crate::state_sync::{
+    self, StateSyncCodec, StateSyncProtocol, StateSyncRequest, StateSyncResponse,
+};
+
+/// Protocol version announced through identify
+const PROTOCOL_VERSION: &str = "/mp/1.0.0";
+
+/// Interval between two Kademlia bootstrap queries
+const BOOTSTRAP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Interval between two peer reputation updates
+const REPUTATION_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Timeout for a state sync request, checkpoints may take a while to transfer
+const STATE_SYNC_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Pending state sync requests waiting for their response
+type PendingRequests = HashMap<RequestId, oneshot::Sender<Result<StateSyncResponse>>>;
+
+/// Behaviours composing the node's swarm
+#[derive(NetworkBehaviour)]
+pub(crate) struct Behaviour {
+    gossipsub: gossipsub::Behaviour,
+    kademlia: Kademlia<MemoryStore>,
+    identify: identify::Behaviour,
+    mdns: Toggle<mdns::tokio::Behaviour>,
+    state_sync: request_response::Behaviour<StateSyncCodec>,
+}
+
+/// Requests from the network handle to the swarm task
+pub(crate) enum Command {
+    /// Publish an encoded transaction on the transaction topic
+    Publish(Vec<u8>),
+    /// Send a state sync request to a peer
+    StateSync {
+        peer: PeerId,
+        request: StateSyncRequest,
+        reply: oneshot::Sender<Result<StateSyncResponse>>,
+    },
+    /// Penalise a peer for misbehaviour detected outside the swarm
+    Penalize(PeerId, Misbehaviour),
+}
+
+/// Build the swarm and start listening on `listen_address`
+pub(crate) fn build_swarm(
+    keypair: &identity::Keypair,
+    listen_address: SocketAddr,
+    discovery: &DiscoveryService,
+    enable_mdns: bool,
+    max_message_size: usize,
+) -> Result<Swarm<Behaviour>> {
+    let local_peer_id = keypair.public().to_peer_id();
+
+    let transport = dns::TokioDnsConfig::system(tcp::tokio::Transport::new(
+        tcp::Config::default().nodelay(true),
+    ))?
+    .upgrade(upgrade::Version::V1)
+    .authenticate(noise::Config::new(keypair)?)
+    .multiplex(yamux::Config::default())
+    .timeout(Duration::from_secs(20))
+    .boxed();
+
+    // Identical transactions published by different nodes share a message ID.
+    // Messages are only forwarded once the node has validated them.
+    let gossipsub_config = gossipsub::ConfigBuilder::default()
+        .validation_mode(ValidationMode::Strict)
+        .validate_messages()
+        .max_transmit_size(max_message_size)
+        .heartbeat_interval(Duration::from_millis(500))
+        .message_id_fn(|message: &gossipsub::Message| {
+            let mut hasher = DefaultHasher::new();
+            message.data.hash(&mut hasher);
+            gossipsub::MessageId::from(hasher.finish().to_string())
+        })
+        .build()
+        .map_err(|e| anyhow!("Invalid gossipsub configuration: {}", e))?;
+    let mut gossipsub = gossipsub::Behaviour::new(
+        MessageAuthenticity::Signed(keypair.clone()),
+        gossipsub_config,
+    )
+    .map_err(|e| anyhow!("Failed to create gossipsub behaviour: {}", e))?;
+    gossipsub
+        .with_peer_score(
+            invalid_message_score_params(),
+            PeerScoreThresholds::default(),
+        )
+        .map_err(|e| anyhow!("Invalid gossipsub peer score parameters: {}", e))?;
+    gossipsub.subscribe(&protocol::transaction_topic())?;
+
+    let mdns = if enable_mdns {
+        Some(mdns::tokio::Behaviour::new(
+            mdns::Config::default(),
+            local_peer_id,
+        )?)
+    } else {
+        None
+    };
+
+    let behaviour = Behaviour {
+        gossipsub,
+        kademlia: discovery.kademlia(local_peer_id),
+        identify: identify::Behaviour::new(identify::Config::new(
+            PROTOCOL_VERSION.to_string(),
+            keypair.public(),
+        )),
+        mdns: mdns.into(),
+        state_sync: request_response::Behaviour::new(
+            StateSyncCodec,
+            [(StateSyncProtocol, ProtocolSupport::Full)],
+            {
+                let mut config = request_response::Config::default();
+                config.set_request_timeout(STATE_SYNC_TIMEOUT);
+                config
+            },
+        ),
+    };
+
+    let mut swarm = SwarmBuilder::with_tokio_executor(transport, behaviour, local_peer_id).build();
+    swarm.listen_on(socket_to_multiaddr(listen_address))?;
+    for (peer_id, address) in discovery.bootstrap_nodes() {
+        if let Err(e) = swarm.dial(address.clone().with(Protocol::P2p((*peer_id).into()))) {
+            warn!("Failed to dial bootstrap node {}: {}", peer_id, e);
+        }
+    }
+    if !discovery.bootstrap_nodes().is_empty() {
+        let _ = swarm.behaviour_mut().kademlia.bootstrap();
+    }
+
+    Ok(swarm)
+}
+
+/// Gossipsub scoring that only counts messages failing the router's own validation
+///
+/// The router score then drops exactly when a peer sends a message with a bad
+/// signature or malformed envelope, and is folded into the peer's reputation.
+fn invalid_message_score_params() -> PeerScoreParams {
+    let topic_params = TopicScoreParams {
+        topic_weight: 1.0,
+        time_in_mesh_weight: 0.0,
+        first_message_deliveries_weight: 0.0,
+        mesh_message_deliveries_weight: 0.0,
+        mesh_failure_penalty_weight: 0.0,
+        invalid_message_deliveries_weight: -1.0,
+        invalid_message_deliveries_decay: 0.5,
+        ..Default::default()
+    };
+    let mut params = PeerScoreParams {
+        ip_colocation_factor_weight: 0.0,
+        behaviour_penalty_weight: 0.0,
+        ..Default::default()
+    };
+    params
+        .topics
+        .insert(protocol::transaction_topic().hash(), topic_params);
+    params
+}
+
+/// Convert a socket address into a TCP multiaddr
+pub(crate) fn socket_to_multiaddr(address: SocketAddr) -> Multiaddr {
+    Multiaddr::from(address.ip()).with(Protocol::Tcp(address.port()))
+}
+
+/// State shared between the swarm task and the network handle
+pub(crate) struct SwarmState {
+    pub protocol: Arc<Mutex<ProtocolHandler>>,
+    pub reputation: Arc<Mutex<PeerReputation>>,
+    pub tx_sender: mpsc::Sender<Transaction>,
+    pub peers: Arc<RwLock<HashSet<PeerId>>>,
+    pub listen_addresses: Arc<RwLock<Vec<Multiaddr>>>,
+    pub bootstrap_peers: HashSet<PeerId>,
+    pub state: Option<Arc<dyn StateStorage>>,
+}
+
+/// Drive the swarm until the network handle is dropped
+pub(crate) async fn run(
+    mut swarm: Swarm<Behaviour>,
+    mut commands: mpsc::UnboundedReceiver<Command>,
+    state: SwarmState,
+) {
+    let topic = protocol::transaction_topic();
+    let mut bootstrap = time::interval(BOOTSTRAP_INTERVAL);
+    let mut reputation = time::interval(REPUTATION_INTERVAL);
+    let mut router_scores = HashMap::new();
+    let mut pending = PendingRequests::new();
+
+    loop {
+        tokio::select! {
+            command = commands.recv() => match command {
+                Some(Command::Publish(data)) => {
+                    if let Err(e) = swarm.behaviour_mut().gossipsub.publish(topic.clone(), data) {
+                        debug!("Failed to publish transaction: {:?}", e);
+                    }
+                }
+                Some(Command::StateSync { peer, request, reply }) => {
+                    let request_id = swarm.behaviour_mut().state_sync.send_request(&peer, request);
+                    pending.insert(request_id, reply);
+                }
+                Some(Command::Penalize(peer, misbehaviour)) => {
+                    state.penalize(&mut swarm, peer, misbehaviour, Instant::now());
+                }
+                None => break,
+            },
+            event = swarm.select_next_some() => {
+                state.handle_event(&mut swarm, event, &mut pending);
+            }
+            _ = bootstrap.tick() => {
+                // Fails harmlessly while the routing table is empty
+                let _ = swarm.behaviour_mut().kademlia.bootstrap();
+            }
+            _ = reputation.tick() => {
+                state.update_reputation(&mut swarm, &mut router_scores);
+            }
+        }
+    }
+
+    state.peers.write().unwrap().clear();
+    state.listen_addresses.write().unwrap().clear();
+    info!("Network swarm stopped");
+}
+
+impl SwarmState {
+    fn handle_event<E: std::fmt::Debug>(
+        &self,
+        swarm: &mut Swarm<Behaviour>,
+        event: SwarmEvent<BehaviourEvent, E>,
+        pending: &mut PendingRequests,
+    ) {
+        match event {
+            SwarmEvent::NewListenAddr { address, .. } => {
+                info!("Listening on {}/p2p/{}", address, swarm.local_peer_id());
+                self.listen_addresses.write().unwrap().push(address);
+            }
+            SwarmEvent::ExpiredListenAddr { address, .. } => {
+                self.listen_addresses
+                    .write()
+                    .unwrap()
+                    .retain(|listened| *listened != address);
+            }
+            SwarmEvent::ConnectionEstablished { peer_id, .. } => {
+                if self
+                    .reputation
+                    .lock()
+                    .unwrap()
+                    .is_banned(&peer_id, Instant::now())
+                {
+                    debug!("Refusing connection from banned peer {}", peer_id);
+                    let _ = swarm.disconnect_peer_id(peer_id);
+                } else if self.peers.write().unwrap().insert(peer_id) {
+                    debug!("Connected to peer {}", peer_id);
+                }
+            }
+            SwarmEvent::ConnectionClosed {
+                peer_id,
+                num_established,
+                ..
+            } => {
+                if num_established == 0 && self.peers.write().unwrap().remove(&peer_id) {
+                    debug!("Disconnected from peer {}", peer_id);
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Gossipsub(gossipsub::Event::Message {
+                propagation_source,
+                message_id,
+                message,
+            })) => {
+                let acceptance = self.validate_message(swarm, propagation_source, &message.data);
+                let _ = swarm
+                    .behaviour_mut()
+                    .gossipsub
+                    .report_message_validation_result(&message_id, &propagation_source, acceptance);
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::StateSync(event)) => {
+                self.handle_state_sync(swarm, event, pending);
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Identify(identify::Event::Received {
+                peer_id,
+                info,
+            })) => {
+                // Listen addresses reported by the peer make it reachable for the DHT
+                for address in info.listen_addrs {
+                    swarm
+                        .behaviour_mut()
+                        .kademlia
+                        .add_address(&peer_id, address);
+                }
+                // A bootstrap node knows more peers once it has identified them, look
+                // them up again instead of waiting for the next periodic query
+                if self.bootstrap_peers.contains(&peer_id) {
+                    let _ = swarm.behaviour_mut().kademlia.bootstrap();
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Kademlia(KademliaEvent::RoutingUpdated {
+                peer,
+                is_new_peer: true,
+                ..
+            })) => {
+                if !swarm.is_connected(&peer) {
+                    debug!("Discovered peer {} through the DHT", peer);
+                    self.dial(swarm, peer);
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Mdns(mdns::Event::Discovered(discovered))) => {
+                for (peer_id, address) in discovered {
+                    debug!("Discovered peer {} at {} through mDNS", peer_id, address);
+                    swarm
+                        .behaviour_mut()
+                        .kademlia
+                        .add_address(&peer_id, address);
+                    if !swarm.is_connected(&peer_id) {
+                        self.dial(swarm, peer_id);
+                    }
+                }
+            }
+            SwarmEvent::OutgoingConnectionError { peer_id, error } => {
+                debug!("Failed to connect to {:?}: {}", peer_id, error);
+            }
+            _ => {}
+        }
+    }
+
+    /// Serve state sync requests and route responses to the waiting requester
+    fn handle_state_sync(
+        &self,
+        swarm: &mut Swarm<Behaviour>,
+        event: request_response::Event<StateSyncRequest, StateSyncResponse>,
+        pending: &mut PendingRequests,
+    ) {
+        match event {
+            request_response::Event::Message {
+                peer,
+                message:
+                    request_response::Message::Request {
+                        request, channel, ..
+                    },
+            } => {
+                debug!("State sync request from {}: {:?}", peer, request);
+                let response = state_sync::handle_request(self.state.as_ref(), request);
+                if swarm
+                    .behaviour_mut()
+                    .state_sync
+                    .send_response(channel, response)
+                    .is_err()
+                {
+                    debug!("State sync requester {} went away", peer);
+                }
+            }
+            request_response::Event::Message {
+                message:
+                    request_response::Message::Response {
+                        request_id,
+                        response,
+                    },
+                ..
+            } => {
+                if let Some(reply) = pending.remove(&request_id) {
+                    let _ = reply.send(Ok(response));
+                }
+            }
+            request_response::Event::OutboundFailure {
+                peer,
+                request_id,
+                error,
+            } => {
+                if let Some(reply) = pending.remove(&request_id) {
+                    let _ = reply.send(Err(anyhow!(
+                        "State sync request to {} failed: {}",
+                        peer,
+                        error
+                    )));
+                }
+            }
+            request_response::Event::InboundFailure { peer, error, .. } => {
+                debug!(
+                    "Failed to answer state sync request from {}: {}",
+                    peer, error
+                );
+            }
+            request_response::Event::ResponseSent { .. } => {}
+        }
+    }
+
+    /// Check a gossiped message, forwarding new transactions and penalising the sender otherwise
+    ///
+    /// Only well-formed transactions are passed on to other peers, duplicates and
+    /// rate-limited messages are dropped without counting against the gossipsub score.
+    fn validate_message(
+        &self,
+        swarm: &mut Swarm<Behaviour>,
+        source: PeerId,
+        data: &[u8],
+    ) -> MessageAcceptance {
+        let now = Instant::now();
+        if !self.reputation.lock().unwrap().allow_message(source, now) {
+            self.penalize(swarm, source, Misbehaviour::RateLimited, now);
+            return MessageAcceptance::Ignore;
+        }
+
+        let result = self.protocol.lock().unwrap().handle_message(data);
+        match result {
+            Ok(tx) => {
+                if let Err(e) = self.tx_sender.try_send(tx) {
+                    warn!("Dropping received transaction: {}", e);
+                }
+                MessageAcceptance::Accept
+            }
+            Err(misbehaviour) => {
+                debug!("Invalid message from {}: {:?}", source, misbehaviour);
+                self.penalize(swarm, source, misbehaviour, now);
+                match misbehaviour {
+                    Misbehaviour::DuplicateMessage => MessageAcceptance::Ignore,
+                    _ => MessageAcceptance::Reject,
+                }
+            }
+        }
+    }
+
+    /// Penalise a peer, disconnecting it if that gets it banned
+    fn penalize(
+        &self,
+        swarm: &mut Swarm<Behaviour>,
+        peer: PeerId,
+        misbehaviour: Misbehaviour,
+        now: Instant,
+    ) {
+        if self
+            .reputation
+            .lock()
+            .unwrap()
+            .penalize(peer, misbehaviour, now)
+        {
+            let behaviour = swarm.behaviour_mut();
+            behaviour.gossipsub.blacklist_peer(&peer);
+            behaviour.kademlia.remove_peer(&peer);
+            let _ = swarm.disconnect_peer_id(peer);
+        }
+    }
+
+    /// Fold gossipsub validation failures into the reputation and lift expired bans
+    fn update_reputation(
+        &self,
+        swarm: &mut Swarm<Behaviour>,
+        router_scores: &mut HashMap<PeerId, f64>,
+    ) {
+        let now = Instant::now();
+        let connected: Vec<PeerId> = self.peers.read().unwrap().iter().copied().collect();
+        for peer in connected {
+            let score = swarm
+                .behaviour()
+                .gossipsub
+                .peer_score(&peer)
+                .unwrap_or_default();
+            let previous = router_scores.insert(peer, score).unwrap_or_default();
+            if score < previous {
+                self.penalize(swarm, peer, Misbehaviour::InvalidSignature, now);
+            }
+        }
+        router_scores.retain(|peer, _| self.peers.read().unwrap().contains(peer));
+
+        let unbanned = self.reputation.lock().unwrap().refresh(now);
+        for peer in unbanned {
+            swarm
+                .behaviour_mut()
+                .gossipsub
+                .remove_blacklisted_peer(&peer);
+        }
+    }
+
+    /// Dial a peer through its known addresses unless it is banned or a dial is in flight
+    fn dial(&self, swarm: &mut Swarm<Behaviour>, peer_id: PeerId) {
+        if self
+            .reputation
+            .lock()
+            .unwrap()
+            .is_banned(&peer_id, Instant::now())
+        {
+            return;
+        }
+        let opts = DialOpts::peer_id(peer_id)
+            .condition(PeerCondition::NotDialing)
+            .build();
+        if let Err(e) = swarm.dial(opts) {
+            debug!("Failed to dial {}: {}", peer_id, e);
+        }
+    }
+}
@@ -0,0 +1,64 @@
+//! Helpers shared by the network integration tests
+#![allow(dead_code)]
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use mp_common::types::{Transaction, TransactionType};
+use mp_common::utils::create_transaction;
+use mp_network::config::NetworkConfig;
+use mp_network::Network;
+use tokio::time::{sleep, Instant};
+
+/// Node listening on a loopback port picked when it starts, see [`bootstrap_address`]
+pub fn network_config(bootstrap_nodes: Vec<String>) -> NetworkConfig {
+    NetworkConfig {
+        listen_address: SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+        bootstrap_nodes,
+        key_path: None,
+        enable_mdns: false,
+        state_sync: false,
+        reputation: Default::default(),
+    }
+}
+
+/// Wait until the started `network` listens, returning the address to bootstrap from
+pub async fn bootstrap_address(network: &Arc<dyn Network>) -> String {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        if let Some(address) = network.listen_addresses().into_iter().next() {
+            return address;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "{} is not listening",
+            network.local_peer_id()
+        );
+        sleep(Duration::from_millis(10)).await;
+    }
+}
+
+pub fn test_transaction(payload: &str) -> Transaction {
+    create_transaction(
+        TransactionType::StateChange,
+        payload.as_bytes().to_vec(),
+        None,
+        Default::default(),
+        Default::default(),
+    )
+}
+
+/// Wait until `network` is connected to the peer with the given ID
+pub async fn wait_for_peer(network: &Arc<dyn Network>, peer: &str) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !network.peers().iter().any(|p| p == peer) {
+        assert!(
+            Instant::now() < deadline,
+            "{} did not connect to {}",
+            network.local_peer_id(),
+            peer
+        );
+        sleep(Duration::from_millis(50)).await;
+    }
+}
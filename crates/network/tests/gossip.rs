@@ -0,0 +1,128 @@
+mod common;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use mp_common::types::Transaction;
+use mp_network::config::NetworkConfig;
+use mp_network::{create_network, Network};
+use tokio::sync::mpsc;
+use tokio::time::{sleep, timeout, Instant};
+
+use common::{bootstrap_address, network_config, test_transaction, wait_for_peer};
+
+const MAX_TX_SIZE: usize = 1024 * 1024;
+
+fn start_node(
+    config: NetworkConfig,
+    max_tx_size: usize,
+) -> (Arc<dyn Network>, mpsc::Receiver<Transaction>) {
+    let network = create_network(config, max_tx_size, None).unwrap();
+    let received = network.get_received_tx_channel();
+    network.start().unwrap();
+    (network, received)
+}
+
+#[tokio::test]
+async fn test_gossip_between_discovered_peers() {
+    let (a, mut rx_a) = start_node(network_config(vec![]), MAX_TX_SIZE);
+    let bootstrap = vec![bootstrap_address(&a).await];
+
+    let (b, mut rx_b) = start_node(network_config(bootstrap.clone()), MAX_TX_SIZE);
+    wait_for_peer(&b, &a.local_peer_id()).await;
+
+    // C only knows A and has to find B through the DHT
+    let (c, mut rx_c) = start_node(network_config(bootstrap), MAX_TX_SIZE);
+    wait_for_peer(&c, &b.local_peer_id()).await;
+    wait_for_peer(&b, &c.local_peer_id()).await;
+
+    // The mesh forms asynchronously, keep publishing until the first copy arrives
+    let tx = test_transaction("gossip");
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let received = loop {
+        assert!(Instant::now() < deadline, "transaction was not gossiped");
+        let _ = c.broadcast_transaction(tx.clone());
+        if let Ok(Some(received)) = timeout(Duration::from_millis(200), rx_b.recv()).await {
+            break received;
+        }
+    };
+    assert_eq!(received.id, tx.id);
+    assert_eq!(received.payload, tx.payload);
+
+    let received = timeout(Duration::from_secs(10), rx_a.recv())
+        .await
+        .expect("transaction did not reach A")
+        .unwrap();
+    assert_eq!(received.id, tx.id);
+
+    // Every node delivers a transaction once, and the sender never receives its own
+    sleep(Duration::from_millis(500)).await;
+    assert!(rx_a.try_recv().is_err());
+    assert!(rx_b.try_recv().is_err());
+    assert!(rx_c.try_recv().is_err());
+
+    for network in [a, b, c] {
+        network.stop().unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_peer_banned_for_oversized_payloads() {
+    let (a, mut rx_a) = start_node(network_config(vec![]), 16);
+    let (b, _rx_b) = start_node(
+        network_config(vec![bootstrap_address(&a).await]),
+        MAX_TX_SIZE,
+    );
+    wait_for_peer(&a, &b.local_peer_id()).await;
+
+    // B accepts the payloads it sends, A penalises B for each one until it bans it
+    let deadline = Instant::now() + Duration::from_secs(15);
+    let mut sent = 0;
+    let banned = loop {
+        assert!(Instant::now() < deadline, "B was not banned");
+        let _ = b.broadcast_transaction(test_transaction(&"x".repeat(64)));
+        sent += 1;
+        sleep(Duration::from_millis(100)).await;
+        if let Some(peer) = a
+            .peer_list()
+            .into_iter()
+            .find(|peer| peer.peer_id == b.local_peer_id() && peer.banned_for.is_some())
+        {
+            break peer;
+        }
+    };
+    assert!(sent >= 5);
+    assert!(banned.score <= -100);
+    assert!(rx_a.try_recv().is_err());
+
+    // The banned peer is disconnected and cannot reconnect
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while a.peers().contains(&b.local_peer_id()) {
+        assert!(
+            Instant::now() < deadline,
+            "banned peer was not disconnected"
+        );
+        sleep(Duration::from_millis(50)).await;
+    }
+
+    a.stop().unwrap();
+    b.stop().unwrap();
+}
+
+#[tokio::test]
+async fn test_peer_identity_persisted_in_key_path() {
+    let dir = tempfile::tempdir().unwrap();
+    let key_path = dir.path().join("network").join("node.key");
+    let config = NetworkConfig {
+        key_path: Some(key_path.to_string_lossy().to_string()),
+        ..network_config(vec![])
+    };
+
+    let first = create_network(config.clone(), MAX_TX_SIZE, None).unwrap();
+    assert!(key_path.exists());
+    let second = create_network(config, MAX_TX_SIZE, None).unwrap();
+    assert_eq!(first.local_peer_id(), second.local_peer_id());
+
+    let other = create_network(network_config(vec![]), MAX_TX_SIZE, None).unwrap();
+    assert_ne!(first.local_peer_id(), other.local_peer_id());
+}
@@ -0,0 +1,78 @@
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+use libp2p::PeerId;
+use mp_network::config::ReputationConfig;
+use mp_network::reputation::{Misbehaviour, PeerReputation};
+
+fn reputation() -> PeerReputation {
+    PeerReputation::new(ReputationConfig {
+        ban_threshold: -100,
+        ban_duration: 60,
+        max_messages_per_second: 3,
+        recovery_per_second: 10,
+    })
+}
+
+#[test]
+fn test_ban_after_repeated_misbehaviour() {
+    let mut reputation = reputation();
+    let peer = PeerId::random();
+    let now = Instant::now();
+
+    for _ in 0..4 {
+        assert!(!reputation.penalize(peer, Misbehaviour::UndecodableMessage, now));
+    }
+    assert_eq!(reputation.score(&peer), -80);
+    assert!(!reputation.is_banned(&peer, now));
+
+    assert!(reputation.penalize(peer, Misbehaviour::OversizedPayload, now));
+    assert!(reputation.is_banned(&peer, now));
+    // Further offences neither extend nor repeat the ban
+    assert!(!reputation.penalize(peer, Misbehaviour::InvalidSignature, now));
+
+    let listed = reputation.peer_list(&HashSet::new(), now);
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0].peer_id, peer.to_string());
+    assert!(!listed[0].connected);
+    assert_eq!(listed[0].banned_for, Some(60));
+
+    // Scores do not recover during the ban, which is lifted once it expires
+    assert!(reputation.refresh(now + Duration::from_secs(30)).is_empty());
+    assert_eq!(reputation.score(&peer), -100);
+    let later = now + Duration::from_secs(61);
+    assert_eq!(reputation.refresh(later), vec![peer]);
+    assert!(!reputation.is_banned(&peer, later));
+    assert_eq!(reputation.score(&peer), 0);
+}
+
+#[test]
+fn test_score_recovers_over_time() {
+    let mut reputation = reputation();
+    let peer = PeerId::random();
+    let now = Instant::now();
+
+    reputation.penalize(peer, Misbehaviour::InvalidSignature, now);
+    assert_eq!(reputation.score(&peer), -50);
+
+    reputation.refresh(now + Duration::from_secs(2));
+    assert_eq!(reputation.score(&peer), -30);
+    reputation.refresh(now + Duration::from_secs(10));
+    assert_eq!(reputation.score(&peer), 0);
+    assert!(reputation.peer_list(&HashSet::new(), now).is_empty());
+}
+
+#[test]
+fn test_rate_limit_per_window() {
+    let mut reputation = reputation();
+    let peer = PeerId::random();
+    let other = PeerId::random();
+    let now = Instant::now();
+
+    for _ in 0..3 {
+        assert!(reputation.allow_message(peer, now));
+    }
+    assert!(!reputation.allow_message(peer, now + Duration::from_millis(500)));
+    assert!(reputation.allow_message(other, now));
+    assert!(reputation.allow_message(peer, now + Duration::from_secs(1)));
+}
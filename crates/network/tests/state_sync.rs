@@ -0,0 +1,225 @@
+mod common;
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use mp_network::config::NetworkConfig;
+use mp_network::state_sync::verify_diff_chain;
+use mp_network::{create_network, Network};
+use mp_state::config::StateConfig;
+use mp_state::diff::{StateDiff, StateOperation};
+use mp_state::{create_state_storage, StateStorage};
+use tokio::time::{sleep, Instant};
+
+use common::{bootstrap_address, network_config, wait_for_peer};
+
+/// Node serving and requesting state sync
+fn sync_config(bootstrap_nodes: Vec<String>) -> NetworkConfig {
+    NetworkConfig {
+        state_sync: true,
+        ..network_config(bootstrap_nodes)
+    }
+}
+
+fn state_storage(dir: &Path) -> Arc<dyn StateStorage> {
+    let storage = create_state_storage(StateConfig {
+        db_type: "sqlite".to_string(),
+        db_connection: dir.join("state.db").to_string_lossy().to_string(),
+        state_root_path: dir.join("state_root").to_string_lossy().to_string(),
+    })
+    .unwrap();
+    storage.start().unwrap();
+    storage
+}
+
+fn diff(prev_root: &str, new_root: &str, entries: &[(&str, &str)]) -> StateDiff {
+    let mut diff = StateDiff::new(prev_root.to_string());
+    diff.new_root = new_root.to_string();
+    for (key, value) in entries {
+        diff.insert(key.to_string(), value.to_string());
+    }
+    diff
+}
+
+fn insert(key: &str, value: &str) -> StateOperation {
+    StateOperation::Insert {
+        key: key.to_string(),
+        value: value.to_string(),
+    }
+}
+
+#[tokio::test]
+async fn test_new_node_catches_up_from_checkpoint_and_diffs() {
+    let dir_a = tempfile::tempdir().unwrap();
+    let dir_b = tempfile::tempdir().unwrap();
+
+    let state_a = state_storage(dir_a.path());
+    state_a
+        .apply_operations(vec![insert("alice", "10"), insert("bob", "5")])
+        .unwrap();
+    let root_2 = state_a
+        .apply_operations(vec![
+            insert("carol", "7"),
+            StateOperation::Delete {
+                key: "bob".to_string(),
+            },
+        ])
+        .unwrap()
+        .new_root;
+
+    let a = create_network(sync_config(vec![]), 1024, Some(state_a.clone())).unwrap();
+    a.start().unwrap();
+
+    let state_b = state_storage(dir_b.path());
+    let b = create_network(
+        sync_config(vec![bootstrap_address(&a).await]),
+        1024,
+        Some(state_b.clone()),
+    )
+    .unwrap();
+    b.start().unwrap();
+    wait_for_peer(&b, &a.local_peer_id()).await;
+
+    // The fresh node imports A's checkpoint
+    assert_eq!(b.sync_state().await.unwrap(), root_2);
+    assert_eq!(state_b.get_state_root().unwrap(), root_2);
+    assert_eq!(
+        state_b.export_snapshot().unwrap().entries,
+        state_a.export_snapshot().unwrap().entries
+    );
+
+    // Once A moves on, B follows through the diffs alone
+    state_a.apply_operations(vec![insert("dave", "1")]).unwrap();
+    let root_4 = state_a
+        .apply_operations(vec![insert("alice", "11")])
+        .unwrap()
+        .new_root;
+    assert_eq!(b.sync_state().await.unwrap(), root_4);
+    let snapshot = state_b.export_snapshot().unwrap();
+    assert_eq!(snapshot.root, root_4);
+    assert_eq!(snapshot.entries, state_a.export_snapshot().unwrap().entries);
+    assert_eq!(state_b.get_state_root().unwrap(), root_4);
+    assert_eq!(state_b.diffs_since(&root_2, 10).unwrap().unwrap().len(), 2);
+
+    a.stop().unwrap();
+    b.stop().unwrap();
+}
+
+/// Start a node syncing from `peer`, with fresh state under `dir`
+async fn start_follower(
+    peer: &Arc<dyn Network>,
+    dir: &Path,
+) -> (Arc<dyn Network>, Arc<dyn StateStorage>) {
+    let state = state_storage(dir);
+    let network = create_network(
+        sync_config(vec![bootstrap_address(peer).await]),
+        1024,
+        Some(state.clone()),
+    )
+    .unwrap();
+    network.start().unwrap();
+    wait_for_peer(&network, &peer.local_peer_id()).await;
+    (network, state)
+}
+
+/// Wait until `network` has penalized `peer`
+async fn wait_for_penalty(network: &Arc<dyn Network>, peer: &Arc<dyn Network>) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        let score = network
+            .peer_list()
+            .into_iter()
+            .find(|info| info.peer_id == peer.local_peer_id())
+            .map_or(0, |info| info.score);
+        if score < 0 {
+            return;
+        }
+        assert!(Instant::now() < deadline, "peer was not penalized");
+        sleep(Duration::from_millis(50)).await;
+    }
+}
+
+#[tokio::test]
+async fn test_forged_state_is_rolled_back_and_penalized() {
+    let dir_a = tempfile::tempdir().unwrap();
+    let dir_b = tempfile::tempdir().unwrap();
+    let dir_c = tempfile::tempdir().unwrap();
+
+    let state_a = state_storage(dir_a.path());
+    let root_1 = state_a
+        .apply_operations(vec![insert("alice", "10")])
+        .unwrap()
+        .new_root;
+    let a = create_network(sync_config(vec![]), 1024, Some(state_a.clone())).unwrap();
+    a.start().unwrap();
+
+    let (b, state_b) = start_follower(&a, dir_b.path()).await;
+    assert_eq!(b.sync_state().await.unwrap(), root_1);
+
+    // A serves two diffs, the second one altered behind its back
+    state_a.apply_operations(vec![insert("bob", "5")]).unwrap();
+    state_a
+        .apply_operations(vec![insert("carol", "7")])
+        .unwrap();
+    let db_a = rusqlite::Connection::open(dir_a.path().join("state.db")).unwrap();
+    db_a.execute(
+        "UPDATE state_operations SET value = '500' WHERE key = 'carol'",
+        [],
+    )
+    .unwrap();
+
+    // B rolls back the diff it applied from the batch and penalizes A
+    assert!(b.sync_state().await.is_err());
+    assert_eq!(state_b.get_state_root().unwrap(), root_1);
+    assert_eq!(state_b.create_checkpoint().unwrap().new_root, root_1);
+    assert_eq!(
+        state_b.export_snapshot().unwrap().entries,
+        vec![("alice".to_string(), "10".to_string())]
+    );
+    assert!(state_b
+        .diffs_since(&root_1, 10)
+        .unwrap()
+        .unwrap()
+        .is_empty());
+    wait_for_penalty(&b, &a).await;
+
+    // A checkpoint whose entries do not lead to its root is not imported
+    db_a.execute(
+        "UPDATE state_entries SET value = '1000' WHERE key = 'alice'",
+        [],
+    )
+    .unwrap();
+    let (c, state_c) = start_follower(&a, dir_c.path()).await;
+    assert!(c.sync_state().await.is_err());
+    assert!(state_c.export_snapshot().unwrap().entries.is_empty());
+    wait_for_penalty(&c, &a).await;
+
+    a.stop().unwrap();
+    b.stop().unwrap();
+    c.stop().unwrap();
+}
+
+#[tokio::test]
+async fn test_sync_requires_a_peer() {
+    let dir = tempfile::tempdir().unwrap();
+    let network =
+        create_network(sync_config(vec![]), 1024, Some(state_storage(dir.path()))).unwrap();
+    network.start().unwrap();
+    assert!(network.sync_state().await.is_err());
+    network.stop().unwrap();
+}
+
+#[test]
+fn test_diff_chain_verification() {
+    let diffs = vec![
+        diff("root-1", "root-2", &[("a", "1")]),
+        diff("root-2", "root-3", &[("b", "2")]),
+    ];
+    assert_eq!(verify_diff_chain("root-1", &diffs).unwrap(), "root-3");
+    assert_eq!(verify_diff_chain("root-1", &[]).unwrap(), "root-1");
+    assert!(verify_diff_chain("root-0", &diffs).is_err());
+
+    let gap = vec![diffs[0].clone(), diff("root-9", "root-10", &[])];
+    assert!(verify_diff_chain("root-1", &gap).is_err());
+}
@@ -40,6 +40,7 @@ async fn main() -> Result<()> {
         api_address: "127.0.0.1:8545".to_string(),
         max_tx_size: 1048576,
         tx_timeout: 60,
+        max_queued_per_sender: mp_mempool::nonce::DEFAULT_MAX_QUEUED_PER_SENDER,
     };
 
     // Note: You would need to create a real consensus engine and transaction pool in a production environment
@@ -55,6 +56,7 @@ async fn main() -> Result<()> {
         exec_sender.clone(),
         Arc::new(tx_pool) as Arc<dyn TransactionPool + Send + Sync>,
         api_key_store.clone(),
+        None,
     );
 
     // Process execution requests in a separate task (simulating the executor)
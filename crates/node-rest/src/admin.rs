@@ -2,7 +2,14 @@ use anyhow::{anyhow, Result};
 use dstack::{TappdClientT, TdxQuoteResponse, WorkerInfo};
 use hyper::service::{make_service_fn, service_fn};
 use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use mp_consensus::block::BlockStore;
+use mp_consensus::config::NodeInfo;
+use mp_consensus::{ClusterMembership, StateControl};
+use mp_executor::bridge::ExecutionBridge;
+use mp_network::Network;
 use mp_poc::PublicKey;
+use mp_state::transactions::TransactionQuery;
+use mp_state::StateStorage;
 use primitive_types::H384;
 use serde::{Deserialize, Serialize};
 use serde_human_bytes as hex_bytes;
@@ -13,6 +20,14 @@ use tracing::{error, info};
 
 use crate::api_key_store::ApiKeyStore;
 
+/// Number of blocks listed when the query does not give a limit
+const DEFAULT_BLOCK_LIMIT: usize = 20;
+/// Maximum number of blocks listed at once
+const MAX_BLOCK_LIMIT: usize = 100;
+/// Maximum number of transactions listed at once
+const MAX_TRANSACTION_LIMIT: usize = 1000;
+
+#[derive(Clone)]
 pub struct AdminInterface {
     /// API key store
     api_key_store: Arc<ApiKeyStore>,
@@ -21,6 +36,53 @@ pub struct AdminInterface {
     app_env: Arc<Mutex<dyn TappdClientT>>,
 
     poc_quote: PoCQuote,
+
+    /// Consensus cluster membership, if the consensus engine supports changing it
+    cluster: Option<Arc<dyn ClusterMembership>>,
+
+    /// P2P network, for inspecting peers
+    network: Arc<dyn Network>,
+
+    /// Consensus-driven state administration, if the consensus engine supports it
+    state_control: Option<Arc<dyn StateControl>>,
+
+    /// Execution bridge, for inspecting the requests waiting per contract
+    executor: Arc<ExecutionBridge>,
+
+    /// Blocks sealed by the consensus engine
+    blocks: Arc<BlockStore>,
+
+    /// State storage, for reading past states and the transaction history
+    state: Arc<dyn StateStorage>,
+}
+
+/// Request for reverting the state
+#[derive(Debug, Deserialize)]
+struct RevertStateRequest {
+    /// State root to revert to
+    root: String,
+}
+
+/// Read of a past state, by its root or by a consensus log index
+#[derive(Debug, Deserialize)]
+struct StateHistoryQuery {
+    /// Key to read the value of
+    key: Option<String>,
+    /// Prefix of the keys to list the entries of
+    prefix: Option<String>,
+    /// State root to read at
+    root: Option<String>,
+    /// Consensus log index to read at, instead of a root
+    log_index: Option<u64>,
+}
+
+/// Page of sealed blocks
+#[derive(Debug, Serialize)]
+struct ListBlocksResponse {
+    /// Height of the most recent block, if any was sealed
+    latest_height: Option<u64>,
+    /// Blocks in height order
+    blocks: Vec<mp_common::block::Block>,
 }
 
 /// Request for generating an API key
@@ -65,41 +127,53 @@ struct KeyInfo {
 
 impl AdminInterface {
     /// Create a new admin interface
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         api_key_store: Arc<ApiKeyStore>,
         app_env: Arc<Mutex<dyn TappdClientT>>,
         poc_quote: PoCQuote,
+        cluster: Option<Arc<dyn ClusterMembership>>,
+        network: Arc<dyn Network>,
+        state_control: Option<Arc<dyn StateControl>>,
+        executor: Arc<ExecutionBridge>,
+        blocks: Arc<BlockStore>,
+        state: Arc<dyn StateStorage>,
     ) -> Self {
         Self {
             api_key_store,
             app_env,
             poc_quote,
+            cluster,
+            network,
+            state_control,
+            executor,
+            blocks,
+            state,
         }
     }
 
     /// Start the admin interface HTTP server
     pub async fn start(&self, bind_address: &str) -> Result<()> {
         let addr: SocketAddr = bind_address.parse()?;
-        let api_key_store = self.api_key_store.clone();
-        let app_env = self.app_env.clone();
+        self.serve(std::net::TcpListener::bind(addr)?).await
+    }
+
+    /// Serve the admin interface on a bound listener
+    pub async fn serve(&self, listener: std::net::TcpListener) -> Result<()> {
+        let addr = listener.local_addr()?;
+        let admin = self.clone();
 
         let make_service = make_service_fn(move |_| {
-            let api_key_store = api_key_store.clone();
-            let app_env = app_env.clone();
-            let poc_quote = self.poc_quote.clone();
+            let admin = admin.clone();
 
             async move {
                 Ok::<_, hyper::Error>(service_fn(move |req| {
-                    let api_key_store = api_key_store.clone();
-                    let app_env = app_env.clone();
-                    let poc_quote = poc_quote.clone();
-
-                    async move { handle_admin_request(req, api_key_store, app_env, poc_quote).await }
+                    handle_admin_request(req, admin.clone())
                 }))
             }
         });
 
-        let server = Server::bind(&addr).serve(make_service);
+        let server = Server::from_tcp(listener)?.serve(make_service);
         info!("Admin interface listening on {}", addr);
 
         server
@@ -111,10 +185,27 @@ impl AdminInterface {
 /// Handle incoming admin HTTP requests
 async fn handle_admin_request(
     req: Request<Body>,
-    api_key_store: Arc<ApiKeyStore>,
-    app_env: Arc<Mutex<dyn TappdClientT>>,
-    poc_quote: PoCQuote,
+    admin: AdminInterface,
 ) -> Result<Response<Body>, hyper::Error> {
+    let AdminInterface {
+        api_key_store,
+        app_env,
+        poc_quote,
+        cluster,
+        network,
+        state_control,
+        executor,
+        blocks,
+        state,
+    } = admin;
+
+    if req.uri().path().starts_with("/cluster") {
+        return match cluster {
+            Some(cluster) => handle_cluster_request(req, cluster).await,
+            None => Ok(not_found_response("Cluster membership is not available")),
+        };
+    }
+
     match (req.method(), req.uri().path()) {
         // Generate API key
         (&Method::POST, "/api-keys") => {
@@ -224,6 +315,121 @@ async fn handle_admin_request(
                 .unwrap())
         }
 
+        // Connected, penalised and banned peers
+        (&Method::GET, "/peers") => {
+            let json = serde_json::to_string(&network.peer_list()).unwrap();
+            Ok(Response::builder()
+                .status(StatusCode::OK)
+                .header("Content-Type", "application/json")
+                .body(Body::from(json))
+                .unwrap())
+        }
+
+        // Requests waiting per contract in the execution scheduler
+        (&Method::GET, "/executor/metrics") => {
+            let json = serde_json::to_string(&executor.metrics()).unwrap();
+            Ok(Response::builder()
+                .status(StatusCode::OK)
+                .header("Content-Type", "application/json")
+                .body(Body::from(json))
+                .unwrap())
+        }
+
+        // Sealed blocks, `?from=<height>&limit=<count>`
+        (&Method::GET, "/blocks") => {
+            let (from, limit) = match parse_block_range(req.uri().query()) {
+                Ok(range) => range,
+                Err(e) => return Ok(bad_request_response(&e)),
+            };
+            let response = ListBlocksResponse {
+                latest_height: blocks.latest_height(),
+                blocks: blocks.range(from, limit),
+            };
+            let json = serde_json::to_string(&response).unwrap();
+            Ok(Response::builder()
+                .status(StatusCode::OK)
+                .header("Content-Type", "application/json")
+                .body(Body::from(json))
+                .unwrap())
+        }
+
+        // Sealed block at a height
+        (&Method::GET, path) if path.starts_with("/blocks/") => {
+            let block = match path.trim_start_matches("/blocks/") {
+                "latest" => blocks.latest(),
+                height => match height.parse::<u64>() {
+                    Ok(height) => blocks.get(height),
+                    Err(_) => return Ok(bad_request_response("Invalid block height")),
+                },
+            };
+            match block {
+                Some(block) => Ok(Response::builder()
+                    .status(StatusCode::OK)
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(serde_json::to_string(&block).unwrap()))
+                    .unwrap()),
+                None => Ok(not_found_response("Block not found")),
+            }
+        }
+
+        // Committed transactions, most recent first, filtered and paged by the query
+        (&Method::GET, "/transactions") => {
+            let mut query: TransactionQuery =
+                match serde_urlencoded::from_str(req.uri().query().unwrap_or_default()) {
+                    Ok(query) => query,
+                    Err(_) => return Ok(bad_request_response("Invalid query")),
+                };
+            query.limit = query.limit.min(MAX_TRANSACTION_LIMIT);
+            match state.query_transactions(&query) {
+                Ok(page) => Ok(Response::builder()
+                    .status(StatusCode::OK)
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(serde_json::to_string(&page).unwrap()))
+                    .unwrap()),
+                Err(e) => {
+                    error!("Failed to query transactions: {}", e);
+                    Ok(internal_error_response("Failed to query transactions"))
+                }
+            }
+        }
+
+        // Past state, `?key=<key>` or `?prefix=<prefix>`, at `&root=<root>` or `&log_index=<index>`
+        (&Method::GET, "/state/history") => {
+            let query: StateHistoryQuery =
+                match serde_urlencoded::from_str(req.uri().query().unwrap_or_default()) {
+                    Ok(query) => query,
+                    Err(_) => return Ok(bad_request_response("Invalid query")),
+                };
+            Ok(read_state_history(state.as_ref(), query))
+        }
+
+        // Revert the state of every node through consensus
+        (&Method::POST, "/state/revert") => {
+            let state_control = match state_control {
+                Some(state_control) => state_control,
+                None => return Ok(not_found_response("State control is not available")),
+            };
+            let body_bytes = hyper::body::to_bytes(req.into_body()).await?;
+            let revert_req: RevertStateRequest = match serde_json::from_slice(&body_bytes) {
+                Ok(req) => req,
+                Err(_) => return Ok(bad_request_response("Invalid request format")),
+            };
+
+            match state_control.revert_state(revert_req.root.clone()).await {
+                Ok(()) => Ok(Response::builder()
+                    .status(StatusCode::OK)
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(
+                        serde_json::json!({ "root": revert_req.root }).to_string(),
+                    ))
+                    .unwrap()),
+                Err(e) => {
+                    error!("State revert failed: {}", e);
+                    Ok(conflict_response(&e.to_string()))
+                }
+            }
+        }
+
         // Health check
         (&Method::GET, "/health") => Ok(Response::new(Body::from("Admin interface is healthy"))),
 
@@ -232,6 +438,118 @@ async fn handle_admin_request(
     }
 }
 
+/// Handle cluster membership requests
+async fn handle_cluster_request(
+    req: Request<Body>,
+    cluster: Arc<dyn ClusterMembership>,
+) -> Result<Response<Body>, hyper::Error> {
+    let path = req.uri().path().to_string();
+    let result = match (req.method(), path.as_str()) {
+        // Current membership
+        (&Method::GET, "/cluster") => Ok(()),
+
+        // Add a learner
+        (&Method::POST, "/cluster/learners") => {
+            let body_bytes = hyper::body::to_bytes(req.into_body()).await?;
+            let node: NodeInfo = match serde_json::from_slice(&body_bytes) {
+                Ok(node) => node,
+                Err(_) => return Ok(bad_request_response("Invalid request format")),
+            };
+            cluster.add_learner(node).await
+        }
+
+        // Promote a learner to voter
+        (&Method::POST, path) if path.starts_with("/cluster/voters/") => {
+            match path.trim_start_matches("/cluster/voters/").parse::<u64>() {
+                Ok(node_id) => cluster.promote_voter(node_id).await,
+                Err(_) => return Ok(bad_request_response("Invalid node ID")),
+            }
+        }
+
+        // Remove a voter
+        (&Method::DELETE, path) if path.starts_with("/cluster/voters/") => {
+            match path.trim_start_matches("/cluster/voters/").parse::<u64>() {
+                Ok(node_id) => cluster.remove_voter(node_id).await,
+                Err(_) => return Ok(bad_request_response("Invalid node ID")),
+            }
+        }
+
+        _ => return Ok(not_found_response("Endpoint not found")),
+    };
+
+    if let Err(e) = result {
+        error!("Cluster membership request failed: {}", e);
+        return Ok(conflict_response(&e.to_string()));
+    }
+
+    match cluster.status().await {
+        Ok(status) => Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json")
+            .body(Body::from(serde_json::to_string(&status).unwrap()))
+            .unwrap()),
+        Err(e) => {
+            error!("Failed to get cluster status: {}", e);
+            Ok(internal_error_response("Failed to get cluster status"))
+        }
+    }
+}
+
+/// Read a key or the entries under a prefix as of a past state
+fn read_state_history(state: &dyn StateStorage, query: StateHistoryQuery) -> Response<Body> {
+    let root = match (query.root, query.log_index) {
+        (Some(root), None) => root,
+        (None, Some(log_index)) => match state.root_at_log_index(log_index) {
+            Ok(Some(root)) => root,
+            Ok(None) => return not_found_response("No state recorded at this log index"),
+            Err(e) => {
+                error!(
+                    "Failed to look up the state root at log index {}: {}",
+                    log_index, e
+                );
+                return internal_error_response("Failed to look up the state root");
+            }
+        },
+        _ => return bad_request_response("Expected either root or log_index"),
+    };
+
+    // Reads fail only for roots the storage never went through
+    let body = match (query.key, query.prefix) {
+        (Some(key), None) => match state.get_at(&key, &root) {
+            Ok(value) => serde_json::json!({ "root": root, "key": key, "value": value }),
+            Err(e) => return not_found_response(&e.to_string()),
+        },
+        (None, Some(prefix)) => match state.iter_prefix_at(&prefix, &root) {
+            Ok(entries) => serde_json::json!({ "root": root, "entries": entries }),
+            Err(e) => return not_found_response(&e.to_string()),
+        },
+        _ => return bad_request_response("Expected either key or prefix"),
+    };
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+/// Parse the `from` height and `limit` of a block listing
+fn parse_block_range(query: Option<&str>) -> Result<(u64, usize), String> {
+    let mut from = 0;
+    let mut limit = DEFAULT_BLOCK_LIMIT;
+    for pair in query.unwrap_or_default().split('&') {
+        match pair.split_once('=') {
+            Some(("from", value)) => {
+                from = value.parse().map_err(|_| "Invalid from height")?;
+            }
+            Some(("limit", value)) => {
+                limit = value.parse().map_err(|_| "Invalid limit")?;
+            }
+            _ => {}
+        }
+    }
+    Ok((from, limit.min(MAX_BLOCK_LIMIT)))
+}
+
 /// Create a bad request response
 fn bad_request_response(message: &str) -> Response<Body> {
     let error = serde_json::json!({
@@ -258,6 +576,19 @@ fn not_found_response(message: &str) -> Response<Body> {
         .unwrap()
 }
 
+/// Create a conflict response
+fn conflict_response(message: &str) -> Response<Body> {
+    let error = serde_json::json!({
+        "error": message,
+    });
+
+    Response::builder()
+        .status(StatusCode::CONFLICT)
+        .header("Content-Type", "application/json")
+        .body(Body::from(serde_json::to_string(&error).unwrap()))
+        .unwrap()
+}
+
 /// Create an internal error response
 fn internal_error_response(message: &str) -> Response<Body> {
     let error = serde_json::json!({
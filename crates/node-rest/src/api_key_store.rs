@@ -81,6 +81,23 @@ impl ApiKeyStore {
         Ok(())
     }
 
+    /// Record the nonce the mempool expects next from the account of an API key
+    pub async fn set_nonce(&self, api_key: &str, nonce: u64) -> Result<()> {
+        let mut accounts = self.accounts.lock().await;
+
+        let account_info = accounts
+            .get_mut(api_key)
+            .ok_or_else(|| anyhow!("Invalid API key"))?;
+
+        account_info.nonce = nonce;
+
+        // Save updated accounts to file
+        drop(accounts); // Release lock before calling save
+        self.save().await?;
+
+        Ok(())
+    }
+
     /// Generate a new API key and associate it with an address
     pub async fn generate_key(&self, name: Option<String>, address: &str) -> Result<String> {
         let mut accounts = self.accounts.lock().await;
@@ -4,4 +4,4 @@ mod rest_api;
 
 pub use admin::{AdminInterface, PoCQuote};
 pub use api_key_store::ApiKeyStore;
-pub use rest_api::{IntegratedRestApi, RestApiConfig};
+pub use rest_api::{IntegratedRestApi, RestApiConfig, ResultWaiter};
@@ -4,12 +4,15 @@ use hyper::header::HeaderValue;
 use hyper::service::{make_service_fn, service_fn};
 use hyper::{Body, HeaderMap, Method, Request, Response, Server, StatusCode, Uri};
 use mp_common::types::{Transaction, TransactionStatusWithProof, TransactionType};
-use mp_executor::core::ExecutionRequest;
+use mp_consensus::ClusterMembership;
+use mp_mempool::nonce::NonceError;
 use mp_mempool::TransactionPool;
+use mp_network::Network;
 use serde::Deserialize;
 use serde_json::json;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::mpsc::Sender;
 use tokio::sync::oneshot;
 use tracing::{debug, error, info};
@@ -17,6 +20,13 @@ use uuid::Uuid;
 
 use crate::api_key_store::ApiKeyStore;
 
+/// Header carrying the nonce of a transaction among those of its sender
+const NONCE_HEADER: &str = "X-Nonce";
+/// Header carrying the fee offered to replace a pending transaction with the same nonce
+const FEE_HEADER: &str = "X-Fee";
+/// Request headers carrying client credentials, never gossiped
+const CREDENTIAL_HEADERS: [&str; 2] = ["authorization", "x-api-key"];
+
 /// Configuration for the integrated RESTful API
 #[derive(Debug, Clone, Deserialize)]
 pub struct RestApiConfig {
@@ -30,17 +40,21 @@ pub struct RestApiConfig {
     pub tx_timeout: u64,
 }
 
+/// Registration of a client waiting for the outcome of a transaction
+pub type ResultWaiter = (Uuid, oneshot::Sender<TransactionStatusWithProof>);
+
 /// Integrated RESTful API for mp Node
 pub struct IntegratedRestApi {
-    /// Local execution engine (no network calls)
-    execution_request_sender: Sender<(
-        ExecutionRequest,
-        oneshot::Sender<TransactionStatusWithProof>,
-    )>,
+    /// Clients waiting for the execution of their committed transactions
+    result_waiters: Sender<ResultWaiter>,
     /// Local transaction pool (no network calls)
     tx_pool: Arc<dyn TransactionPool + Send + Sync>,
     /// Store for API keys and their associated blockchain addresses
     api_key_store: Arc<ApiKeyStore>,
+    /// Consensus cluster, used to send clients of a follower to the leader
+    cluster: Option<Arc<dyn ClusterMembership>>,
+    /// P2P network accepted transactions are gossiped on
+    network: Arc<dyn Network>,
     /// REST API configuration
     config: RestApiConfig,
 }
@@ -49,17 +63,18 @@ impl IntegratedRestApi {
     /// Create a new integrated REST API
     pub fn new(
         config: RestApiConfig,
-        execution_request_sender: Sender<(
-            ExecutionRequest,
-            oneshot::Sender<TransactionStatusWithProof>,
-        )>,
+        result_waiters: Sender<ResultWaiter>,
         tx_pool: Arc<dyn TransactionPool + Send + Sync>,
         api_key_store: Arc<ApiKeyStore>,
+        cluster: Option<Arc<dyn ClusterMembership>>,
+        network: Arc<dyn Network>,
     ) -> Self {
         Self {
-            execution_request_sender,
+            result_waiters,
             tx_pool,
             api_key_store,
+            cluster,
+            network,
             config,
         }
     }
@@ -69,21 +84,39 @@ impl IntegratedRestApi {
         let addr: SocketAddr = self.config.rest_bind_address.parse()?;
         let api_key_store = self.api_key_store.clone();
         let tx_pool = self.tx_pool.clone();
-        let execution_request_sender = self.execution_request_sender.clone();
+        let result_waiters = self.result_waiters.clone();
+        let cluster = self.cluster.clone();
+        let network = self.network.clone();
+        let tx_timeout = Duration::from_secs(self.config.tx_timeout);
 
         let make_service = make_service_fn(move |_| {
             let api_key_store = api_key_store.clone();
             let tx_pool = tx_pool.clone();
-            let execution_request_sender = execution_request_sender.clone();
+            let result_waiters = result_waiters.clone();
+            let cluster = cluster.clone();
+            let network = network.clone();
 
             async move {
                 Ok::<_, hyper::Error>(service_fn(move |req| {
                     let api_key_store = api_key_store.clone();
                     let tx_pool = tx_pool.clone();
-                    let execution_request_sender = execution_request_sender.clone();
+                    let result_waiters = result_waiters.clone();
+                    let cluster = cluster.clone();
+                    let network = network.clone();
 
                     async move {
-                        handle_request(req, tx_pool, execution_request_sender, api_key_store).await
+                        if let Some(response) = redirect_to_leader(&req, cluster.as_deref()).await {
+                            return Ok(response);
+                        }
+                        handle_request(
+                            req,
+                            tx_pool,
+                            result_waiters,
+                            api_key_store,
+                            network,
+                            tx_timeout,
+                        )
+                        .await
                     }
                 }))
             }
@@ -100,11 +133,10 @@ impl IntegratedRestApi {
 async fn handle_request(
     mut req: Request<Body>,
     tx_pool: Arc<dyn TransactionPool + Send + Sync>,
-    execution_sender: Sender<(
-        ExecutionRequest,
-        oneshot::Sender<TransactionStatusWithProof>,
-    )>,
+    result_waiters: Sender<ResultWaiter>,
     api_key_store: Arc<ApiKeyStore>,
+    network: Arc<dyn Network>,
+    tx_timeout: Duration,
 ) -> Result<Response<Body>, hyper::Error> {
     let payload = match RequestToPayload::from_request(&mut req).await {
         Ok(p) => p,
@@ -121,35 +153,33 @@ async fn handle_request(
         )));
     };
 
-    let address = if !handle.is_request() {
-        // Extract API key from request
-        let api_key = extract_api_key(&req);
-
-        // Get blockchain address and nonce for this API key
-        let (address, nonce) = match &api_key {
-            Some(key) => match api_key_store.get_address_and_nonce(key).await {
-                Ok(info) => info,
-                Err(_) => return Ok(unauthorized_response()),
-            },
-            None => return Ok(unauthorized_response()),
-        };
-
-        debug!(
-            "Processing request with API key -> address: {}, nonce: {}",
-            address, nonce
-        );
-
-        // Increment nonce for the account
-        if let Some(key) = &api_key {
-            if let Err(e) = api_key_store.increment_nonce(key).await {
-                error!("Failed to increment nonce: {}", e);
-            }
-        }
+    let (nonce, fee) = match (
+        header_u64(&payload.headers, NONCE_HEADER),
+        header_u64(&payload.headers, FEE_HEADER),
+    ) {
+        (Ok(nonce), Ok(fee)) => (nonce, fee.unwrap_or_default()),
+        (Err(e), _) | (_, Err(e)) => return Ok(bad_request_response(&e)),
+    };
 
-        Some(address)
-    } else {
-        None
+    // Extract API key from request
+    let api_key = extract_api_key(&req);
+
+    // The API key gives the sender, whose transactions are numbered by nonce.
+    // Anonymous calls could be replayed, every call needs a key.
+    let Some(key) = &api_key else {
+        return Ok(unauthorized_response());
+    };
+    let (address, stored_nonce) = match api_key_store.get_address_and_nonce(key).await {
+        Ok(info) => info,
+        Err(_) => return Ok(unauthorized_response()),
     };
+    debug!(
+        "Processing request with API key -> address: {}, nonce: {}",
+        address, stored_nonce
+    );
+
+    // Resume from the nonce recorded for the key if the pool has not seen the sender
+    tx_pool.seed_nonce(&address, stored_nonce).await;
     println!("[REST] payload: {:?}", hex::encode(&payload.body.to_vec()));
 
     // Create local transaction (no network overhead)
@@ -160,50 +190,149 @@ async fn handle_request(
         method: payload.method.clone(),
         header: payload.headers.clone(),
         payload: payload.body.to_vec(),
-        sender: address,
+        sender: Some(address.clone()),
+        nonce,
+        fee,
         timestamp: chrono::Utc::now(),
         log_index: 0,
     };
 
-    // Create a oneshot channel to receive results - this is the core part of the proactive notification system
+    // The transaction is executed once committed, wait for its result from then on
     let (result_sender, result_receiver) = oneshot::channel();
-    info!("Created new oneshot channel for transaction: {}", tx_id);
+    let rejection = tx_pool.watch_rejection(tx_id).await;
+    if result_waiters.send((tx_id, result_sender)).await.is_err() {
+        error!("Failed to wait for the result of tx {}", tx_id);
+        return Ok(internal_error_response(
+            "Failed to wait for the transaction result",
+        ));
+    }
 
-    // Submit transaction to local mempool
+    // Submit transaction to local mempool, it is ordered through consensus
     match tx_pool.submit_transaction(tx.clone()).await {
-        Ok(_) => info!("Submitted transaction: {} for API key", tx_id),
+        Ok(_) => {
+            info!("Submitted transaction: {} for API key", tx_id);
+            // Peers take the nonce of the sender, the transaction is ordered from here
+            if let Err(e) = network.broadcast_transaction(gossip_copy(&tx)) {
+                error!("Failed to gossip transaction {}: {}", tx_id, e);
+            }
+        }
         Err(e) => {
+            if let Some(e) = e.downcast_ref::<NonceError>() {
+                return Ok(nonce_error_response(e));
+            }
             return Ok(internal_error_response(&format!(
                 "Transaction submission failed: {}",
                 e
-            )))
+            )));
         }
     };
 
-    // Directly send execution request to execution engine
-    info!(
-        "Sending execution request directly: handler={:?}, tx_hash={}",
-        handle, tx_id
-    );
+    let next = tx_pool.next_nonce(&address).await;
+    if let Err(e) = api_key_store.set_nonce(key, next).await {
+        error!("Failed to record nonce: {}", e);
+    }
 
-    let request = ExecutionRequest {
-        transaction_type: handle,
-        input: payload.body.to_vec(),
-        tx_hash: tx_id,
-        method: tx.method,
-        header: tx.header,
+    // The pool may still give up on the transaction before it is ordered
+    let outcome = async {
+        tokio::select! {
+            outcome = result_receiver => outcome,
+            Ok(rejected) = rejection => Ok(rejected),
+        }
     };
+    match tokio::time::timeout(tx_timeout, outcome).await {
+        // Convert transaction result to HTTP response
+        Ok(Ok(status_enum)) => Ok(transaction_result_to_response(status_enum)),
+        Ok(Err(_)) => {
+            error!("Execution result of tx {} was dropped", tx_id);
+            Ok(internal_error_response("Execution result was dropped"))
+        }
+        Err(_) => {
+            error!("Execution of tx {} timed out", tx_id);
+            Ok(gateway_timeout_response(&format!(
+                "Execution timed out after {}s",
+                tx_timeout.as_secs()
+            )))
+        }
+    }
+}
 
-    if let Err(e) = execution_sender.send((request, result_sender)).await {
-        error!("Failed to send execution request: {}", e);
-        return Ok(internal_error_response("Failed to send execution request"));
+/// Copy of a transaction to gossip, without the client's credentials
+fn gossip_copy(transaction: &Transaction) -> Transaction {
+    let mut transaction = transaction.clone();
+    for name in CREDENTIAL_HEADERS {
+        transaction.header.remove(name);
     }
-    info!("Successfully sent execution request for tx: {}", tx_id);
+    transaction
+}
+
+/// Parse an optional numeric header
+fn header_u64(headers: &HeaderMap, name: &str) -> Result<Option<u64>, String> {
+    let Some(value) = headers.get(name) else {
+        return Ok(None);
+    };
+    value
+        .to_str()
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .map(Some)
+        .ok_or_else(|| format!("Invalid {} header", name))
+}
 
-    let status_enum = result_receiver.await.unwrap();
+/// Redirect the client to the leader when this node is a follower
+///
+/// A follower that does not know the leader or its REST address refuses the
+/// request, only the leader accepts transactions.
+async fn redirect_to_leader(
+    req: &Request<Body>,
+    cluster: Option<&dyn ClusterMembership>,
+) -> Option<Response<Body>> {
+    let status = match cluster?.status().await {
+        Ok(status) => status,
+        Err(e) => {
+            error!("Failed to get cluster status: {}", e);
+            return Some(service_unavailable_response(
+                "Cluster status is unavailable, retry later",
+            ));
+        }
+    };
+    if status.is_leader() {
+        return None;
+    }
+    let Some(leader) = status.leader_info() else {
+        return Some(service_unavailable_response(
+            "No leader is known, retry later",
+        ));
+    };
+    let Some(rest_address) = leader.rest_address.as_ref() else {
+        return Some(service_unavailable_response(&format!(
+            "REST address of leader {} is not configured",
+            leader.id
+        )));
+    };
 
-    // Convert transaction result to HTTP response
-    Ok(transaction_result_to_response(status_enum))
+    let path = req
+        .uri()
+        .path_and_query()
+        .map(|p| p.as_str())
+        .unwrap_or("/");
+    let location = if rest_address.contains("://") {
+        format!("{}{}", rest_address.trim_end_matches('/'), path)
+    } else {
+        format!("http://{}{}", rest_address, path)
+    };
+    debug!(
+        "Redirecting request to leader {} at {}",
+        leader.id, location
+    );
+
+    Some(
+        Response::builder()
+            .status(StatusCode::TEMPORARY_REDIRECT)
+            .header("Location", location)
+            .header("X-Leader-Id", leader.id.to_string())
+            .body(Body::empty())
+            .unwrap(),
+    )
 }
 
 /// Extract API key from request
@@ -332,6 +461,50 @@ fn unauthorized_response() -> Response<Body> {
         .unwrap()
 }
 
+/// Create bad request response
+fn bad_request_response(message: &str) -> Response<Body> {
+    let error = json!({"error": message});
+
+    Response::builder()
+        .status(StatusCode::BAD_REQUEST)
+        .header("Content-Type", "application/json")
+        .body(Body::from(serde_json::to_string(&error).unwrap()))
+        .unwrap()
+}
+
+/// Create response for a transaction refused for its nonce
+fn nonce_error_response(e: &NonceError) -> Response<Body> {
+    let error = json!({"error": e.to_string()});
+
+    Response::builder()
+        .status(e.status_code())
+        .header("Content-Type", "application/json")
+        .body(Body::from(serde_json::to_string(&error).unwrap()))
+        .unwrap()
+}
+
+/// Create service unavailable response
+fn service_unavailable_response(message: &str) -> Response<Body> {
+    let error = json!({"error": message});
+
+    Response::builder()
+        .status(StatusCode::SERVICE_UNAVAILABLE)
+        .header("Content-Type", "application/json")
+        .body(Body::from(serde_json::to_string(&error).unwrap()))
+        .unwrap()
+}
+
+/// Create gateway timeout response
+fn gateway_timeout_response(message: &str) -> Response<Body> {
+    let error = json!({"error": message});
+
+    Response::builder()
+        .status(StatusCode::GATEWAY_TIMEOUT)
+        .header("Content-Type", "application/json")
+        .body(Body::from(serde_json::to_string(&error).unwrap()))
+        .unwrap()
+}
+
 /// Create internal error response
 fn internal_error_response(message: &str) -> Response<Body> {
     let error = json!({"error": message});
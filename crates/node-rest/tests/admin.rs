@@ -0,0 +1,244 @@
+use std::net::{Ipv4Addr, SocketAddr, TcpListener};
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use hyper::{Client, StatusCode};
+use mp_common::types::{Transaction, TransactionType};
+use mp_common::utils::create_transaction;
+use mp_common::H128;
+use mp_consensus::block::BlockStore;
+use mp_executor::bridge::ExecutionBridge;
+use mp_executor::core::{ExecutionEngine, ExecutionRequest, ExecutionResult};
+use mp_executor::error::ExecutionError;
+use mp_network::config::NetworkConfig;
+use mp_network::create_network;
+use mp_node_rest::{AdminInterface, ApiKeyStore, PoCQuote};
+use mp_state::config::StateConfig;
+use mp_state::diff::StateOperation;
+use mp_state::{create_state_storage, StateStorage};
+use serde_json::Value;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+/// Engine of an admin interface that never executes anything
+struct IdleEngine;
+
+#[async_trait]
+impl ExecutionEngine for IdleEngine {
+    async fn execute(
+        &self,
+        _request: &mut ExecutionRequest,
+    ) -> Result<ExecutionResult, ExecutionError> {
+        Err(ExecutionError::ExecutionError("not executing".to_string()))
+    }
+
+    async fn start(&self, _module_id: &Uuid) -> Result<()> {
+        Ok(())
+    }
+
+    async fn stop(&self, _module_id: &Uuid) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn sqlite_state_storage(data_dir: &Path) -> Arc<dyn StateStorage> {
+    let storage = create_state_storage(StateConfig {
+        db_type: "sqlite".to_string(),
+        db_connection: data_dir.join("state.db").to_string_lossy().to_string(),
+        state_root_path: data_dir.join("state_root").to_string_lossy().to_string(),
+    })
+    .unwrap();
+    storage.start().unwrap();
+    storage
+}
+
+/// Serve an admin interface over `state` on a loopback port, returning its address
+async fn start_admin(data_dir: &Path, state: Arc<dyn StateStorage>) -> SocketAddr {
+    let api_key_store = ApiKeyStore::new(&data_dir.join("keys.json").to_string_lossy())
+        .await
+        .unwrap();
+    let poc_quote: PoCQuote = serde_json::from_value(serde_json::json!({
+        "quote": "",
+        "event_log": "",
+        "hash_algorithm": "sha512",
+        "prefix": "",
+        "aggregate_public_key": format!("0x{}", "00".repeat(48)),
+    }))
+    .unwrap();
+    let network = create_network(
+        NetworkConfig {
+            listen_address: SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+            bootstrap_nodes: vec![],
+            key_path: None,
+            enable_mdns: false,
+            state_sync: false,
+            reputation: Default::default(),
+        },
+        1024,
+        None,
+    )
+    .unwrap();
+    let bridge = ExecutionBridge::new(Arc::new(IdleEngine), 1, 16, Default::default());
+
+    let admin = AdminInterface::new(
+        api_key_store,
+        Arc::new(Mutex::new(dstack::TappdClient::new("http://127.0.0.1:1"))),
+        poc_quote,
+        None,
+        network,
+        None,
+        Arc::new(bridge),
+        Arc::new(BlockStore::in_memory()),
+        state,
+    );
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move { admin.serve(listener).await });
+    address
+}
+
+async fn get(address: SocketAddr, path_and_query: &str) -> (StatusCode, Value) {
+    let response = Client::new()
+        .get(
+            format!("http://{}{}", address, path_and_query)
+                .parse()
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+fn insert(key: &str, value: &str) -> StateOperation {
+    StateOperation::Insert {
+        key: key.to_string(),
+        value: value.to_string(),
+    }
+}
+
+fn call(contract: H128, sender: &str, log_index: u64) -> Transaction {
+    let mut transaction = create_transaction(
+        TransactionType::Request(contract, "call".into()),
+        vec![],
+        Some(sender.to_string()),
+        Default::default(),
+        Default::default(),
+    );
+    transaction.log_index = log_index;
+    transaction
+}
+
+fn ids(page: &Value) -> Vec<String> {
+    page["transactions"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|transaction| transaction["id"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[tokio::test]
+async fn test_transactions_are_paged_and_filtered() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = sqlite_state_storage(dir.path());
+    let contract_a = H128::from_low_u64_be(0xa);
+    let contract_b = H128::from_low_u64_be(0xb);
+    let history = [
+        call(contract_a, "alice", 1),
+        call(contract_b, "bob", 2),
+        call(contract_a, "alice", 3),
+        call(contract_a, "bob", 4),
+        call(contract_b, "alice", 5),
+    ];
+    for transaction in &history {
+        state.apply_transaction(transaction.clone()).unwrap();
+    }
+    let address = start_admin(dir.path(), state).await;
+    let id = |i: usize| history[i].id.to_string();
+
+    // Pages follow each other through the cursor, most recent first
+    let (status, page) = get(address, "/transactions?limit=2").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(ids(&page), vec![id(4), id(3)]);
+    let cursor = page["next_cursor"].as_u64().unwrap();
+    let (_, page) = get(address, &format!("/transactions?limit=2&cursor={}", cursor)).await;
+    assert_eq!(ids(&page), vec![id(2), id(1)]);
+    let cursor = page["next_cursor"].as_u64().unwrap();
+    let (_, page) = get(address, &format!("/transactions?limit=2&cursor={}", cursor)).await;
+    assert_eq!(ids(&page), vec![id(0)]);
+    assert_eq!(page["next_cursor"], Value::Null);
+
+    // Filters combine
+    let (_, page) = get(address, "/transactions?sender=alice").await;
+    assert_eq!(ids(&page), vec![id(4), id(2), id(0)]);
+    let (_, page) = get(
+        address,
+        &format!("/transactions?sender=alice&contract={:?}", contract_a),
+    )
+    .await;
+    assert_eq!(ids(&page), vec![id(2), id(0)]);
+    let (_, page) = get(address, "/transactions?min_log_index=2&max_log_index=4").await;
+    assert_eq!(ids(&page), vec![id(3), id(2), id(1)]);
+
+    let (status, _) = get(address, "/transactions?limit=many").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_state_history_by_root_and_log_index() {
+    let dir = tempfile::tempdir().unwrap();
+    let state = sqlite_state_storage(dir.path());
+    let first = state
+        .apply_operations(vec![insert("contract/a", "1"), insert("contract/b", "2")])
+        .unwrap();
+    state.record_log_root(4, &first.new_root).unwrap();
+    let second = state
+        .apply_operations(vec![insert("contract/a", "3")])
+        .unwrap();
+    state.record_log_root(9, &second.new_root).unwrap();
+    let address = start_admin(dir.path(), state).await;
+
+    // Log indices read the state left by the latest entry up to them
+    let (status, body) = get(address, "/state/history?key=contract%2Fa&log_index=8").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["root"], first.new_root.as_str());
+    assert_eq!(body["value"], "1");
+
+    let (status, body) = get(address, "/state/history?key=contract/a&log_index=9").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["value"], "3");
+
+    let (status, body) = get(
+        address,
+        &format!("/state/history?prefix=contract/&root={}", first.new_root),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        body["entries"],
+        serde_json::json!([["contract/a", "1"], ["contract/b", "2"]])
+    );
+
+    // Missing keys read as null
+    let (status, body) = get(address, "/state/history?key=missing&log_index=9").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["value"], Value::Null);
+
+    // Nothing is known before the first recorded entry or at unknown roots
+    let (status, _) = get(address, "/state/history?key=contract/a&log_index=3").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = get(address, "/state/history?key=contract/a&root=unknown").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // Exactly one of key and prefix, and one of root and log_index
+    let (status, _) = get(address, "/state/history?log_index=9").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = get(address, "/state/history?key=contract/a").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = get(address, "/state/history?key=contract/a&log_index=nine").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
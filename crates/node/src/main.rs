@@ -5,24 +5,31 @@ use dstack::TdxQuoteArgs;
 use mp_common::types::TransactionStatusWithProof;
 // Transaction type is used in the code but only through imported functions
 // 移除 mp_compute 导入，使用 mp_container 代替
-use mp_consensus::{config::ConsensusConfig, create_consensus_engine};
+use mp_consensus::{config::ConsensusConfig, create_consensus_engine, StateControl};
 use mp_container::{config::ContainerConfig, create_container_environment};
 use mp_executor::{
-    config::ExecutorConfig, core::ExecutionResponse, create_execution_engine, ExecutionEngineType,
+    config::ExecutorConfig,
+    core::{ExecutionFailure, ExecutionResponse, ExecutionResult},
+    create_execution_engine, ExecutionEngineType,
 };
 use mp_mempool::{config::MempoolConfig, create_transaction_pool};
-use mp_network::{config::NetworkConfig, create_network};
+use mp_network::{config::NetworkConfig, create_network, Network};
 use mp_node_rest::PoCQuote;
-use mp_state::{config::StateConfig, create_state_storage};
+use mp_state::{config::StateConfig, create_state_storage, diff::StateOperation};
 use mp_poc::PoC;
 use serde::Deserialize;
 use std::sync::Arc;
 use std::{collections::HashMap, path::Path};
 use tokio::sync::Mutex;
 
-use tracing::{debug, error, info, Level};
+use tracing::{debug, error, info, warn, Level};
 use tracing_subscriber::{EnvFilter, FmtSubscriber};
 
+mod replay;
+
+/// Maximum number of committed transactions executed as one batch
+const MAX_EXECUTION_BATCH: usize = 64;
+
 /// mp Node - A blockchain platform for Web2-style smart contracts using Docker
 #[derive(Parser, Debug)]
 #[clap(author, version, about)]
@@ -38,6 +45,16 @@ struct Args {
     /// Enable REST API
     #[clap(long)]
     with_rest_api: bool,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+/// Tasks run instead of the node
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Re-execute recorded transactions and compare their outputs and PoC roots with the recorded ones
+    Replay(replay::ReplayArgs),
 }
 
 /// Node configuration
@@ -98,6 +115,13 @@ fn main() -> Result<()> {
     info!("Node ID: {}", config.node.node_id);
     info!("Using configuration file: {}", args.config);
 
+    if let Some(Command::Replay(replay_args)) = args.command {
+        return tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?
+            .block_on(replay::run(config, replay_args));
+    }
+
     // Create data directories
     std::fs::create_dir_all("./data/raft")?;
     std::fs::create_dir_all("./data/state_root")?;
@@ -122,6 +146,44 @@ fn main() -> Result<()> {
     Ok(())
 }
 
+/// Wait until the network is connected to a peer, giving up after a while
+async fn wait_for_peers(network: &dyn Network) {
+    let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(30);
+    while network.peers().is_empty() && tokio::time::Instant::now() < deadline {
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    }
+}
+
+/// Commit the state changes of a successful execution through consensus, waiting until applied
+async fn commit_state_changes(state_control: Option<&dyn StateControl>, result: &ExecutionResult) {
+    let tx_hash = result.metadata.tx_hash;
+    let succeeded = result
+        .output
+        .status_code
+        .is_none_or(|status| (200..300).contains(&status));
+    if !succeeded || result.state_diff.operations.is_empty() {
+        return;
+    }
+    let Some(state_control) = state_control else {
+        warn!(
+            "Consensus engine cannot commit the state changes of tx {}",
+            tx_hash
+        );
+        return;
+    };
+
+    let operations: Vec<StateOperation> = result
+        .state_diff
+        .operations
+        .iter()
+        .cloned()
+        .map(Into::into)
+        .collect();
+    if let Err(e) = state_control.commit_state_changes(operations).await {
+        error!("Failed to commit state changes of tx {}: {}", tx_hash, e);
+    }
+}
+
 /// Load configuration from file
 fn load_config(config_path: &Path) -> Result<NodeConfig> {
     let config = Config::builder()
@@ -134,40 +196,78 @@ fn load_config(config_path: &Path) -> Result<NodeConfig> {
 
 /// Run the node with the given configuration
 async fn run_node(config: NodeConfig, with_rest_api: bool) -> Result<()> {
-    // Initialize consensus engine
+    // Initialize state storage
+    info!("Initializing state storage");
+    let state_storage = create_state_storage(config.state)?;
+    state_storage.start()?;
+
+    // Initialize PoC
+    let mock_poc = Arc::new(mp_poc::mock::MockPoC::new());
+    let aggregate_public_key = mock_poc.aggregate_public_key()?;
+    info!(
+        "Aggregate public key: {:?}",
+        hex::encode(aggregate_public_key.to_bytes())
+    );
+
+    // Initialize P2P network, peers can fetch the local state through it
+    info!("Initializing P2P network");
+    let state_sync = config.network.state_sync;
+    let network = create_network(
+        config.network,
+        config.mempool.max_tx_size,
+        Some(state_storage.clone()),
+    )?;
+    let mut gossiped_tx_rx = network.get_received_tx_channel();
+    network.start()?;
+
+    // Catch up with the state of the other nodes before taking part in consensus
+    if state_sync {
+        wait_for_peers(network.as_ref()).await;
+        match network.sync_state().await {
+            Ok(root) => info!("State synced up to root {}", root),
+            Err(e) => warn!("State sync failed, starting from the local state: {}", e),
+        }
+    }
+
+    // Initialize consensus engine, snapshots of the Raft log capture the state storage
     info!("Initializing consensus engine");
-    // Clone the consensus config so we can use it again later
-    let consensus_config = config.consensus.clone();
-    let mut consensus_engine = create_consensus_engine(consensus_config)?;
+    let mut consensus_engine =
+        create_consensus_engine(config.consensus, Some(state_storage.clone()))?;
+
+    // Sealed blocks carry a PoC aggregate over their hash
+    let block_poc = mock_poc.clone();
+    consensus_engine.set_block_signer(Arc::new(move |msg: &[u8]| block_poc.sign_aggregate(msg)));
 
     // Start consensus engine
     consensus_engine.start().await?;
 
-    // Get confirmed transaction channel from consensus
-    let confirmed_tx_rx = consensus_engine.get_confirmed_tx_channel().await;
+    // Transactions are executed once committed, in log order
+    let mut confirmed_tx_rx = consensus_engine.get_confirmed_tx_channel().await;
+    let cluster_membership = consensus_engine.membership();
+    let state_control = consensus_engine.state_control();
+    let blocks = consensus_engine.blocks();
+    let state_changes = state_control.clone();
 
-    // Initialize transaction pool with consensus engine
+    // Initialize transaction pool, it submits transactions to the consensus engine
     info!("Initializing transaction pool");
     let tx_pool = create_transaction_pool(config.mempool, consensus_engine)?;
     tx_pool.start().await?;
 
-    // Create a new consensus engine for other components
-    let _consensus_engine = create_consensus_engine(config.consensus)?;
+    // Transactions gossiped by peers take the nonce of their sender, their node orders them
+    let tx_pool_for_gossip = tx_pool.clone();
+    tokio::spawn(async move {
+        while let Some(tx) = gossiped_tx_rx.recv().await {
+            let tx_id = tx.id;
+            if let Err(e) = tx_pool_for_gossip.add_remote_transaction(tx).await {
+                warn!("Failed to add gossiped transaction {}: {}", tx_id, e);
+            }
+        }
+    });
 
     // Initialize container environment
     info!("Initializing container environment");
     let (tappd_client, container_env) = create_container_environment(config.container).await?;
 
-    // Initialize state storage
-    info!("Initializing state storage");
-    let state_storage = create_state_storage(config.state)?;
-    state_storage.start()?;
-
-    // Initialize P2P network
-    info!("Initializing P2P network");
-    let network = create_network(config.network)?;
-    network.start()?;
-
     info!("mp Node is running with Raft consensus");
 
     // Clone the executor config before we move it
@@ -179,21 +279,15 @@ async fn run_node(config: NodeConfig, with_rest_api: bool) -> Result<()> {
 
     // Initialize execution engine
     info!("Initializing execution engine with compute environment");
-    let exec_engine = create_execution_engine(executor_config.clone()).await?;
+    let exec_engine =
+        create_execution_engine(executor_config.clone(), Some(state_storage.clone())).await?;
 
     // Setup execution bridge for cross-process communication
-    let mut bridge = mp_executor::bridge::ExecutionBridge::new(
+    let bridge = mp_executor::bridge::ExecutionBridge::new(
         exec_engine,
         executor_config.worker_threads,
         1000,
-    );
-
-    // Initialize PoC
-    let mock_poc = Arc::new(mp_poc::mock::MockPoC::new());
-    let aggregate_public_key = mock_poc.aggregate_public_key()?;
-    info!(
-        "Aggregate public key: {:?}",
-        hex::encode(aggregate_public_key.to_bytes())
+        executor_config.timeouts.clone(),
     );
 
     let poc_quote = tappd_client
@@ -206,15 +300,19 @@ async fn run_node(config: NodeConfig, with_rest_api: bool) -> Result<()> {
         })
         .await?;
 
-    // Start the bridge and get result receiver
-    let mut exec_result_rx = bridge.start(config.executor.worker_threads).await?;
-    let exec_request_tx = bridge.get_request_sender();
+    // Outcomes of the committed transactions executed here, in log order
+    let (exec_result_tx, mut exec_result_rx) =
+        tokio::sync::mpsc::channel::<Result<ExecutionResult, ExecutionFailure>>(1000);
+    let bridge = Arc::new(bridge);
 
     // Create a channel for forwarding execution results to the REST API
     let (exec_result_forward_tx, mut exec_result_forward_rx) =
         tokio::sync::mpsc::channel::<ExecutionResponse>(1000);
 
-    let api_result_tx = Arc::new(Mutex::new(HashMap::new()));
+    let api_result_tx = Arc::new(Mutex::new(HashMap::<
+        uuid::Uuid,
+        tokio::sync::oneshot::Sender<TransactionStatusWithProof>,
+    >::new()));
     // Start the integrated REST API if requested
     if with_rest_api {
         info!("Initializing integrated RESTful API");
@@ -225,19 +323,33 @@ async fn run_node(config: NodeConfig, with_rest_api: bool) -> Result<()> {
             let api_key_store =
                 mp_node_rest::ApiKeyStore::new(&rest_config.key_store_path).await?;
 
+            // Senders resume from the nonces recorded before the restart
+            let mut recorded_nonces = HashMap::<String, u64>::new();
+            for account in api_key_store.get_all_keys().await?.into_values() {
+                let next = recorded_nonces.entry(account.address).or_default();
+                *next = (*next).max(account.nonce);
+            }
+            for (sender, next) in recorded_nonces {
+                tx_pool.seed_nonce(&sender, next).await;
+            }
+
             // Create admin interface with the API key store
             let admin_interface = mp_node_rest::AdminInterface::new(
                 api_key_store.clone(),
                 tappd_client.clone(),
                 PoCQuote::new(poc_quote, aggregate_public_key),
+                cluster_membership.clone(),
+                network.clone(),
+                state_control,
+                bridge.clone(),
+                blocks,
+                state_storage.clone(),
             );
             let admin_bind_address = rest_config.admin_bind_address.clone();
 
-            // Create a channel for direct execution requests
-            let (exec_sender, mut exec_receiver) = tokio::sync::mpsc::channel(1000);
-
-            // Clone exec_request_tx for use in the task
-            let exec_request_tx_for_task = exec_request_tx.clone();
+            // Create a channel for clients waiting on the results of their transactions
+            let (result_waiters, mut result_waiter_rx) =
+                tokio::sync::mpsc::channel::<mp_node_rest::ResultWaiter>(1000);
 
             // Clone tx_pool for use in REST API and result processor
             let tx_pool_clone =
@@ -246,9 +358,11 @@ async fn run_node(config: NodeConfig, with_rest_api: bool) -> Result<()> {
             // Create the integrated REST API
             let rest_api = mp_node_rest::IntegratedRestApi::new(
                 rest_config,
-                exec_sender,
+                result_waiters,
                 tx_pool_clone,
                 api_key_store,
+                cluster_membership,
+                network.clone(),
             );
 
             // Start admin interface in a separate task
@@ -266,23 +380,16 @@ async fn run_node(config: NodeConfig, with_rest_api: bool) -> Result<()> {
                 }
             });
             let api_result_tx_clone = api_result_tx.clone();
-            // Process execution requests from the REST API
+            // Register REST API clients waiting for the execution of their transactions
             tokio::spawn(async move {
-                info!("Starting REST API execution request processing");
-
-                while let Some((request, sender)) = exec_receiver.recv().await {
-                    let tx_hash = request.tx_hash.clone();
-                    info!("Processing REST API execution request for tx: {}", tx_hash);
-
-                    // Do not create a new oneshot channel
-                    // The REST API has already created a channel and stored the receiver in the result_receivers map
-                    // We only need to ensure the result_sender field in the execution request is correctly passed
-                    api_result_tx_clone.lock().await.insert(tx_hash, sender);
-                    // Forward request to executor
-                    if let Err(e) = exec_request_tx_for_task.send(request).await {
-                        error!("Failed to forward execution request: {}", e);
-                        continue;
-                    }
+                info!("Starting REST API result waiter registration");
+
+                while let Some((tx_hash, sender)) = result_waiter_rx.recv().await {
+                    debug!("REST API client waits for the result of tx: {}", tx_hash);
+                    let mut waiters = api_result_tx_clone.lock().await;
+                    // Clients that went away, e.g. after a refused submission, wait no more
+                    waiters.retain(|_, waiter| !waiter.is_closed());
+                    waiters.insert(tx_hash, sender);
                 }
             });
 
@@ -324,37 +431,78 @@ async fn run_node(config: NodeConfig, with_rest_api: bool) -> Result<()> {
         }
     }
 
-    // Process submitted transactions, execute then consensus
-    let tx_pool_clone = tx_pool.clone();
-    let exec_request_tx_clone = exec_request_tx.clone();
+    // Record committed transactions in the history and execute those submitted here.
+    // Transactions committed meanwhile are executed as one batch, in parallel where they
+    // do not conflict, and the state changes of a batch are committed in log order before
+    // the next batch runs, so no execution reads state older than the ones before it.
+    let tx_pool_for_commits = tx_pool.clone();
+    let batch_bridge = bridge.clone();
+    let history_storage = state_storage.clone();
     let _tx_processing_handle = tokio::spawn(async move {
-        info!("Starting transaction processing (execute-then-consensus model)");
-
-        // Get pending transactions
-        let mut pending_tx_rx = tx_pool_clone.get_pending_tx_channel().await;
+        info!("Starting committed transaction processing");
+
+        while let Some(tx) = confirmed_tx_rx.recv().await {
+            let mut committed = vec![tx];
+            while committed.len() < MAX_EXECUTION_BATCH {
+                match confirmed_tx_rx.try_recv() {
+                    Ok(tx) => committed.push(tx),
+                    Err(_) => break,
+                }
+            }
 
-        while let Some(tx) = pending_tx_rx.recv().await {
-            let tx_id = tx.id;
-            info!("Processing new transaction: {}", tx_id);
-
-            // Create execution request
-            let request = mp_executor::core::ExecutionRequest {
-                header: tx.header,
-                transaction_type: tx.tx_type,
-                input: tx.payload,
-                tx_hash: tx_id,
-                method: tx.method,
-            };
+            let mut requests = Vec::new();
+            for tx in committed {
+                let tx_id = tx.id;
+                let storage = history_storage.clone();
+                let record = tx.clone();
+                match tokio::task::spawn_blocking(move || storage.apply_transaction(record)).await
+                {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => {
+                        error!("Failed to record confirmed transaction {}: {}", tx_id, e)
+                    }
+                    Err(e) => error!("Failed to record confirmed transaction {}: {}", tx_id, e),
+                }
 
-            // 1. Send to execution module first
-            info!("Sending transaction {} to executor", tx_id);
-            if let Err(e) = exec_request_tx_clone.send(request).await {
-                error!("Failed to send transaction to executor: {}", e);
+                // Every node records the transaction, the node its client waits on executes it.
+                // The pooled copy still has the method and headers consensus does not carry.
+                let Some(tx) = tx_pool_for_commits.get_transaction(&tx_id).await else {
+                    debug!("Transaction {} was submitted on another node", tx_id);
+                    continue;
+                };
+
+                requests.push(mp_executor::core::ExecutionRequest {
+                    header: tx.header,
+                    transaction_type: tx.tx_type,
+                    input: tx.payload,
+                    tx_hash: tx_id,
+                    method: tx.method,
+                    cancel: Default::default(),
+                    pending_writes: Default::default(),
+                    state_root: None,
+                });
+            }
+            if requests.is_empty() {
                 continue;
             }
 
-            // Note: Execution result will be handled by another task and then submitted to consensus
-            // This allows transactions to be submitted faster, without waiting for execution to complete
+            info!("Executing a batch of {} committed transactions", requests.len());
+            let results = batch_bridge.execute_batch(requests.clone()).await;
+            for (request, result) in requests.iter().zip(results) {
+                let outcome = match result {
+                    Ok(result) => {
+                        commit_state_changes(state_changes.as_deref(), &result).await;
+                        Ok(result)
+                    }
+                    Err(e) => {
+                        error!("Execution of tx {} failed: {}", request.tx_hash, e);
+                        Err(ExecutionFailure::new(request, &e))
+                    }
+                };
+                if let Err(e) = exec_result_tx.send(outcome).await {
+                    error!("Failed to forward execution outcome: {}", e);
+                }
+            }
         }
     });
 
@@ -401,7 +549,43 @@ async fn run_node(config: NodeConfig, with_rest_api: bool) -> Result<()> {
     // Main execution result processing task
     let _result_processing_handle = tokio::spawn(async move {
         info!("Starting execution result processing with consensus");
-        while let Some(result) = exec_result_rx.recv().await {
+        while let Some(outcome) = exec_result_rx.recv().await {
+            let result = match outcome {
+                Ok(result) => result,
+                Err(failure) => {
+                    let tx_hash = failure.metadata.tx_hash;
+                    error!("Execution of tx {} failed: {}", tx_hash, failure.message);
+
+                    // Failures are signed and recorded like results, with the error as output
+                    let output = failure.output();
+                    let status = failure.status_code();
+                    let signed_aggregate = mock_poc
+                        .generate_aggregate(vec![(
+                            failure.input.clone(),
+                            serde_json::to_vec(&output).unwrap(),
+                        )])
+                        .unwrap();
+                    let sender = api_result_tx_clone.lock().await.remove(&tx_hash);
+                    if let Some(sender) = sender {
+                        let poc: PoC = signed_aggregate.clone().try_into().unwrap();
+                        if let Err(e) = sender.send(TransactionStatusWithProof::Failed(
+                            output.clone(),
+                            status,
+                            None,
+                            Some(serde_json::json!(poc)),
+                        )) {
+                            error!("Failed to send execution failure to REST API: {:?}", e);
+                        }
+                    }
+                    if let Err(e) = tx_pool_clone
+                        .update_transaction_failure(&tx_hash, output, status, signed_aggregate)
+                        .await
+                    {
+                        error!("Failed to record execution failure in mempool: {}", e);
+                    }
+                    continue;
+                }
+            };
             let tx_hash = &result.metadata.tx_hash;
             info!("Received execution result for tx: {}", tx_hash);
             let result_output = result.output.output.clone();
@@ -451,7 +635,7 @@ async fn run_node(config: NodeConfig, with_rest_api: bool) -> Result<()> {
                         .unwrap();
 
                     let status = execution_response.result.output.status_code.unwrap_or(200) as u16;
-                    if 200 <= status && status < 300 {
+                    if (200..300).contains(&status) {
                         if let Err(e) = sender.send(TransactionStatusWithProof::Confirmed(
                             result_output.clone(),
                             status,
@@ -460,15 +644,13 @@ async fn run_node(config: NodeConfig, with_rest_api: bool) -> Result<()> {
                         )) {
                             error!("Failed to send execution result to REST API: {:?}", e);
                         }
-                    } else {
-                        if let Err(e) = sender.send(TransactionStatusWithProof::Failed(
-                            result_output.clone(),
-                            status,
-                            Some(headers),
-                            Some(serde_json::json!(poc)),
-                        )) {
-                            error!("Failed to send execution result to REST API: {:?}", e);
-                        }
+                    } else if let Err(e) = sender.send(TransactionStatusWithProof::Failed(
+                        result_output.clone(),
+                        status,
+                        Some(headers),
+                        Some(serde_json::json!(poc)),
+                    )) {
+                        error!("Failed to send execution result to REST API: {:?}", e);
                     }
                 } else {
                     error!("Failed to get result to REST API, tx: {}", tx_hash);
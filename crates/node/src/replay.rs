@@ -0,0 +1,113 @@
+use anyhow::{bail, Result};
+use mp_container::create_container_environment;
+use mp_executor::replay::{ReplayOutcome, ReplaySelection, Replayer};
+use mp_executor::{create_execution_engine, ExecutionEngineType};
+use mp_state::create_state_storage;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::NodeConfig;
+
+/// Transactions to replay, by log index range or by ID
+#[derive(clap::Args, Debug)]
+pub struct ReplayArgs {
+    /// First log index to replay
+    #[clap(long, requires = "to", conflicts_with = "tx_ids")]
+    from: Option<u64>,
+
+    /// Last log index to replay
+    #[clap(long, requires = "from")]
+    to: Option<u64>,
+
+    /// ID of a transaction to replay, can be repeated
+    #[clap(long = "tx", required_unless_present = "from")]
+    tx_ids: Vec<Uuid>,
+}
+
+impl ReplayArgs {
+    fn selection(&self) -> ReplaySelection {
+        match (self.from, self.to) {
+            (Some(from), Some(to)) => ReplaySelection::LogIndices { from, to },
+            _ => ReplaySelection::Transactions(self.tx_ids.clone()),
+        }
+    }
+}
+
+/// Replay recorded transactions and compare them with their recorded executions
+///
+/// Runs against the state database of the configuration, which should be a copy
+/// of a node's, with containers of its own. Fails if any replay diverged.
+pub async fn run(config: NodeConfig, args: ReplayArgs) -> Result<()> {
+    let state_storage = create_state_storage(config.state)?;
+    state_storage.start()?;
+
+    let (_, container_env) = create_container_environment(config.container).await?;
+    let mut executor_config = config.executor;
+    executor_config.engine_type = ExecutionEngineType::Network;
+    executor_config.container_environment = Some(container_env);
+    let timeouts = executor_config.timeouts.clone();
+    let engine = create_execution_engine(executor_config, Some(state_storage.clone())).await?;
+    let replayer = Replayer::new(engine, state_storage, timeouts);
+
+    let transactions = replayer.select(&args.selection())?;
+    let Some(first) = transactions.first() else {
+        info!("No recorded transactions to replay");
+        return Ok(());
+    };
+    let restored = replayer.restore_containers(first.log_index).await?;
+    info!(
+        "Re-ran {} container transactions before log index {}",
+        restored, first.log_index
+    );
+
+    let mut mismatches = 0;
+    for transaction in &transactions {
+        let report = replayer.replay(transaction).await?;
+        match &report.outcome {
+            ReplayOutcome::Matched => info!("tx {} at {}: matched", report.tx_id, report.log_index),
+            ReplayOutcome::Diverged {
+                recorded,
+                output,
+                poc_root,
+            } => error!(
+                "tx {} at {}: diverged, output {} (recorded {}), PoC root {} (recorded {})",
+                report.tx_id,
+                report.log_index,
+                output,
+                recorded.output,
+                poc_root,
+                recorded.poc_root
+            ),
+            ReplayOutcome::NotRecorded => warn!(
+                "tx {} at {}: no recorded execution, skipped",
+                report.tx_id, report.log_index
+            ),
+            ReplayOutcome::Unchecked => {
+                info!(
+                    "tx {} at {}: not a request, unchecked",
+                    report.tx_id, report.log_index
+                )
+            }
+            ReplayOutcome::Failed(e) => {
+                error!("tx {} at {}: failed, {}", report.tx_id, report.log_index, e)
+            }
+        }
+        if report.is_mismatch() {
+            mismatches += 1;
+        }
+    }
+
+    info!(
+        "Replayed {} transactions, {} mismatched",
+        transactions.len(),
+        mismatches
+    );
+    if mismatches > 0 {
+        bail!(
+            "{} of {} replayed transactions did not match their records",
+            mismatches,
+            transactions.len()
+        );
+    }
+    Ok(())
+}
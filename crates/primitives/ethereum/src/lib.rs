@@ -3,7 +3,7 @@
 mod receipt;
 mod trie;
 
-pub use eth_trie::TrieError;
+pub use eth_trie::{EthTrie, MemoryDB, Trie, TrieError};
 pub use ethereum::{
     util, AccessListItem, Block, BlockV0, BlockV1, BlockV2, EIP1559Transaction,
     EIP1559TransactionMessage, EIP2930Transaction, EIP2930TransactionMessage, EnvelopedDecodable,
@@ -11,7 +11,10 @@ pub use ethereum::{
     TransactionAction, TransactionSignature, TransactionV0, TransactionV1, TransactionV2,
 };
 pub use receipt::{EIP1559ReceiptData, EIP2930ReceiptData, EIP658ReceiptData, Log, Receipt};
-pub use trie::{calculate_root, generate_proof, order_generate_proof, order_verify_proof};
+pub use trie::{
+    calculate_root, calculate_trie_root, generate_proof, order_generate_proof, order_verify_proof,
+    verify_proof,
+};
 
 pub mod keccak {
     use ethereum_types::H256;
@@ -15,7 +15,8 @@ where
     verify_proof::<H>(root_hash, &rlp::encode(&indexed), proof)
 }
 
-fn verify_proof<H>(
+/// Verify a proof for `key` against `root_hash`, returning the proven value or `None` if absent
+pub fn verify_proof<H>(
     root_hash: H::Out,
     key: &[u8],
     proof: Vec<Vec<u8>>,
@@ -83,3 +84,17 @@ where
     }
     trie.root_hash()
 }
+
+/// Calculate the trie root of a set of key/value pairs
+pub fn calculate_trie_root<I, A, B>(input: I) -> Result<H256, eth_trie::TrieError>
+where
+    I: IntoIterator<Item = (A, B)>,
+    A: AsRef<[u8]>,
+    B: AsRef<[u8]>,
+{
+    let mut trie = EthTrie::new(Arc::new(eth_trie::MemoryDB::new(true)));
+    for (key, val) in input.into_iter() {
+        trie.insert(key.as_ref(), val.as_ref())?;
+    }
+    trie.root_hash()
+}
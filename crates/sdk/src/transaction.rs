@@ -27,6 +27,8 @@ pub struct TransactionBuilder {
     tx_type: TransactionType,
     payload: Vec<u8>,
     sender: Option<String>,
+    nonce: Option<u64>,
+    fee: u64,
 }
 
 impl TransactionBuilder {
@@ -36,6 +38,8 @@ impl TransactionBuilder {
             tx_type,
             payload: Vec::new(),
             sender: None,
+            nonce: None,
+            fee: 0,
         }
     }
 
@@ -75,15 +79,30 @@ impl TransactionBuilder {
         self
     }
 
+    /// Set the nonce of the transaction among those of its sender
+    pub fn nonce(mut self, nonce: u64) -> Self {
+        self.nonce = Some(nonce);
+        self
+    }
+
+    /// Set the fee, raise it to replace a pending transaction with the same nonce
+    pub fn fee(mut self, fee: u64) -> Self {
+        self.fee = fee;
+        self
+    }
+
     /// Build the transaction
     pub fn build(self) -> Transaction {
-        create_transaction(
+        let mut transaction = create_transaction(
             self.tx_type,
             self.payload,
             self.sender,
             Method::POST,
             HeaderMap::new(),
-        )
+        );
+        transaction.nonce = self.nonce;
+        transaction.fee = self.fee;
+        transaction
     }
 }
 
@@ -3,10 +3,10 @@ use serde::{Deserialize, Serialize};
 /// State storage configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StateConfig {
-    /// Database type
+    /// Database type, `sqlite` or `sled`
     pub db_type: String,
 
-    /// Database connection string
+    /// Database connection string, the database directory for sled
     pub db_connection: String,
 
     /// State root storage path
@@ -1,3 +1,85 @@
+pub mod migrations;
+pub mod sled;
 pub mod sqlite;
 
 // Add other database implementations here
+
+use anyhow::{anyhow, Result};
+use std::sync::Mutex;
+
+use crate::diff::{StateDiff, StateOperation};
+use crate::proof::StateTrie;
+
+/// Run `f` on the trie of the current state, built from `load_entries` on first use
+///
+/// The lock is held while `f` runs, writers of the state go through here so
+/// that they apply one diff at a time.
+pub(crate) fn with_trie<T>(
+    trie: &Mutex<Option<StateTrie>>,
+    load_entries: impl FnOnce() -> Result<Vec<(String, String)>>,
+    f: impl FnOnce(&mut StateTrie) -> Result<T>,
+) -> Result<T> {
+    let mut trie = trie.lock().unwrap();
+    if trie.is_none() {
+        let entries = load_entries()?;
+        *trie = Some(StateTrie::new(
+            entries.iter().map(|(k, v)| (k.as_str(), v.as_str())),
+        )?);
+    }
+    f(trie.as_mut().unwrap())
+}
+
+/// Check `diff` against the trie of the current state, then store it with `write`
+///
+/// The diff must start at the current root and its operations must lead to the
+/// root it claims. The trie goes back to the current root if the diff is
+/// refused or cannot be stored.
+pub(crate) fn commit_diff(
+    trie: &mut StateTrie,
+    diff: &StateDiff,
+    write: impl FnOnce(&StateDiff) -> Result<()>,
+) -> Result<()> {
+    let root = trie.root();
+    if diff.prev_root != root {
+        return Err(anyhow!(
+            "Diff starts at state root {} but the state is at {}",
+            diff.prev_root,
+            root
+        ));
+    }
+
+    let new_root = trie.apply(&diff.operations)?;
+    let committed = if new_root != diff.new_root {
+        Err(anyhow!(
+            "Diff claims state root {} but its operations lead to {}",
+            diff.new_root,
+            new_root
+        ))
+    } else {
+        write(diff)
+    };
+    if committed.is_err() {
+        trie.undo(&root)?;
+    }
+    committed
+}
+
+/// Apply `operations` to the trie of the current state and store them with `write`
+///
+/// Operations leaving the state unchanged are not stored.
+pub(crate) fn commit_operations(
+    trie: &mut StateTrie,
+    operations: Vec<StateOperation>,
+    write: impl FnOnce(&StateDiff) -> Result<()>,
+) -> Result<StateDiff> {
+    let mut diff = StateDiff::new(trie.root());
+    diff.new_root = trie.apply(&operations)?;
+    diff.operations = operations;
+    if diff.new_root != diff.prev_root {
+        if let Err(e) = write(&diff) {
+            trie.undo(&diff.prev_root)?;
+            return Err(e);
+        }
+    }
+    Ok(diff)
+}
@@ -0,0 +1,145 @@
+use anyhow::{anyhow, Context, Result};
+use diesel::connection::SimpleConnection;
+use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Text};
+use diesel::sqlite::SqliteConnection;
+use hash_db::Hasher;
+use mp_ethereum::keccak::KeccakHasher;
+use tracing::info;
+
+/// Schema change of the SQLite state database
+pub struct Migration {
+    /// Position of the migration, applied in increasing order
+    pub version: i64,
+    /// Short description recorded next to the version
+    pub name: &'static str,
+    /// SQL script run when the migration is applied
+    pub sql: &'static str,
+}
+
+/// Every known migration, in order
+///
+/// Applied scripts must never change, add a new migration instead.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial_schema",
+        sql: include_str!("migrations/01_initial_schema.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "transaction_history",
+        sql: include_str!("migrations/02_transaction_history.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "execution_records",
+        sql: include_str!("migrations/03_execution_records.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "empty_trie_root",
+        sql: include_str!("migrations/04_empty_trie_root.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "log_roots",
+        sql: include_str!("migrations/05_log_roots.sql"),
+    },
+];
+
+impl Migration {
+    /// Checksum of the script, detects edits to applied migrations
+    pub fn checksum(&self) -> String {
+        hex::encode(KeccakHasher::hash(self.sql.as_bytes()))
+    }
+}
+
+/// Latest schema version known to this build
+pub fn latest_version() -> i64 {
+    MIGRATIONS.last().map_or(0, |migration| migration.version)
+}
+
+#[derive(QueryableByName)]
+struct AppliedMigration {
+    #[diesel(sql_type = BigInt)]
+    version: i64,
+    #[diesel(sql_type = Text)]
+    checksum: String,
+}
+
+/// Bring the database schema up to the latest version
+///
+/// Fails without changing anything if the database has a newer schema than this
+/// build knows, or if an applied migration no longer matches its script.
+/// Databases created before versioning run the first migrations again, these
+/// only create what is missing.
+pub fn run(conn: &mut SqliteConnection) -> Result<()> {
+    conn.batch_execute(
+        "CREATE TABLE IF NOT EXISTS schema_version (
+             version INTEGER PRIMARY KEY,
+             name TEXT NOT NULL,
+             checksum TEXT NOT NULL,
+             applied_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+         )",
+    )?;
+
+    let applied =
+        diesel::sql_query("SELECT version, checksum FROM schema_version ORDER BY version")
+            .load::<AppliedMigration>(conn)?;
+
+    for migration in &applied {
+        match MIGRATIONS.iter().find(|m| m.version == migration.version) {
+            Some(known) if known.checksum() != migration.checksum => {
+                return Err(anyhow!(
+                    "Migration {} ({}) was changed after it was applied",
+                    known.version,
+                    known.name
+                ));
+            }
+            Some(_) => {}
+            None if migration.version > latest_version() => {
+                return Err(anyhow!(
+                    "Database schema version {} is newer than the latest known version {}, upgrade the node",
+                    migration.version,
+                    latest_version()
+                ));
+            }
+            None => {
+                return Err(anyhow!(
+                    "Database has unknown migration {}",
+                    migration.version
+                ));
+            }
+        }
+    }
+
+    for migration in MIGRATIONS
+        .iter()
+        .filter(|m| !applied.iter().any(|a| a.version == m.version))
+    {
+        conn.transaction::<_, anyhow::Error, _>(|tx| {
+            tx.batch_execute(migration.sql).with_context(|| {
+                format!(
+                    "Failed to apply migration {} ({})",
+                    migration.version, migration.name
+                )
+            })?;
+            diesel::sql_query(
+                "INSERT INTO schema_version (version, name, checksum) VALUES (?, ?, ?)",
+            )
+            .bind::<BigInt, _>(migration.version)
+            .bind::<Text, _>(migration.name)
+            .bind::<Text, _>(migration.checksum())
+            .execute(tx)?;
+            Ok(())
+        })?;
+
+        info!(
+            "Applied state database migration {} ({})",
+            migration.version, migration.name
+        );
+    }
+
+    Ok(())
+}
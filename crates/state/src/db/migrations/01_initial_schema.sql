@@ -44,3 +44,4 @@ CREATE TABLE IF NOT EXISTS state_operations (
 -- Create indexes
 CREATE INDEX IF NOT EXISTS idx_state_diffs_roots ON state_diffs(prev_root_hash, new_root_hash);
 CREATE INDEX IF NOT EXISTS idx_state_operations_diff_id ON state_operations(diff_id);
+CREATE INDEX IF NOT EXISTS idx_state_operations_key ON state_operations(key, diff_id);
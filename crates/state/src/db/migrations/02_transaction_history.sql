@@ -0,0 +1,21 @@
+-- Transaction history
+
+-- Applied transactions, seq orders them by the time they were recorded
+CREATE TABLE IF NOT EXISTS transactions (
+    seq INTEGER PRIMARY KEY AUTOINCREMENT,
+    id TEXT NOT NULL,
+    type TEXT NOT NULL,
+    contract TEXT, -- address of request transactions
+    payload BLOB NOT NULL,
+    timestamp TEXT NOT NULL, -- RFC 3339 in UTC with nanoseconds, sorts by time
+    sender TEXT,
+    log_index INTEGER NOT NULL,
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    UNIQUE(id)
+);
+
+-- Create indexes
+CREATE INDEX IF NOT EXISTS idx_transactions_sender ON transactions(sender, seq);
+CREATE INDEX IF NOT EXISTS idx_transactions_contract ON transactions(contract, sender, seq);
+CREATE INDEX IF NOT EXISTS idx_transactions_timestamp ON transactions(timestamp);
+CREATE INDEX IF NOT EXISTS idx_transactions_log_index ON transactions(log_index);
@@ -0,0 +1,12 @@
+-- Execution records
+
+-- Outcome of every executed request, replayed to check determinism
+CREATE TABLE IF NOT EXISTS executions (
+    tx_id TEXT PRIMARY KEY,
+    method TEXT NOT NULL,
+    headers TEXT NOT NULL, -- JSON array of name/value pairs
+    state_root TEXT NOT NULL,
+    output TEXT NOT NULL, -- JSON
+    poc_root TEXT NOT NULL,
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+);
@@ -0,0 +1,26 @@
+-- Empty trie root
+
+-- The state before any diff was recorded under an all-zero root, it is now the
+-- root of the empty trie. Only the most recent of the two rows is kept.
+DELETE FROM state_roots
+WHERE root_hash = '56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421'
+  AND id < (SELECT id FROM state_roots
+            WHERE root_hash = '0000000000000000000000000000000000000000000000000000000000000000');
+DELETE FROM state_roots
+WHERE root_hash = '0000000000000000000000000000000000000000000000000000000000000000'
+  AND EXISTS (SELECT 1 FROM state_roots
+              WHERE root_hash = '56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421');
+UPDATE state_roots
+SET root_hash = '56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421'
+WHERE root_hash = '0000000000000000000000000000000000000000000000000000000000000000';
+
+UPDATE state_diffs
+SET prev_root_hash = '56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421'
+WHERE prev_root_hash = '0000000000000000000000000000000000000000000000000000000000000000';
+UPDATE state_diffs
+SET new_root_hash = '56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421'
+WHERE new_root_hash = '0000000000000000000000000000000000000000000000000000000000000000';
+
+UPDATE executions
+SET state_root = '56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421'
+WHERE state_root = '0000000000000000000000000000000000000000000000000000000000000000';
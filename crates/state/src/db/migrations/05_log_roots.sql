@@ -0,0 +1,7 @@
+-- Log roots
+
+-- State root reached by the consensus log entry that last changed the state
+CREATE TABLE IF NOT EXISTS log_roots (
+    log_index INTEGER PRIMARY KEY,
+    root_hash TEXT NOT NULL
+);
@@ -0,0 +1,741 @@
+use crate::diff::{StateDiff, StateOperation, StateSnapshot};
+use ::sled::transaction::{ConflictableTransactionError, TransactionError, Transactional};
+use ::sled::{Db, IVec, Tree};
+use anyhow::{anyhow, Result};
+use ethereum_types::H128;
+use mp_common::types::Transaction;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tracing::info;
+use uuid::Uuid;
+
+use super::{commit_diff, commit_operations, with_trie};
+use crate::config::StateConfig;
+use crate::diff::StateDiffStorage;
+use crate::proof::{StateProof, StateTrie};
+use crate::transactions::{self, ExecutionRecord, TransactionPage, TransactionQuery};
+use crate::{StateStorage, EMPTY_STATE_ROOT};
+
+/// Key of the current state root in the metadata tree
+const CURRENT_ROOT: &[u8] = b"current_root";
+
+/// Key of the layout version in the metadata tree
+const SCHEMA_VERSION: &[u8] = b"schema_version";
+
+/// Layout of the trees written by this build, bump it when they change
+///
+/// Version 2 records the initial state under the empty trie root instead of [`ZERO_ROOT`].
+const LATEST_SCHEMA_VERSION: u64 = 2;
+
+/// Root the initial state was recorded under before schema version 2
+const ZERO_ROOT: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Sled-based state storage
+///
+/// Entries, roots, diffs and transactions each live in their own tree, sled's
+/// equivalent of column families. Diffs are applied atomically across trees.
+pub struct SledStateStorage {
+    config: StateConfig,
+    db: Db,
+    /// Key/value pairs of the current state
+    entries: Tree,
+    /// Every state root reached, mapped to the ID of the last diff applied at that point
+    roots: Tree,
+    /// Applied diffs keyed by their big-endian ID
+    diffs: Tree,
+    /// Every value a key took, keyed by the key, a separator and the big-endian diff ID
+    history: Tree,
+    /// Applied transactions keyed by their big-endian sequence number
+    transactions: Tree,
+    /// Sequence number of every applied transaction ID
+    transaction_ids: Tree,
+    /// Transaction index keyed by sender, a separator and the sequence number
+    transactions_by_sender: Tree,
+    /// Transaction index keyed by contract address and the sequence number
+    transactions_by_contract: Tree,
+    /// Execution records keyed by transaction ID
+    executions: Tree,
+    /// State root reached at each consensus log index that changed the state, keyed big-endian
+    log_roots: Tree,
+    /// Current state root
+    meta: Tree,
+    /// Trie of the current state, built on first use and shared with the clones
+    /// of the storage, its lock is held by every writer of the state
+    trie: Arc<Mutex<Option<StateTrie>>>,
+}
+
+impl SledStateStorage {
+    /// Create a new sled-based state storage, `db_connection` is the database directory
+    pub fn new(config: StateConfig) -> Result<Self> {
+        // Create state root directory if it doesn't exist
+        fs::create_dir_all(&config.state_root_path)?;
+
+        let db = open_db(&config.db_connection)?;
+        let storage = Self {
+            entries: db.open_tree("entries")?,
+            roots: db.open_tree("roots")?,
+            diffs: db.open_tree("diffs")?,
+            history: db.open_tree("history")?,
+            transactions: db.open_tree("transactions")?,
+            transaction_ids: db.open_tree("transaction_ids")?,
+            transactions_by_sender: db.open_tree("transactions_by_sender")?,
+            transactions_by_contract: db.open_tree("transactions_by_contract")?,
+            executions: db.open_tree("executions")?,
+            log_roots: db.open_tree("log_roots")?,
+            meta: db.open_tree("meta")?,
+            trie: Arc::new(Mutex::new(None)),
+            db,
+            config,
+        };
+
+        storage.initialize_database()?;
+        Ok(storage)
+    }
+
+    /// Record the initial empty state root if the database is new
+    fn initialize_database(&self) -> Result<()> {
+        // Refuse a layout written by a newer build, older ones are upgraded here
+        let version = match self.meta.get(SCHEMA_VERSION)? {
+            Some(version) => decode_id(&version)?,
+            None => 0,
+        };
+        if version > LATEST_SCHEMA_VERSION {
+            return Err(anyhow!(
+                "Database schema version {} is newer than the latest known version {}, upgrade the node",
+                version,
+                LATEST_SCHEMA_VERSION
+            ));
+        }
+        if version < 2 {
+            self.rename_zero_root()?;
+        }
+        if version < LATEST_SCHEMA_VERSION {
+            self.meta
+                .insert(SCHEMA_VERSION, &LATEST_SCHEMA_VERSION.to_be_bytes())?;
+        }
+
+        if self.meta.get(CURRENT_ROOT)?.is_none() {
+            (&self.roots, &self.meta)
+                .transaction(|(roots, meta)| {
+                    roots.insert(EMPTY_STATE_ROOT.as_bytes(), &0u64.to_be_bytes())?;
+                    meta.insert(CURRENT_ROOT, EMPTY_STATE_ROOT.as_bytes())?;
+                    Ok(())
+                })
+                .map_err(transaction_error)?;
+
+            info!("Created initial state root: {}", EMPTY_STATE_ROOT);
+        }
+        Ok(())
+    }
+
+    /// Record the initial state under the empty trie root instead of [`ZERO_ROOT`]
+    fn rename_zero_root(&self) -> Result<()> {
+        let Some(mut position) = self.roots.get(ZERO_ROOT.as_bytes())? else {
+            return Ok(());
+        };
+        // Keep the later position if the empty state was reached again since
+        if let Some(reached) = self.roots.get(EMPTY_STATE_ROOT.as_bytes())? {
+            if decode_id(&reached)? > decode_id(&position)? {
+                position = reached;
+            }
+        }
+        let rename = |root: &mut String| {
+            let renamed = root == ZERO_ROOT;
+            if renamed {
+                *root = EMPTY_STATE_ROOT.to_string();
+            }
+            renamed
+        };
+
+        let mut diffs = Vec::new();
+        for entry in self.diffs.iter() {
+            let (id, encoded) = entry?;
+            let mut diff: StateDiff = serde_json::from_slice(&encoded)?;
+            if rename(&mut diff.prev_root) | rename(&mut diff.new_root) {
+                diffs.push((id, serde_json::to_vec(&diff)?));
+            }
+        }
+        let mut executions = Vec::new();
+        for entry in self.executions.iter() {
+            let (tx_id, encoded) = entry?;
+            let mut record: ExecutionRecord = serde_json::from_slice(&encoded)?;
+            if rename(&mut record.state_root) {
+                executions.push((tx_id, serde_json::to_vec(&record)?));
+            }
+        }
+
+        (&self.roots, &self.diffs, &self.executions, &self.meta)
+            .transaction(|(roots, diffs_tree, executions_tree, meta)| {
+                roots.remove(ZERO_ROOT.as_bytes())?;
+                roots.insert(EMPTY_STATE_ROOT.as_bytes(), position.clone())?;
+                for (id, diff) in &diffs {
+                    diffs_tree.insert(id, diff.as_slice())?;
+                }
+                for (tx_id, record) in &executions {
+                    executions_tree.insert(tx_id, record.as_slice())?;
+                }
+                if meta.get(CURRENT_ROOT)?.as_deref() == Some(ZERO_ROOT.as_bytes()) {
+                    meta.insert(CURRENT_ROOT, EMPTY_STATE_ROOT.as_bytes())?;
+                }
+                Ok(())
+            })
+            .map_err(transaction_error)?;
+
+        info!("Renamed the initial state root to {}", EMPTY_STATE_ROOT);
+        Ok(())
+    }
+
+    /// Get the current state root
+    fn current_root(&self) -> Result<String> {
+        let root = self
+            .meta
+            .get(CURRENT_ROOT)?
+            .ok_or_else(|| anyhow!("State root is missing"))?;
+        Ok(String::from_utf8(root.to_vec())?)
+    }
+
+    /// Load all state entries, ordered by key
+    fn load_entries(&self) -> Result<Vec<(String, String)>> {
+        self.entries
+            .iter()
+            .map(|entry| {
+                let (key, value) = entry?;
+                Ok((
+                    String::from_utf8(key.to_vec())?,
+                    String::from_utf8(value.to_vec())?,
+                ))
+            })
+            .collect()
+    }
+
+    /// Get the ID of the last diff that led to `root`, 0 for the initial empty state
+    fn root_position(&self, root: &str) -> Result<[u8; 8]> {
+        let id = self
+            .roots
+            .get(root.as_bytes())?
+            .ok_or_else(|| anyhow!("State root {} is not part of the history", root))?;
+        Ok(decode_id(&id)?.to_be_bytes())
+    }
+
+    /// Run `f` on the trie of the current state, holding its lock
+    fn with_trie<T>(&self, f: impl FnOnce(&mut StateTrie) -> Result<T>) -> Result<T> {
+        with_trie(&self.trie, || self.load_entries(), f)
+    }
+
+    /// Load the transaction recorded with the given sequence number
+    fn load_transaction(&self, seq: &[u8]) -> Result<Option<Transaction>> {
+        self.transactions
+            .get(seq)?
+            .map(|encoded| Ok(serde_json::from_slice(&encoded)?))
+            .transpose()
+    }
+
+    /// Record a diff and apply its operations, the caller holds the trie lock
+    fn write_diff(&self, diff: &StateDiff) -> Result<()> {
+        // IDs are monotonic, 0 is reserved for the initial state
+        let id = (self.db.generate_id()? + 1).to_be_bytes();
+        let encoded = serde_json::to_vec(diff)?;
+
+        (
+            &self.entries,
+            &self.roots,
+            &self.diffs,
+            &self.history,
+            &self.meta,
+        )
+            .transaction(|(entries, roots, diffs, history, meta)| {
+                if roots.get(diff.prev_root.as_bytes())?.is_none() {
+                    return Err(ConflictableTransactionError::Abort(format!(
+                        "Unknown previous state root {}",
+                        diff.prev_root
+                    )));
+                }
+
+                for op in &diff.operations {
+                    match op {
+                        StateOperation::Insert { key, value } => {
+                            entries.insert(key.as_bytes(), value.as_bytes())?;
+                            history.insert(history_key(key, &id), encode_history(Some(value)))?;
+                        }
+                        StateOperation::Delete { key } => {
+                            entries.remove(key.as_bytes())?;
+                            history.insert(history_key(key, &id), encode_history(None))?;
+                        }
+                    }
+                }
+
+                diffs.insert(&id, encoded.as_slice())?;
+                roots.insert(diff.new_root.as_bytes(), &id)?;
+                meta.insert(CURRENT_ROOT, diff.new_root.as_bytes())?;
+                Ok(())
+            })
+            .map_err(transaction_error)
+    }
+}
+
+impl StateDiffStorage for SledStateStorage {
+    fn apply_diff(&self, diff: &StateDiff) -> Result<()> {
+        self.with_trie(|trie| commit_diff(trie, diff, |diff| self.write_diff(diff)))
+    }
+
+    fn apply_operations(&self, operations: Vec<StateOperation>) -> Result<StateDiff> {
+        self.with_trie(|trie| commit_operations(trie, operations, |diff| self.write_diff(diff)))
+    }
+
+    fn create_checkpoint(&self) -> Result<StateDiff> {
+        let root_hash = self.current_root()?;
+
+        // Create an empty diff checkpoint with the current root
+        Ok(StateDiff {
+            prev_root: root_hash.clone(),
+            new_root: root_hash,
+            operations: Vec::new(),
+        })
+    }
+
+    fn export_snapshot(&self) -> Result<StateSnapshot> {
+        // Sled has no read snapshots, retry until no diff landed during the export
+        loop {
+            let root = self.current_root()?;
+            let entries = self.load_entries()?;
+            if self.current_root()? == root {
+                return Ok(StateSnapshot { root, entries });
+            }
+        }
+    }
+
+    fn import_snapshot(&self, snapshot: &StateSnapshot) -> Result<()> {
+        let diff = self.with_trie(|trie| {
+            let diff = StateDiff::between(&self.export_snapshot()?, snapshot);
+            commit_diff(trie, &diff, |diff| self.write_diff(diff))?;
+            Ok(diff)
+        })?;
+
+        info!(
+            "Imported state snapshot with {} entries at root {} ({} changes)",
+            snapshot.entries.len(),
+            snapshot.root,
+            diff.len()
+        );
+        Ok(())
+    }
+
+    fn revert_to(&self, root: &str) -> Result<()> {
+        let mut trie = self.trie.lock().unwrap();
+        if self.current_root()? == root {
+            return Ok(());
+        }
+        let position = self.root_position(root)?;
+        let first_undone = (u64::from_be_bytes(position) + 1).to_be_bytes();
+
+        // Collect the undone diffs, the keys they touched and the roots they led to
+        let mut undone = Vec::new();
+        let mut keys = BTreeSet::new();
+        let mut lost_roots = BTreeSet::new();
+        for entry in self.diffs.range(first_undone..) {
+            let (id, diff) = entry?;
+            let diff: StateDiff = serde_json::from_slice(&diff)?;
+            for op in &diff.operations {
+                match op {
+                    StateOperation::Insert { key, .. } | StateOperation::Delete { key } => {
+                        keys.insert(key.clone());
+                    }
+                }
+            }
+            lost_roots.insert(diff.new_root);
+            undone.push(id);
+        }
+
+        // Restore every touched key to its last value up to `root`
+        let mut restored = Vec::with_capacity(keys.len());
+        let mut history_entries = Vec::new();
+        for key in keys {
+            let mut value = None;
+            for entry in self
+                .history
+                .range(history_key(&key, &[0; 8])..=history_key(&key, &[0xff; 8]))
+            {
+                let (history_key, history_value) = entry?;
+                if history_key[history_key.len() - 8..] > position[..] {
+                    history_entries.push(history_key);
+                } else {
+                    value = decode_history(&history_value)?;
+                }
+            }
+            restored.push((key, value));
+        }
+
+        // Roots that were also reached before the undone diffs keep their last position
+        let mut root_positions = Vec::new();
+        if !lost_roots.is_empty() {
+            for entry in self.diffs.range(..first_undone).rev() {
+                let (id, diff) = entry?;
+                let diff: StateDiff = serde_json::from_slice(&diff)?;
+                if lost_roots.remove(&diff.new_root) {
+                    root_positions.push((diff.new_root, id));
+                }
+                if lost_roots.is_empty() {
+                    break;
+                }
+            }
+        }
+        if lost_roots.remove(EMPTY_STATE_ROOT) {
+            root_positions.push((EMPTY_STATE_ROOT.to_string(), IVec::from(&[0; 8])));
+        }
+
+        (
+            &self.entries,
+            &self.roots,
+            &self.diffs,
+            &self.history,
+            &self.meta,
+        )
+            .transaction(|(entries, roots, diffs, history, meta)| {
+                for (key, value) in &restored {
+                    match value {
+                        Some(value) => entries.insert(key.as_bytes(), value.as_bytes())?,
+                        None => entries.remove(key.as_bytes())?,
+                    };
+                }
+                for history_key in &history_entries {
+                    history.remove(history_key)?;
+                }
+                for id in &undone {
+                    diffs.remove(id)?;
+                }
+                for lost_root in &lost_roots {
+                    roots.remove(lost_root.as_bytes())?;
+                }
+                for (reached_root, id) in &root_positions {
+                    roots.insert(reached_root.as_bytes(), id.clone())?;
+                }
+                meta.insert(CURRENT_ROOT, root.as_bytes())?;
+                Ok(())
+            })
+            .map_err(transaction_error)?;
+        // Rebuilt from the restored entries on next use
+        *trie = None;
+
+        info!(
+            "Reverted {} state diffs back to root {}",
+            undone.len(),
+            root
+        );
+        Ok(())
+    }
+
+    fn diffs_since(&self, root: &str, limit: usize) -> Result<Option<Vec<StateDiff>>> {
+        if self.current_root()? == root {
+            return Ok(Some(Vec::new()));
+        }
+
+        let reached_at = match self.roots.get(root.as_bytes())? {
+            Some(id) => decode_id(&id)?,
+            None => return Ok(None),
+        };
+
+        self.diffs
+            .range((reached_at + 1).to_be_bytes()..)
+            .take(limit)
+            .map(|entry| {
+                let (_, diff) = entry?;
+                Ok(serde_json::from_slice(&diff)?)
+            })
+            .collect::<Result<Vec<_>>>()
+            .map(Some)
+    }
+}
+
+impl StateStorage for SledStateStorage {
+    fn start(&self) -> Result<()> {
+        info!("Starting sled state storage");
+        self.initialize_database()
+    }
+
+    fn stop(&self) -> Result<()> {
+        info!("Stopping sled state storage");
+        self.db.flush()?;
+        Ok(())
+    }
+
+    fn apply_transaction(&self, transaction: Transaction) -> Result<()> {
+        info!("Applying transaction: {:?}", transaction.id);
+
+        // Store the transaction and its index entries, replays of a recorded one are ignored
+        let id = transaction.id.to_string();
+        let seq = (self.db.generate_id()? + 1).to_be_bytes();
+        let encoded = serde_json::to_vec(&transaction)?;
+        (
+            &self.transactions,
+            &self.transaction_ids,
+            &self.transactions_by_sender,
+            &self.transactions_by_contract,
+        )
+            .transaction(|(by_seq, ids, by_sender, by_contract)| {
+                if ids.get(id.as_bytes())?.is_some() {
+                    return Ok(());
+                }
+                by_seq.insert(&seq, encoded.as_slice())?;
+                ids.insert(id.as_bytes(), &seq)?;
+                if let Some(sender) = &transaction.sender {
+                    by_sender.insert(sender_index_key(sender, &seq), &[])?;
+                }
+                if let Some(contract) = transactions::contract_of(&transaction) {
+                    by_contract.insert(contract_index_key(&contract, &seq), &[])?;
+                }
+                Ok(())
+            })
+            .map_err(transaction_error)?;
+
+        // Update the state root
+        self.with_trie(|trie| {
+            let state_root_path = Path::new(&self.config.state_root_path).join("state_root");
+            fs::write(state_root_path, trie.root().as_bytes())?;
+            Ok(())
+        })
+    }
+
+    fn get_state_root(&self) -> Result<String> {
+        self.with_trie(|trie| Ok(trie.root()))
+    }
+
+    fn get_proof(&self, key: &str) -> Result<StateProof> {
+        self.with_trie(|trie| trie.proof(key))
+    }
+
+    fn get_at(&self, key: &str, root: &str) -> Result<Option<String>> {
+        if self.current_root()? == root {
+            return self
+                .entries
+                .get(key.as_bytes())?
+                .map(|value| Ok(String::from_utf8(value.to_vec())?))
+                .transpose();
+        }
+
+        // The latest value the key took up to `root`
+        let position = self.root_position(root)?;
+        match self
+            .history
+            .range(history_key(key, &[0; 8])..=history_key(key, &position))
+            .next_back()
+        {
+            Some(entry) => decode_history(&entry?.1),
+            None => Ok(None),
+        }
+    }
+
+    fn iter_prefix_at(&self, prefix: &str, root: &str) -> Result<Vec<(String, String)>> {
+        if self.current_root()? == root {
+            return self
+                .entries
+                .scan_prefix(prefix.as_bytes())
+                .map(|entry| {
+                    let (key, value) = entry?;
+                    Ok((
+                        String::from_utf8(key.to_vec())?,
+                        String::from_utf8(value.to_vec())?,
+                    ))
+                })
+                .collect();
+        }
+
+        // History entries are grouped by key, keep the last one up to `root` for each
+        let position = self.root_position(root)?;
+        let mut latest: Vec<(String, Option<String>)> = Vec::new();
+        for entry in self.history.scan_prefix(prefix.as_bytes()) {
+            let (history_key, value) = entry?;
+            let (key, id) = history_key.split_at(history_key.len() - HISTORY_SUFFIX_LENGTH);
+            if id[1..] > position[..] {
+                continue;
+            }
+
+            let key = String::from_utf8(key.to_vec())?;
+            let value = decode_history(&value)?;
+            match latest.last_mut() {
+                Some((last, last_value)) if *last == key => *last_value = value,
+                _ => latest.push((key, value)),
+            }
+        }
+
+        // A key sorts after the longer keys it prefixes once the separator is appended
+        let mut entries: Vec<(String, String)> = latest
+            .into_iter()
+            .filter_map(|(key, value)| value.map(|value| (key, value)))
+            .collect();
+        entries.sort();
+        Ok(entries)
+    }
+
+    fn get_transaction(&self, id: &Uuid) -> Result<Option<Transaction>> {
+        match self.transaction_ids.get(id.to_string().as_bytes())? {
+            Some(seq) => self.load_transaction(&seq),
+            None => Ok(None),
+        }
+    }
+
+    fn record_execution(&self, record: &ExecutionRecord) -> Result<()> {
+        self.executions.insert(
+            record.tx_id.to_string().as_bytes(),
+            serde_json::to_vec(record)?,
+        )?;
+        Ok(())
+    }
+
+    fn get_execution(&self, tx_id: &Uuid) -> Result<Option<ExecutionRecord>> {
+        self.executions
+            .get(tx_id.to_string().as_bytes())?
+            .map(|record| Ok(serde_json::from_slice(&record)?))
+            .transpose()
+    }
+
+    fn record_log_root(&self, log_index: u64, root: &str) -> Result<()> {
+        self.log_roots
+            .insert(log_index.to_be_bytes(), root.as_bytes())?;
+        Ok(())
+    }
+
+    fn root_at_log_index(&self, log_index: u64) -> Result<Option<String>> {
+        self.log_roots
+            .range(..=log_index.to_be_bytes())
+            .next_back()
+            .transpose()?
+            .map(|(_, root)| Ok(String::from_utf8(root.to_vec())?))
+            .transpose()
+    }
+
+    fn query_transactions(&self, query: &TransactionQuery) -> Result<TransactionPage> {
+        let before = query.cursor.unwrap_or(u64::MAX).to_be_bytes();
+
+        // Walk the narrowest index backwards from the cursor, the other filters are
+        // checked against each transaction
+        let sequence: Box<dyn DoubleEndedIterator<Item = ::sled::Result<(IVec, IVec)>>> =
+            match (&query.contract, &query.sender) {
+                (Some(contract), _) => Box::new(self.transactions_by_contract.range(
+                    contract_index_key(contract, &[0; 8])..contract_index_key(contract, &before),
+                )),
+                (None, Some(sender)) => {
+                    Box::new(self.transactions_by_sender.range(
+                        sender_index_key(sender, &[0; 8])..sender_index_key(sender, &before),
+                    ))
+                }
+                (None, None) => Box::new(self.transactions.range(..before)),
+            };
+
+        let mut page = TransactionPage::default();
+        let mut last_seq = None;
+        for entry in sequence.rev() {
+            let (key, _) = entry?;
+            let seq = &key[key.len() - 8..];
+            let transaction = self
+                .load_transaction(seq)?
+                .ok_or_else(|| anyhow!("Missing indexed transaction {:?}", seq))?;
+            if !query.matches(&transaction) {
+                continue;
+            }
+
+            // A match beyond the limit means another page follows
+            if page.transactions.len() == query.limit {
+                page.next_cursor = last_seq;
+                break;
+            }
+            last_seq = Some(decode_id(seq)?);
+            page.transactions.push(transaction);
+        }
+        Ok(page)
+    }
+
+    fn clone(&self) -> Arc<dyn StateStorage> {
+        Arc::new(SledStateStorage {
+            config: self.config.clone(),
+            db: self.db.clone(),
+            entries: self.entries.clone(),
+            roots: self.roots.clone(),
+            diffs: self.diffs.clone(),
+            history: self.history.clone(),
+            transactions: self.transactions.clone(),
+            transaction_ids: self.transaction_ids.clone(),
+            transactions_by_sender: self.transactions_by_sender.clone(),
+            transactions_by_contract: self.transactions_by_contract.clone(),
+            executions: self.executions.clone(),
+            log_roots: self.log_roots.clone(),
+            meta: self.meta.clone(),
+            trie: self.trie.clone(),
+        })
+    }
+}
+
+/// Separator between a key and a big-endian ID in history and index keys, never part of UTF-8 text
+const HISTORY_SEPARATOR: u8 = 0xff;
+
+/// Length of the separator and diff ID ending a history key
+const HISTORY_SUFFIX_LENGTH: usize = 9;
+
+fn history_key(key: &str, id: &[u8; 8]) -> Vec<u8> {
+    let mut history_key = Vec::with_capacity(key.len() + HISTORY_SUFFIX_LENGTH);
+    history_key.extend_from_slice(key.as_bytes());
+    history_key.push(HISTORY_SEPARATOR);
+    history_key.extend_from_slice(id);
+    history_key
+}
+
+fn encode_history(value: Option<&String>) -> Vec<u8> {
+    match value {
+        Some(value) => [&[1], value.as_bytes()].concat(),
+        None => vec![0],
+    }
+}
+
+fn decode_history(value: &[u8]) -> Result<Option<String>> {
+    match value.split_first() {
+        Some((1, value)) => Ok(Some(String::from_utf8(value.to_vec())?)),
+        Some((0, [])) => Ok(None),
+        _ => Err(anyhow!("Invalid history entry")),
+    }
+}
+
+fn sender_index_key(sender: &str, seq: &[u8; 8]) -> Vec<u8> {
+    [sender.as_bytes(), &[HISTORY_SEPARATOR], seq].concat()
+}
+
+fn contract_index_key(contract: &H128, seq: &[u8; 8]) -> Vec<u8> {
+    [contract.as_bytes(), seq].concat()
+}
+
+fn decode_id(id: &[u8]) -> Result<u64> {
+    let id: [u8; 8] = id
+        .try_into()
+        .map_err(|_| anyhow!("Invalid ID of {} bytes", id.len()))?;
+    Ok(u64::from_be_bytes(id))
+}
+
+/// Open the database, waiting for a just dropped instance to release its lock
+///
+/// Sled's background IO threads can hold the file lock for a moment after the
+/// last handle is gone, which makes an immediate reopen in the same process fail.
+fn open_db(path: &str) -> Result<Db> {
+    for _ in 0..OPEN_RETRIES {
+        match ::sled::open(path) {
+            // Sled reports the lock as a generic IO error
+            Err(::sled::Error::Io(e)) if e.to_string().contains("could not acquire lock") => {
+                std::thread::sleep(OPEN_RETRY_DELAY)
+            }
+            result => return Ok(result?),
+        }
+    }
+    Ok(::sled::open(path)?)
+}
+
+/// Attempts at opening a locked database before giving up
+const OPEN_RETRIES: usize = 50;
+
+/// Pause between attempts at opening a locked database
+const OPEN_RETRY_DELAY: std::time::Duration = std::time::Duration::from_millis(20);
+
+fn transaction_error(e: TransactionError<String>) -> anyhow::Error {
+    match e {
+        TransactionError::Abort(e) => anyhow!(e),
+        TransactionError::Storage(e) => e.into(),
+    }
+}
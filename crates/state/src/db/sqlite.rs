@@ -1,25 +1,43 @@
-use crate::diff::{StateDiff, StateOperation};
-use anyhow::{Context, Result};
+use crate::diff::{StateDiff, StateOperation, StateSnapshot};
+use anyhow::Result;
+use diesel::connection::SimpleConnection;
 use diesel::prelude::*;
-use diesel::r2d2::{self, ConnectionManager, Pool};
-use diesel::sql_types::{BigInt, Bool, Text};
+use diesel::r2d2::{self, ConnectionManager, CustomizeConnection, Pool};
+use diesel::sql_types::{BigInt, Binary, Bool, Nullable, Text};
 use diesel::sqlite::SqliteConnection;
 use diesel::QueryableByName;
 use mp_common::types::Transaction;
 use std::fs;
 use std::path::Path;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 use tracing::info;
+use uuid::Uuid;
 
+use super::{commit_diff, commit_operations, migrations, with_trie};
 use crate::config::StateConfig;
 use crate::diff::StateDiffStorage;
-use crate::StateStorage;
+use crate::proof::{StateProof, StateTrie};
+use crate::transactions::{self, ExecutionRecord, TransactionPage, TransactionQuery};
+use crate::{StateStorage, EMPTY_STATE_ROOT};
 
 /// SQLite-based state storage
 pub struct SqliteStateStorage {
     config: StateConfig,
     connection_pool: Pool<ConnectionManager<SqliteConnection>>,
-    state_mutex: Mutex<()>,
+    /// Trie of the current state, built on first use and shared with the clones
+    /// of the storage, its lock is held by every writer of the state
+    trie: Arc<Mutex<Option<StateTrie>>>,
+}
+
+/// Waits for the lock held by another pooled connection instead of failing
+#[derive(Debug)]
+struct BusyTimeout;
+
+impl CustomizeConnection<SqliteConnection, r2d2::Error> for BusyTimeout {
+    fn on_acquire(&self, conn: &mut SqliteConnection) -> std::result::Result<(), r2d2::Error> {
+        conn.batch_execute("PRAGMA busy_timeout = 5000")
+            .map_err(r2d2::Error::QueryError)
+    }
 }
 
 /// SQLite database schema models
@@ -87,13 +105,16 @@ impl SqliteStateStorage {
 
         // Set up connection pool
         let manager = ConnectionManager::<SqliteConnection>::new(&config.db_connection);
-        let pool = r2d2::Pool::builder().max_size(5).build(manager)?;
+        let pool = r2d2::Pool::builder()
+            .max_size(5)
+            .connection_customizer(Box::new(BusyTimeout))
+            .build(manager)?;
 
         // Create a simple state storage
         let storage = Self {
             config,
             connection_pool: pool,
-            state_mutex: Mutex::new(()),
+            trie: Arc::new(Mutex::new(None)),
         };
 
         // Initialize the database
@@ -107,15 +128,8 @@ impl SqliteStateStorage {
         // Get a connection from the pool
         let mut conn = self.connection_pool.get()?;
 
-        // Load and execute the initial schema SQL script
-        let schema_sql = include_str!("migrations/01_initial_schema.sql");
-
-        // Split the SQL statements and execute them one by one
-        for statement in schema_sql.split(';').filter(|s| !s.trim().is_empty()) {
-            diesel::sql_query(statement)
-                .execute(&mut conn)
-                .with_context(|| format!("Failed to execute SQL: {}", statement))?;
-        }
+        // Bring the schema up to date
+        migrations::run(&mut conn)?;
 
         // Create initial state root if none exists
         #[derive(QueryableByName, Debug)]
@@ -132,53 +146,47 @@ impl SqliteStateStorage {
 
         if !root_exists {
             // Create an initial empty state root
-            let initial_root = "0000000000000000000000000000000000000000000000000000000000000000";
             diesel::sql_query(
                 "INSERT INTO state_roots (root_hash, transaction_hash) 
                  VALUES (?, NULL)",
             )
-            .bind::<Text, _>(initial_root)
+            .bind::<Text, _>(EMPTY_STATE_ROOT)
             .execute(&mut conn)?;
 
-            info!("Created initial state root: {}", initial_root);
+            info!("Created initial state root: {}", EMPTY_STATE_ROOT);
         }
 
         Ok(())
     }
 
-    /// Calculate a simple state root hash
-    fn calculate_state_root(&self) -> Result<String> {
-        // Get a connection from the pool
+    /// Load all state entries, ordered by key
+    fn load_entries(&self) -> Result<Vec<(String, String)>> {
         let mut conn = self.connection_pool.get()?;
 
-        // This is a simplified implementation
-        // In a real implementation, we would calculate a Merkle root of the state
-
-        // Define a simple struct to hold our query results
         #[derive(QueryableByName)]
-        struct KeyRow {
-            #[diesel(sql_type = diesel::sql_types::Text)]
+        struct EntryRow {
+            #[diesel(sql_type = Text)]
             key: String,
+            #[diesel(sql_type = Text)]
+            value: String,
         }
 
-        // Get all state keys
-        let results =
-            diesel::sql_query("SELECT key FROM state ORDER BY key").load::<KeyRow>(&mut conn)?;
-
-        // Extract keys from the results
-        let keys: Vec<String> = results.into_iter().map(|row| row.key).collect();
-
-        // Calculate a simple hash
-        let keys_str = keys.join(",");
-        let hash = mp_common::utils::calculate_hash(keys_str.as_bytes());
+        Ok(
+            diesel::sql_query("SELECT key, value FROM state_entries ORDER BY key")
+                .load::<EntryRow>(&mut conn)?
+                .into_iter()
+                .map(|row| (row.key, row.value))
+                .collect(),
+        )
+    }
 
-        Ok(hash)
+    /// Run `f` on the trie of the current state, holding its lock
+    fn with_trie<T>(&self, f: impl FnOnce(&mut StateTrie) -> Result<T>) -> Result<T> {
+        with_trie(&self.trie, || self.load_entries(), f)
     }
-}
 
-// Implement StateDiffStorage for SqliteStateStorage
-impl StateDiffStorage for SqliteStateStorage {
-    fn apply_diff(&self, diff: &StateDiff) -> Result<()> {
+    /// Record a diff and apply its operations, the caller holds the trie lock
+    fn write_diff(&self, diff: &StateDiff) -> Result<()> {
         let mut conn = match self.connection_pool.get() {
             Ok(conn) => conn,
             Err(e) => {
@@ -208,23 +216,8 @@ impl StateDiffStorage for SqliteStateStorage {
                     return Err(diesel::result::Error::NotFound.into());
                 }
                 
-                // Insert new root if it doesn't exist
-                let new_root_exists = diesel::sql_query(
-                    "SELECT EXISTS(SELECT 1 FROM state_roots WHERE root_hash = ? LIMIT 1) as exists_flag"
-                )
-                .bind::<Text, _>(&diff.new_root)
-                .get_result::<ExistsFlag>(tx)
-                .map(|flag| flag.exists_flag)
-                .unwrap_or(false);
-                
-                if !new_root_exists {
-                    diesel::sql_query(
-                        "INSERT INTO state_roots (root_hash, transaction_hash) 
-                        VALUES (?, NULL)"
-                    )
-                    .bind::<Text, _>(&diff.new_root)
-                    .execute(tx)?;
-                }
+                // Record the new root as the latest one, even if the state was there before
+                set_current_root(tx, &diff.new_root)?;
                 
                 // Insert the diff record
                 let diff_id = diesel::sql_query(
@@ -282,15 +275,23 @@ impl StateDiffStorage for SqliteStateStorage {
                 Ok(())
             })
     }
+}
+
+// Implement StateDiffStorage for SqliteStateStorage
+impl StateDiffStorage for SqliteStateStorage {
+    fn apply_diff(&self, diff: &StateDiff) -> Result<()> {
+        self.with_trie(|trie| commit_diff(trie, diff, |diff| self.write_diff(diff)))
+    }
+
+    fn apply_operations(&self, operations: Vec<StateOperation>) -> Result<StateDiff> {
+        self.with_trie(|trie| commit_operations(trie, operations, |diff| self.write_diff(diff)))
+    }
 
     fn create_checkpoint(&self) -> Result<StateDiff> {
         let mut conn = self.connection_pool.get()?;
 
         // Get the current state root
-        let root_hash =
-            diesel::sql_query("SELECT root_hash FROM state_roots ORDER BY id DESC LIMIT 1")
-                .get_result::<schema::StringResult>(&mut conn)?
-                .root_hash;
+        let root_hash = current_root(&mut conn)?;
 
         // Create an empty diff checkpoint with the current root
         Ok(StateDiff {
@@ -299,6 +300,327 @@ impl StateDiffStorage for SqliteStateStorage {
             operations: Vec::new(),
         })
     }
+
+    fn export_snapshot(&self) -> Result<StateSnapshot> {
+        let mut conn = self.connection_pool.get()?;
+
+        #[derive(QueryableByName)]
+        struct EntryRow {
+            #[diesel(sql_type = Text)]
+            key: String,
+            #[diesel(sql_type = Text)]
+            value: String,
+        }
+
+        // Read the root and the entries in one transaction so they match
+        conn.transaction::<_, anyhow::Error, _>(|tx| {
+            let root = current_root(tx)?;
+            let entries = diesel::sql_query("SELECT key, value FROM state_entries ORDER BY key")
+                .load::<EntryRow>(tx)?
+                .into_iter()
+                .map(|row| (row.key, row.value))
+                .collect();
+
+            Ok(StateSnapshot { root, entries })
+        })
+    }
+
+    fn import_snapshot(&self, snapshot: &StateSnapshot) -> Result<()> {
+        let diff = self.with_trie(|trie| {
+            let diff = StateDiff::between(&self.export_snapshot()?, snapshot);
+            commit_diff(trie, &diff, |diff| self.write_diff(diff))?;
+            Ok(diff)
+        })?;
+
+        info!(
+            "Imported state snapshot with {} entries at root {} ({} changes)",
+            snapshot.entries.len(),
+            snapshot.root,
+            diff.len()
+        );
+        Ok(())
+    }
+
+    fn revert_to(&self, root: &str) -> Result<()> {
+        let mut conn = self.connection_pool.get()?;
+        let mut trie = self.trie.lock().unwrap();
+
+        #[derive(QueryableByName)]
+        struct KeyRow {
+            #[diesel(sql_type = Text)]
+            key: String,
+        }
+
+        #[derive(QueryableByName)]
+        struct ValueRow {
+            #[diesel(sql_type = Nullable<Text>)]
+            value: Option<String>,
+        }
+
+        let reverted = conn.transaction::<_, anyhow::Error, _>(|tx| {
+            if current_root(tx)? == root {
+                return Ok(0);
+            }
+            let position = root_position(tx, root)?;
+
+            // Restore every key touched since `root` to its last value up to it
+            let keys =
+                diesel::sql_query("SELECT DISTINCT key FROM state_operations WHERE diff_id > ?")
+                    .bind::<BigInt, _>(position)
+                    .load::<KeyRow>(tx)?;
+            for KeyRow { key } in keys {
+                let value = diesel::sql_query(
+                    "SELECT value FROM state_operations WHERE key = ? AND diff_id <= ?
+                     ORDER BY diff_id DESC, id DESC LIMIT 1",
+                )
+                .bind::<Text, _>(&key)
+                .bind::<BigInt, _>(position)
+                .get_result::<ValueRow>(tx)
+                .optional()?
+                .and_then(|row| row.value);
+
+                match value {
+                    Some(value) => {
+                        diesel::sql_query(
+                            "INSERT OR REPLACE INTO state_entries (key, value) VALUES (?, ?)",
+                        )
+                        .bind::<Text, _>(&key)
+                        .bind::<Text, _>(value)
+                        .execute(tx)?;
+                    }
+                    None => {
+                        diesel::sql_query("DELETE FROM state_entries WHERE key = ?")
+                            .bind::<Text, _>(&key)
+                            .execute(tx)?;
+                    }
+                }
+            }
+
+            // Forget the undone diffs and the roots only they led to
+            diesel::sql_query("DELETE FROM state_operations WHERE diff_id > ?")
+                .bind::<BigInt, _>(position)
+                .execute(tx)?;
+            let reverted = diesel::sql_query("DELETE FROM state_diffs WHERE id > ?")
+                .bind::<BigInt, _>(position)
+                .execute(tx)?;
+            diesel::sql_query(
+                "DELETE FROM state_roots WHERE root_hash != ?
+                 AND root_hash NOT IN (SELECT new_root_hash FROM state_diffs)",
+            )
+            .bind::<Text, _>(EMPTY_STATE_ROOT)
+            .execute(tx)?;
+            set_current_root(tx, root)?;
+
+            Ok(reverted)
+        })?;
+        // Rebuilt from the restored entries on next use
+        *trie = None;
+
+        info!("Reverted {} state diffs back to root {}", reverted, root);
+        Ok(())
+    }
+
+    fn diffs_since(&self, root: &str, limit: usize) -> Result<Option<Vec<StateDiff>>> {
+        let mut conn = self.connection_pool.get()?;
+
+        #[derive(QueryableByName)]
+        struct MaybeId {
+            #[diesel(sql_type = Nullable<BigInt>)]
+            id: Option<i64>,
+        }
+
+        #[derive(QueryableByName)]
+        struct DiffRow {
+            #[diesel(sql_type = BigInt)]
+            id: i64,
+            #[diesel(sql_type = Text)]
+            prev_root_hash: String,
+            #[diesel(sql_type = Text)]
+            new_root_hash: String,
+        }
+
+        #[derive(QueryableByName)]
+        struct OperationRow {
+            #[diesel(sql_type = Text)]
+            operation_type: String,
+            #[diesel(sql_type = Text)]
+            key: String,
+            #[diesel(sql_type = Nullable<Text>)]
+            value: Option<String>,
+        }
+
+        conn.transaction::<_, anyhow::Error, _>(|tx| {
+            if current_root(tx)? == root {
+                return Ok(Some(Vec::new()));
+            }
+
+            // Continue after the last time the state reached `root`, or from the first
+            // diff leaving it if `root` was imported rather than reached through a diff
+            let reached =
+                diesel::sql_query("SELECT MAX(id) AS id FROM state_diffs WHERE new_root_hash = ?")
+                    .bind::<Text, _>(root)
+                    .get_result::<MaybeId>(tx)?
+                    .id;
+            let first_id = match reached {
+                Some(id) => id + 1,
+                None => match diesel::sql_query(
+                    "SELECT MIN(id) AS id FROM state_diffs WHERE prev_root_hash = ?",
+                )
+                .bind::<Text, _>(root)
+                .get_result::<MaybeId>(tx)?
+                .id
+                {
+                    Some(id) => id,
+                    None => return Ok(None),
+                },
+            };
+
+            let rows = diesel::sql_query(
+                "SELECT id, prev_root_hash, new_root_hash FROM state_diffs
+                 WHERE id >= ? ORDER BY id LIMIT ?",
+            )
+            .bind::<BigInt, _>(first_id)
+            .bind::<BigInt, _>(limit as i64)
+            .load::<DiffRow>(tx)?;
+
+            let mut diffs = Vec::with_capacity(rows.len());
+            for row in rows {
+                let operations = diesel::sql_query(
+                    "SELECT operation_type, key, value FROM state_operations
+                     WHERE diff_id = ? ORDER BY id",
+                )
+                .bind::<BigInt, _>(row.id)
+                .load::<OperationRow>(tx)?
+                .into_iter()
+                .map(|op| match (op.operation_type.as_str(), op.value) {
+                    ("insert", Some(value)) => Ok(StateOperation::Insert { key: op.key, value }),
+                    ("delete", _) => Ok(StateOperation::Delete { key: op.key }),
+                    (other, _) => Err(anyhow::anyhow!(
+                        "Invalid operation {} on key {} in diff {}",
+                        other,
+                        op.key,
+                        row.id
+                    )),
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+                diffs.push(StateDiff {
+                    prev_root: row.prev_root_hash,
+                    new_root: row.new_root_hash,
+                    operations,
+                });
+            }
+
+            Ok(Some(diffs))
+        })
+    }
+}
+
+/// Get the latest recorded state root
+fn current_root(conn: &mut SqliteConnection) -> Result<String> {
+    Ok(
+        diesel::sql_query("SELECT root_hash FROM state_roots ORDER BY id DESC LIMIT 1")
+            .get_result::<schema::StringResult>(conn)?
+            .root_hash,
+    )
+}
+
+/// Get the ID of the last diff that led to `root`, 0 for the initial empty state
+fn root_position(conn: &mut SqliteConnection, root: &str) -> Result<i64> {
+    #[derive(QueryableByName)]
+    struct MaybeId {
+        #[diesel(sql_type = Nullable<BigInt>)]
+        id: Option<i64>,
+    }
+
+    let reached =
+        diesel::sql_query("SELECT MAX(id) AS id FROM state_diffs WHERE new_root_hash = ?")
+            .bind::<Text, _>(root)
+            .get_result::<MaybeId>(conn)?
+            .id;
+    match reached {
+        Some(id) => Ok(id),
+        None if root == EMPTY_STATE_ROOT => Ok(0),
+        None => Err(anyhow::anyhow!(
+            "State root {} is not part of the history",
+            root
+        )),
+    }
+}
+
+/// Record `root` as the latest state root
+fn set_current_root(conn: &mut SqliteConnection, root: &str) -> QueryResult<()> {
+    diesel::sql_query("DELETE FROM state_roots WHERE root_hash = ?")
+        .bind::<Text, _>(root)
+        .execute(conn)?;
+    diesel::sql_query("INSERT INTO state_roots (root_hash, transaction_hash) VALUES (?, NULL)")
+        .bind::<Text, _>(root)
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Columns of a recorded transaction, completed with a filter and order by the caller
+const SELECT_TRANSACTIONS: &str =
+    "SELECT seq, id, type AS tx_type, payload, timestamp, sender, log_index FROM transactions";
+
+#[derive(QueryableByName)]
+struct TransactionRow {
+    #[diesel(sql_type = BigInt)]
+    seq: i64,
+    #[diesel(sql_type = Text)]
+    id: String,
+    #[diesel(sql_type = Text)]
+    tx_type: String,
+    #[diesel(sql_type = Binary)]
+    payload: Vec<u8>,
+    #[diesel(sql_type = Text)]
+    timestamp: String,
+    #[diesel(sql_type = Nullable<Text>)]
+    sender: Option<String>,
+    #[diesel(sql_type = BigInt)]
+    log_index: i64,
+}
+
+impl TransactionRow {
+    fn into_transaction(self) -> Result<Transaction> {
+        Ok(serde_json::from_value(serde_json::json!({
+            "id": self.id,
+            "tx_type": self.tx_type,
+            "payload": self.payload,
+            "timestamp": self.timestamp,
+            "sender": self.sender,
+            "log_index": self.log_index,
+        }))?)
+    }
+}
+
+#[derive(QueryableByName)]
+struct ExecutionRow {
+    #[diesel(sql_type = Text)]
+    tx_id: String,
+    #[diesel(sql_type = Text)]
+    method: String,
+    #[diesel(sql_type = Text)]
+    headers: String,
+    #[diesel(sql_type = Text)]
+    state_root: String,
+    #[diesel(sql_type = Text)]
+    output: String,
+    #[diesel(sql_type = Text)]
+    poc_root: String,
+}
+
+impl ExecutionRow {
+    fn into_record(self) -> Result<ExecutionRecord> {
+        Ok(ExecutionRecord {
+            tx_id: self.tx_id.parse()?,
+            method: self.method,
+            headers: serde_json::from_str(&self.headers)?,
+            state_root: self.state_root,
+            output: serde_json::from_str(&self.output)?,
+            poc_root: self.poc_root,
+        })
+    }
 }
 
 impl StateStorage for SqliteStateStorage {
@@ -319,39 +641,252 @@ impl StateStorage for SqliteStateStorage {
         // Get a connection from the pool
         let mut conn = self.connection_pool.get()?;
 
-        // Use a mutex to ensure only one transaction is processed at a time
-        let _lock = self.state_mutex.lock().unwrap();
+        // Hold the state lock so that the written root is the current one
+        self.with_trie(|trie| {
+            // Store the transaction, replays of a recorded one are ignored
+            let tx_type = serde_json::to_value(&transaction.tx_type)?;
+            diesel::sql_query(
+                "INSERT OR IGNORE INTO transactions
+                 (id, type, contract, payload, timestamp, sender, log_index)
+                 VALUES (?, ?, ?, ?, ?, ?, ?)",
+            )
+            .bind::<Text, _>(transaction.id.to_string())
+            .bind::<Text, _>(tx_type.as_str().unwrap_or_default())
+            .bind::<Nullable<Text>, _>(
+                transactions::contract_of(&transaction).map(|c| transactions::format_contract(&c)),
+            )
+            .bind::<Binary, _>(&transaction.payload)
+            .bind::<Text, _>(transactions::format_timestamp(&transaction.timestamp))
+            .bind::<Nullable<Text>, _>(&transaction.sender)
+            .bind::<BigInt, _>(transaction.log_index as i64)
+            .execute(&mut conn)?;
+
+            // Update the state root
+            let state_root_path = Path::new(&self.config.state_root_path).join("state_root");
+            fs::write(state_root_path, trie.root().as_bytes())?;
+            Ok(())
+        })
+    }
+
+    fn get_state_root(&self) -> Result<String> {
+        self.with_trie(|trie| Ok(trie.root()))
+    }
+
+    fn get_proof(&self, key: &str) -> Result<StateProof> {
+        self.with_trie(|trie| trie.proof(key))
+    }
+
+    fn get_at(&self, key: &str, root: &str) -> Result<Option<String>> {
+        let mut conn = self.connection_pool.get()?;
+
+        #[derive(QueryableByName)]
+        struct ValueRow {
+            #[diesel(sql_type = Nullable<Text>)]
+            value: Option<String>,
+        }
+
+        conn.transaction::<_, anyhow::Error, _>(|tx| {
+            if current_root(tx)? == root {
+                return Ok(
+                    diesel::sql_query("SELECT value FROM state_entries WHERE key = ?")
+                        .bind::<Text, _>(key)
+                        .get_result::<ValueRow>(tx)
+                        .optional()?
+                        .and_then(|row| row.value),
+                );
+            }
 
-        // Store the transaction
+            // The latest operation on the key up to `root` holds its value, deletes have none
+            let position = root_position(tx, root)?;
+            Ok(diesel::sql_query(
+                "SELECT value FROM state_operations WHERE key = ? AND diff_id <= ?
+                 ORDER BY diff_id DESC, id DESC LIMIT 1",
+            )
+            .bind::<Text, _>(key)
+            .bind::<BigInt, _>(position)
+            .get_result::<ValueRow>(tx)
+            .optional()?
+            .and_then(|row| row.value))
+        })
+    }
+
+    fn iter_prefix_at(&self, prefix: &str, root: &str) -> Result<Vec<(String, String)>> {
+        let mut conn = self.connection_pool.get()?;
+
+        #[derive(QueryableByName)]
+        struct EntryRow {
+            #[diesel(sql_type = Text)]
+            key: String,
+            #[diesel(sql_type = Nullable<Text>)]
+            value: Option<String>,
+        }
+
+        let prefix_length = prefix.chars().count() as i64;
+        let rows = conn.transaction::<_, anyhow::Error, _>(|tx| {
+            if current_root(tx)? == root {
+                return Ok(diesel::sql_query(
+                    "SELECT key, value FROM state_entries
+                     WHERE key >= ? AND substr(key, 1, ?) = ? ORDER BY key",
+                )
+                .bind::<Text, _>(prefix)
+                .bind::<BigInt, _>(prefix_length)
+                .bind::<Text, _>(prefix)
+                .load::<EntryRow>(tx)?);
+            }
+
+            let position = root_position(tx, root)?;
+            Ok(diesel::sql_query(
+                "SELECT o.key, o.value FROM state_operations o
+                 WHERE o.key >= ? AND substr(o.key, 1, ?) = ? AND o.id = (
+                     SELECT MAX(id) FROM state_operations WHERE key = o.key AND diff_id <= ?
+                 )
+                 ORDER BY o.key",
+            )
+            .bind::<Text, _>(prefix)
+            .bind::<BigInt, _>(prefix_length)
+            .bind::<Text, _>(prefix)
+            .bind::<BigInt, _>(position)
+            .load::<EntryRow>(tx)?)
+        })?;
+
+        Ok(rows
+            .into_iter()
+            .filter_map(|row| row.value.map(|value| (row.key, value)))
+            .collect())
+    }
+
+    fn get_transaction(&self, id: &Uuid) -> Result<Option<Transaction>> {
+        let mut conn = self.connection_pool.get()?;
+        diesel::sql_query(format!("{} WHERE id = ?", SELECT_TRANSACTIONS))
+            .bind::<Text, _>(id.to_string())
+            .get_result::<TransactionRow>(&mut conn)
+            .optional()?
+            .map(TransactionRow::into_transaction)
+            .transpose()
+    }
+
+    fn record_execution(&self, record: &ExecutionRecord) -> Result<()> {
+        let mut conn = self.connection_pool.get()?;
         diesel::sql_query(
-            "INSERT INTO transactions (id, type, payload, timestamp, sender)
-             VALUES (?, ?, ?, ?, ?)",
+            "INSERT OR REPLACE INTO executions
+             (tx_id, method, headers, state_root, output, poc_root)
+             VALUES (?, ?, ?, ?, ?, ?)",
         )
-        .bind::<diesel::sql_types::Text, _>(transaction.id.to_string())
-        .bind::<diesel::sql_types::Text, _>(format!("{:?}", transaction.tx_type))
-        .bind::<diesel::sql_types::Binary, _>(transaction.payload)
-        .bind::<diesel::sql_types::Text, _>(transaction.timestamp.to_rfc3339())
-        .bind::<diesel::sql_types::Nullable<diesel::sql_types::Text>, _>(transaction.sender)
+        .bind::<Text, _>(record.tx_id.to_string())
+        .bind::<Text, _>(&record.method)
+        .bind::<Text, _>(serde_json::to_string(&record.headers)?)
+        .bind::<Text, _>(&record.state_root)
+        .bind::<Text, _>(serde_json::to_string(&record.output)?)
+        .bind::<Text, _>(&record.poc_root)
         .execute(&mut conn)?;
+        Ok(())
+    }
 
-        // Update the state root
-        let state_root = self.calculate_state_root()?;
-        let state_root_path = Path::new(&self.config.state_root_path).join("state_root");
-        fs::write(state_root_path, state_root.as_bytes())?;
+    fn get_execution(&self, tx_id: &Uuid) -> Result<Option<ExecutionRecord>> {
+        let mut conn = self.connection_pool.get()?;
+        diesel::sql_query(
+            "SELECT tx_id, method, headers, state_root, output, poc_root
+             FROM executions WHERE tx_id = ?",
+        )
+        .bind::<Text, _>(tx_id.to_string())
+        .get_result::<ExecutionRow>(&mut conn)
+        .optional()?
+        .map(ExecutionRow::into_record)
+        .transpose()
+    }
 
+    fn record_log_root(&self, log_index: u64, root: &str) -> Result<()> {
+        let mut conn = self.connection_pool.get()?;
+        diesel::sql_query("INSERT OR REPLACE INTO log_roots (log_index, root_hash) VALUES (?, ?)")
+            .bind::<BigInt, _>(log_index as i64)
+            .bind::<Text, _>(root)
+            .execute(&mut conn)?;
         Ok(())
     }
 
-    fn get_state_root(&self) -> Result<String> {
-        let state_root = self.calculate_state_root()?;
-        Ok(state_root)
+    fn root_at_log_index(&self, log_index: u64) -> Result<Option<String>> {
+        let mut conn = self.connection_pool.get()?;
+
+        #[derive(QueryableByName)]
+        struct RootRow {
+            #[diesel(sql_type = Text)]
+            root_hash: String,
+        }
+
+        Ok(diesel::sql_query(
+            "SELECT root_hash FROM log_roots WHERE log_index <= ?
+             ORDER BY log_index DESC LIMIT 1",
+        )
+        .bind::<BigInt, _>(log_index.min(i64::MAX as u64) as i64)
+        .get_result::<RootRow>(&mut conn)
+        .optional()?
+        .map(|row| row.root_hash))
+    }
+
+    fn query_transactions(&self, query: &TransactionQuery) -> Result<TransactionPage> {
+        let mut conn = self.connection_pool.get()?;
+
+        let mut sql = diesel::sql_query(format!("{} WHERE 1 = 1", SELECT_TRANSACTIONS))
+            .into_boxed::<diesel::sqlite::Sqlite>();
+        if let Some(sender) = &query.sender {
+            sql = sql.sql(" AND sender = ?").bind::<Text, _>(sender.clone());
+        }
+        if let Some(contract) = &query.contract {
+            sql = sql
+                .sql(" AND contract = ?")
+                .bind::<Text, _>(transactions::format_contract(contract));
+        }
+        if let Some(since) = &query.since {
+            sql = sql
+                .sql(" AND timestamp >= ?")
+                .bind::<Text, _>(transactions::format_timestamp(since));
+        }
+        if let Some(until) = &query.until {
+            sql = sql
+                .sql(" AND timestamp < ?")
+                .bind::<Text, _>(transactions::format_timestamp(until));
+        }
+        if let Some(index) = query.min_log_index {
+            sql = sql
+                .sql(" AND log_index >= ?")
+                .bind::<BigInt, _>(index as i64);
+        }
+        if let Some(index) = query.max_log_index {
+            sql = sql
+                .sql(" AND log_index <= ?")
+                .bind::<BigInt, _>(index as i64);
+        }
+        if let Some(cursor) = query.cursor {
+            sql = sql.sql(" AND seq < ?").bind::<BigInt, _>(cursor as i64);
+        }
+
+        // One extra row tells whether another page follows
+        let mut rows = sql
+            .sql(" ORDER BY seq DESC LIMIT ?")
+            .bind::<BigInt, _>(query.limit as i64 + 1)
+            .load::<TransactionRow>(&mut conn)?;
+
+        let next_cursor = if rows.len() > query.limit {
+            rows.truncate(query.limit);
+            rows.last().map(|row| row.seq as u64)
+        } else {
+            None
+        };
+
+        Ok(TransactionPage {
+            transactions: rows
+                .into_iter()
+                .map(TransactionRow::into_transaction)
+                .collect::<Result<_>>()?,
+            next_cursor,
+        })
     }
 
     fn clone(&self) -> std::sync::Arc<dyn StateStorage> {
         std::sync::Arc::new(SqliteStateStorage {
             config: self.config.clone(),
             connection_pool: self.connection_pool.clone(),
-            state_mutex: Mutex::new(()),
+            trie: self.trie.clone(),
         })
     }
 }
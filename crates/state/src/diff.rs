@@ -1,5 +1,6 @@
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 /// Operation to be applied to the state
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -61,17 +62,91 @@ impl StateDiff {
     pub fn len(&self) -> usize {
         self.operations.len()
     }
+
+    /// Create the diff turning the `current` snapshot into `target`
+    pub fn between(current: &StateSnapshot, target: &StateSnapshot) -> Self {
+        let target_entries: HashMap<&str, &str> = target
+            .entries
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect();
+        let current_entries: HashMap<&str, &str> = current
+            .entries
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect();
+
+        let mut diff = StateDiff::new(current.root.clone());
+        diff.new_root = target.root.clone();
+        for (key, _) in &current.entries {
+            if !target_entries.contains_key(key.as_str()) {
+                diff.delete(key.clone());
+            }
+        }
+        for (key, value) in &target.entries {
+            if current_entries.get(key.as_str()) != Some(&value.as_str()) {
+                diff.insert(key.clone(), value.clone());
+            }
+        }
+        diff
+    }
+}
+
+/// Full copy of the state at a given root, used for snapshots and catch-up
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StateSnapshot {
+    /// State root hash the entries belong to
+    pub root: String,
+
+    /// All key/value pairs, ordered by key
+    pub entries: Vec<(String, String)>,
 }
 
 /// Extension trait for state storage to support diff-based updates
 pub trait StateDiffStorage {
     /// Apply a state diff to the storage
+    ///
+    /// The diff must start at the current root and its operations must lead to
+    /// its `new_root`, otherwise it is rejected and the state is left as it was.
     fn apply_diff(&self, diff: &StateDiff) -> Result<()>;
 
     /// Create a checkpoint of the current state
     /// Returns a StateDiff with the current state root but no operations
     fn create_checkpoint(&self) -> Result<StateDiff>;
 
+    /// Export all state entries together with the current state root
+    fn export_snapshot(&self) -> Result<StateSnapshot>;
+
+    /// Replace the whole state with the given snapshot
+    ///
+    /// The replacement is recorded as a diff from the current root, so the
+    /// history keeps covering every state the storage went through. A snapshot
+    /// whose entries do not lead to its root is rejected.
+    fn import_snapshot(&self, snapshot: &StateSnapshot) -> Result<()>;
+
+    /// Get up to `limit` applied diffs leading from `root` towards the current state, in order
+    ///
+    /// Returns `None` if `root` is not a state this storage went through.
+    fn diffs_since(&self, root: &str, limit: usize) -> Result<Option<Vec<StateDiff>>>;
+
+    /// Undo every diff applied after the state was last at `root`
+    ///
+    /// The value each key had at `root` is the latest operation on it up to that
+    /// point, so overwrites and deletes are inverted from the recorded history.
+    /// The undone diffs are pruned from the history, as if they had never been
+    /// applied: the roots only they led to can no longer be read or synced from.
+    ///
+    /// Only call this when applying a revert decided through consensus, otherwise
+    /// the node's state diverges from the rest of the cluster.
+    fn revert_to(&self, root: &str) -> Result<()>;
+
+    /// Apply `operations` on top of the current state, recording them as a diff
+    ///
+    /// Unlike `apply_diff` the roots are not given but computed here, from the
+    /// current root to the root the operations lead to. Operations leaving the
+    /// state unchanged are not recorded.
+    fn apply_operations(&self, operations: Vec<StateOperation>) -> Result<StateDiff>;
+
     /// Apply multiple diffs in a batch
     fn batch_apply_diffs(&self, diffs: Vec<StateDiff>) -> Result<()> {
         for diff in diffs {
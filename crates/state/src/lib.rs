@@ -1,12 +1,21 @@
 pub mod config;
 pub mod db;
 pub mod diff;
+pub mod proof;
+pub mod transactions;
 
 use anyhow::Result;
 use mp_common::types::Transaction;
 use std::sync::Arc;
+use uuid::Uuid;
 
 use crate::diff::StateDiffStorage;
+use crate::proof::StateProof;
+use crate::transactions::{ExecutionRecord, TransactionPage, TransactionQuery};
+
+/// State root before any diff is applied, the root of the empty trie
+pub const EMPTY_STATE_ROOT: &str =
+    "56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421";
 
 /// State storage interface
 pub trait StateStorage: Send + Sync + StateDiffStorage {
@@ -16,12 +25,67 @@ pub trait StateStorage: Send + Sync + StateDiffStorage {
     /// Stop the state storage
     fn stop(&self) -> Result<()>;
 
-    /// Apply a transaction to the state
+    /// Apply a transaction to the state and record it in the transaction history
+    ///
+    /// Recording a transaction whose ID is already in the history is a no-op.
     fn apply_transaction(&self, transaction: Transaction) -> Result<()>;
 
+    /// Get a recorded transaction by its ID
+    fn get_transaction(&self, id: &Uuid) -> Result<Option<Transaction>>;
+
+    /// Get a page of the recorded transactions matching `query`, most recent first
+    fn query_transactions(&self, query: &TransactionQuery) -> Result<TransactionPage>;
+
+    /// Record the execution of a transaction, replacing an earlier record of it
+    fn record_execution(&self, record: &ExecutionRecord) -> Result<()>;
+
+    /// Get the recorded execution of a transaction
+    fn get_execution(&self, tx_id: &Uuid) -> Result<Option<ExecutionRecord>>;
+
     /// Get the current state root hash
+    ///
+    /// This is the root of the Merkle-Patricia trie over all state entries.
     fn get_state_root(&self) -> Result<String>;
 
+    /// Get a proof of the value of `key`, or of its absence, against the current state root
+    fn get_proof(&self, key: &str) -> Result<StateProof>;
+
+    /// Get the value of `key` as of the state at `root`
+    ///
+    /// Fails if the storage never went through `root`.
+    fn get_at(&self, key: &str, root: &str) -> Result<Option<String>>;
+
+    /// Get the entries whose key starts with `prefix` as of the state at `root`, ordered by key
+    fn iter_prefix_at(&self, prefix: &str, root: &str) -> Result<Vec<(String, String)>>;
+
+    /// Record `root` as the state root reached by the consensus log entry at `log_index`
+    ///
+    /// Recording an index again replaces its root.
+    fn record_log_root(&self, log_index: u64, root: &str) -> Result<()>;
+
+    /// Get the state root as of the consensus log entry at `log_index`
+    ///
+    /// This is the root recorded at the latest index up to `log_index`, `None` before the
+    /// first recorded one.
+    fn root_at_log_index(&self, log_index: u64) -> Result<Option<String>>;
+
+    /// Get a proof of the value of `key`, or of its absence, as of the state at `root`
+    ///
+    /// With the state root of a sealed block, proves what `get_at` reads for that block.
+    fn get_proof_at(&self, key: &str, root: &str) -> Result<StateProof> {
+        let entries = self.iter_prefix_at("", root)?;
+        let proof =
+            proof::generate_proof(entries.iter().map(|(k, v)| (k.as_str(), v.as_str())), key)?;
+        if proof.root != root {
+            return Err(anyhow::anyhow!(
+                "State at root {} rebuilds to root {}",
+                root,
+                proof.root
+            ));
+        }
+        Ok(proof)
+    }
+
     /// Clone this state storage instance
     fn clone(&self) -> Arc<dyn StateStorage>;
 }
@@ -35,6 +99,10 @@ pub fn create_state_storage(
             let storage = db::sqlite::SqliteStateStorage::new(config)?;
             Ok(Arc::new(storage))
         }
+        "sled" => {
+            let storage = db::sled::SledStateStorage::new(config)?;
+            Ok(Arc::new(storage))
+        }
         _ => Err(anyhow::anyhow!(
             "Unsupported database type: {}",
             config.db_type
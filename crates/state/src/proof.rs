@@ -0,0 +1,251 @@
+use anyhow::{anyhow, Result};
+use ethereum_types::H256;
+use hash_db::Hasher;
+use mp_ethereum::keccak::KeccakHasher;
+use mp_ethereum::{EthTrie, MemoryDB, Trie};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::diff::StateOperation;
+
+/// Updates after which [`StateTrie`] rebuilds itself to drop the nodes of earlier roots
+const TRIE_REBUILD_INTERVAL: usize = 1024;
+
+/// Proof that a key holds a value, or is absent, in the state with the given root
+///
+/// The state root is a Merkle-Patricia trie over the raw keys, each value is stored
+/// RLP encoded so that empty values remain part of the state.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateProof {
+    /// State root the proof was generated against
+    pub root: String,
+
+    /// Proven key
+    pub key: String,
+
+    /// Value of the key, `None` if the proof shows the key is absent
+    pub value: Option<String>,
+
+    /// Hex encoded trie nodes on the path from the root to the key
+    pub nodes: Vec<String>,
+}
+
+impl StateProof {
+    /// Check the proof against its root and the claimed value
+    pub fn verify(&self) -> Result<()> {
+        let root = parse_root(&self.root)?;
+        let nodes = self
+            .nodes
+            .iter()
+            .map(hex::decode)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| anyhow!("Invalid proof node: {}", e))?;
+
+        // The empty trie has no nodes to walk, every key is absent from it
+        let proven = if root == KeccakHasher::hash(&rlp::NULL_RLP) {
+            None
+        } else {
+            mp_ethereum::verify_proof::<KeccakHasher>(root, self.key.as_bytes(), nodes)
+                .map_err(|e| anyhow!("Invalid proof for key {}: {}", self.key, e))?
+                .map(|encoded| decode_value(&encoded))
+                .transpose()?
+        };
+
+        if proven != self.value {
+            return Err(anyhow!(
+                "Proof for key {} shows {:?} instead of {:?}",
+                self.key,
+                proven,
+                self.value
+            ));
+        }
+        Ok(())
+    }
+
+    /// Check the proof against `state_root`, such as the state root of a sealed block
+    pub fn verify_against(&self, state_root: &str) -> Result<()> {
+        if self.root != state_root {
+            return Err(anyhow!(
+                "Proof for key {} is against state root {} instead of {}",
+                self.key,
+                self.root,
+                state_root
+            ));
+        }
+        self.verify()
+    }
+}
+
+/// Calculate the state root of the given key/value pairs
+pub fn state_root<'a, I>(entries: I) -> Result<String>
+where
+    I: IntoIterator<Item = (&'a str, &'a str)>,
+{
+    let root = mp_ethereum::calculate_trie_root(
+        entries
+            .into_iter()
+            .map(|(key, value)| (key.as_bytes(), encode_value(value))),
+    )
+    .map_err(|e| anyhow!("Failed to calculate state root: {}", e))?;
+    Ok(hex::encode(root.as_bytes()))
+}
+
+/// Generate the proof for `key` over the given key/value pairs
+pub fn generate_proof<'a, I>(entries: I, key: &str) -> Result<StateProof>
+where
+    I: IntoIterator<Item = (&'a str, &'a str)>,
+{
+    let mut value = None;
+    let pairs: Vec<(&[u8], Vec<u8>)> = entries
+        .into_iter()
+        .inspect(|(k, v)| {
+            if *k == key {
+                value = Some(v.to_string());
+            }
+        })
+        .map(|(k, v)| (k.as_bytes(), encode_value(v)))
+        .collect();
+
+    let (root, nodes) = mp_ethereum::generate_proof(pairs, key.as_bytes())
+        .map_err(|e| anyhow!("Failed to generate proof for key {}: {}", key, e))?;
+
+    Ok(StateProof {
+        root: hex::encode(root.as_bytes()),
+        key: key.to_string(),
+        value,
+        nodes: nodes.iter().map(hex::encode).collect(),
+    })
+}
+
+/// Trie of the current state, kept in memory and updated with every diff
+///
+/// The nodes of earlier roots stay in memory so that an update the storage fails
+/// to commit can be undone, the trie drops them by rebuilding itself every
+/// [`TRIE_REBUILD_INTERVAL`] updates.
+pub struct StateTrie {
+    trie: EthTrie<MemoryDB>,
+    root: H256,
+    updates: usize,
+}
+
+impl StateTrie {
+    /// Build the trie of the given key/value pairs
+    pub fn new<'a, I>(entries: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = (&'a str, &'a str)>,
+    {
+        let mut trie = EthTrie::new(Arc::new(MemoryDB::new(false)));
+        for (key, value) in entries {
+            trie.insert(key.as_bytes(), &encode_value(value))?;
+        }
+        let root = trie.root_hash()?;
+        Ok(Self {
+            trie,
+            root,
+            updates: 0,
+        })
+    }
+
+    /// Current state root
+    pub fn root(&self) -> String {
+        hex::encode(self.root.as_bytes())
+    }
+
+    /// Apply `operations` and return the state root they lead to
+    pub fn apply(&mut self, operations: &[StateOperation]) -> Result<String> {
+        if self.updates >= TRIE_REBUILD_INTERVAL {
+            self.rebuild()?;
+        }
+        self.updates += 1;
+
+        let applied = operations.iter().try_for_each(|operation| match operation {
+            StateOperation::Insert { key, value } => {
+                self.trie.insert(key.as_bytes(), &encode_value(value))
+            }
+            StateOperation::Delete { key } => self.trie.remove(key.as_bytes()).map(|_| ()),
+        });
+        match applied.and_then(|_| self.trie.root_hash()) {
+            Ok(root) => {
+                self.root = root;
+                Ok(self.root())
+            }
+            Err(e) => {
+                let root = self.root;
+                self.reset(root)?;
+                Err(anyhow!("Failed to update the state trie: {}", e))
+            }
+        }
+    }
+
+    /// Go back to `root`, the root before an update that was not committed
+    pub fn undo(&mut self, root: &str) -> Result<()> {
+        self.reset(parse_root(root)?)
+    }
+
+    /// Generate the proof for `key` against the current root
+    pub fn proof(&mut self, key: &str) -> Result<StateProof> {
+        let value = self
+            .trie
+            .get(key.as_bytes())
+            .map_err(|e| anyhow!("Failed to read key {}: {}", key, e))?
+            .map(|encoded| decode_value(&encoded))
+            .transpose()?;
+        let nodes = self
+            .trie
+            .get_proof(key.as_bytes())
+            .map_err(|e| anyhow!("Failed to generate proof for key {}: {}", key, e))?;
+
+        Ok(StateProof {
+            root: self.root(),
+            key: key.to_string(),
+            value,
+            nodes: nodes.iter().map(hex::encode).collect(),
+        })
+    }
+
+    /// Go back to the earlier root `root`, whose nodes are still in memory
+    fn reset(&mut self, root: H256) -> Result<()> {
+        self.trie = self.trie.at_root(root);
+        // Committing loads the root node, proofs cannot start from its bare hash
+        self.root = self.trie.root_hash()?;
+        Ok(())
+    }
+
+    /// Copy the entries of the current root into a new trie
+    fn rebuild(&mut self) -> Result<()> {
+        let mut trie = EthTrie::new(Arc::new(MemoryDB::new(false)));
+        for (key, value) in self.trie.iter() {
+            trie.insert(&key, &value)?;
+        }
+        let root = trie.root_hash()?;
+        if root != self.root {
+            return Err(anyhow!(
+                "Rebuilt state trie has root {:?} instead of {:?}",
+                root,
+                self.root
+            ));
+        }
+        self.trie = trie;
+        self.updates = 0;
+        Ok(())
+    }
+}
+
+fn encode_value(value: &str) -> Vec<u8> {
+    rlp::encode(&value.as_bytes()).to_vec()
+}
+
+fn decode_value(encoded: &[u8]) -> Result<String> {
+    let value = rlp::Rlp::new(encoded)
+        .data()
+        .map_err(|e| anyhow!("Invalid proven value: {}", e))?;
+    String::from_utf8(value.to_vec()).map_err(|e| anyhow!("Invalid proven value: {}", e))
+}
+
+fn parse_root(root: &str) -> Result<H256> {
+    let bytes = hex::decode(root).map_err(|e| anyhow!("Invalid state root {}: {}", root, e))?;
+    if bytes.len() != H256::len_bytes() {
+        return Err(anyhow!("Invalid state root {}: expected 32 bytes", root));
+    }
+    Ok(H256::from_slice(&bytes))
+}
@@ -0,0 +1,141 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use ethereum_types::H128;
+use mp_common::types::{Transaction, TransactionType};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Default number of transactions returned per page
+pub const DEFAULT_PAGE_SIZE: usize = 100;
+
+/// Filter and page position of a transaction history query
+///
+/// Every set field must match. Results are ordered from the most recently
+/// recorded transaction to the oldest.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransactionQuery {
+    /// Only transactions sent by this sender
+    #[serde(default)]
+    pub sender: Option<String>,
+
+    /// Only requests to this contract address
+    #[serde(default)]
+    pub contract: Option<H128>,
+
+    /// Only transactions with a timestamp at or after this time
+    #[serde(default)]
+    pub since: Option<DateTime<Utc>>,
+
+    /// Only transactions with a timestamp before this time
+    #[serde(default)]
+    pub until: Option<DateTime<Utc>>,
+
+    /// Only transactions at or above this log index
+    #[serde(default)]
+    pub min_log_index: Option<u64>,
+
+    /// Only transactions at or below this log index
+    #[serde(default)]
+    pub max_log_index: Option<u64>,
+
+    /// Continue after the page that returned this cursor
+    #[serde(default)]
+    pub cursor: Option<u64>,
+
+    /// Maximum number of transactions returned
+    #[serde(default = "default_page_size")]
+    pub limit: usize,
+}
+
+impl Default for TransactionQuery {
+    fn default() -> Self {
+        Self {
+            sender: None,
+            contract: None,
+            since: None,
+            until: None,
+            min_log_index: None,
+            max_log_index: None,
+            cursor: None,
+            limit: DEFAULT_PAGE_SIZE,
+        }
+    }
+}
+
+impl TransactionQuery {
+    /// Check the filters against a transaction, ignoring the page position
+    pub fn matches(&self, transaction: &Transaction) -> bool {
+        self.sender
+            .as_ref()
+            .is_none_or(|sender| transaction.sender.as_ref() == Some(sender))
+            && self
+                .contract
+                .is_none_or(|contract| contract_of(transaction) == Some(contract))
+            && self
+                .since
+                .is_none_or(|since| transaction.timestamp >= since)
+            && self.until.is_none_or(|until| transaction.timestamp < until)
+            && self
+                .min_log_index
+                .is_none_or(|index| transaction.log_index >= index)
+            && self
+                .max_log_index
+                .is_none_or(|index| transaction.log_index <= index)
+    }
+}
+
+/// One page of a transaction history query
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TransactionPage {
+    /// Matching transactions, most recently recorded first
+    pub transactions: Vec<Transaction>,
+
+    /// Cursor of the next page, `None` once the history is exhausted
+    pub next_cursor: Option<u64>,
+}
+
+/// Recorded execution of a request transaction
+///
+/// Holds what the history lacks to run the request again, and the outcome it
+/// had, so that a replay can tell whether execution is deterministic.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExecutionRecord {
+    /// ID of the executed transaction
+    pub tx_id: Uuid,
+
+    /// HTTP method of the request
+    pub method: String,
+
+    /// HTTP headers of the request, in order
+    pub headers: Vec<(String, String)>,
+
+    /// State root the execution read from
+    pub state_root: String,
+
+    /// Output returned by the contract
+    pub output: serde_json::Value,
+
+    /// Hex-encoded PoC root over the input and output
+    pub poc_root: String,
+}
+
+/// Contract address a transaction calls, if it is a request
+pub fn contract_of(transaction: &Transaction) -> Option<H128> {
+    match transaction.tx_type {
+        TransactionType::Request(contract, _) => Some(contract),
+        _ => None,
+    }
+}
+
+/// Format a contract address the way transaction types spell it
+pub fn format_contract(contract: &H128) -> String {
+    format!("{:?}", contract)
+}
+
+/// Format a timestamp so that the text order matches the time order
+pub fn format_timestamp(timestamp: &DateTime<Utc>) -> String {
+    timestamp.to_rfc3339_opts(SecondsFormat::Nanos, true)
+}
+
+fn default_page_size() -> usize {
+    DEFAULT_PAGE_SIZE
+}
@@ -0,0 +1,750 @@
+//! Behaviour every state storage backend must share
+
+use std::path::Path;
+use std::sync::Arc;
+
+use chrono::{Duration, TimeZone, Utc};
+use ethereum_types::H128;
+use mp_common::types::{Transaction, TransactionType};
+use mp_common::utils::create_transaction;
+use mp_state::config::StateConfig;
+use mp_state::diff::{StateDiff, StateOperation, StateSnapshot};
+use mp_state::transactions::{ExecutionRecord, TransactionQuery};
+use mp_state::{create_state_storage, StateStorage, EMPTY_STATE_ROOT};
+
+fn open(db_type: &str, dir: &Path) -> Arc<dyn StateStorage> {
+    let storage = create_state_storage(StateConfig {
+        db_type: db_type.to_string(),
+        db_connection: dir.join("state.db").to_string_lossy().to_string(),
+        state_root_path: dir.join("state_root").to_string_lossy().to_string(),
+    })
+    .unwrap();
+    storage.start().unwrap();
+    storage
+}
+
+fn diff(prev_root: &str, new_root: &str, inserts: &[(&str, &str)], deletes: &[&str]) -> StateDiff {
+    let mut diff = StateDiff::new(prev_root.to_string());
+    diff.new_root = new_root.to_string();
+    for (key, value) in inserts {
+        diff.insert(key.to_string(), value.to_string());
+    }
+    for key in deletes {
+        diff.delete(key.to_string());
+    }
+    diff
+}
+
+fn entries(storage: &Arc<dyn StateStorage>) -> Vec<(String, String)> {
+    storage.export_snapshot().unwrap().entries
+}
+
+fn pairs(entries: &[(&str, &str)]) -> Vec<(String, String)> {
+    entries
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+/// State root of the given entries
+fn root(entries: &[(&str, &str)]) -> String {
+    mp_state::proof::state_root(entries.iter().copied()).unwrap()
+}
+
+fn initial_state(db_type: &str) {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = open(db_type, dir.path());
+
+    assert_eq!(EMPTY_STATE_ROOT, root(&[]));
+    assert_eq!(
+        storage.create_checkpoint().unwrap().new_root,
+        EMPTY_STATE_ROOT
+    );
+    assert_eq!(storage.get_state_root().unwrap(), EMPTY_STATE_ROOT);
+    assert!(entries(&storage).is_empty());
+    assert_eq!(
+        storage
+            .diffs_since(EMPTY_STATE_ROOT, 10)
+            .unwrap()
+            .unwrap()
+            .len(),
+        0
+    );
+    assert!(storage.diffs_since("unknown", 10).unwrap().is_none());
+}
+
+fn apply_diffs(db_type: &str) {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = open(db_type, dir.path());
+    let root_1 = root(&[("alice", "10"), ("bob", "5")]);
+    let root_2 = root(&[("alice", "10"), ("carol", "")]);
+    let root_3 = root(&[("alice", "11"), ("carol", "")]);
+
+    storage
+        .apply_diff(&diff(
+            EMPTY_STATE_ROOT,
+            &root_1,
+            &[("bob", "5"), ("alice", "10")],
+            &[],
+        ))
+        .unwrap();
+    storage
+        .batch_apply_diffs(vec![
+            diff(&root_1, &root_2, &[("carol", "")], &["bob"]),
+            diff(&root_2, &root_3, &[("alice", "11")], &["missing"]),
+        ])
+        .unwrap();
+
+    assert_eq!(storage.create_checkpoint().unwrap().new_root, root_3);
+    assert_eq!(storage.get_state_root().unwrap(), root_3);
+    assert_eq!(entries(&storage), pairs(&[("alice", "11"), ("carol", "")]));
+
+    // A diff from an unknown or earlier root is rejected without touching the state
+    let root_4 = root(&[("alice", "11"), ("carol", ""), ("dave", "1")]);
+    for prev_root in ["unknown", root_2.as_str()] {
+        assert!(storage
+            .apply_diff(&diff(prev_root, &root_4, &[("dave", "1")], &[]))
+            .is_err());
+    }
+    assert_eq!(storage.create_checkpoint().unwrap().new_root, root_3);
+    assert_eq!(entries(&storage), pairs(&[("alice", "11"), ("carol", "")]));
+
+    // So is a diff whose operations do not lead to the root it claims
+    let error = storage
+        .apply_diff(&diff(&root_3, &root_4, &[("dave", "2")], &[]))
+        .unwrap_err();
+    assert!(error.to_string().contains("operations lead to"));
+    assert_eq!(storage.create_checkpoint().unwrap().new_root, root_3);
+    assert_eq!(storage.get_state_root().unwrap(), root_3);
+    assert_eq!(entries(&storage), pairs(&[("alice", "11"), ("carol", "")]));
+    storage
+        .apply_diff(&diff(&root_3, &root_4, &[("dave", "1")], &[]))
+        .unwrap();
+}
+
+fn serve_diffs(db_type: &str) {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = open(db_type, dir.path());
+    let root_1 = root(&[("a", "1")]);
+    let root_2 = root(&[("a", "1"), ("b", "2")]);
+    let root_3 = root(&[("b", "2")]);
+    let history = vec![
+        diff(EMPTY_STATE_ROOT, &root_1, &[("a", "1")], &[]),
+        diff(&root_1, &root_2, &[("b", "2")], &[]),
+        diff(&root_2, &root_3, &[], &["a"]),
+    ];
+    storage.batch_apply_diffs(history.clone()).unwrap();
+
+    let roots = |diffs: Vec<StateDiff>| -> Vec<String> {
+        diffs.into_iter().map(|diff| diff.new_root).collect()
+    };
+    assert_eq!(
+        roots(storage.diffs_since(EMPTY_STATE_ROOT, 10).unwrap().unwrap()),
+        vec![root_1.clone(), root_2.clone(), root_3.clone()]
+    );
+    assert_eq!(
+        roots(storage.diffs_since(&root_1, 1).unwrap().unwrap()),
+        vec![root_2.clone()]
+    );
+    assert!(storage
+        .diffs_since(&root_3, 10)
+        .unwrap()
+        .unwrap()
+        .is_empty());
+
+    let served = storage.diffs_since(&root_2, 10).unwrap().unwrap();
+    assert_eq!(served.len(), 1);
+    assert_eq!(served[0].prev_root, root_2);
+    assert_eq!(served[0].len(), history[2].len());
+}
+
+fn snapshots(db_type: &str) {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = open(db_type, dir.path());
+    let root_1 = root(&[("old", "1")]);
+    storage
+        .apply_diff(&diff(EMPTY_STATE_ROOT, &root_1, &[("old", "1")], &[]))
+        .unwrap();
+
+    // A snapshot whose entries do not lead to its root is rejected
+    let imported = root(&[("a", "1"), ("b", "2")]);
+    let forged = StateSnapshot {
+        root: imported.clone(),
+        entries: pairs(&[("a", "1"), ("b", "3")]),
+    };
+    assert!(storage.import_snapshot(&forged).is_err());
+    assert_eq!(entries(&storage), pairs(&[("old", "1")]));
+    assert_eq!(storage.get_state_root().unwrap(), root_1);
+
+    let snapshot = StateSnapshot {
+        root: imported.clone(),
+        entries: pairs(&[("a", "1"), ("b", "2")]),
+    };
+    storage.import_snapshot(&snapshot).unwrap();
+    let exported = storage.export_snapshot().unwrap();
+    assert_eq!(exported.root, snapshot.root);
+    assert_eq!(exported.entries, snapshot.entries);
+
+    // Diffs continue from the imported root
+    let root_2 = root(&[("a", "1"), ("b", "2"), ("c", "3")]);
+    storage
+        .apply_diff(&diff(&imported, &root_2, &[("c", "3")], &[]))
+        .unwrap();
+    let served = storage.diffs_since(&imported, 10).unwrap().unwrap();
+    assert_eq!(served.len(), 1);
+    assert_eq!(served[0].new_root, root_2);
+}
+
+fn state_roots_and_proofs(db_type: &str) {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = open(db_type, dir.path());
+    let empty_root = storage.get_state_root().unwrap();
+    storage.get_proof("alice").unwrap().verify().unwrap();
+
+    let root_1 = root(&[("alice", "10"), ("bob", "5")]);
+    storage
+        .apply_diff(&diff(
+            EMPTY_STATE_ROOT,
+            &root_1,
+            &[("alice", "10"), ("bob", "5")],
+            &[],
+        ))
+        .unwrap();
+    let state_root = storage.get_state_root().unwrap();
+    assert_ne!(state_root, empty_root);
+    // The root only depends on the content, so every backend agrees on it
+    assert_eq!(state_root, root_1);
+
+    let proof = storage.get_proof("alice").unwrap();
+    assert_eq!(proof.root, state_root);
+    assert_eq!(proof.value.as_deref(), Some("10"));
+    proof.verify().unwrap();
+    storage.get_proof("carol").unwrap().verify().unwrap();
+
+    // The cached trie follows later diffs
+    let root_2 = root(&[("alice", "11"), ("bob", "5")]);
+    storage
+        .apply_diff(&diff(&root_1, &root_2, &[("alice", "11")], &[]))
+        .unwrap();
+    let proof = storage.get_proof("alice").unwrap();
+    assert_eq!(proof.root, root_2);
+    assert_eq!(proof.value.as_deref(), Some("11"));
+    proof.verify().unwrap();
+}
+
+fn historical_queries(db_type: &str) {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = open(db_type, dir.path());
+    let root_1 = root(&[("contract/a", "1"), ("contract/ab", "2"), ("other", "x")]);
+    let root_2 = root(&[("contract/a", "3"), ("other", "x")]);
+    let root_3 = root(&[("contract/a", "3"), ("contract/b", "4"), ("other", "x")]);
+    storage
+        .batch_apply_diffs(vec![
+            diff(
+                EMPTY_STATE_ROOT,
+                &root_1,
+                &[("contract/a", "1"), ("contract/ab", "2"), ("other", "x")],
+                &[],
+            ),
+            diff(&root_1, &root_2, &[("contract/a", "3")], &["contract/ab"]),
+            diff(&root_2, &root_3, &[("contract/b", "4")], &[]),
+        ])
+        .unwrap();
+
+    assert_eq!(
+        storage.get_at("contract/a", EMPTY_STATE_ROOT).unwrap(),
+        None
+    );
+    assert_eq!(
+        storage.get_at("contract/a", &root_1).unwrap().as_deref(),
+        Some("1")
+    );
+    assert_eq!(
+        storage.get_at("contract/a", &root_2).unwrap().as_deref(),
+        Some("3")
+    );
+    assert_eq!(storage.get_at("contract/ab", &root_2).unwrap(), None);
+    assert_eq!(
+        storage.get_at("contract/a", &root_3).unwrap().as_deref(),
+        Some("3")
+    );
+    assert!(storage.get_at("contract/a", "unknown").is_err());
+
+    assert_eq!(
+        storage.iter_prefix_at("contract/", &root_1).unwrap(),
+        pairs(&[("contract/a", "1"), ("contract/ab", "2")])
+    );
+    assert_eq!(
+        storage.iter_prefix_at("contract/a", &root_2).unwrap(),
+        pairs(&[("contract/a", "3")])
+    );
+    assert_eq!(
+        storage.iter_prefix_at("contract/", &root_3).unwrap(),
+        pairs(&[("contract/a", "3"), ("contract/b", "4")])
+    );
+    assert!(storage
+        .iter_prefix_at("contract/", EMPTY_STATE_ROOT)
+        .unwrap()
+        .is_empty());
+
+    // Imported snapshots stay part of the history
+    let imported = root(&[("contract/c", "5"), ("other", "x")]);
+    let root_4 = root(&[("contract/c", "5"), ("contract/d", "6"), ("other", "x")]);
+    storage
+        .import_snapshot(&StateSnapshot {
+            root: imported.clone(),
+            entries: pairs(&[("contract/c", "5"), ("other", "x")]),
+        })
+        .unwrap();
+    storage
+        .apply_diff(&diff(&imported, &root_4, &[("contract/d", "6")], &[]))
+        .unwrap();
+    assert_eq!(
+        storage.iter_prefix_at("contract/", &imported).unwrap(),
+        pairs(&[("contract/c", "5")])
+    );
+    assert_eq!(
+        storage.get_at("other", &imported).unwrap().as_deref(),
+        Some("x")
+    );
+    assert_eq!(
+        storage.get_at("contract/a", &root_3).unwrap().as_deref(),
+        Some("3")
+    );
+    assert_eq!(
+        storage.iter_prefix_at("contract/", &root_4).unwrap(),
+        pairs(&[("contract/c", "5"), ("contract/d", "6")])
+    );
+}
+
+fn log_roots(db_type: &str) {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = open(db_type, dir.path());
+    let root_1 = root(&[("a", "1")]);
+    let root_2 = root(&[("a", "2")]);
+    storage
+        .batch_apply_diffs(vec![
+            diff(EMPTY_STATE_ROOT, &root_1, &[("a", "1")], &[]),
+            diff(&root_1, &root_2, &[("a", "2")], &[]),
+        ])
+        .unwrap();
+    storage.record_log_root(3, &root_1).unwrap();
+    storage.record_log_root(7, &root_2).unwrap();
+
+    assert_eq!(storage.root_at_log_index(2).unwrap(), None);
+    assert_eq!(storage.root_at_log_index(3).unwrap(), Some(root_1.clone()));
+    assert_eq!(storage.root_at_log_index(6).unwrap(), Some(root_1.clone()));
+    assert_eq!(storage.root_at_log_index(7).unwrap(), Some(root_2.clone()));
+    assert_eq!(storage.root_at_log_index(u64::MAX).unwrap(), Some(root_2));
+
+    // Log indices read the state through the root they map to
+    let root = storage.root_at_log_index(5).unwrap().unwrap();
+    assert_eq!(storage.get_at("a", &root).unwrap().as_deref(), Some("1"));
+
+    // Recording an index again replaces its root
+    storage.record_log_root(3, EMPTY_STATE_ROOT).unwrap();
+    assert_eq!(
+        storage.root_at_log_index(4).unwrap().as_deref(),
+        Some(EMPTY_STATE_ROOT)
+    );
+}
+
+fn revert(db_type: &str) {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = open(db_type, dir.path());
+    let root_1 = root(&[("a", "1"), ("b", "2")]);
+    let root_2 = root(&[("a", "3"), ("c", "4")]);
+    let root_3 = root(&[("b", "2"), ("d", "5")]);
+    storage
+        .batch_apply_diffs(vec![
+            diff(EMPTY_STATE_ROOT, &root_1, &[("a", "1"), ("b", "2")], &[]),
+            diff(&root_1, &root_2, &[("a", "3"), ("c", "4")], &["b"]),
+            diff(&root_2, &root_1, &[("a", "1"), ("b", "2")], &["c"]),
+            diff(&root_1, &root_3, &[("d", "5")], &["a"]),
+        ])
+        .unwrap();
+    assert_eq!(entries(&storage).len(), 2);
+
+    // Overwrites and deletes since the last time at root_1 are undone
+    storage.revert_to(&root_1).unwrap();
+    assert_eq!(storage.create_checkpoint().unwrap().new_root, root_1);
+    assert_eq!(storage.get_state_root().unwrap(), root_1);
+    assert_eq!(entries(&storage), pairs(&[("a", "1"), ("b", "2")]));
+    assert!(storage
+        .diffs_since(&root_1, 10)
+        .unwrap()
+        .unwrap()
+        .is_empty());
+    assert!(storage.diffs_since(&root_3, 10).unwrap().is_none());
+    assert!(storage.get_at("d", &root_3).is_err());
+    assert!(storage
+        .apply_diff(&diff(&root_3, &root_3, &[], &[]))
+        .is_err());
+    // The undone diff is gone from the history, so it can be applied again
+    storage
+        .apply_diff(&diff(&root_1, &root_3, &[("d", "5")], &["a"]))
+        .unwrap();
+    assert_eq!(storage.diffs_since(&root_1, 10).unwrap().unwrap().len(), 1);
+
+    // Roots reached before the undone diffs remain usable
+    storage.revert_to(&root_2).unwrap();
+    assert_eq!(entries(&storage), pairs(&[("a", "3"), ("c", "4")]));
+    assert_eq!(storage.get_at("a", &root_1).unwrap().as_deref(), Some("1"));
+    let root_5 = root(&[("a", "3"), ("c", "4"), ("e", "6")]);
+    storage
+        .apply_diff(&diff(&root_2, &root_5, &[("e", "6")], &[]))
+        .unwrap();
+    assert_eq!(
+        entries(&storage),
+        pairs(&[("a", "3"), ("c", "4"), ("e", "6")])
+    );
+
+    storage.revert_to(EMPTY_STATE_ROOT).unwrap();
+    assert!(entries(&storage).is_empty());
+    assert!(storage.revert_to(&root_1).is_err());
+    storage
+        .apply_diff(&diff(
+            EMPTY_STATE_ROOT,
+            &root(&[("f", "7")]),
+            &[("f", "7")],
+            &[],
+        ))
+        .unwrap();
+    assert_eq!(
+        storage
+            .diffs_since(EMPTY_STATE_ROOT, 10)
+            .unwrap()
+            .unwrap()
+            .len(),
+        1
+    );
+}
+
+fn persistence(db_type: &str) {
+    let dir = tempfile::tempdir().unwrap();
+    let root_1 = root(&[("a", "1")]);
+    {
+        let storage = open(db_type, dir.path());
+        storage
+            .apply_diff(&diff(EMPTY_STATE_ROOT, &root_1, &[("a", "1")], &[]))
+            .unwrap();
+        storage.stop().unwrap();
+    }
+
+    let storage = open(db_type, dir.path());
+    assert_eq!(storage.create_checkpoint().unwrap().new_root, root_1);
+    assert_eq!(storage.get_state_root().unwrap(), root_1);
+    assert_eq!(entries(&storage), pairs(&[("a", "1")]));
+    assert_eq!(
+        storage
+            .diffs_since(EMPTY_STATE_ROOT, 10)
+            .unwrap()
+            .unwrap()
+            .len(),
+        1
+    );
+}
+
+fn apply_operations(db_type: &str) {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = open(db_type, dir.path());
+    let insert = |key: &str, value: &str| StateOperation::Insert {
+        key: key.to_string(),
+        value: value.to_string(),
+    };
+
+    let diff = storage
+        .apply_operations(vec![insert("a", "1"), insert("b", "2")])
+        .unwrap();
+    assert_eq!(diff.prev_root, EMPTY_STATE_ROOT);
+    assert_eq!(diff.new_root, storage.get_state_root().unwrap());
+    assert_eq!(storage.create_checkpoint().unwrap().new_root, diff.new_root);
+    assert_eq!(entries(&storage), pairs(&[("a", "1"), ("b", "2")]));
+
+    let diff = storage
+        .apply_operations(vec![
+            insert("a", "3"),
+            StateOperation::Delete {
+                key: "b".to_string(),
+            },
+        ])
+        .unwrap();
+    assert_eq!(diff.new_root, storage.get_state_root().unwrap());
+    assert_eq!(entries(&storage), pairs(&[("a", "3")]));
+
+    // Operations leaving the state as it is are not recorded
+    let unchanged = storage.apply_operations(vec![insert("a", "3")]).unwrap();
+    assert_eq!(unchanged.prev_root, unchanged.new_root);
+    assert_eq!(
+        storage
+            .diffs_since(EMPTY_STATE_ROOT, 10)
+            .unwrap()
+            .unwrap()
+            .len(),
+        2
+    );
+}
+
+fn concurrent_writers(db_type: &str) {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = open(db_type, dir.path());
+
+    // Clones share the lock, so each diff starts at the root the previous one left
+    let writers: Vec<_> = (0..8)
+        .map(|writer| {
+            let storage = storage.clone();
+            std::thread::spawn(move || {
+                for i in 0..10 {
+                    storage
+                        .apply_operations(vec![StateOperation::Insert {
+                            key: format!("{}-{}", writer, i),
+                            value: i.to_string(),
+                        }])
+                        .unwrap();
+                }
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+
+    assert_eq!(entries(&storage).len(), 80);
+    let diffs = storage.diffs_since(EMPTY_STATE_ROOT, 100).unwrap().unwrap();
+    assert_eq!(diffs.len(), 80);
+    for pair in diffs.windows(2) {
+        assert_eq!(pair[0].new_root, pair[1].prev_root);
+    }
+    assert_eq!(
+        diffs.last().unwrap().new_root,
+        storage.get_state_root().unwrap()
+    );
+}
+
+fn transaction(tx_type: TransactionType, sender: &str, log_index: u64) -> Transaction {
+    let mut transaction = create_transaction(
+        tx_type,
+        log_index.to_be_bytes().to_vec(),
+        Some(sender.to_string()),
+        Default::default(),
+        Default::default(),
+    );
+    transaction.timestamp =
+        Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap() + Duration::seconds(log_index as i64);
+    transaction.log_index = log_index;
+    transaction
+}
+
+fn log_indexes(transactions: &[Transaction]) -> Vec<u64> {
+    transactions.iter().map(|tx| tx.log_index).collect()
+}
+
+fn transaction_history(db_type: &str) {
+    let dir = tempfile::tempdir().unwrap();
+    let contract_a = H128::from_low_u64_be(0xa);
+    let contract_b = H128::from_low_u64_be(0xb);
+    let call = |contract, log_index| {
+        transaction(
+            TransactionType::Request(contract, "call".into()),
+            "alice",
+            log_index,
+        )
+    };
+    let history = [
+        call(contract_a, 1),
+        call(contract_a, 2),
+        call(contract_b, 3),
+        call(contract_a, 4),
+        transaction(TransactionType::StateChange, "bob", 5),
+    ];
+
+    {
+        let storage = open(db_type, dir.path());
+        for tx in &history {
+            storage.apply_transaction(tx.clone()).unwrap();
+        }
+        // Replaying a recorded transaction does not duplicate it
+        storage.apply_transaction(history[0].clone()).unwrap();
+        storage.stop().unwrap();
+    }
+
+    let storage = open(db_type, dir.path());
+    let recorded = storage.get_transaction(&history[2].id).unwrap().unwrap();
+    assert_eq!(recorded.id, history[2].id);
+    assert_eq!(recorded.tx_type, history[2].tx_type);
+    assert_eq!(recorded.payload, history[2].payload);
+    assert_eq!(recorded.timestamp, history[2].timestamp);
+    assert_eq!(recorded.sender, history[2].sender);
+    assert_eq!(recorded.log_index, 3);
+    assert!(storage
+        .get_transaction(&uuid::Uuid::new_v4())
+        .unwrap()
+        .is_none());
+
+    let all = storage
+        .query_transactions(&TransactionQuery::default())
+        .unwrap();
+    assert_eq!(log_indexes(&all.transactions), vec![5, 4, 3, 2, 1]);
+    assert_eq!(all.next_cursor, None);
+
+    // Last calls to a contract by a sender, paged
+    let mut query = TransactionQuery {
+        sender: Some("alice".into()),
+        contract: Some(contract_a),
+        limit: 2,
+        ..Default::default()
+    };
+    let page = storage.query_transactions(&query).unwrap();
+    assert_eq!(log_indexes(&page.transactions), vec![4, 2]);
+    assert!(page.next_cursor.is_some());
+    query.cursor = page.next_cursor;
+    let page = storage.query_transactions(&query).unwrap();
+    assert_eq!(log_indexes(&page.transactions), vec![1]);
+    assert_eq!(page.next_cursor, None);
+
+    let by_sender = TransactionQuery {
+        sender: Some("bob".into()),
+        ..Default::default()
+    };
+    assert_eq!(
+        log_indexes(&storage.query_transactions(&by_sender).unwrap().transactions),
+        vec![5]
+    );
+
+    let by_time = TransactionQuery {
+        since: Some(history[1].timestamp),
+        until: Some(history[3].timestamp),
+        ..Default::default()
+    };
+    assert_eq!(
+        log_indexes(&storage.query_transactions(&by_time).unwrap().transactions),
+        vec![3, 2]
+    );
+
+    let by_log_index = TransactionQuery {
+        contract: Some(contract_a),
+        min_log_index: Some(2),
+        max_log_index: Some(4),
+        ..Default::default()
+    };
+    assert_eq!(
+        log_indexes(
+            &storage
+                .query_transactions(&by_log_index)
+                .unwrap()
+                .transactions
+        ),
+        vec![4, 2]
+    );
+}
+
+fn execution_records(db_type: &str) {
+    let dir = tempfile::tempdir().unwrap();
+    let record = ExecutionRecord {
+        tx_id: uuid::Uuid::new_v4(),
+        method: "POST".to_string(),
+        headers: vec![("content-type".to_string(), "application/json".to_string())],
+        state_root: EMPTY_STATE_ROOT.to_string(),
+        output: serde_json::json!({ "status": 200, "body": { "value": 1 } }),
+        poc_root: "ab".repeat(32),
+    };
+    let rerun = ExecutionRecord {
+        output: serde_json::json!({ "status": 200, "body": { "value": 2 } }),
+        poc_root: "cd".repeat(32),
+        ..record.clone()
+    };
+
+    {
+        let storage = open(db_type, dir.path());
+        storage.record_execution(&record).unwrap();
+        assert_eq!(
+            storage.get_execution(&record.tx_id).unwrap(),
+            Some(record.clone())
+        );
+
+        // Recording the transaction again replaces its record
+        storage.record_execution(&rerun).unwrap();
+        storage.stop().unwrap();
+    }
+
+    let storage = open(db_type, dir.path());
+    assert_eq!(storage.get_execution(&record.tx_id).unwrap(), Some(rerun));
+    assert!(storage
+        .get_execution(&uuid::Uuid::new_v4())
+        .unwrap()
+        .is_none());
+}
+
+macro_rules! conformance_tests {
+    ($backend:ident) => {
+        mod $backend {
+            #[test]
+            fn test_initial_state() {
+                super::initial_state(stringify!($backend));
+            }
+
+            #[test]
+            fn test_apply_diffs() {
+                super::apply_diffs(stringify!($backend));
+            }
+
+            #[test]
+            fn test_serve_diffs() {
+                super::serve_diffs(stringify!($backend));
+            }
+
+            #[test]
+            fn test_snapshots() {
+                super::snapshots(stringify!($backend));
+            }
+
+            #[test]
+            fn test_state_roots_and_proofs() {
+                super::state_roots_and_proofs(stringify!($backend));
+            }
+
+            #[test]
+            fn test_historical_queries() {
+                super::historical_queries(stringify!($backend));
+            }
+
+            #[test]
+            fn test_log_roots() {
+                super::log_roots(stringify!($backend));
+            }
+
+            #[test]
+            fn test_revert() {
+                super::revert(stringify!($backend));
+            }
+
+            #[test]
+            fn test_persistence() {
+                super::persistence(stringify!($backend));
+            }
+
+            #[test]
+            fn test_apply_operations() {
+                super::apply_operations(stringify!($backend));
+            }
+
+            #[test]
+            fn test_concurrent_writers() {
+                super::concurrent_writers(stringify!($backend));
+            }
+
+            #[test]
+            fn test_transaction_history() {
+                super::transaction_history(stringify!($backend));
+            }
+
+            #[test]
+            fn test_execution_records() {
+                super::execution_records(stringify!($backend));
+            }
+        }
+    };
+}
+
+conformance_tests!(sqlite);
+conformance_tests!(sled);
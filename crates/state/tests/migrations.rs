@@ -0,0 +1,215 @@
+use std::path::Path;
+
+use mp_state::config::StateConfig;
+use mp_state::db::migrations::{latest_version, MIGRATIONS};
+use mp_state::diff::StateDiff;
+use mp_state::proof::state_root;
+use mp_state::{create_state_storage, StateStorage, EMPTY_STATE_ROOT};
+use rusqlite::Connection;
+
+/// Root the initial state was recorded under by earlier builds
+const ZERO_ROOT: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+fn open(db_type: &str, dir: &Path) -> anyhow::Result<()> {
+    open_storage(db_type, dir).map(|_| ())
+}
+
+fn open_storage(db_type: &str, dir: &Path) -> anyhow::Result<std::sync::Arc<dyn StateStorage>> {
+    let storage = create_state_storage(StateConfig {
+        db_type: db_type.to_string(),
+        db_connection: dir.join("state.db").to_string_lossy().to_string(),
+        state_root_path: dir.join("state_root").to_string_lossy().to_string(),
+    })?;
+    storage.start()?;
+    Ok(storage)
+}
+
+/// Check a database written by an earlier build, whose initial state was at [`ZERO_ROOT`]
+/// and which then applied `a = 1`
+fn assert_zero_root_renamed(db_type: &str, dir: &Path) {
+    let root_1 = state_root([("a", "1")]).unwrap();
+    let storage = open_storage(db_type, dir).unwrap();
+    assert_eq!(storage.create_checkpoint().unwrap().new_root, root_1);
+    assert_eq!(storage.get_state_root().unwrap(), root_1);
+    let diffs = storage.diffs_since(EMPTY_STATE_ROOT, 10).unwrap().unwrap();
+    assert_eq!(diffs.len(), 1);
+    assert_eq!(diffs[0].prev_root, EMPTY_STATE_ROOT);
+    assert!(storage.diffs_since(ZERO_ROOT, 10).unwrap().is_none());
+
+    // The history continues and reverts back to the empty state
+    storage
+        .apply_operations(vec![mp_state::diff::StateOperation::Delete {
+            key: "a".to_string(),
+        }])
+        .unwrap();
+    assert_eq!(storage.get_state_root().unwrap(), EMPTY_STATE_ROOT);
+    storage.revert_to(&root_1).unwrap();
+    storage.revert_to(EMPTY_STATE_ROOT).unwrap();
+    assert!(storage.export_snapshot().unwrap().entries.is_empty());
+}
+
+fn applied_versions(dir: &Path) -> Vec<i64> {
+    let conn = Connection::open(dir.join("state.db")).unwrap();
+    let mut statement = conn
+        .prepare("SELECT version FROM schema_version ORDER BY version")
+        .unwrap();
+    let versions = statement
+        .query_map([], |row| row.get(0))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    versions
+}
+
+#[test]
+fn test_migrations_applied_once_in_order() {
+    let dir = tempfile::tempdir().unwrap();
+    open("sqlite", dir.path()).unwrap();
+    open("sqlite", dir.path()).unwrap();
+
+    let known: Vec<i64> = MIGRATIONS.iter().map(|m| m.version).collect();
+    assert_eq!(applied_versions(dir.path()), known);
+    assert_eq!(known.last(), Some(&latest_version()));
+}
+
+#[test]
+fn test_unversioned_database_is_adopted() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        // A database created before migrations were versioned
+        let conn = Connection::open(dir.path().join("state.db")).unwrap();
+        conn.execute_batch(MIGRATIONS[0].sql).unwrap();
+        conn.execute(
+            "INSERT INTO state_entries (key, value) VALUES ('a', '1')",
+            [],
+        )
+        .unwrap();
+    }
+
+    open("sqlite", dir.path()).unwrap();
+    assert_eq!(applied_versions(dir.path()).len(), MIGRATIONS.len());
+
+    let conn = Connection::open(dir.path().join("state.db")).unwrap();
+    let value: String = conn
+        .query_row(
+            "SELECT value FROM state_entries WHERE key = 'a'",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(value, "1");
+}
+
+#[test]
+fn test_changed_migration_is_refused() {
+    let dir = tempfile::tempdir().unwrap();
+    open("sqlite", dir.path()).unwrap();
+
+    Connection::open(dir.path().join("state.db"))
+        .unwrap()
+        .execute(
+            "UPDATE schema_version SET checksum = 'edited' WHERE version = 1",
+            [],
+        )
+        .unwrap();
+
+    let error = open("sqlite", dir.path()).unwrap_err();
+    assert!(error.to_string().contains("changed after it was applied"));
+}
+
+#[test]
+fn test_newer_schema_is_refused() {
+    let dir = tempfile::tempdir().unwrap();
+    open("sqlite", dir.path()).unwrap();
+
+    Connection::open(dir.path().join("state.db"))
+        .unwrap()
+        .execute(
+            "INSERT INTO schema_version (version, name, checksum) VALUES (?1, 'future', '')",
+            [latest_version() + 1],
+        )
+        .unwrap();
+
+    let error = open("sqlite", dir.path()).unwrap_err();
+    assert!(error
+        .to_string()
+        .contains("newer than the latest known version"));
+}
+
+#[test]
+fn test_newer_sled_layout_is_refused() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let db = sled::open(dir.path().join("state.db")).unwrap();
+        db.open_tree("meta")
+            .unwrap()
+            .insert("schema_version", &u64::MAX.to_be_bytes())
+            .unwrap();
+        db.flush().unwrap();
+    }
+
+    let error = open("sled", dir.path()).unwrap_err();
+    assert!(error
+        .to_string()
+        .contains("newer than the latest known version"));
+}
+
+#[test]
+fn test_zero_root_is_renamed_to_the_empty_trie_root() {
+    let dir = tempfile::tempdir().unwrap();
+    let root_1 = state_root([("a", "1")]).unwrap();
+    {
+        // A database written before migration 4
+        let conn = Connection::open(dir.path().join("state.db")).unwrap();
+        for migration in &MIGRATIONS[..3] {
+            conn.execute_batch(migration.sql).unwrap();
+        }
+        conn.execute(
+            "INSERT INTO state_roots (root_hash) VALUES (?1), (?2)",
+            [ZERO_ROOT, root_1.as_str()],
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO state_diffs (prev_root_hash, new_root_hash) VALUES (?1, ?2)",
+            [ZERO_ROOT, root_1.as_str()],
+        )
+        .unwrap();
+        conn.execute_batch(
+            "INSERT INTO state_operations (diff_id, operation_type, key, value)
+             VALUES (1, 'insert', 'a', '1');
+             INSERT INTO state_entries (key, value) VALUES ('a', '1');",
+        )
+        .unwrap();
+    }
+
+    assert_zero_root_renamed("sqlite", dir.path());
+}
+
+#[test]
+fn test_zero_root_is_renamed_in_sled_layout_1() {
+    let dir = tempfile::tempdir().unwrap();
+    let root_1 = state_root([("a", "1")]).unwrap();
+    {
+        let mut diff = StateDiff::new(ZERO_ROOT.to_string());
+        diff.new_root = root_1.clone();
+        diff.insert("a".to_string(), "1".to_string());
+
+        let db = sled::open(dir.path().join("state.db")).unwrap();
+        let meta = db.open_tree("meta").unwrap();
+        meta.insert("schema_version", &1u64.to_be_bytes()).unwrap();
+        meta.insert("current_root", root_1.as_bytes()).unwrap();
+        let roots = db.open_tree("roots").unwrap();
+        roots.insert(ZERO_ROOT, &0u64.to_be_bytes()).unwrap();
+        roots
+            .insert(root_1.as_bytes(), &1u64.to_be_bytes())
+            .unwrap();
+        db.open_tree("diffs")
+            .unwrap()
+            .insert(1u64.to_be_bytes(), serde_json::to_vec(&diff).unwrap())
+            .unwrap();
+        db.open_tree("entries").unwrap().insert("a", "1").unwrap();
+        db.flush().unwrap();
+    }
+
+    assert_zero_root_renamed("sled", dir.path());
+}
@@ -0,0 +1,134 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use mp_state::config::StateConfig;
+use mp_state::diff::StateOperation;
+use mp_state::proof::{state_root, StateProof, StateTrie};
+use mp_state::{create_state_storage, StateStorage, EMPTY_STATE_ROOT};
+
+fn state_storage(dir: &Path) -> Arc<dyn StateStorage> {
+    create_state_storage(StateConfig {
+        db_type: "sqlite".to_string(),
+        db_connection: dir.join("state.db").to_string_lossy().to_string(),
+        state_root_path: dir.join("state_root").to_string_lossy().to_string(),
+    })
+    .unwrap()
+}
+
+fn apply(storage: &Arc<dyn StateStorage>, entries: &[(&str, &str)]) {
+    let operations = entries
+        .iter()
+        .map(|(key, value)| StateOperation::Insert {
+            key: key.to_string(),
+            value: value.to_string(),
+        })
+        .collect();
+    storage.apply_operations(operations).unwrap();
+}
+
+#[test]
+fn test_state_root_commits_to_values() {
+    let same_keys = state_root([("alice", "10"), ("bob", "5")]).unwrap();
+    let other_values = state_root([("alice", "10"), ("bob", "6")]).unwrap();
+    assert_ne!(same_keys, other_values);
+    assert_eq!(same_keys.len(), EMPTY_STATE_ROOT.len());
+
+    // The empty state has the root of the empty trie
+    assert_eq!(state_root(std::iter::empty()).unwrap(), EMPTY_STATE_ROOT);
+
+    // Empty values are part of the state
+    assert_ne!(
+        state_root([("alice", "")]).unwrap(),
+        state_root(std::iter::empty()).unwrap()
+    );
+}
+
+#[test]
+fn test_inclusion_and_exclusion_proofs() {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = state_storage(dir.path());
+    apply(&storage, &[("alice", "10"), ("bob", "5"), ("carol", "")]);
+    let root = storage.get_state_root().unwrap();
+
+    let proof = storage.get_proof("bob").unwrap();
+    assert_eq!(proof.root, root);
+    assert_eq!(proof.value.as_deref(), Some("5"));
+    proof.verify().unwrap();
+
+    let empty = storage.get_proof("carol").unwrap();
+    assert_eq!(empty.value.as_deref(), Some(""));
+    empty.verify().unwrap();
+
+    let absent = storage.get_proof("dave").unwrap();
+    assert_eq!(absent.value, None);
+    absent.verify().unwrap();
+
+    // Tampered claims are rejected
+    let forged = StateProof {
+        value: Some("500".to_string()),
+        ..proof.clone()
+    };
+    assert!(forged.verify().is_err());
+    let hidden = StateProof {
+        value: None,
+        ..proof.clone()
+    };
+    assert!(hidden.verify().is_err());
+
+    // The proof does not hold against a later state
+    apply(&storage, &[("bob", "6")]);
+    let stale = StateProof {
+        root: storage.get_state_root().unwrap(),
+        ..proof
+    };
+    assert!(stale.verify().is_err());
+    storage.get_proof("bob").unwrap().verify().unwrap();
+}
+
+#[test]
+fn test_proof_on_empty_state() {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = state_storage(dir.path());
+
+    let proof = storage.get_proof("alice").unwrap();
+    assert_eq!(proof.root, storage.get_state_root().unwrap());
+    assert_eq!(proof.value, None);
+    proof.verify().unwrap();
+}
+
+#[test]
+fn test_state_trie_follows_updates() {
+    let mut trie = StateTrie::new([("alice", "10")]).unwrap();
+    let mut entries = std::collections::BTreeMap::from([("alice".to_string(), "10".to_string())]);
+
+    // Enough updates for the trie to rebuild itself along the way
+    for i in 0..1500u32 {
+        let key = format!("key-{}", i % 100);
+        let operation = if i % 7 == 0 {
+            entries.remove(&key);
+            StateOperation::Delete { key }
+        } else {
+            entries.insert(key.clone(), i.to_string());
+            StateOperation::Insert {
+                key,
+                value: i.to_string(),
+            }
+        };
+        let root = trie.apply(&[operation]).unwrap();
+        assert_eq!(root, trie.root());
+    }
+    assert_eq!(
+        trie.root(),
+        state_root(entries.iter().map(|(k, v)| (k.as_str(), v.as_str()))).unwrap()
+    );
+    trie.proof("key-1").unwrap().verify().unwrap();
+
+    // An update can be undone while the previous root is kept
+    let before = trie.root();
+    trie.apply(&[StateOperation::Delete {
+        key: "alice".to_string(),
+    }])
+    .unwrap();
+    trie.undo(&before).unwrap();
+    assert_eq!(trie.proof("alice").unwrap().value.as_deref(), Some("10"));
+}